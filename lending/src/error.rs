@@ -1,6 +1,7 @@
 //! Error types
 
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive as _;
 use solana_program::{
     decode_error::DecodeError,
     program_error::ProgramError,
@@ -13,7 +14,7 @@ pub enum LendingError {
     ///
     #[cfg(feature = "friend")]
     #[error("Obligation is already in binding")]
-    AlreadyBindFriend,
+    ObligationAlreadyBound,
     /// The account cannot be initialized because it is already in use.
     #[error("Account is already initialized")]
     AlreadyInitialized,
@@ -159,6 +160,124 @@ pub enum LendingError {
     /// Unmatched accounts
     #[error("Unmatched accounts")]
     UnmatchedAccounts,
+    /// Obligation can not bind to itself
+    #[cfg(feature = "friend")]
+    #[error("Obligation friend is invalid")]
+    ObligationInvalidFriend,
+    /// Dex pool too shallow
+    #[error("Dex pool liquidity is too shallow")]
+    DexPoolTooShallow,
+    /// Obligation not single pair
+    #[error("Obligation must hold exactly one collateral and one loan")]
+    ObligationNotSinglePair,
+    /// Token decimals out of range
+    #[error("Token decimals is out of range")]
+    InvalidTokenDecimals,
+    /// Oracle price out of range
+    #[error("Oracle price or exponent is out of range")]
+    InvalidOraclePrice,
+    /// Leverage open health below manager minimum
+    #[error("Leverage position health is below the minimum for opening")]
+    LeverageOpenTooRisky,
+    /// Invalid oracle params
+    #[error("Invalid oracle params")]
+    InvalidOracleParams,
+    /// Invalid liquidation fee config
+    #[error("Invalid liquidation fee config")]
+    InvalidLiquidationFeeConfig,
+    /// Friend pair borrow value above manager cap
+    #[error("Combined borrow value of friend obligations exceeds the cap")]
+    FriendLeverageCapExceeded,
+    /// SoToken mint has preset authority
+    #[error("SoToken mint is invalid")]
+    InvalidSoTokenMint,
+    /// Sweep supply account is forbidden
+    #[error("Cannot sweep market reserve supply account")]
+    CannotSweepSupplyAccount,
+    /// Borrow within cooldown after large repay
+    #[error("Borrow is not allowed during cooldown after large repay")]
+    BorrowCooldown,
+    /// Invalid staker fee config
+    #[error("Invalid staker fee config")]
+    InvalidStakerFeeConfig,
+    /// Oracle account owned by unexpected program
+    #[error("Oracle account is not owned by the expected oracle program")]
+    InvalidOracleOwner,
+    /// Manager paused
+    #[error("Manager is paused")]
+    ManagerPaused,
+    /// No pending config
+    #[error("Market reserve has no pending config")]
+    PendingConfigNotFound,
+    /// Config timelock not elapsed
+    #[error("Config timelock has not elapsed")]
+    ConfigTimelockNotElapsed,
+    /// Config timelock can not be shortened
+    #[error("Config timelock can only be increased")]
+    ConfigTimelockDecrease,
+    /// Token sync native failed
+    #[error("Token sync native failed")]
+    TokenSyncNativeFailed,
+    /// Sotoken restricted
+    #[error("Sotoken of market reserve is restricted")]
+    SotokenRestricted,
+    /// Insurance bucket insufficient
+    #[error("Insurance bucket is insufficient")]
+    InsuranceBucketInsufficient,
+    /// Insufficient oracle quorum
+    #[error("Fresh oracle sources are below the quorum")]
+    InsufficientOracleQuorum,
+    /// Max leverage exceeded
+    #[error("Leverage exceeds the manager max leverage")]
+    MaxLeverageExceeded,
+    /// Collateral loan same reserve
+    #[error("Collateral and loan market reserve are the same")]
+    CollateralLoanSameReserve,
+    /// Invalid liquidity status bands
+    #[error("Liquidity status bands are invalid")]
+    InvalidLiquidityStatusBands,
+    /// Insurance depleted
+    #[error("Insurance is depleted by liquidation shortfall")]
+    InsuranceDepleted,
+    /// Loan diversity cap exceeded
+    #[error("Obligation loans reach the manager max loans per obligation")]
+    LoanDiversityCapExceeded,
+    /// Unsupported token extension
+    #[error("Token mint is not a plain spl token mint")]
+    UnsupportedTokenExtension,
+    /// Obligation not whitelisted
+    #[error("Obligation is not whitelisted for permissioned reserve")]
+    ObligationNotWhitelisted,
+    /// Use deposit and pledge
+    #[error("Market reserve takes collateral by pledge only, use deposit and pledge")]
+    UseDepositAndPledge,
+    /// Collateral concentration exceeded
+    #[error("Collateral value share in obligation exceeds concentration cap")]
+    CollateralConcentrationExceeded,
+    /// Obligation exposure cap exceeded
+    #[error("Obligation value exceeds manager exposure cap")]
+    ObligationExposureCapExceeded,
+    /// Oracle quote mismatch
+    #[error("Oracle quote currency does not match manager quote currency")]
+    OracleQuoteMismatch,
+    /// Collateral too fresh
+    #[error("Collateral pledged within cooldown can not be borrowed against")]
+    CollateralTooFresh,
+    /// Market reserve not empty
+    #[error("Market reserve already has sotoken supply")]
+    MarketReserveNotEmpty,
+    /// Deposit not whitelisted
+    #[error("Depositor is not whitelisted during guarded launch")]
+    DepositNotWhitelisted,
+    /// Requires companion collateral
+    #[error("Collateral can not be borrowed against without companion collateral")]
+    RequiresCompanionCollateral,
+    /// Invalid keeper tip config
+    #[error("Keeper tip requires a min gap not below lax stale slots")]
+    InvalidKeeperTipConfig,
+    /// Market reserve not bootstrapped
+    #[error("Market reserve accepts no deposit before bootstrap")]
+    MarketReserveNotBootstrapped,
 }
 
 impl From<LendingError> for ProgramError {
@@ -171,4 +290,32 @@ impl<T> DecodeError<T> for LendingError {
     fn type_of() -> &'static str {
         "Lending Error"
     }
-}
\ No newline at end of file
+}
+
+/// `(code, name, message)` of every error variant in code order, for client error tables
+pub fn lending_error_table() -> Vec<(u32, String, String)> {
+    (0u32..)
+        .map_while(|code| LendingError::from_u32(code).map(|error| (code, format!("{:?}", error), error.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_table_covers_every_variant() {
+        let table = lending_error_table();
+        assert!(table.iter().enumerate().all(|(i, (code, _, _))| *code == i as u32));
+        assert!(LendingError::from_u32(table.len() as u32).is_none());
+
+        let code = LendingError::CollateralTooFresh as u32;
+        assert_eq!(
+            table[code as usize],
+            (code, "CollateralTooFresh".to_string(), "Collateral pledged within cooldown can not be borrowed against".to_string()),
+        );
+        let code = LendingError::AlreadyInitialized as u32;
+        assert_eq!(table[code as usize].1, "AlreadyInitialized");
+        assert_eq!(table[code as usize].2, "Account is already initialized");
+    }
+}
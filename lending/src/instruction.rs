@@ -3,14 +3,16 @@
 use crate::{
     error::LendingError,
     id,
-    oracle::{OracleConfig, OracleType},
-    state::{CollateralConfig, IndexedCollateralConfig, IndexedLoanConfig, LiquidityConfig, RateModel},
+    oracle::{OracleConfig, OracleParams, OracleType},
+    state::{CollateralConfig, DepositWhitelist, IndexedCollateralConfig, IndexedLoanConfig, LiquidityConfig, LiquidityStatusBands, RateModel, StakerFeeConfig},
 };
 use solana_program::{
+    clock::Slot,
     instruction::{AccountMeta, Instruction},
     msg,
     program_error::ProgramError,
     pubkey::{Pubkey, PUBKEY_BYTES},
+    system_program,
     sysvar,
 };
 use std::{convert::TryInto, mem::size_of};
@@ -82,6 +84,27 @@ pub enum LendingInstruction {
     OpenLeveragePositionByRaydiumBaseIn(u64, u64),
     /// 30
     OpenLeveragePositionByRaydiumBaseOut(u64, u64),
+    /// 31
+    RepayAllAndRedeemAll(bool),
+    /// 32
+    ClaimStakerFee,
+    /// 33
+    RefreshAll,
+    /// 34
+    ApplyPendingConfig,
+    /// 35
+    RefreshUserObligationPartial,
+    /// 36
+    DepositPledgeBorrow(u64, u64),
+    /// 37
+    RepayFromPledgedCollateral(u64),
+    /// 38
+    RefreshMarketReserveWithTip,
+    // 39 left unused, sotoken is a plain transferable token with no on-chain principal to skim yield above
+    /// 40
+    LiquidateDirect(u64),
+    /// 41
+    RefreshUserObligationForBorrow(Pubkey),
     /// 100
     #[cfg(feature = "unique-credit")]
     InitUniqueCredit(Pubkey, u64),
@@ -112,6 +135,110 @@ pub enum LendingInstruction {
     /// 112
     #[cfg(feature = "unique-credit")]
     UpdateUniqueCreditLimit(u64),
+    /// 113
+    BatchUpdateMarketReserveRateModel(RateModel),
+    /// 114
+    BatchUpdateMarketReserveCollateralConfig(CollateralConfig),
+    /// 115
+    BatchUpdateMarketReserveLiquidityConfig(LiquidityConfig),
+    /// 116
+    GetReserveParams,
+    /// 117
+    UpdateManagerMinLeverageOpenHealth(u16),
+    /// 118
+    UpdateMarketReserveDebtOracleConfig(Option<OracleConfig>),
+    /// 119
+    UpdateMarketReserveOracleParams(OracleParams),
+    /// 120
+    UpdateManagerLiquidationFee(u8, Option<Pubkey>),
+    /// 121
+    UpdateManagerFriendBorrowValueCap(u64),
+    /// 122
+    UpdateManagerBorrowMaxObligationAge(u64),
+    /// 123
+    SweepStrayTokens,
+    /// 124
+    UpdateMarketReserveStakerFeeConfig(StakerFeeConfig),
+    /// 125
+    UpdateManagerCollateralDustValue(u64),
+    /// 126
+    UpdateManagerFlashLoanRepayTolerance(u64),
+    /// 127
+    SetManagerPaused(bool),
+    /// 128
+    UpdateManagerConfigTimelock(u64),
+    /// 129
+    UpdateMarketReserveSotokenRestriction(bool),
+    /// 130
+    InitMedianOracle(u8, Vec<OracleConfig>),
+    /// 131
+    UpdateManagerMaxLeverage(u8),
+    /// 132
+    UpdateMarketReserveLiquidityStatusBands(LiquidityStatusBands),
+    /// 133
+    UpdateManagerLiquidationBackstopCap(u64),
+    /// 134
+    UpdateManagerKeeperTip(u64, u64),
+    /// 135
+    UpdateManagerMaxLoansPerObligation(u8),
+    /// 136
+    AllowObligation,
+    /// 137
+    DisallowObligation,
+    /// 138
+    TransferInsurance(u64),
+    /// 139
+    SetEmergencyLiquidation(bool),
+    /// 140
+    UpdateManagerObligationExposureCap(u64, u64),
+    /// 141
+    UpdateManagerQuoteCurrency([u8; 4]),
+    /// 142
+    UpdateManagerCollateralCooldown(u8),
+    /// 143
+    BootstrapReserve(u64),
+    /// 144
+    InitDepositWhitelist(Slot, Vec<Pubkey>),
+    /// 145
+    ExtendMarketReserve,
+    /// 146
+    UpdateMarketReserveLiquidationBackstopBudget(u64),
+    /// 147
+    ApplyManagerPendingConfig,
+    /// 148
+    ExtendManager,
+    /// 149
+    InitDexOracle(u64),
+}
+
+const RATE_MODEL_LEN: usize = 34;
+const ORACLE_CONFIG_LEN: usize = PUBKEY_BYTES + 1;
+const COLLATERAL_CONFIG_LEN: usize = 16;
+const LIQUIDITY_CONFIG_LEN: usize = 36;
+const STAKER_FEE_CONFIG_LEN: usize = 1 + PUBKEY_BYTES;
+
+/// largest instruction data queued under manager config timelock, tag included,
+/// insurance moves are queued with amount and destination key
+pub const MAX_TIMELOCKED_DATA_LEN: usize = max_len(&[
+    1 + RATE_MODEL_LEN,
+    1 + COLLATERAL_CONFIG_LEN,
+    1 + LIQUIDITY_CONFIG_LEN,
+    2 + ORACLE_CONFIG_LEN,
+    1 + STAKER_FEE_CONFIG_LEN,
+    3 + PUBKEY_BYTES,
+    9 + PUBKEY_BYTES,
+]);
+
+const fn max_len(lens: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < lens.len() {
+        if lens[i] > max {
+            max = lens[i];
+        }
+        i += 1;
+    }
+    max
 }
 
 impl LendingInstruction {
@@ -243,6 +370,32 @@ impl LendingInstruction {
                 let (collateral_amount, _rest) = Self::unpack_u64(rest)?;
                 Self::OpenLeveragePositionByRaydiumBaseOut(max_borrow_amount, collateral_amount)
             }
+            31 => {
+                let (close_obligation, _rest) = Self::unpack_bool(rest)?;
+                Self::RepayAllAndRedeemAll(close_obligation)
+            }
+            32 => Self::ClaimStakerFee,
+            33 => Self::RefreshAll,
+            34 => Self::ApplyPendingConfig,
+            35 => Self::RefreshUserObligationPartial,
+            36 => {
+                let (deposit_amount, rest) = Self::unpack_u64(rest)?;
+                let (borrow_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::DepositPledgeBorrow(deposit_amount, borrow_amount)
+            }
+            37 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::RepayFromPledgedCollateral(amount)
+            }
+            38 => Self::RefreshMarketReserveWithTip,
+            40 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::LiquidateDirect(amount)
+            }
+            41 => {
+                let (borrow_reserve, _rest) = Self::unpack_pubkey(rest)?;
+                Self::RefreshUserObligationForBorrow(borrow_reserve)
+            }
             #[cfg(feature = "unique-credit")]
             100 => {
                 let (authority, rest) = Self::unpack_pubkey(rest)?;
@@ -297,6 +450,164 @@ impl LendingInstruction {
                 let (amount, _rest) = Self::unpack_u64(rest)?;
                 Self::UpdateUniqueCreditLimit(amount)
             }
+            113 => {
+                let (model, _rest) = Self::unpack_rate_model(rest)?;
+                Self::BatchUpdateMarketReserveRateModel(model)
+            }
+            114 => {
+                let (config, _rest) = Self::unpack_collateral_config(rest)?;
+                Self::BatchUpdateMarketReserveCollateralConfig(config)
+            }
+            115 => {
+                let (config, _rest) = Self::unpack_liquidity_config(rest)?;
+                Self::BatchUpdateMarketReserveLiquidityConfig(config)
+            }
+            116 => Self::GetReserveParams,
+            117 => {
+                let (bps, _rest) = Self::unpack_u16(rest)?;
+                Self::UpdateManagerMinLeverageOpenHealth(bps)
+            }
+            118 => {
+                let (enable, rest) = Self::unpack_bool(rest)?;
+                let config = if enable {
+                    let (config, _rest) = Self::unpack_oracle_config(rest)?;
+                    Some(config)
+                } else {
+                    None
+                };
+                Self::UpdateMarketReserveDebtOracleConfig(config)
+            }
+            119 => {
+                let (params, _rest) = Self::unpack_oracle_params(rest)?;
+                Self::UpdateMarketReserveOracleParams(params)
+            }
+            120 => {
+                let (ratio, rest) = Self::unpack_u8(rest)?;
+                let (enable, rest) = Self::unpack_bool(rest)?;
+                let receiver = if enable {
+                    let (receiver, _rest) = Self::unpack_pubkey(rest)?;
+                    Some(receiver)
+                } else {
+                    None
+                };
+                Self::UpdateManagerLiquidationFee(ratio, receiver)
+            }
+            121 => {
+                let (cap, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerFriendBorrowValueCap(cap)
+            }
+            122 => {
+                let (max_age_slots, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerBorrowMaxObligationAge(max_age_slots)
+            }
+            123 => Self::SweepStrayTokens,
+            124 => {
+                let (ratio, rest) = Self::unpack_u8(rest)?;
+                let (account, _rest) = Self::unpack_pubkey(rest)?;
+                Self::UpdateMarketReserveStakerFeeConfig(StakerFeeConfig { ratio, account })
+            }
+            125 => {
+                let (dust_value, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerCollateralDustValue(dust_value)
+            }
+            126 => {
+                let (tolerance, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerFlashLoanRepayTolerance(tolerance)
+            }
+            127 => {
+                let (paused, _rest) = Self::unpack_bool(rest)?;
+                Self::SetManagerPaused(paused)
+            }
+            128 => {
+                let (timelock_slots, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerConfigTimelock(timelock_slots)
+            }
+            129 => {
+                let (restricted, _rest) = Self::unpack_bool(rest)?;
+                Self::UpdateMarketReserveSotokenRestriction(restricted)
+            }
+            130 => {
+                let (quorum, rest) = Self::unpack_u8(rest)?;
+                let (len, mut rest) = Self::unpack_u8(rest)?;
+                let mut sources = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    let (source, _rest) = Self::unpack_oracle_config(rest)?;
+                    sources.push(source);
+                    rest = _rest;
+                }
+                Self::InitMedianOracle(quorum, sources)
+            }
+            131 => {
+                let (max_leverage_x, _rest) = Self::unpack_u8(rest)?;
+                Self::UpdateManagerMaxLeverage(max_leverage_x)
+            }
+            132 => {
+                let (tight_bps, rest) = Self::unpack_u16(rest)?;
+                let (critical_bps, _rest) = Self::unpack_u16(rest)?;
+                Self::UpdateMarketReserveLiquidityStatusBands(LiquidityStatusBands { tight_bps, critical_bps })
+            }
+            133 => {
+                let (cap, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerLiquidationBackstopCap(cap)
+            }
+            134 => {
+                let (tip_value, rest) = Self::unpack_u64(rest)?;
+                let (min_gap_slots, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerKeeperTip(tip_value, min_gap_slots)
+            }
+            135 => {
+                let (max_loans, _rest) = Self::unpack_u8(rest)?;
+                Self::UpdateManagerMaxLoansPerObligation(max_loans)
+            }
+            136 => Self::AllowObligation,
+            137 => Self::DisallowObligation,
+            138 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::TransferInsurance(amount)
+            }
+            139 => {
+                let (enable, _rest) = Self::unpack_bool(rest)?;
+                Self::SetEmergencyLiquidation(enable)
+            }
+            140 => {
+                let (max_collateral_value, rest) = Self::unpack_u64(rest)?;
+                let (max_loan_value, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateManagerObligationExposureCap(max_collateral_value, max_loan_value)
+            }
+            141 => {
+                let (quote_currency, _rest) = Self::unpack_quote_currency(rest)?;
+                Self::UpdateManagerQuoteCurrency(quote_currency)
+            }
+            142 => {
+                let (cooldown_slots, _rest) = Self::unpack_u8(rest)?;
+                Self::UpdateManagerCollateralCooldown(cooldown_slots)
+            }
+            143 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::BootstrapReserve(amount)
+            }
+            144 => {
+                let (until_slot, rest) = Self::unpack_u64(rest)?;
+                let (len, mut rest) = Self::unpack_u8(rest)?;
+                let mut depositors = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    let (depositor, _rest) = Self::unpack_pubkey(rest)?;
+                    depositors.push(depositor);
+                    rest = _rest;
+                }
+                Self::InitDepositWhitelist(until_slot, depositors)
+            }
+            145 => Self::ExtendMarketReserve,
+            146 => {
+                let (budget, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateMarketReserveLiquidationBackstopBudget(budget)
+            }
+            147 => Self::ApplyManagerPendingConfig,
+            148 => Self::ExtendManager,
+            149 => {
+                let (min_quote_liquidity, _rest) = Self::unpack_u64(rest)?;
+                Self::InitDexOracle(min_quote_liquidity)
+            }
             _ => {
                 msg!("Instruction cannot be unpacked");
                 return Err(LendingError::InstructionUnpackError.into());
@@ -324,8 +635,9 @@ impl LendingInstruction {
         let (optimal, rest) = Self::unpack_u64(rest)?;
         let (kink, rest) = Self::unpack_u8(rest)?;
         let (max, rest) = Self::unpack_u128(rest)?;
+        let (smoothing_factor, rest) = Self::unpack_u8(rest)?;
 
-        Ok((RateModel { offset, optimal, kink, max }, rest))
+        Ok((RateModel { offset, optimal, kink, max, smoothing_factor }, rest))
     }
 
     fn unpack_oracle_config(input: &[u8]) -> Result<(OracleConfig, &[u8]), ProgramError> {
@@ -335,12 +647,42 @@ impl LendingInstruction {
         Ok((OracleConfig { oracle, oracle_type: OracleType::from(oracle_type) }, rest))
     }
 
+    fn unpack_oracle_params(input: &[u8]) -> Result<(OracleParams, &[u8]), ProgramError> {
+        let (stale_threshold, rest) = Self::unpack_u64(input)?;
+        let (max_confidence_bps, rest) = Self::unpack_u16(rest)?;
+        let (max_deviation_bps, rest) = Self::unpack_u16(rest)?;
+
+        Ok((OracleParams { stale_threshold, max_confidence_bps, max_deviation_bps }, rest))
+    }
+
     fn unpack_collateral_config(input: &[u8]) -> Result<(CollateralConfig, &[u8]), ProgramError> {
         let (borrow_value_ratio, rest) = Self::unpack_u8(input)?;
         let (liquidation_value_ratio, rest) = Self::unpack_u8(rest)?;
         let (liquidation_penalty_ratio, rest) = Self::unpack_u8(rest)?;
+        let (full_liquidation_health_bps, rest) = Self::unpack_u16(rest)?;
+        let (borrower_liquidation_rebate_bps, rest) = Self::unpack_u16(rest)?;
+        let (max_post_liquidation_health_bps, rest) = Self::unpack_u16(rest)?;
+        let (permissioned, rest) = Self::unpack_bool(rest)?;
+        let (max_concentration_ratio, rest) = Self::unpack_u8(rest)?;
+        let (bonus_by_collateral_bps, rest) = Self::unpack_u16(rest)?;
+        let (bonus_by_loan_bps, rest) = Self::unpack_u16(rest)?;
+        let (requires_companion_collateral, rest) = Self::unpack_bool(rest)?;
 
-        Ok((CollateralConfig { borrow_value_ratio, liquidation_value_ratio, liquidation_penalty_ratio }, rest))
+        Ok((
+            CollateralConfig {
+                borrow_value_ratio,
+                liquidation_value_ratio,
+                liquidation_penalty_ratio,
+                full_liquidation_health_bps,
+                borrower_liquidation_rebate_bps,
+                max_post_liquidation_health_bps,
+                permissioned,
+                max_concentration_ratio,
+                bonus_by_collateral_bps,
+                bonus_by_loan_bps,
+                requires_companion_collateral,
+            }, rest
+        ))
     }
 
     fn unpack_liquidity_config(input: &[u8]) -> Result<(LiquidityConfig, &[u8]), ProgramError> {
@@ -348,6 +690,10 @@ impl LendingInstruction {
         let (borrow_tax_rate, rest) = Self::unpack_u8(rest)?;
         let (flash_loan_fee_rate, rest) = Self::unpack_u64(rest)?;
         let (max_deposit, rest) = Self::unpack_u64(rest)?;
+        let (new_loan_fee, rest) = Self::unpack_u64(rest)?;
+        let (borrow_cooldown_slots, rest) = Self::unpack_u64(rest)?;
+        let (large_repay_ratio, rest) = Self::unpack_u8(rest)?;
+        let (compound_by_seconds, rest) = Self::unpack_bool(rest)?;
 
         Ok((
             LiquidityConfig {
@@ -355,6 +701,10 @@ impl LendingInstruction {
                 borrow_tax_rate,
                 flash_loan_fee_rate,
                 max_deposit,
+                new_loan_fee,
+                borrow_cooldown_slots,
+                large_repay_ratio,
+                compound_by_seconds,
             }, rest
         ))
     }
@@ -369,6 +719,18 @@ impl LendingInstruction {
         Ok((pk, rest))
     }
 
+    fn unpack_quote_currency(input: &[u8]) -> Result<([u8; 4], &[u8]), ProgramError> {
+        if input.len() < 4 {
+            msg!("Quote currency cannot be unpacked");
+            return Err(LendingError::InstructionUnpackError.into());
+        }
+        let (quote_currency, rest) = input.split_at(4);
+        let quote_currency = quote_currency
+            .try_into()
+            .map_err(|_| LendingError::InstructionUnpackError)?;
+        Ok((quote_currency, rest))
+    }
+
     fn unpack_u128(input: &[u8]) -> Result<(u128, &[u8]), ProgramError> {
         if input.len() < 16 {
             msg!("u128 cannot be unpacked");
@@ -397,6 +759,20 @@ impl LendingInstruction {
         Ok((amount, rest))
     }
 
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        if input.len() < 2 {
+            msg!("u16 cannot be unpacked");
+            return Err(LendingError::InstructionUnpackError.into());
+        }
+        let (amount, rest) = input.split_at(2);
+        let amount = amount
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u16::from_le_bytes)
+            .ok_or(LendingError::InstructionUnpackError)?;
+        Ok((amount, rest))
+    }
+
     fn unpack_u8(input: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
         if input.is_empty() {
             msg!("u8 cannot be unpacked");
@@ -558,6 +934,32 @@ impl LendingInstruction {
                 buf.extend_from_slice(&max_borrow_amount.to_le_bytes());
                 buf.extend_from_slice(&collateral_amount.to_le_bytes());
             }
+            Self::RepayAllAndRedeemAll(close_obligation) => {
+                buf.push(31);
+                buf.extend_from_slice(&(close_obligation as u8).to_le_bytes());
+            }
+            Self::ClaimStakerFee => buf.push(32),
+            Self::RefreshAll => buf.push(33),
+            Self::ApplyPendingConfig => buf.push(34),
+            Self::RefreshUserObligationPartial => buf.push(35),
+            Self::DepositPledgeBorrow(deposit_amount, borrow_amount) => {
+                buf.push(36);
+                buf.extend_from_slice(&deposit_amount.to_le_bytes());
+                buf.extend_from_slice(&borrow_amount.to_le_bytes());
+            }
+            Self::RepayFromPledgedCollateral(amount) => {
+                buf.push(37);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::RefreshMarketReserveWithTip => buf.push(38),
+            Self::LiquidateDirect(amount) => {
+                buf.push(40);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::RefreshUserObligationForBorrow(borrow_reserve) => {
+                buf.push(41);
+                buf.extend_from_slice(borrow_reserve.as_ref());
+            }
             #[cfg(feature = "unique-credit")]
             Self::InitUniqueCredit(authority, amount) => {
                 buf.push(100);
@@ -615,6 +1017,156 @@ impl LendingInstruction {
                 buf.push(112);
                 buf.extend_from_slice(&amount.to_le_bytes());
             }
+            Self::BatchUpdateMarketReserveRateModel(model) => {
+                buf.push(113);
+                Self::pack_rate_model(model, &mut buf);
+            }
+            Self::BatchUpdateMarketReserveCollateralConfig(config) => {
+                buf.push(114);
+                Self::pack_collateral_config(config, &mut buf);
+            }
+            Self::BatchUpdateMarketReserveLiquidityConfig(config) => {
+                buf.push(115);
+                Self::pack_liquidity_config(config, &mut buf);
+            }
+            Self::GetReserveParams => buf.push(116),
+            Self::UpdateManagerMinLeverageOpenHealth(bps) => {
+                buf.push(117);
+                buf.extend_from_slice(&bps.to_le_bytes());
+            }
+            Self::UpdateMarketReserveDebtOracleConfig(config) => {
+                buf.push(118);
+                if let Some(config) = config {
+                    buf.push(1);
+                    Self::pack_oracle_config(config, &mut buf);
+                } else {
+                    buf.push(0);
+                }
+            }
+            Self::UpdateMarketReserveOracleParams(params) => {
+                buf.push(119);
+                Self::pack_oracle_params(params, &mut buf);
+            }
+            Self::UpdateManagerLiquidationFee(ratio, receiver) => {
+                buf.push(120);
+                buf.extend_from_slice(&ratio.to_le_bytes());
+                if let Some(receiver) = receiver {
+                    buf.push(1);
+                    buf.extend_from_slice(receiver.as_ref());
+                } else {
+                    buf.push(0);
+                }
+            }
+            Self::UpdateManagerFriendBorrowValueCap(cap) => {
+                buf.push(121);
+                buf.extend_from_slice(&cap.to_le_bytes());
+            }
+            Self::UpdateManagerBorrowMaxObligationAge(max_age_slots) => {
+                buf.push(122);
+                buf.extend_from_slice(&max_age_slots.to_le_bytes());
+            }
+            Self::SweepStrayTokens => buf.push(123),
+            Self::UpdateMarketReserveStakerFeeConfig(config) => {
+                buf.push(124);
+                buf.extend_from_slice(&config.ratio.to_le_bytes());
+                buf.extend_from_slice(config.account.as_ref());
+            }
+            Self::UpdateManagerCollateralDustValue(dust_value) => {
+                buf.push(125);
+                buf.extend_from_slice(&dust_value.to_le_bytes());
+            }
+            Self::UpdateManagerFlashLoanRepayTolerance(tolerance) => {
+                buf.push(126);
+                buf.extend_from_slice(&tolerance.to_le_bytes());
+            }
+            Self::SetManagerPaused(paused) => {
+                buf.push(127);
+                buf.extend_from_slice(&(paused as u8).to_le_bytes());
+            }
+            Self::UpdateManagerConfigTimelock(timelock_slots) => {
+                buf.push(128);
+                buf.extend_from_slice(&timelock_slots.to_le_bytes());
+            }
+            Self::UpdateMarketReserveSotokenRestriction(restricted) => {
+                buf.push(129);
+                buf.extend_from_slice(&(restricted as u8).to_le_bytes());
+            }
+            Self::InitMedianOracle(quorum, ref sources) => {
+                buf.push(130);
+                buf.extend_from_slice(&quorum.to_le_bytes());
+                buf.extend_from_slice(&(sources.len() as u8).to_le_bytes());
+                for &source in sources {
+                    Self::pack_oracle_config(source, &mut buf);
+                }
+            }
+            Self::UpdateManagerMaxLeverage(max_leverage_x) => {
+                buf.push(131);
+                buf.extend_from_slice(&max_leverage_x.to_le_bytes());
+            }
+            Self::UpdateMarketReserveLiquidityStatusBands(bands) => {
+                buf.push(132);
+                buf.extend_from_slice(&bands.tight_bps.to_le_bytes());
+                buf.extend_from_slice(&bands.critical_bps.to_le_bytes());
+            }
+            Self::UpdateManagerLiquidationBackstopCap(cap) => {
+                buf.push(133);
+                buf.extend_from_slice(&cap.to_le_bytes());
+            }
+            Self::UpdateManagerKeeperTip(tip_value, min_gap_slots) => {
+                buf.push(134);
+                buf.extend_from_slice(&tip_value.to_le_bytes());
+                buf.extend_from_slice(&min_gap_slots.to_le_bytes());
+            }
+            Self::UpdateManagerMaxLoansPerObligation(max_loans) => {
+                buf.push(135);
+                buf.extend_from_slice(&max_loans.to_le_bytes());
+            }
+            Self::AllowObligation => buf.push(136),
+            Self::DisallowObligation => buf.push(137),
+            Self::TransferInsurance(amount) => {
+                buf.push(138);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetEmergencyLiquidation(enable) => {
+                buf.push(139);
+                buf.extend_from_slice(&(enable as u8).to_le_bytes());
+            }
+            Self::UpdateManagerObligationExposureCap(max_collateral_value, max_loan_value) => {
+                buf.push(140);
+                buf.extend_from_slice(&max_collateral_value.to_le_bytes());
+                buf.extend_from_slice(&max_loan_value.to_le_bytes());
+            }
+            Self::UpdateManagerQuoteCurrency(quote_currency) => {
+                buf.push(141);
+                buf.extend_from_slice(&quote_currency);
+            }
+            Self::UpdateManagerCollateralCooldown(cooldown_slots) => {
+                buf.push(142);
+                buf.extend_from_slice(&cooldown_slots.to_le_bytes());
+            }
+            Self::BootstrapReserve(amount) => {
+                buf.push(143);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::InitDepositWhitelist(until_slot, ref depositors) => {
+                buf.push(144);
+                buf.extend_from_slice(&until_slot.to_le_bytes());
+                buf.extend_from_slice(&(depositors.len() as u8).to_le_bytes());
+                for depositor in depositors {
+                    buf.extend_from_slice(depositor.as_ref());
+                }
+            }
+            Self::ExtendMarketReserve => buf.push(145),
+            Self::UpdateMarketReserveLiquidationBackstopBudget(budget) => {
+                buf.push(146);
+                buf.extend_from_slice(&budget.to_le_bytes());
+            }
+            Self::ApplyManagerPendingConfig => buf.push(147),
+            Self::ExtendManager => buf.push(148),
+            Self::InitDexOracle(min_quote_liquidity) => {
+                buf.push(149);
+                buf.extend_from_slice(&min_quote_liquidity.to_le_bytes());
+            }
         }
         buf
     }
@@ -624,6 +1176,7 @@ impl LendingInstruction {
         buf.extend_from_slice(&model.optimal.to_le_bytes());
         buf.extend_from_slice(&model.kink.to_le_bytes());
         buf.extend_from_slice(&model.max.to_le_bytes());
+        buf.extend_from_slice(&model.smoothing_factor.to_le_bytes());
     }
 
     fn pack_oracle_config(config: OracleConfig, buf: &mut Vec<u8>) {
@@ -632,10 +1185,24 @@ impl LendingInstruction {
         buf.extend_from_slice(&oracle_type_u8.to_le_bytes());
     }
 
+    fn pack_oracle_params(params: OracleParams, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&params.stale_threshold.to_le_bytes());
+        buf.extend_from_slice(&params.max_confidence_bps.to_le_bytes());
+        buf.extend_from_slice(&params.max_deviation_bps.to_le_bytes());
+    }
+
     fn pack_collateral_config(config: CollateralConfig, buf: &mut Vec<u8>) {
         buf.extend_from_slice(&config.borrow_value_ratio.to_le_bytes());
         buf.extend_from_slice(&config.liquidation_value_ratio.to_le_bytes());
         buf.extend_from_slice(&config.liquidation_penalty_ratio.to_le_bytes());
+        buf.extend_from_slice(&config.full_liquidation_health_bps.to_le_bytes());
+        buf.extend_from_slice(&config.borrower_liquidation_rebate_bps.to_le_bytes());
+        buf.extend_from_slice(&config.max_post_liquidation_health_bps.to_le_bytes());
+        buf.extend_from_slice(&(config.permissioned as u8).to_le_bytes());
+        buf.extend_from_slice(&config.max_concentration_ratio.to_le_bytes());
+        buf.extend_from_slice(&config.bonus_by_collateral_bps.to_le_bytes());
+        buf.extend_from_slice(&config.bonus_by_loan_bps.to_le_bytes());
+        buf.extend_from_slice(&(config.requires_companion_collateral as u8).to_le_bytes());
     }
 
     fn pack_liquidity_config(config: LiquidityConfig, buf: &mut Vec<u8>) {
@@ -643,6 +1210,10 @@ impl LendingInstruction {
         buf.extend_from_slice(&config.borrow_tax_rate.to_le_bytes());
         buf.extend_from_slice(&config.flash_loan_fee_rate.to_le_bytes());
         buf.extend_from_slice(&config.max_deposit.to_le_bytes());
+        buf.extend_from_slice(&config.new_loan_fee.to_le_bytes());
+        buf.extend_from_slice(&config.borrow_cooldown_slots.to_le_bytes());
+        buf.extend_from_slice(&config.large_repay_ratio.to_le_bytes());
+        buf.extend_from_slice(&(config.compound_by_seconds as u8).to_le_bytes());
     }
 }
 
@@ -673,6 +1244,7 @@ pub fn init_market_reserve(
     collateral_config: CollateralConfig,
     liquidity_config: LiquidityConfig,
     rate_model: RateModel,
+    pyth_product_key: Option<Pubkey>,
 ) -> Instruction {
     let program_id = id();
     let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
@@ -680,20 +1252,25 @@ pub fn init_market_reserve(
         &program_id,
     );
 
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new(supply_token_account_key, false),
+        AccountMeta::new(market_reserve_key, false),
+        AccountMeta::new_readonly(token_mint_key, false),
+        AccountMeta::new(sotoken_mint_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    if let Some(pyth_product_key) = pyth_product_key {
+        accounts.push(AccountMeta::new_readonly(pyth_product_key, false));
+    }
+
     Instruction {
         program_id,
-        accounts: vec![
-            AccountMeta::new_readonly(sysvar::rent::id(), false),
-            AccountMeta::new_readonly(sysvar::clock::id(), false),
-            AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new_readonly(manager_authority_key, false),
-            AccountMeta::new(supply_token_account_key, false),
-            AccountMeta::new(market_reserve_key, false),
-            AccountMeta::new_readonly(token_mint_key, false),
-            AccountMeta::new(sotoken_mint_key, false),
-            AccountMeta::new_readonly(authority_key, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
-        ],
+        accounts,
         data: LendingInstruction::InitMarketReserve(oracle_config, collateral_config, liquidity_config, rate_model).pack(),
     }
 }
@@ -721,6 +1298,74 @@ pub fn refresh_market_reserves<T: IntoIterator<Item = Pubkey>>(updating_keys: T)
     }
 }
 
+// (market reserve, price oracle, debt oracle)
+pub fn refresh_market_reserves_with_debt_oracles<T: IntoIterator<Item = (Pubkey, Pubkey, Option<Pubkey>)>>(updating_keys: T) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(sysvar::clock::id(), false)];
+
+    for (market_reserve_key, oracle_key, debt_oracle_key) in updating_keys {
+        accounts.push(AccountMeta::new(market_reserve_key, false));
+        accounts.push(AccountMeta::new_readonly(oracle_key, false));
+        if let Some(debt_oracle_key) = debt_oracle_key {
+            accounts.push(AccountMeta::new_readonly(debt_oracle_key, false));
+        }
+    }
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshMarketReserves.pack(),
+    }
+}
+
+/// median oracle sources follow the median oracle, in the order stored,
+/// dex oracle is followed by its base pool, quote pool and quote reserve
+pub fn refresh_market_reserves_with_median_sources<T: IntoIterator<Item = (Pubkey, Pubkey, Vec<Pubkey>, Option<Pubkey>)>>(updating_keys: T) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(sysvar::clock::id(), false)];
+
+    for (market_reserve_key, oracle_key, source_keys, debt_oracle_key) in updating_keys {
+        accounts.push(AccountMeta::new(market_reserve_key, false));
+        accounts.push(AccountMeta::new_readonly(oracle_key, false));
+        accounts.extend(source_keys.into_iter().map(|source_key| AccountMeta::new_readonly(source_key, false)));
+        if let Some(debt_oracle_key) = debt_oracle_key {
+            accounts.push(AccountMeta::new_readonly(debt_oracle_key, false));
+        }
+    }
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshMarketReserves.pack(),
+    }
+}
+
+pub fn refresh_market_reserve_with_tip(
+    manager_key: Pubkey,
+    manager_authority_key: Pubkey,
+    market_reserve_key: Pubkey,
+    oracle_keys: Vec<Pubkey>,
+    supply_token_account_key: Pubkey,
+    keeper_token_account_key: Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new(market_reserve_key, false),
+    ];
+    accounts.extend(oracle_keys.into_iter().map(|key| AccountMeta::new_readonly(key, false)));
+    accounts.extend(vec![
+        AccountMeta::new(supply_token_account_key, false),
+        AccountMeta::new(keeper_token_account_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshMarketReserveWithTip.pack(),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn deposit_or_withdraw<const IS_DEPOSIT: bool>(
     manager_key: Pubkey,
@@ -738,20 +1383,25 @@ pub fn deposit_or_withdraw<const IS_DEPOSIT: bool>(
         &program_id,
     );
 
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new(market_reserve_key, false),
+        AccountMeta::new(sotoken_mint_key, false),
+        AccountMeta::new(supply_token_account_key, false),
+        AccountMeta::new_readonly(user_authority_key, true),
+        AccountMeta::new(user_token_account_key, false),
+        AccountMeta::new(user_sotoken_account_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    if IS_DEPOSIT {
+        accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&market_reserve_key, &program_id).0, false));
+    }
+
     Instruction {
         program_id,
-        accounts: vec![
-            AccountMeta::new_readonly(sysvar::clock::id(), false),
-            AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new_readonly(manager_authority_key, false),
-            AccountMeta::new(market_reserve_key, false),
-            AccountMeta::new(sotoken_mint_key, false),
-            AccountMeta::new(supply_token_account_key, false),
-            AccountMeta::new_readonly(user_authority_key, true),
-            AccountMeta::new(user_token_account_key, false),
-            AccountMeta::new(user_sotoken_account_key, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
-        ],
+        accounts,
         data: if IS_DEPOSIT {
             LendingInstruction::Deposit(amount)
         } else {
@@ -779,11 +1429,13 @@ pub fn init_user_obligation(
 }
 
 pub fn refresh_user_obligation<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
     user_obligation_key: Pubkey,
     market_reserve_keys: T,
 ) -> Instruction {
     let mut accounts = vec![
         AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
         AccountMeta::new(user_obligation_key, false),
     ];
 
@@ -800,8 +1452,87 @@ pub fn refresh_user_obligation<T: IntoIterator<Item = Pubkey>>(
     }
 }
 
+// a chunk of market reserves, obligation is fresh once every entry has been covered
+pub fn refresh_user_obligation_partial<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    user_obligation_key: Pubkey,
+    market_reserve_keys: T,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new(user_obligation_key, false),
+    ];
+
+    accounts.extend(
+        market_reserve_keys
+            .into_iter()
+            .map(|key| AccountMeta::new_readonly(key, false))
+    );
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshUserObligationPartial.pack(),
+    }
+}
+
+// only collateral reserves and the borrow reserve must be strictly fresh, other loan reserves lax
+pub fn refresh_user_obligation_for_borrow<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    user_obligation_key: Pubkey,
+    borrow_market_reserve_key: Pubkey,
+    market_reserve_keys: T,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new(user_obligation_key, false),
+    ];
+
+    accounts.extend(
+        market_reserve_keys
+            .into_iter()
+            .map(|key| AccountMeta::new_readonly(key, false))
+    );
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshUserObligationForBorrow(borrow_market_reserve_key).pack(),
+    }
+}
+
+// (market reserve, price oracle, debt oracle), obligation goes last
+pub fn refresh_all<T: IntoIterator<Item = (Pubkey, Pubkey, Option<Pubkey>)>>(
+    manager_key: Pubkey,
+    updating_keys: T,
+    user_obligation_key: Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+    ];
+
+    for (market_reserve_key, oracle_key, debt_oracle_key) in updating_keys {
+        accounts.push(AccountMeta::new(market_reserve_key, false));
+        accounts.push(AccountMeta::new_readonly(oracle_key, false));
+        if let Some(debt_oracle_key) = debt_oracle_key {
+            accounts.push(AccountMeta::new_readonly(debt_oracle_key, false));
+        }
+    }
+    accounts.push(AccountMeta::new(user_obligation_key, false));
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RefreshAll.pack(),
+    }
+}
+
 #[cfg(feature = "friend")]
 pub fn bind_friend(
+    manager_key: Pubkey,
     user_obligation_key: Pubkey,
     friend_obligation_key: Pubkey,
     user_authority_key: Pubkey,
@@ -814,6 +1545,7 @@ pub fn bind_friend(
             AccountMeta::new(friend_obligation_key, false),
             AccountMeta::new_readonly(user_authority_key, true),
             AccountMeta::new_readonly(friend_authority_key, true),
+            AccountMeta::new_readonly(manager_key, false),
         ],
         data: LendingInstruction::BindFriend.pack(),
     }
@@ -821,6 +1553,7 @@ pub fn bind_friend(
 
 #[cfg(feature = "friend")]
 pub fn unbind_friend(
+    manager_key: Pubkey,
     user_obligation_key: Pubkey,
     friend_obligation_key: Pubkey,
     user_authority_key: Pubkey,
@@ -834,6 +1567,7 @@ pub fn unbind_friend(
             AccountMeta::new(friend_obligation_key, false),
             AccountMeta::new_readonly(user_authority_key, true),
             AccountMeta::new_readonly(friend_authority_key, true),
+            AccountMeta::new_readonly(manager_key, false),
         ],
         data: LendingInstruction::UnbindFriend.pack(),
     }
@@ -841,6 +1575,7 @@ pub fn unbind_friend(
 
 #[allow(clippy::too_many_arguments)]
 pub fn pledge_collateral(
+    manager_key: Pubkey,
     market_reserve_key: Pubkey,
     sotoken_mint_key: Pubkey,
     user_obligation_key: Pubkey,
@@ -857,6 +1592,8 @@ pub fn pledge_collateral(
             AccountMeta::new_readonly(user_authority_key, true),
             AccountMeta::new(user_sotoken_account_key, false),
             AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
         ],
         data: LendingInstruction::PledgeCollateral(amount).pack(),
     }
@@ -864,6 +1601,7 @@ pub fn pledge_collateral(
 
 #[allow(clippy::too_many_arguments)]
 pub fn deposit_and_pledge(
+    manager_key: Pubkey,
     market_reserve_key: Pubkey,
     supply_token_account_key: Pubkey,
     user_obligation_key: Pubkey,
@@ -871,8 +1609,11 @@ pub fn deposit_and_pledge(
     user_token_account_key: Pubkey,
     amount: u64,
 ) -> Instruction {
+    let program_id = id();
+    let (deposit_whitelist_key, _bump_seed) = DepositWhitelist::find_address(&market_reserve_key, &program_id);
+
     Instruction {
-        program_id: id(),
+        program_id,
         accounts: vec![
             AccountMeta::new_readonly(sysvar::clock::id(), false),
             AccountMeta::new(market_reserve_key, false),
@@ -881,6 +1622,8 @@ pub fn deposit_and_pledge(
             AccountMeta::new_readonly(user_authority_key, true),
             AccountMeta::new(user_token_account_key, false),
             AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(deposit_whitelist_key, false),
         ],
         data: LendingInstruction::DepositAndPledge(amount).pack(),
     }
@@ -1095,8 +1838,93 @@ pub fn borrow_liquidity(
     }
 }
 
+// reserves as (market reserve, price oracle, debt oracle)
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_pledge_borrow(
+    manager_key: Pubkey,
+    collateral_reserve_keys: (Pubkey, Pubkey, Option<Pubkey>),
+    collateral_supply_token_account_key: Pubkey,
+    loan_reserve_keys: (Pubkey, Pubkey, Option<Pubkey>),
+    loan_supply_token_account_key: Pubkey,
+    user_obligation_key: Pubkey,
+    friend_obligation_key: Option<Pubkey>,
+    user_authority_key: Pubkey,
+    user_collateral_token_account_key: Pubkey,
+    user_loan_token_account_key: Pubkey,
+    deposit_amount: u64,
+    borrow_amount: u64,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+    ];
+
+    for ((market_reserve_key, oracle_key, debt_oracle_key), supply_token_account_key) in [
+        (collateral_reserve_keys, collateral_supply_token_account_key),
+        (loan_reserve_keys, loan_supply_token_account_key),
+    ] {
+        accounts.push(AccountMeta::new(market_reserve_key, false));
+        accounts.push(AccountMeta::new_readonly(oracle_key, false));
+        if let Some(debt_oracle_key) = debt_oracle_key {
+            accounts.push(AccountMeta::new_readonly(debt_oracle_key, false));
+        }
+        accounts.push(AccountMeta::new(supply_token_account_key, false));
+    }
+
+    accounts.push(AccountMeta::new(user_obligation_key, false));
+    if let Some(friend_obligation_key) = friend_obligation_key {
+        accounts.push(AccountMeta::new_readonly(friend_obligation_key, false));
+    }
+    accounts.push(AccountMeta::new_readonly(user_authority_key, true));
+    accounts.push(AccountMeta::new(user_collateral_token_account_key, false));
+    accounts.push(AccountMeta::new(user_loan_token_account_key, false));
+    accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_reserve_keys.0, &program_id).0, false));
+
+    Instruction {
+        program_id,
+        accounts,
+        data: LendingInstruction::DepositPledgeBorrow(deposit_amount, borrow_amount).pack(),
+    }
+}
+
+pub fn repay_from_pledged_collateral(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    user_obligation_key: Pubkey,
+    friend_obligation_key: Option<Pubkey>,
+    user_authority_key: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new(market_reserve_key, false),
+        AccountMeta::new(user_obligation_key, false),
+        AccountMeta::new_readonly(user_authority_key, true),
+    ];
+
+    if let Some(friend_obligation_key) = friend_obligation_key {
+        accounts.insert(4, AccountMeta::new_readonly(friend_obligation_key, false))
+    }
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::RepayFromPledgedCollateral(amount).pack(),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn repay_loan(
+    manager_key: Pubkey,
     market_reserve_key: Pubkey,
     supply_token_account_key: Pubkey,
     user_obligation_key: Pubkey,
@@ -1114,6 +1942,7 @@ pub fn repay_loan(
             AccountMeta::new_readonly(user_authority_key, true),
             AccountMeta::new(user_token_account_key, false),
             AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
         ],
         data: LendingInstruction::RepayLoan(amount).pack(),
     }
@@ -1131,6 +1960,7 @@ pub fn liquidate<const IS_COLLATERAL: bool>(
     liquidator_authority_key: Pubkey,
     liquidator_token_account_key: Pubkey,
     liquidator_sotoken_account_key: Pubkey,
+    liquidation_fee_receiver_owner_key: Option<Pubkey>,
     amount: u64,
 ) -> Instruction {
     let program_id = id();
@@ -1143,7 +1973,7 @@ pub fn liquidate<const IS_COLLATERAL: bool>(
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(manager_key, false),
         AccountMeta::new_readonly(manager_authority_key, false),
-        AccountMeta::new_readonly(collateral_market_reserve_key, false),
+        AccountMeta::new(collateral_market_reserve_key, false),
         AccountMeta::new(sotoken_mint_key, false),
         AccountMeta::new(loan_market_reserve_key, false),
         AccountMeta::new(supply_token_account_key, false),
@@ -1158,6 +1988,11 @@ pub fn liquidate<const IS_COLLATERAL: bool>(
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
 
+    if let Some(liquidation_fee_receiver_owner_key) = liquidation_fee_receiver_owner_key {
+        let liquidation_fee_receiver_key = crate::associated_token_address(&liquidation_fee_receiver_owner_key, &sotoken_mint_key);
+        accounts.push(AccountMeta::new(liquidation_fee_receiver_key, false));
+    }
+
     Instruction {
         program_id,
         accounts,
@@ -1169,6 +2004,60 @@ pub fn liquidate<const IS_COLLATERAL: bool>(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn liquidate_direct(
+    manager_key: Pubkey,
+    collateral_market_reserve_key: Pubkey,
+    collateral_supply_account_key: Pubkey,
+    loan_market_reserve_key: Pubkey,
+    loan_supply_account_key: Pubkey,
+    user_obligation_key: Pubkey,
+    friend_obligation_key: Option<Pubkey>,
+    liquidator_authority_key: Pubkey,
+    liquidator_loan_token_account_key: Pubkey,
+    liquidator_collateral_token_account_key: Pubkey,
+    sotoken_mint_and_liquidation_fee_receiver_owner_keys: Option<(Pubkey, Pubkey)>,
+    amount: u64,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new(collateral_market_reserve_key, false),
+        AccountMeta::new(collateral_supply_account_key, false),
+        AccountMeta::new(loan_market_reserve_key, false),
+        AccountMeta::new(loan_supply_account_key, false),
+        AccountMeta::new(user_obligation_key, false),
+        AccountMeta::new_readonly(liquidator_authority_key, true),
+        AccountMeta::new(liquidator_loan_token_account_key, false),
+        AccountMeta::new(liquidator_collateral_token_account_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    if let Some(friend_obligation_key) = friend_obligation_key {
+        accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
+    }
+
+    // only with manager receiver set and collateral sotoken unrestricted
+    if let Some((sotoken_mint_key, liquidation_fee_receiver_owner_key)) = sotoken_mint_and_liquidation_fee_receiver_owner_keys {
+        let liquidation_fee_receiver_key = crate::associated_token_address(&liquidation_fee_receiver_owner_key, &sotoken_mint_key);
+        accounts.push(AccountMeta::new(sotoken_mint_key, false));
+        accounts.push(AccountMeta::new(liquidation_fee_receiver_key, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts,
+        data: LendingInstruction::LiquidateDirect(amount).pack(),
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn flash_liquidation<T: IntoIterator<Item = AccountMeta>, const IS_COLLATERAL: bool>(
     manager_key: Pubkey,
@@ -1357,6 +2246,7 @@ pub fn open_leverage_position_by_orca_base_in(
         AccountMeta::new(pool_dest_token_account_key, false),
         AccountMeta::new(pool_fee_account, false),
     ];
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_market_reserve_key, &program_id).0, false));
     if let Some(friend_obligation_key) = friend_obligation_key {
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
@@ -1497,6 +2387,7 @@ pub fn open_leverage_position_by_orca_twice_base_in(
         AccountMeta::new(pool_2_dest_token_account_key, false),
         AccountMeta::new(pool_2_fee_account, false),
     ];
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_market_reserve_key, &program_id).0, false));
     if let Some(friend_obligation_key) = friend_obligation_key {
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
@@ -1643,6 +2534,7 @@ pub fn easy_repay_by_raydium_base_out(
         AccountMeta::new(serum_dest_token_account, false),
         AccountMeta::new_readonly(serum_vault_signer, false),
     ];
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_market_reserve_key, &program_id).0, false));
     if let Some(friend_obligation_key) = friend_obligation_key {
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
@@ -1715,6 +2607,7 @@ pub fn open_leverage_position_by_raydium_base_in(
         AccountMeta::new(serum_dest_token_account, false),
         AccountMeta::new_readonly(serum_vault_signer, false),
     ];
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_market_reserve_key, &program_id).0, false));
     if let Some(friend_obligation_key) = friend_obligation_key {
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
@@ -1787,6 +2680,7 @@ pub fn open_leverage_position_by_raydium_base_out(
         AccountMeta::new(serum_dest_token_account, false),
         AccountMeta::new_readonly(serum_vault_signer, false),
     ];
+    accounts.push(AccountMeta::new_readonly(DepositWhitelist::find_address(&collateral_market_reserve_key, &program_id).0, false));
     if let Some(friend_obligation_key) = friend_obligation_key {
         accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
     }
@@ -1798,6 +2692,94 @@ pub fn open_leverage_position_by_raydium_base_out(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn repay_all_and_redeem_all(
+    manager_key: Pubkey,
+    collateral_market_reserve_key: Pubkey,
+    sotoken_mint_key: Pubkey,
+    loan_market_reserve_key: Pubkey,
+    supply_token_account_key: Pubkey,
+    user_obligation_key: Pubkey,
+    friend_obligation_key: Option<Pubkey>,
+    user_authority_key: Pubkey,
+    user_token_account_key: Pubkey,
+    user_sotoken_account_key: Pubkey,
+    rent_receiver_key: Option<Pubkey>,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new_readonly(collateral_market_reserve_key, false),
+        AccountMeta::new(sotoken_mint_key, false),
+        AccountMeta::new(loan_market_reserve_key, false),
+        AccountMeta::new(supply_token_account_key, false),
+        AccountMeta::new(user_obligation_key, false),
+        AccountMeta::new_readonly(user_authority_key, true),
+        AccountMeta::new(user_token_account_key, false),
+        AccountMeta::new(user_sotoken_account_key, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    if let Some(friend_obligation_key) = friend_obligation_key {
+        accounts.insert(8, AccountMeta::new_readonly(friend_obligation_key, false))
+    }
+
+    if let Some(rent_receiver_key) = rent_receiver_key {
+        accounts.push(AccountMeta::new(rent_receiver_key, false));
+    }
+
+    Instruction {
+        program_id,
+        accounts,
+        data: LendingInstruction::RepayAllAndRedeemAll(rent_receiver_key.is_some()).pack(),
+    }
+}
+
+pub fn claim_staker_fee(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    supply_token_account_key: Pubkey,
+    staker_fee_account_key: Pubkey,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(manager_authority_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new(supply_token_account_key, false),
+            AccountMeta::new(staker_fee_account_key, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: LendingInstruction::ClaimStakerFee.pack(),
+    }
+}
+
+pub fn apply_pending_config(market_reserve_key: Pubkey) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new(market_reserve_key, false),
+        ],
+        data: LendingInstruction::ApplyPendingConfig.pack(),
+    }
+}
+
 #[cfg(feature = "unique-credit")]
 pub fn init_unique_credit(
     manager_key: Pubkey,
@@ -1935,6 +2917,7 @@ pub fn control_market_reserve_liquidity(
             AccountMeta::new_readonly(manager_key, false),
             AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
         data: LendingInstruction::ControlMarketReserveLiquidity(enable).pack(),
     }
@@ -1952,6 +2935,7 @@ pub fn update_market_reserve_rate_model(
             AccountMeta::new_readonly(manager_key, false),
             AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
         data: LendingInstruction::UpdateMarketReserveRateModel(model).pack(),
     }
@@ -1969,6 +2953,7 @@ pub fn update_market_reserve_collateral_config(
             AccountMeta::new_readonly(manager_key, false),
             AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
         data: LendingInstruction::UpdateMarketReserveCollateralConfig(config).pack(),
     }
@@ -1986,6 +2971,7 @@ pub fn update_market_reserve_liquidity_config(
             AccountMeta::new_readonly(manager_key, false),
             AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
         data: LendingInstruction::UpdateMarketReserveLiquidityConfig(config).pack(),
     }
@@ -1996,72 +2982,252 @@ pub fn update_market_reserve_oracle_config(
     market_reserve_key: Pubkey,
     authority_key: Pubkey,
     config: OracleConfig,
+    pyth_product_key: Option<Pubkey>,
 ) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new(market_reserve_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if let Some(pyth_product_key) = pyth_product_key {
+        accounts.push(AccountMeta::new_readonly(pyth_product_key, false));
+    }
+
     Instruction {
         program_id: id(),
-        accounts: vec![
-            AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new(market_reserve_key, false),
-            AccountMeta::new_readonly(authority_key, true),
-        ],
+        accounts,
         data: LendingInstruction::UpdateMarketReserveOracleConfig(config).pack(),
     }
 }
 
-#[cfg(feature = "unique-credit")]
-pub fn update_unique_credit_limit(
+pub fn update_market_reserve_debt_oracle_config(
     manager_key: Pubkey,
     market_reserve_key: Pubkey,
-    unique_credit_key: Pubkey,
     authority_key: Pubkey,
-    amount: u64,
+    config: Option<OracleConfig>,
+    pyth_product_key: Option<Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new(market_reserve_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if let Some(pyth_product_key) = pyth_product_key {
+        accounts.push(AccountMeta::new_readonly(pyth_product_key, false));
+    }
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: LendingInstruction::UpdateMarketReserveDebtOracleConfig(config).pack(),
+    }
+}
+
+pub fn update_market_reserve_oracle_params(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    authority_key: Pubkey,
+    params: OracleParams,
 ) -> Instruction {
     Instruction {
         program_id: id(),
         accounts: vec![
             AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new_readonly(market_reserve_key, false),
-            AccountMeta::new(unique_credit_key, false),
+            AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
-        data: LendingInstruction::UpdateUniqueCreditLimit(amount).pack(),
+        data: LendingInstruction::UpdateMarketReserveOracleParams(params).pack(),
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn reduce_insurance(
+pub fn update_market_reserve_staker_fee_config(
     manager_key: Pubkey,
     market_reserve_key: Pubkey,
-    supply_token_account_key: Pubkey,
     authority_key: Pubkey,
-    receiver_token_account_key: Pubkey,
-    amount: u64,
+    config: StakerFeeConfig,
 ) -> Instruction {
-    let program_id = id();
-    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
-        &[manager_key.as_ref()],
-        &program_id,
-    );
-
     Instruction {
-        program_id,
+        program_id: id(),
         accounts: vec![
             AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new_readonly(manager_authority_key, false),
             AccountMeta::new(market_reserve_key, false),
-            AccountMeta::new(supply_token_account_key, false),
             AccountMeta::new_readonly(authority_key, true),
-            AccountMeta::new(receiver_token_account_key, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
         ],
-        data: LendingInstruction::ReduceInsurance(amount).pack(),
+        data: LendingInstruction::UpdateMarketReserveStakerFeeConfig(config).pack(),
     }
 }
 
-pub fn change_manager_owner(
+pub fn update_market_reserve_sotoken_restriction(
     manager_key: Pubkey,
+    market_reserve_key: Pubkey,
     authority_key: Pubkey,
-    new_authority_key: Pubkey,
+    restricted: bool,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateMarketReserveSotokenRestriction(restricted).pack(),
+    }
+}
+
+pub fn update_market_reserve_liquidity_status_bands(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    authority_key: Pubkey,
+    bands: LiquidityStatusBands,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateMarketReserveLiquidityStatusBands(bands).pack(),
+    }
+}
+
+pub fn init_median_oracle(
+    manager_key: Pubkey,
+    median_oracle_key: Pubkey,
+    authority_key: Pubkey,
+    quorum: u8,
+    sources: Vec<OracleConfig>,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(median_oracle_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::InitMedianOracle(quorum, sources).pack(),
+    }
+}
+
+pub fn init_dex_oracle(
+    manager_key: Pubkey,
+    dex_oracle_key: Pubkey,
+    authority_key: Pubkey,
+    base_pool_key: Pubkey,
+    quote_pool_key: Pubkey,
+    quote_reserve_key: Pubkey,
+    min_quote_liquidity: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(dex_oracle_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(base_pool_key, false),
+            AccountMeta::new_readonly(quote_pool_key, false),
+            AccountMeta::new_readonly(quote_reserve_key, false),
+        ],
+        data: LendingInstruction::InitDexOracle(min_quote_liquidity).pack(),
+    }
+}
+
+#[cfg(feature = "unique-credit")]
+pub fn update_unique_credit_limit(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    unique_credit_key: Pubkey,
+    authority_key: Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(market_reserve_key, false),
+            AccountMeta::new(unique_credit_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::UpdateUniqueCreditLimit(amount).pack(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn reduce_insurance(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    supply_token_account_key: Pubkey,
+    authority_key: Pubkey,
+    receiver_token_account_key: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(manager_authority_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new(supply_token_account_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new(receiver_token_account_key, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::ReduceInsurance(amount).pack(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_insurance(
+    manager_key: Pubkey,
+    from_market_reserve_key: Pubkey,
+    from_supply_token_account_key: Pubkey,
+    to_market_reserve_key: Pubkey,
+    to_supply_token_account_key: Pubkey,
+    authority_key: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(manager_authority_key, false),
+            AccountMeta::new(from_market_reserve_key, false),
+            AccountMeta::new(from_supply_token_account_key, false),
+            AccountMeta::new(to_market_reserve_key, false),
+            AccountMeta::new(to_supply_token_account_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::TransferInsurance(amount).pack(),
+    }
+}
+
+pub fn change_manager_owner(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    new_authority_key: Pubkey,
 ) -> Instruction {
     Instruction {
         program_id: id(),
@@ -2072,4 +3238,557 @@ pub fn change_manager_owner(
         ],
         data: LendingInstruction::ChangeManagerOwner.pack(),
     }
-}
\ No newline at end of file
+}
+
+pub fn update_manager_min_leverage_open_health(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    min_health_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerMinLeverageOpenHealth(min_health_bps).pack(),
+    }
+}
+
+pub fn update_manager_max_leverage(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    max_leverage_x: u8,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerMaxLeverage(max_leverage_x).pack(),
+    }
+}
+
+pub fn update_manager_liquidation_backstop_cap(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    cap: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerLiquidationBackstopCap(cap).pack(),
+    }
+}
+
+pub fn update_manager_keeper_tip(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    tip_value: u64,
+    min_gap_slots: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerKeeperTip(tip_value, min_gap_slots).pack(),
+    }
+}
+
+pub fn update_manager_max_loans_per_obligation(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    max_loans: u8,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerMaxLoansPerObligation(max_loans).pack(),
+    }
+}
+
+pub fn update_manager_obligation_exposure_cap(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    max_collateral_value: u64,
+    max_loan_value: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerObligationExposureCap(max_collateral_value, max_loan_value).pack(),
+    }
+}
+
+pub fn update_manager_quote_currency(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    quote_currency: [u8; 4],
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerQuoteCurrency(quote_currency).pack(),
+    }
+}
+
+pub fn update_manager_collateral_cooldown(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    cooldown_slots: u8,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerCollateralCooldown(cooldown_slots).pack(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn bootstrap_reserve(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    sotoken_mint_key: Pubkey,
+    supply_token_account_key: Pubkey,
+    locked_sotoken_account_key: Pubkey,
+    authority_key: Pubkey,
+    authority_token_account_key: Pubkey,
+    amount: u64,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(manager_authority_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new(sotoken_mint_key, false),
+            AccountMeta::new(supply_token_account_key, false),
+            AccountMeta::new(locked_sotoken_account_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new(authority_token_account_key, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: LendingInstruction::BootstrapReserve(amount).pack(),
+    }
+}
+
+pub fn init_deposit_whitelist(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    authority_key: Pubkey,
+    until_slot: Slot,
+    depositors: Vec<Pubkey>,
+) -> Instruction {
+    let program_id = id();
+    let (deposit_whitelist_key, _bump_seed) = DepositWhitelist::find_address(&market_reserve_key, &program_id);
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new(deposit_whitelist_key, false),
+            AccountMeta::new(authority_key, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: LendingInstruction::InitDepositWhitelist(until_slot, depositors).pack(),
+    }
+}
+
+pub fn apply_manager_pending_config(manager_key: Pubkey) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new(manager_key, false),
+        ],
+        data: LendingInstruction::ApplyManagerPendingConfig.pack(),
+    }
+}
+
+pub fn extend_manager(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new(authority_key, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: LendingInstruction::ExtendManager.pack(),
+    }
+}
+
+pub fn extend_market_reserve(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    authority_key: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new(authority_key, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: LendingInstruction::ExtendMarketReserve.pack(),
+    }
+}
+
+pub fn update_market_reserve_liquidation_backstop_budget(
+    manager_key: Pubkey,
+    market_reserve_key: Pubkey,
+    authority_key: Pubkey,
+    budget: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(market_reserve_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateMarketReserveLiquidationBackstopBudget(budget).pack(),
+    }
+}
+
+pub fn allow_obligation(
+    manager_key: Pubkey,
+    user_obligation_key: Pubkey,
+    authority_key: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(user_obligation_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::AllowObligation.pack(),
+    }
+}
+
+pub fn disallow_obligation(
+    manager_key: Pubkey,
+    user_obligation_key: Pubkey,
+    authority_key: Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new(user_obligation_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::DisallowObligation.pack(),
+    }
+}
+
+pub fn update_manager_liquidation_fee(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    ratio: u8,
+    receiver_owner_key: Option<Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerLiquidationFee(ratio, receiver_owner_key).pack(),
+    }
+}
+
+pub fn update_manager_friend_borrow_value_cap(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    cap: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerFriendBorrowValueCap(cap).pack(),
+    }
+}
+
+pub fn update_manager_borrow_max_obligation_age(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    max_age_slots: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerBorrowMaxObligationAge(max_age_slots).pack(),
+    }
+}
+
+pub fn update_manager_collateral_dust_value(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    dust_value: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerCollateralDustValue(dust_value).pack(),
+    }
+}
+
+pub fn update_manager_flash_loan_repay_tolerance(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    tolerance: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::UpdateManagerFlashLoanRepayTolerance(tolerance).pack(),
+    }
+}
+
+pub fn set_manager_paused(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    paused: bool,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::SetManagerPaused(paused).pack(),
+    }
+}
+
+pub fn set_emergency_liquidation(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    enable: bool,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: LendingInstruction::SetEmergencyLiquidation(enable).pack(),
+    }
+}
+
+pub fn update_manager_config_timelock(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    timelock_slots: u64,
+) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new(manager_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+        ],
+        data: LendingInstruction::UpdateManagerConfigTimelock(timelock_slots).pack(),
+    }
+}
+
+pub fn sweep_stray_tokens(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    stray_token_mint_key: Pubkey,
+    receiver_token_account_key: Pubkey,
+) -> Instruction {
+    let program_id = id();
+    let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
+        &[manager_key.as_ref()],
+        &program_id,
+    );
+    let stray_token_account_key = crate::associated_token_address(&manager_authority_key, &stray_token_mint_key);
+
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(manager_key, false),
+            AccountMeta::new_readonly(manager_authority_key, false),
+            AccountMeta::new_readonly(authority_key, true),
+            AccountMeta::new(stray_token_account_key, false),
+            AccountMeta::new_readonly(stray_token_mint_key, false),
+            AccountMeta::new(receiver_token_account_key, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: LendingInstruction::SweepStrayTokens.pack(),
+    }
+}
+
+fn batch_operate_market_reserve<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    market_reserve_keys: T,
+    instruction: LendingInstruction,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(authority_key, true),
+    ];
+    accounts.extend(market_reserve_keys
+        .into_iter()
+        .map(|market_reserve_key| AccountMeta::new(market_reserve_key, false))
+    );
+
+    Instruction {
+        program_id: id(),
+        accounts,
+        data: instruction.pack(),
+    }
+}
+
+pub fn batch_update_market_reserve_rate_model<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    market_reserve_keys: T,
+    model: RateModel,
+) -> Instruction {
+    batch_operate_market_reserve(
+        manager_key,
+        authority_key,
+        market_reserve_keys,
+        LendingInstruction::BatchUpdateMarketReserveRateModel(model),
+    )
+}
+
+pub fn batch_update_market_reserve_collateral_config<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    market_reserve_keys: T,
+    config: CollateralConfig,
+) -> Instruction {
+    batch_operate_market_reserve(
+        manager_key,
+        authority_key,
+        market_reserve_keys,
+        LendingInstruction::BatchUpdateMarketReserveCollateralConfig(config),
+    )
+}
+
+pub fn batch_update_market_reserve_liquidity_config<T: IntoIterator<Item = Pubkey>>(
+    manager_key: Pubkey,
+    authority_key: Pubkey,
+    market_reserve_keys: T,
+    config: LiquidityConfig,
+) -> Instruction {
+    batch_operate_market_reserve(
+        manager_key,
+        authority_key,
+        market_reserve_keys,
+        LendingInstruction::BatchUpdateMarketReserveLiquidityConfig(config),
+    )
+}
+
+pub fn get_reserve_params(market_reserve_key: Pubkey) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![
+            AccountMeta::new_readonly(market_reserve_key, false),
+        ],
+        data: LendingInstruction::GetReserveParams.pack(),
+    }
+}
+
+/// variant with its parameters of packed instruction data, for transaction inspection tooling
+pub fn decode_lending_instruction(data: &[u8]) -> Result<LendingInstruction, ProgramError> {
+    LendingInstruction::unpack(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_packed_instructions() {
+        assert_eq!(
+            decode_lending_instruction(&LendingInstruction::Deposit(100).pack()).unwrap(),
+            LendingInstruction::Deposit(100),
+        );
+        assert_eq!(
+            decode_lending_instruction(&LendingInstruction::BorrowLiquidity(u64::MAX).pack()).unwrap(),
+            LendingInstruction::BorrowLiquidity(u64::MAX),
+        );
+        assert_eq!(
+            decode_lending_instruction(&LendingInstruction::RefreshMarketReserves.pack()).unwrap(),
+            LendingInstruction::RefreshMarketReserves,
+        );
+        assert_eq!(decode_lending_instruction(&[255]).unwrap_err(), LendingError::InstructionUnpackError.into());
+    }
+
+    #[test]
+    fn timelocked_data_fits_pending_config() {
+        let oracle_config = OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Pyth };
+        assert_eq!(LendingInstruction::UpdateMarketReserveRateModel(RateModel::default()).pack().len(), 1 + RATE_MODEL_LEN);
+        assert_eq!(LendingInstruction::UpdateMarketReserveCollateralConfig(CollateralConfig::default()).pack().len(), 1 + COLLATERAL_CONFIG_LEN);
+        assert_eq!(LendingInstruction::UpdateMarketReserveLiquidityConfig(LiquidityConfig::default()).pack().len(), 1 + LIQUIDITY_CONFIG_LEN);
+        assert_eq!(LendingInstruction::UpdateMarketReserveDebtOracleConfig(Some(oracle_config)).pack().len(), 2 + ORACLE_CONFIG_LEN);
+        assert_eq!(LendingInstruction::UpdateMarketReserveStakerFeeConfig(StakerFeeConfig::default()).pack().len(), 1 + STAKER_FEE_CONFIG_LEN);
+        assert_eq!(LendingInstruction::UpdateManagerLiquidationFee(10, Some(Pubkey::new_unique())).pack().len(), 3 + PUBKEY_BYTES);
+        assert_eq!(LendingInstruction::ReduceInsurance(u64::MAX).pack().len() + PUBKEY_BYTES, 9 + PUBKEY_BYTES);
+        assert_eq!(MAX_TIMELOCKED_DATA_LEN, 9 + PUBKEY_BYTES);
+    }
+}
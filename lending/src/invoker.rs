@@ -106,6 +106,21 @@ pub fn process_token_revoke<'a>(
     result.map_err(|_| LendingError::TokenRevokeFailed.into())
 }
 
+#[inline(always)]
+pub fn process_token_sync_native<'a>(
+    token_program: &AccountInfo<'a>,
+    native_account: &AccountInfo<'a>,
+) -> ProgramResult {
+    let result = invoke(
+        &spl_token::instruction::sync_native(
+            token_program.key,
+            native_account.key,
+        )?,
+        &[native_account.clone(), token_program.clone()],
+    );
+    result.map_err(|_| LendingError::TokenSyncNativeFailed.into())
+}
+
 #[inline(always)]
 pub fn process_token_burn<'a>(
     token_program: &AccountInfo<'a>,
@@ -253,6 +268,36 @@ pub fn process_optimal_create_account<'a>(
     )
 }
 
+/// Grow program owned account to `data_len`, payer tops up rent and new bytes start zeroed
+pub fn process_optimal_realloc<'a>(
+    rent_info: &AccountInfo<'a>,
+    target_account_info: &AccountInfo<'a>,
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    data_len: usize,
+) -> ProgramResult {
+    let required_lamports = Rent::from_account_info(rent_info)?
+        .minimum_balance(data_len)
+        .saturating_sub(target_account_info.lamports());
+
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(
+                payer_info.key,
+                target_account_info.key,
+                required_lamports,
+            ),
+            &[
+                payer_info.clone(),
+                target_account_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+
+    target_account_info.realloc(data_len, true)
+}
+
 /// Invoke signed unless signers seeds are empty
 #[inline(always)]
 pub fn invoke_optionally_signed(
@@ -22,6 +22,20 @@ use error::LendingError;
 
 solana_program::declare_id!("Soda111Jv27so2PRBd6ofRptC6dKxosdN5ByFhCcR3V");
 
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: solana_program::pubkey::Pubkey =
+    solana_program::pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// associated token account of wallet, which can only be created by the ata program
+pub fn associated_token_address(
+    wallet: &solana_program::pubkey::Pubkey,
+    mint: &solana_program::pubkey::Pubkey,
+) -> solana_program::pubkey::Pubkey {
+    solana_program::pubkey::Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token::id().as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    ).0
+}
+
 /// Data
 pub trait Data: Sized {
     fn to_vec(self) -> Vec<u8>;
@@ -45,6 +59,26 @@ fn assert_uninitialized<T: Pack + IsInitialized>(account_info: &AccountInfo) ->
     }
 }
 
+fn assert_fresh_sotoken_mint(account_info: &AccountInfo) -> ProgramResult {
+    let mint = spl_token::state::Mint::unpack_unchecked(&account_info.try_borrow_data()?)?;
+    if mint.mint_authority.is_some() || mint.freeze_authority.is_some() {
+        msg!("SoToken mint must not have mint or freeze authority before init");
+        Err(LendingError::InvalidSoTokenMint.into())
+    } else {
+        Ok(())
+    }
+}
+
+// token-2022 mints (e.g. transfer fee) break received amount == transferred amount, so they are rejected
+fn assert_supported_token_mint(account_info: &AccountInfo) -> ProgramResult {
+    if account_info.owner != &spl_token::id() || account_info.data_len() != spl_token::state::Mint::LEN {
+        msg!("Token mint owned by other program or with extensions is not supported");
+        Err(LendingError::UnsupportedTokenExtension.into())
+    } else {
+        Ok(())
+    }
+}
+
 #[inline(always)]
 fn handle_amount<F: FnOnce()>(amount: u64, notify: F) -> Result<Option<u64>, ProgramError> {
     if amount == 0 {
@@ -55,4 +89,83 @@ fn handle_amount<F: FnOnce()>(amount: u64, notify: F) -> Result<Option<u64>, Pro
     } else {
         Ok(Some(amount))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::{clock::Epoch, program_option::COption, pubkey::Pubkey};
+    use spl_token::state::{Account, Mint};
+
+    #[test]
+    fn already_initialized_token_accounts_are_rejected() {
+        let key = Pubkey::new_unique();
+        let owner = spl_token::id();
+        let mut lamports = 0;
+
+        let mut data = vec![0; Mint::LEN];
+        let mint_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, Epoch::default());
+        assert_eq!(assert_uninitialized::<Mint>(&mint_info), Ok(()));
+
+        Mint::pack(Mint { is_initialized: true, ..Mint::default() }, &mut mint_info.try_borrow_mut_data().unwrap()).unwrap();
+        assert_eq!(assert_uninitialized::<Mint>(&mint_info), Err(LendingError::AlreadyInitialized.into()));
+
+        let mut lamports = 0;
+        let mut data = vec![0; Account::LEN];
+        let account_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, Epoch::default());
+        assert_eq!(assert_uninitialized::<Account>(&account_info), Ok(()));
+
+        Account::pack(
+            Account { state: spl_token::state::AccountState::Initialized, ..Account::default() },
+            &mut account_info.try_borrow_mut_data().unwrap(),
+        ).unwrap();
+        assert_eq!(assert_uninitialized::<Account>(&account_info), Err(LendingError::AlreadyInitialized.into()));
+    }
+
+    #[test]
+    fn sotoken_mint_with_preset_authority_is_rejected() {
+        let key = Pubkey::new_unique();
+        let owner = spl_token::id();
+        let mut lamports = 0;
+
+        let mut data = vec![0; Mint::LEN];
+        let mint_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, Epoch::default());
+        assert_eq!(assert_fresh_sotoken_mint(&mint_info), Ok(()));
+
+        Mint::pack(
+            Mint { mint_authority: COption::Some(Pubkey::new_unique()), ..Mint::default() },
+            &mut mint_info.try_borrow_mut_data().unwrap(),
+        ).unwrap();
+        assert_eq!(assert_uninitialized::<Mint>(&mint_info), Ok(()));
+        assert_eq!(assert_fresh_sotoken_mint(&mint_info), Err(LendingError::InvalidSoTokenMint.into()));
+
+        Mint::pack(
+            Mint { freeze_authority: COption::Some(Pubkey::new_unique()), ..Mint::default() },
+            &mut mint_info.try_borrow_mut_data().unwrap(),
+        ).unwrap();
+        assert_eq!(assert_fresh_sotoken_mint(&mint_info), Err(LendingError::InvalidSoTokenMint.into()));
+    }
+
+    #[test]
+    fn token_mint_with_extension_is_rejected() {
+        let key = Pubkey::new_unique();
+        let owner = spl_token::id();
+        let mut lamports = 0;
+
+        let mut data = vec![0; Mint::LEN];
+        let mint_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, Epoch::default());
+        assert_eq!(assert_supported_token_mint(&mint_info), Ok(()));
+
+        // token-2022 mint with transfer fee extension: other owner, account type and extension data appended
+        let token_2022 = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = vec![0; Account::LEN + 1 + 108];
+        let mint_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &token_2022, false, Epoch::default());
+        assert_eq!(assert_supported_token_mint(&mint_info), Err(LendingError::UnsupportedTokenExtension.into()));
+
+        let mut lamports = 0;
+        let mut data = vec![0; Account::LEN + 1 + 108];
+        let mint_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, Epoch::default());
+        assert_eq!(assert_supported_token_mint(&mint_info), Err(LendingError::UnsupportedTokenExtension.into()));
+    }
+}
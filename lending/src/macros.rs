@@ -69,6 +69,32 @@ macro_rules! create_unique_credit {
     };
 }
 
+#[macro_export]
+macro_rules! create_median_oracle {
+    ($moi:ident; $iter:expr, $id:expr, $rent:expr) => {
+        let $moi = next_account_info($iter)?;
+        if $moi.owner != $id {
+            msg!("Median oracle provided is not owned by the lending program");
+            return Err(LendingError::InvalidAccountOwner.into());
+        }
+        assert_rent_exempt($rent, $moi)?;
+        assert_uninitialized::<MedianOracle>($moi)?;
+    };
+}
+
+#[macro_export]
+macro_rules! create_dex_oracle {
+    ($doi:ident; $iter:expr, $id:expr, $rent:expr) => {
+        let $doi = next_account_info($iter)?;
+        if $doi.owner != $id {
+            msg!("Dex oracle provided is not owned by the lending program");
+            return Err(LendingError::InvalidAccountOwner.into());
+        }
+        assert_rent_exempt($rent, $doi)?;
+        assert_uninitialized::<DexOracle>($doi)?;
+    };
+}
+
 #[macro_export]
 macro_rules! get_manager {
     ($mi:ident, $m:ident; $iter:expr, $id:expr) => {
@@ -138,6 +164,13 @@ macro_rules! get_supply_account {
             return Err(LendingError::UnmatchedAccounts.into()); 
         }
     };
+    ($sma:ident; $iter:expr, $mr:expr, $ma:expr) => {
+        get_supply_account!($sma; $iter, $mr);
+        if &Account::unpack(&$sma.try_borrow_data()?)?.owner != $ma {
+            msg!("Supply token account is not owned by manager authority");
+            return Err(LendingError::InvalidTokenAccountOwner.into());
+        }
+    };
 }
 
 #[macro_export]
@@ -329,4 +362,26 @@ macro_rules! get_receiver_program {
             return Err(ProgramError::IncorrectProgramId);
         }
     };
-}
\ No newline at end of file
+}
+
+#[macro_export]
+macro_rules! get_liquidation_fee_receiver {
+    ($ri:ident; $iter:expr, $m:expr, $mint:expr) => {
+        let $ri = if let COption::Some(receiver) = $m.liquidation_fee_receiver.as_ref() {
+            let receiver_info = next_account_info($iter)?;
+            if receiver_info.key != &$crate::associated_token_address(receiver, $mint) {
+                msg!("Liquidation fee receiver provided is not associated token account of receiver in manager");
+                return Err(LendingError::UnmatchedAccounts.into());
+            }
+
+            // receiver without token account for this sotoken never blocks liquidation, share goes to insurance
+            if receiver_info.owner == &spl_token::id() && Account::unpack(&receiver_info.try_borrow_data()?).is_ok() {
+                Some(receiver_info)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+    };
+}
@@ -98,6 +98,32 @@ impl Decimal {
     }
 }
 
+#[cfg(not(target_arch = "bpf"))]
+impl Decimal {
+    /// Max
+    pub fn max_value() -> Self {
+        Self(U192::MAX)
+    }
+
+    /// Saturating add, display only
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Self(self.0.saturating_add(rhs.0))
+    }
+
+    /// Saturating sub, display only
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Self(self.0.saturating_sub(rhs.0))
+    }
+
+    /// Saturating mul, display only
+    pub fn saturating_mul(self, rhs: Self) -> Self {
+        self.0
+            .checked_mul(rhs.0)
+            .map(|val| Self(val / Self::wad()))
+            .unwrap_or_else(Self::max_value)
+    }
+}
+
 impl fmt::Display for Decimal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut scaled_val = self.0.to_string();
@@ -159,6 +185,16 @@ impl TryDiv<u64> for Decimal {
     }
 }
 
+impl TryDiv<u128> for Decimal {
+    fn try_div(self, rhs: u128) -> Result<Self, ProgramError> {
+        Ok(Self(
+            self.0
+                .checked_div(U192::from(rhs))
+                .ok_or(LendingError::MathOverflow)?,
+        ))
+    }
+}
+
 impl TryDiv<Rate> for Decimal {
     fn try_div(self, rhs: Rate) -> Result<Self, ProgramError> {
         self.try_div(Self::from(rhs))
@@ -187,6 +223,16 @@ impl TryMul<u64> for Decimal {
     }
 }
 
+impl TryMul<u128> for Decimal {
+    fn try_mul(self, rhs: u128) -> Result<Self, ProgramError> {
+        Ok(Self(
+            self.0
+                .checked_mul(U192::from(rhs))
+                .ok_or(LendingError::MathOverflow)?,
+        ))
+    }
+}
+
 impl TryMul<Rate> for Decimal {
     fn try_mul(self, rhs: Rate) -> Result<Self, ProgramError> {
         self.try_mul(Self::from(rhs))
@@ -213,4 +259,15 @@ mod test {
     fn test_scaler() {
         assert_eq!(U192::exp10(SCALE), Decimal::wad());
     }
+
+    #[test]
+    fn test_saturating() {
+        let max = Decimal::max_value();
+        assert_eq!(max.saturating_add(Decimal::one()), max);
+        assert_eq!(Decimal::from(2u64).saturating_add(Decimal::one()), Decimal::from(3u64));
+        assert_eq!(Decimal::one().saturating_sub(Decimal::from(2u64)), Decimal::zero());
+        assert_eq!(Decimal::from(3u64).saturating_sub(Decimal::one()), Decimal::from(2u64));
+        assert_eq!(max.saturating_mul(Decimal::from(2u64)), max);
+        assert_eq!(Decimal::from(2u64).saturating_mul(Decimal::from(3u64)), Decimal::from(6u64));
+    }
 }
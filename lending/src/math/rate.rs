@@ -61,6 +61,11 @@ impl Rate {
         Self(U128::from(percent as u64 * PERCENT_SCALER))
     }
 
+    /// Create scaled decimal from basis points value
+    pub fn from_bps(bps: u16) -> Self {
+        Self(U128::from(bps as u64 * BPS_SCALER))
+    }
+
     /// Return raw scaled value
     #[allow(clippy::wrong_self_convention)]
     pub fn to_scaled_val(&self) -> u128 {
@@ -87,7 +92,7 @@ impl Rate {
         Ok(u64::try_from(rounded_val).map_err(|_| LendingError::MathOverflow)?)
     }
 
-    /// Calculates base^exp
+    /// Calculates base^exp, MathOverflow if the result does not fit
     pub fn try_pow(&self, mut exp: u64) -> Result<Rate, ProgramError> {
         let mut base = *self;
         let mut ret = if exp % 2 != 0 {
@@ -96,13 +101,15 @@ impl Rate {
             Rate(Self::wad())
         };
 
+        // square only while bits remain, a spare square could overflow when the result fits
+        exp /= 2;
         while exp > 0 {
-            exp /= 2;
             base = base.try_mul(base)?;
 
             if exp % 2 != 0 {
                 ret = ret.try_mul(base)?;
             }
+            exp /= 2;
         }
 
         Ok(ret)
@@ -201,4 +208,27 @@ mod test {
     fn checked_pow() {
         assert_eq!(Rate::one(), Rate::one().try_pow(u64::MAX).unwrap());
     }
+
+    #[test]
+    fn pow_without_spare_square() {
+        // 25 * 25 overflows on its own, 5^3 fits
+        let five = Rate::one().try_mul(5u64).unwrap();
+        assert_eq!(five.try_pow(3).unwrap(), Rate::one().try_mul(125u64).unwrap());
+    }
+
+    #[test]
+    fn pow_over_long_horizon() {
+        // 10% a year compounded per slot over a year of slots ≈ e^0.1
+        let slots_per_year = 78_840_000;
+        let slot_rate = Rate::one().try_add(Rate::from_percent(10).try_div(slots_per_year).unwrap()).unwrap();
+        let compounded = slot_rate.try_pow(slots_per_year).unwrap();
+        assert!(compounded > Rate::from_scaled_val(1_105_100_000_000_000_000));
+        assert!(compounded < Rate::from_scaled_val(1_105_200_000_000_000_000));
+
+        // extreme gap fails cleanly instead of wrapping
+        assert_eq!(
+            Rate::from_percent(101).try_pow(u64::MAX).unwrap_err(),
+            LendingError::MathOverflow.into(),
+        );
+    }
 }
@@ -8,6 +8,7 @@
 //! ... round data
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use num_traits::ToPrimitive;
 use solana_program::{
     msg,
     borsh::try_from_slice_unchecked,
@@ -17,6 +18,10 @@ use solana_program::{
     program_error::ProgramError, account_info::AccountInfo,
 };
 use crate::{error::LendingError, math::{Decimal, TryDiv}};
+use super::OracleParams;
+
+// chainlink store program shares its id across clusters
+pub const CHAINLINK_PROGRAM_ID: Pubkey = solana_program::pubkey!("HEvSKofvBgfaexv23kMabbYqxasxU3mQ4ibBMEmJWHny");
 
 pub const MAX_ORACLES: usize = 8;
 
@@ -80,8 +85,8 @@ pub struct Config {
     pub decimals: u8,
 }
 
-pub fn get_chainlink_price(account_info: &AccountInfo, clock: &Clock) -> Result<Decimal, ProgramError> {
-    const STALE_AFTER_SECS_ELAPSED: i64 = 30;
+pub fn get_chainlink_price(account_info: &AccountInfo, clock: &Clock, params: &OracleParams) -> Result<Decimal, ProgramError> {
+    const STALE_AFTER_SECS_ELAPSED: u64 = 30;
 
     let data = account_info.try_borrow_data()?;
     let aggregator = try_from_slice_unchecked::<Aggregator>(&data[..4096])?;
@@ -107,7 +112,10 @@ pub fn get_chainlink_price(account_info: &AccountInfo, clock: &Clock) -> Result<
     let secs_eplased = clock.unix_timestamp
         .checked_sub(agg_lastupdate)
         .ok_or(LendingError::MathOverflow)?;
-    if secs_eplased >= STALE_AFTER_SECS_ELAPSED {
+    let stale_after = params.stale_after(STALE_AFTER_SECS_ELAPSED)
+        .to_i64()
+        .ok_or(LendingError::MathOverflow)?;
+    if secs_eplased >= stale_after {
         msg!("Chainlink oracle price is stale");
         return Err(LendingError::InvalidPriceOracle.into());
     }
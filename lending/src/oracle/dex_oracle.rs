@@ -1,8 +1,189 @@
-// use solana_program::clock::Slot;
+#![allow(missing_docs)]
+use solana_program::{
+    msg,
+    account_info::AccountInfo,
+    clock::Clock,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+use spl_token::state::Account;
 
-// use crate::math::Decimal;
+use crate::{
+    error::LendingError,
+    math::{Decimal, TryDiv, TryMul},
+    state::{calculate_decimals, DexOracle, MarketReserve},
+};
 
-// pub struct DexOracle {
-//     pub last_slot: Slot,
-//     pub price: Option<Decimal>,
-// }
\ No newline at end of file
+use super::OracleType;
+
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DexPoolSide {
+    pub amount: u64,
+    pub decimal: u8,
+}
+
+// base price = quote price * (quote amount / 10^quote decimal) / (base amount / 10^base decimal)
+pub fn calculate_dex_price(
+    base: DexPoolSide,
+    quote: DexPoolSide,
+    quote_price: Decimal,
+    min_quote_liquidity: u64,
+) -> Result<Decimal, ProgramError> {
+    if base.amount == 0 || quote.amount < min_quote_liquidity {
+        msg!("Dex pool liquidity is below the minimum");
+        return Err(LendingError::DexPoolTooShallow.into());
+    }
+
+    quote_price
+        .try_mul(quote.amount)?
+        .try_div(calculate_decimals(quote.decimal)?)?
+        .try_mul(calculate_decimals(base.decimal)?)?
+        .try_div(base.amount)
+}
+
+fn unpack_pool_account(pool_info: &AccountInfo, mint: &Pubkey) -> Result<Account, ProgramError> {
+    if pool_info.owner != &spl_token::id() {
+        msg!("Dex pool account is not owned by the token program");
+        return Err(LendingError::InvalidOracleOwner.into());
+    }
+    let pool_account = Account::unpack(&pool_info.try_borrow_data()?)?;
+    if &pool_account.mint != mint {
+        msg!("Dex pool account mint is not matched");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+
+    Ok(pool_account)
+}
+
+// sources are base pool, quote pool and quote reserve, in that order
+pub fn get_dex_price(
+    dex_oracle: &DexOracle,
+    base_mint: &Pubkey,
+    base_decimal: u8,
+    source_infos: &[AccountInfo],
+    clock: &Clock,
+) -> Result<Decimal, ProgramError> {
+    let (base_pool_info, quote_pool_info, quote_reserve_info) = match source_infos {
+        [base_pool_info, quote_pool_info, quote_reserve_info] if
+            base_pool_info.key == &dex_oracle.base_pool &&
+            quote_pool_info.key == &dex_oracle.quote_pool &&
+            quote_reserve_info.key == &dex_oracle.quote_reserve => (base_pool_info, quote_pool_info, quote_reserve_info),
+        _ => {
+            msg!("Dex pool accounts provided are not matched with dex oracle");
+            return Err(LendingError::InvalidPriceOracle.into());
+        }
+    };
+
+    if quote_reserve_info.owner != &crate::id() {
+        msg!("Quote reserve of dex oracle is not owned by the lending program");
+        return Err(LendingError::InvalidOracleOwner.into());
+    }
+    let quote_reserve = MarketReserve::unpack(&quote_reserve_info.try_borrow_data()?)?;
+    if quote_reserve.manager != dex_oracle.manager {
+        msg!("Quote reserve of dex oracle is not matched with manager of dex oracle");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+    // quote price never comes from another pool, so dex prices never chain or loop
+    if quote_reserve.oracle_info.config.oracle_type == OracleType::Dex {
+        msg!("Quote reserve of dex oracle can not be priced by dex");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+    if quote_reserve.last_update.is_lax_stale(clock.slot)? {
+        msg!("Quote reserve of dex oracle is stale");
+        return Err(LendingError::MarketReserveStale.into());
+    }
+
+    let base_pool = unpack_pool_account(base_pool_info, base_mint)?;
+    let quote_pool = unpack_pool_account(quote_pool_info, &quote_reserve.token_config.mint_pubkey)?;
+
+    calculate_dex_price(
+        DexPoolSide { amount: base_pool.amount, decimal: base_decimal },
+        DexPoolSide { amount: quote_pool.amount, decimal: quote_reserve.token_config.decimal },
+        quote_reserve.oracle_info.price,
+        dex_oracle.min_quote_liquidity,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::clock::Epoch;
+    use spl_token::state::AccountState;
+    use crate::{
+        oracle::OracleConfig,
+        state::{CollateralConfig, LiquidityConfig, RateModel, TokenConfig},
+    };
+
+    fn pack_pool(mint: Pubkey, amount: u64) -> Vec<u8> {
+        let mut data = vec![0; Account::LEN];
+        Account::pack(Account { mint, amount, state: AccountState::Initialized, ..Account::default() }, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn deep_pool_price() {
+        // 1_000 base (decimal 9) against 25_000 quote (decimal 6) priced at 1 dollar
+        let price = calculate_dex_price(
+            DexPoolSide { amount: 1_000_000_000_000, decimal: 9 },
+            DexPoolSide { amount: 25_000_000_000, decimal: 6 },
+            Decimal::one(),
+            10_000_000_000,
+        ).unwrap();
+        assert_eq!(price, Decimal::from(25u64));
+    }
+
+    #[test]
+    fn shallow_pool_rejected() {
+        let result = calculate_dex_price(
+            DexPoolSide { amount: 1_000_000_000, decimal: 9 },
+            DexPoolSide { amount: 25_000_000, decimal: 6 },
+            Decimal::one(),
+            10_000_000_000,
+        );
+        assert_eq!(result.unwrap_err(), LendingError::DexPoolTooShallow.into());
+    }
+
+    #[test]
+    fn dex_price_from_pool_accounts() {
+        let (manager, base_mint, quote_mint) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (base_pool_key, quote_pool_key, quote_reserve_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let dex_oracle = DexOracle::new(manager, base_pool_key, quote_pool_key, quote_reserve_key, 10_000_000_000);
+
+        let mut quote_reserve = MarketReserve::new(
+            0,
+            manager,
+            TokenConfig { mint_pubkey: quote_mint, supply_account: Pubkey::default(), decimal: 6 },
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig::default(),
+            Pubkey::default(),
+            CollateralConfig::default(),
+            RateModel::default(),
+        );
+        quote_reserve.oracle_info.price = Decimal::one();
+        quote_reserve.last_update.update_slot(1_000, false);
+        let mut quote_reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(quote_reserve, &mut quote_reserve_data).unwrap();
+        let mut base_pool_data = pack_pool(base_mint, 1_000_000_000_000);
+        let mut quote_pool_data = pack_pool(quote_mint, 25_000_000_000);
+
+        let (token_program_id, program_id) = (spl_token::id(), crate::id());
+        let (mut lamports_0, mut lamports_1, mut lamports_2) = (0, 0, 0);
+        let source_infos = [
+            AccountInfo::new(&base_pool_key, false, false, &mut lamports_0, &mut base_pool_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&quote_pool_key, false, false, &mut lamports_1, &mut quote_pool_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&quote_reserve_key, false, false, &mut lamports_2, &mut quote_reserve_data, &program_id, false, Epoch::default()),
+        ];
+
+        let clock = Clock { slot: 1_000, ..Clock::default() };
+        assert_eq!(get_dex_price(&dex_oracle, &base_mint, 9, &source_infos, &clock).unwrap(), Decimal::from(25u64));
+        // pools swapped or base of another token
+        let swapped = [source_infos[1].clone(), source_infos[0].clone(), source_infos[2].clone()];
+        assert_eq!(get_dex_price(&dex_oracle, &base_mint, 9, &swapped, &clock).unwrap_err(), LendingError::InvalidPriceOracle.into());
+        assert_eq!(get_dex_price(&dex_oracle, &quote_mint, 9, &source_infos, &clock).unwrap_err(), LendingError::InvalidPriceOracle.into());
+        // quote price must be fresh
+        let clock = Clock { slot: 1_100, ..Clock::default() };
+        assert_eq!(get_dex_price(&dex_oracle, &base_mint, 9, &source_infos, &clock).unwrap_err(), LendingError::MarketReserveStale.into());
+    }
+}
@@ -0,0 +1,121 @@
+#![allow(missing_docs)]
+use solana_program::{
+    msg,
+    account_info::AccountInfo,
+    clock::Clock,
+    program_error::ProgramError,
+};
+
+use crate::{
+    error::LendingError,
+    math::{Decimal, TryAdd, TryDiv},
+    state::{MedianOracle, MIN_MEDIAN_QUORUM},
+};
+use super::OracleParams;
+
+// stale or invalid sources are skipped, at least 3 fresh prices and even count averages the two middle,
+// so one outlier never moves the price outside of the honest ones
+pub fn calculate_median_price(
+    prices: Vec<Result<Decimal, ProgramError>>,
+    quorum: u8,
+) -> Result<Decimal, ProgramError> {
+    let mut prices = prices
+        .into_iter()
+        .filter_map(|price| price.ok())
+        .collect::<Vec<_>>();
+
+    let quorum = quorum.max(MIN_MEDIAN_QUORUM);
+    if prices.len() < quorum as usize {
+        msg!("Fresh oracle sources {} are below the quorum {}", prices.len(), quorum);
+        return Err(LendingError::InsufficientOracleQuorum.into());
+    }
+
+    prices.sort();
+    let middle = prices.len() / 2;
+    if prices.len() % 2 == 0 {
+        prices[middle - 1].try_add(prices[middle])?.try_div(2u64)
+    } else {
+        Ok(prices[middle])
+    }
+}
+
+pub fn get_median_price(
+    median_oracle: &MedianOracle,
+    source_infos: &[AccountInfo],
+    clock: &Clock,
+    params: &OracleParams,
+) -> Result<Decimal, ProgramError> {
+    if source_infos.len() != median_oracle.sources.len() ||
+        source_infos
+            .iter()
+            .zip(median_oracle.sources.iter())
+            .any(|(info, source)| info.key != &source.oracle) {
+        msg!("Oracle sources provided are not matched with median oracle");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+
+    let prices = source_infos
+        .iter()
+        .zip(median_oracle.sources.iter())
+        .map(|(info, source)| source.oracle_type.parse_price(info, clock, params))
+        .collect();
+
+    calculate_median_price(prices, median_oracle.quorum)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn median_skips_stale_source_and_ignores_outlier() {
+        // one feed stale, one outlier among the fresh
+        let prices = vec![
+            Ok(Decimal::from(100u64)),
+            Err(LendingError::InvalidOraclePrice.into()),
+            Ok(Decimal::from(1_000u64)),
+            Ok(Decimal::from(102u64)),
+        ];
+        assert_eq!(calculate_median_price(prices, 3).unwrap(), Decimal::from(102u64));
+
+        // low outlier is ignored the same way
+        let prices = vec![
+            Ok(Decimal::from(1u64)),
+            Ok(Decimal::from(100u64)),
+            Ok(Decimal::from(102u64)),
+        ];
+        assert_eq!(calculate_median_price(prices, 3).unwrap(), Decimal::from(100u64));
+
+        // even count averages the two middle, both honest
+        let prices = vec![
+            Ok(Decimal::from(1u64)),
+            Ok(Decimal::from(100u64)),
+            Ok(Decimal::from(102u64)),
+            Ok(Decimal::from(1_000u64)),
+        ];
+        assert_eq!(calculate_median_price(prices, 3).unwrap(), Decimal::from(101u64));
+    }
+
+    #[test]
+    fn median_below_quorum_rejected() {
+        let prices = vec![
+            Ok(Decimal::from(100u64)),
+            Err(LendingError::InvalidOraclePrice.into()),
+            Err(LendingError::InvalidOraclePrice.into()),
+        ];
+        assert_eq!(
+            calculate_median_price(prices, 2).unwrap_err(),
+            LendingError::InsufficientOracleQuorum.into(),
+        );
+
+        // two fresh prices never make a median, whatever the quorum
+        let prices = vec![
+            Ok(Decimal::from(1u64)),
+            Ok(Decimal::from(100u64)),
+        ];
+        assert_eq!(
+            calculate_median_price(prices, 1).unwrap_err(),
+            LendingError::InsufficientOracleQuorum.into(),
+        );
+    }
+}
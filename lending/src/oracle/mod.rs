@@ -3,16 +3,21 @@ mod pyth;
 mod chainlink;
 mod switchboard;
 mod dex_oracle;
+mod sotoken;
+mod median;
 
 pub use pyth::*;
 pub use chainlink::*;
 pub use switchboard::*;
+pub use dex_oracle::*;
+pub use sotoken::*;
+pub use median::*;
 
 use borsh::{BorshSerialize, BorshDeserialize};
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
-use solana_program::{clock::Clock, entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey, account_info::AccountInfo};
-use crate::{math::Decimal, state::Param};
+use solana_program::{msg, clock::{Clock, Slot}, entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey, account_info::AccountInfo};
+use crate::{error::LendingError, math::{Decimal, Rate, TryMul, TrySub}, state::{DexOracle, MedianOracle, Param}};
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, FromPrimitive, ToPrimitive, PartialEq)]
 pub enum OracleType {
@@ -22,14 +27,54 @@ pub enum OracleType {
     ChainLink,
     ///
     Switchboard,
+    /// oracle is a dex oracle account, pool accounts and quote reserve are passed along on refresh
+    Dex,
+    /// oracle is the source market reserve of the sotoken
+    SoToken,
+    /// oracle is a median oracle account, sources are passed along on refresh
+    Median,
 }
 
 impl OracleType {
-    pub fn parse_price(&self, account_info: &AccountInfo, clock: &Clock) -> Result<Decimal, ProgramError> {
+    pub fn program_id(&self) -> Option<Pubkey> {
         match self {
-            OracleType::Pyth => get_pyth_price(account_info, clock),
-            OracleType::ChainLink => get_chainlink_price(account_info, clock),
-            OracleType::Switchboard => get_switchboard_price(account_info, clock),
+            OracleType::Pyth => Some(PYTH_PROGRAM_ID),
+            OracleType::ChainLink => Some(CHAINLINK_PROGRAM_ID),
+            OracleType::Switchboard => Some(SWITCHBOARD_PROGRAM_ID),
+            OracleType::Dex => Some(crate::id()),
+            OracleType::SoToken => Some(crate::id()),
+            OracleType::Median => Some(crate::id()),
+        }
+    }
+
+    pub fn check_owner(&self, account_info: &AccountInfo) -> ProgramResult {
+        match self.program_id() {
+            Some(program_id) if account_info.owner != &program_id => {
+                msg!("Oracle account is not owned by {}", program_id);
+                Err(LendingError::InvalidOracleOwner.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn parse_price(&self, account_info: &AccountInfo, clock: &Clock, params: &OracleParams) -> Result<Decimal, ProgramError> {
+        self.check_owner(account_info)?;
+
+        match self {
+            OracleType::Pyth => get_pyth_price(account_info, clock, params),
+            OracleType::ChainLink => get_chainlink_price(account_info, clock, params),
+            OracleType::Switchboard => get_switchboard_price(account_info, clock, params),
+            OracleType::Dex => {
+                // dex price needs its pool accounts and quote reserve, see `get_dex_price`
+                msg!("Dex oracle price cannot be parsed from a single price account");
+                Err(LendingError::InvalidPriceOracle.into())
+            }
+            OracleType::SoToken => get_sotoken_price(account_info, clock),
+            OracleType::Median => {
+                // median price needs its source accounts, see `get_median_price`
+                msg!("Median oracle price cannot be parsed from a single price account");
+                Err(LendingError::InvalidPriceOracle.into())
+            }
         }
     }
 }
@@ -60,19 +105,214 @@ impl Param for OracleConfig {
     }
 }
 
+/// expected quote of an oracle must match its reported quote, all zero expected for unchecked
+pub fn check_oracle_quote(expected: &[u8; 4], oracle_quote: &str) -> ProgramResult {
+    if expected == &[0; 4] {
+        return Ok(());
+    }
+
+    let len = expected.iter().position(|byte| *byte == 0).unwrap_or(expected.len());
+    if oracle_quote.as_bytes() == &expected[..len] {
+        Ok(())
+    } else {
+        msg!("Oracle quotes in {} rather than manager quote currency", oracle_quote);
+        Err(LendingError::OracleQuoteMismatch.into())
+    }
+}
+
+/// previous price older than this is no reference for the deviation check
+pub const MAX_DEVIATION_REFERENCE_AGE_SLOTS: Slot = 150;
+
+/// Oracle safety params, zero keeps the default or disables the check
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OracleParams {
+    /// in secs for pyth and chainlink, in slots for switchboard
+    pub stale_threshold: u64,
+    ///
+    pub max_confidence_bps: u16,
+    /// max price change between two refreshes
+    pub max_deviation_bps: u16,
+}
+
+impl Param for OracleParams {
+    fn assert_valid(&self) -> ProgramResult {
+        if self.max_confidence_bps < 10_000 &&
+            self.max_deviation_bps < 10_000 &&
+            (self.max_confidence_bps == 0 ||
+                self.max_deviation_bps == 0 ||
+                self.max_confidence_bps <= self.max_deviation_bps) {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidOracleParams.into())
+        }
+    }
+}
+
+impl OracleParams {
+    ///
+    pub fn stale_after(&self, default: u64) -> u64 {
+        if self.stale_threshold == 0 { default } else { self.stale_threshold }
+    }
+    /// previous price read at unknown (zero) or old slot is skipped
+    pub fn check_deviation(&self, previous: Decimal, previous_slot: Slot, current: Decimal, slot: Slot) -> ProgramResult {
+        if self.max_deviation_bps == 0 ||
+            previous == Decimal::zero() ||
+            previous_slot == 0 ||
+            slot.saturating_sub(previous_slot) > MAX_DEVIATION_REFERENCE_AGE_SLOTS {
+            return Ok(());
+        }
+
+        let diff = if current > previous {
+            current.try_sub(previous)?
+        } else {
+            previous.try_sub(current)?
+        };
+        if diff > previous.try_mul(Rate::from_bps(self.max_deviation_bps))? {
+            msg!("Oracle price deviates too much from previous price");
+            Err(LendingError::InvalidOraclePrice.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct OracleInfo {
     ///
     pub price: Decimal,
+    /// slot `price` was read at, zero if unknown
+    pub price_slot: Slot,
     ///
     pub config: OracleConfig,
+    /// price used to value debt, only meaningful when `debt_config` is set
+    pub debt_price: Decimal,
+    /// slot `debt_price` was read at, zero if unknown
+    pub debt_price_slot: Slot,
+    ///
+    pub debt_config: Option<OracleConfig>,
+    ///
+    pub params: OracleParams,
+    /// manager quote currency the price oracle was checked against, all zero if never
+    pub quote_currency: [u8; 4],
 }
 
 impl OracleInfo {
     ///
     pub fn update_price(&mut self, account: &AccountInfo, clock: &Clock) -> ProgramResult {
-        self.price = self.config.oracle_type.parse_price(account, clock)?;
+        let price = self.config.oracle_type.parse_price(account, clock, &self.params)?;
+        self.params.check_deviation(self.price, self.price_slot, price, clock.slot)?;
+        self.price = price;
+        self.price_slot = clock.slot;
 
         Ok(())
     }
+    ///
+    pub fn update_median_price(
+        &mut self,
+        median_oracle: &MedianOracle,
+        source_infos: &[AccountInfo],
+        clock: &Clock,
+    ) -> ProgramResult {
+        let price = get_median_price(median_oracle, source_infos, clock, &self.params)?;
+        self.params.check_deviation(self.price, self.price_slot, price, clock.slot)?;
+        self.price = price;
+        self.price_slot = clock.slot;
+
+        Ok(())
+    }
+    ///
+    pub fn update_dex_price(
+        &mut self,
+        dex_oracle: &DexOracle,
+        base_mint: &Pubkey,
+        base_decimal: u8,
+        source_infos: &[AccountInfo],
+        clock: &Clock,
+    ) -> ProgramResult {
+        let price = get_dex_price(dex_oracle, base_mint, base_decimal, source_infos, clock)?;
+        self.params.check_deviation(self.price, self.price_slot, price, clock.slot)?;
+        self.price = price;
+        self.price_slot = clock.slot;
+
+        Ok(())
+    }
+    ///
+    pub fn update_debt_price(&mut self, account: &AccountInfo, clock: &Clock) -> ProgramResult {
+        let config = self.debt_config.ok_or(LendingError::InvalidPriceOracle)?;
+        let price = config.oracle_type.parse_price(account, clock, &self.params)?;
+        if price == Decimal::zero() {
+            msg!("Debt oracle price can not be zero");
+            return Err(LendingError::InvalidOraclePrice.into());
+        }
+        self.params.check_deviation(self.debt_price, self.debt_price_slot, price, clock.slot)?;
+        self.debt_price = price;
+        self.debt_price_slot = clock.slot;
+
+        Ok(())
+    }
+    /// falls back to primary price when no debt oracle is set
+    pub fn price_for_debt(&self) -> Decimal {
+        if self.debt_config.is_some() {
+            self.debt_price
+        } else {
+            self.price
+        }
+    }
+}
+
+/// Optional oracle used to value debt of market reserve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebtOracleConfig(pub Option<OracleConfig>);
+
+impl Param for DebtOracleConfig {
+    fn assert_valid(&self) -> ProgramResult {
+        if let Some(config) = self.0 {
+            config.assert_valid()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::clock::Epoch;
+
+    #[test]
+    fn oracle_account_with_wrong_owner_is_rejected() {
+        let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut lamports = 0;
+        let mut data = vec![0; 8];
+        let account_info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, Epoch::default());
+
+        for oracle_type in [OracleType::Pyth, OracleType::ChainLink, OracleType::Switchboard, OracleType::Dex] {
+            assert_eq!(
+                oracle_type.parse_price(&account_info, &Clock::default(), &OracleParams::default()),
+                Err(LendingError::InvalidOracleOwner.into()),
+            );
+        }
+    }
+
+    #[test]
+    fn oracle_quote_must_match_manager_quote() {
+        assert_eq!(check_oracle_quote(b"USD\0", "USD"), Ok(()));
+        assert_eq!(check_oracle_quote(b"USDC", "USDC"), Ok(()));
+        assert_eq!(check_oracle_quote(b"USD\0", "EUR"), Err(LendingError::OracleQuoteMismatch.into()));
+        assert_eq!(check_oracle_quote(b"USD\0", "USDC"), Err(LendingError::OracleQuoteMismatch.into()));
+        // unchecked when manager quote is unset
+        assert_eq!(check_oracle_quote(&[0; 4], "EUR"), Ok(()));
+    }
+
+    #[test]
+    fn deviation_reference_bounded_by_age() {
+        let params = OracleParams { max_deviation_bps: 1000, ..OracleParams::default() };
+        let (previous, current) = (Decimal::from(100u64), Decimal::from(120u64));
+
+        assert_eq!(params.check_deviation(previous, 100, current, 200), Err(LendingError::InvalidOraclePrice.into()));
+        assert_eq!(params.check_deviation(previous, 100, Decimal::from(110u64), 200), Ok(()));
+        // reference too old or read at unknown slot
+        assert_eq!(params.check_deviation(previous, 100, current, 100 + MAX_DEVIATION_REFERENCE_AGE_SLOTS + 1), Ok(()));
+        assert_eq!(params.check_deviation(previous, 0, current, 200), Ok(()));
+    }
 }
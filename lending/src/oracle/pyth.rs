@@ -1,14 +1,18 @@
 #![allow(missing_docs)]
-use std::convert::TryInto;
-
 use num_traits::ToPrimitive;
-use pyth_sdk_solana::load_price_feed_from_account_info;
-use solana_program::{msg, clock::Clock, program_error::ProgramError, account_info::AccountInfo};
+use pyth_sdk_solana::{load_price_feed_from_account_info, state::load_product_account};
+use solana_program::{msg, clock::Clock, program_error::ProgramError, pubkey::Pubkey, account_info::AccountInfo};
+
+use crate::{math::{Decimal, TryDiv}, error::LendingError};
+use super::OracleParams;
 
-use crate::{math::{Decimal, TryMul, TryDiv}, error::LendingError};
+#[cfg(feature = "devnet")]
+pub const PYTH_PROGRAM_ID: Pubkey = solana_program::pubkey!("gSbePebfvPy7tRqimPoVecS2UsBvYv46ynrzWocc92s");
+#[cfg(not(feature = "devnet"))]
+pub const PYTH_PROGRAM_ID: Pubkey = solana_program::pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi9epH");
 
-pub fn get_pyth_price(account_info: &AccountInfo, clock: &Clock) -> Result<Decimal, ProgramError> {
-    const STALE_AFTER_SECS_ELAPSED: i64 = 180;
+pub fn get_pyth_price(account_info: &AccountInfo, clock: &Clock, params: &OracleParams) -> Result<Decimal, ProgramError> {
+    const STALE_AFTER_SECS_ELAPSED: u64 = 180;
 
     let price_feed = load_price_feed_from_account_info(account_info)?;
     let price = if let Some(price) = price_feed.get_current_price() {
@@ -19,7 +23,10 @@ pub fn get_pyth_price(account_info: &AccountInfo, clock: &Clock) -> Result<Decim
             .checked_sub(timestamp)
             .ok_or(LendingError::InvalidPriceOracle)?;
 
-        if time_elapsed >= STALE_AFTER_SECS_ELAPSED {
+        let stale_after = params.stale_after(STALE_AFTER_SECS_ELAPSED)
+            .to_i64()
+            .ok_or(LendingError::MathOverflow)?;
+        if time_elapsed >= stale_after {
             msg!("Pyth oracle price is stale");
             return Err(LendingError::InvalidPriceOracle.into());
         }
@@ -27,23 +34,82 @@ pub fn get_pyth_price(account_info: &AccountInfo, clock: &Clock) -> Result<Decim
         price
     };
 
-    if price.expo >= 0 {
-        let exponent = price.expo
-            .try_into()
-            .map_err(|_| LendingError::MathOverflow)?;
-        let zeros = 10u64
-            .checked_pow(exponent)
-            .ok_or(LendingError::MathOverflow)?;
-        Decimal::from(price.price.to_u64().ok_or(LendingError::MathOverflow)?).try_mul(zeros)
-    } else {
-        let exponent = price.expo
-            .checked_abs()
-            .ok_or(LendingError::MathOverflow)?
-            .try_into()
-            .map_err(|_| LendingError::MathOverflow)?;
-        let decimals = 10u64
-            .checked_pow(exponent)
-            .ok_or(LendingError::MathOverflow)?;
-        Decimal::from(price.price.to_u64().ok_or(LendingError::MathOverflow)?).try_div(decimals)
+    check_pyth_confidence(price.price, price.conf, params.max_confidence_bps)?;
+    scale_pyth_price(price.price, price.expo)
+}
+
+/// quote currency from the attributes of pyth product account of `price_key`
+pub fn get_pyth_quote_currency(product_info: &AccountInfo, price_key: &Pubkey) -> Result<String, ProgramError> {
+    if product_info.owner != &PYTH_PROGRAM_ID {
+        msg!("Pyth product account is not owned by {}", PYTH_PROGRAM_ID);
+        return Err(LendingError::InvalidOracleOwner.into());
+    }
+
+    let data = product_info.try_borrow_data()?;
+    let product = load_product_account(&data).map_err(|_| LendingError::InvalidPriceOracle)?;
+    if product.px_acc.val != price_key.to_bytes() {
+        msg!("Pyth product account does not belong to the price oracle");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+
+    product
+        .iter()
+        .find(|(key, _)| *key == "quote_currency")
+        .map(|(_, value)| value.to_string())
+        .ok_or_else(|| {
+            msg!("Pyth product account has no quote currency");
+            LendingError::InvalidPriceOracle.into()
+        })
+}
+
+// reject price whose confidence interval is too wide relative to price
+pub fn check_pyth_confidence(price: i64, conf: u64, max_confidence_bps: u16) -> Result<(), ProgramError> {
+    if max_confidence_bps > 0 &&
+        (conf as u128) * 10_000 > (price.max(0) as u128) * (max_confidence_bps as u128) {
+        msg!("Pyth oracle price confidence {} is too wide for price {}", conf, price);
+        return Err(LendingError::InvalidOraclePrice.into());
+    }
+
+    Ok(())
+}
+
+/// accepted range of pyth price exponent
+pub const MIN_PYTH_EXPONENT: i32 = -12;
+pub const MAX_PYTH_EXPONENT: i32 = 0;
+
+pub fn scale_pyth_price(price: i64, expo: i32) -> Result<Decimal, ProgramError> {
+    if !(MIN_PYTH_EXPONENT..=MAX_PYTH_EXPONENT).contains(&expo) {
+        msg!("Pyth oracle price exponent {} is out of range", expo);
+        return Err(LendingError::InvalidOraclePrice.into());
+    }
+
+    let decimals = 10u64
+        .checked_pow(expo.unsigned_abs())
+        .ok_or(LendingError::MathOverflow)?;
+    Decimal::from(price.to_u64().ok_or(LendingError::InvalidOraclePrice)?).try_div(decimals)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scale_normal_exponent() {
+        assert_eq!(scale_pyth_price(2_500_000_000, -8).unwrap(), Decimal::from(25u64));
+        assert_eq!(scale_pyth_price(7, 0).unwrap(), Decimal::from(7u64));
+    }
+
+    #[test]
+    fn confidence_check() {
+        assert!(check_pyth_confidence(10_000, 100, 100).is_ok());
+        assert!(check_pyth_confidence(10_000, 101, 100).is_err());
+        assert!(check_pyth_confidence(10_000, 5_000, 0).is_ok());
+    }
+
+    #[test]
+    fn reject_out_of_range_exponent() {
+        assert_eq!(scale_pyth_price(1, -13).unwrap_err(), LendingError::InvalidOraclePrice.into());
+        assert_eq!(scale_pyth_price(1, 3).unwrap_err(), LendingError::InvalidOraclePrice.into());
+        assert_eq!(scale_pyth_price(-1, -8).unwrap_err(), LendingError::InvalidOraclePrice.into());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,144 @@
+#![allow(missing_docs)]
+use solana_program::{
+    msg,
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    error::LendingError,
+    math::{Decimal, TryMul},
+    state::MarketReserve,
+};
+
+use super::OracleType;
+
+// sotoken price = underlying price * liquidity per sotoken, sotoken shares decimal with underlying
+pub fn calculate_sotoken_price(source_reserve: &MarketReserve, clock: &Clock) -> Result<Decimal, ProgramError> {
+    // only plain oracles as source, so sotoken prices never chain or loop
+    if source_reserve.oracle_info.config.oracle_type == OracleType::SoToken {
+        msg!("Source reserve of sotoken oracle can not be priced by sotoken");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+    if source_reserve.last_update.is_lax_stale(clock.slot)? {
+        msg!("Source reserve of sotoken oracle is stale");
+        return Err(LendingError::MarketReserveStale.into());
+    }
+
+    source_reserve.oracle_info.price.try_mul(source_reserve.exchange_rate()?)
+}
+
+pub fn get_sotoken_price(source_reserve_info: &AccountInfo, clock: &Clock) -> Result<Decimal, ProgramError> {
+    let source_reserve = MarketReserve::unpack(&source_reserve_info.try_borrow_data()?)?;
+
+    calculate_sotoken_price(&source_reserve, clock)
+}
+
+/// source reserve must be in the same market as the reserve priced by its sotoken
+pub fn assert_sotoken_source_manager(source_reserve_info: &AccountInfo, manager: &Pubkey) -> ProgramResult {
+    OracleType::SoToken.check_owner(source_reserve_info)?;
+    let source_reserve = MarketReserve::unpack(&source_reserve_info.try_borrow_data()?)?;
+
+    if &source_reserve.manager == manager {
+        Ok(())
+    } else {
+        msg!("Source reserve of sotoken oracle is not matched with manager of market reserve");
+        Err(LendingError::InvalidPriceOracle.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::clock::Epoch;
+    use crate::{
+        math::{TryAdd, TryDiv, TrySub},
+        oracle::OracleConfig,
+        state::{CollateralConfig, LiquidityConfig, RateModel, TokenConfig},
+    };
+
+    fn new_source_reserve() -> MarketReserve {
+        let mut reserve = MarketReserve::new(
+            0,
+            Pubkey::default(),
+            TokenConfig::default(),
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig { max_deposit: u64::MAX, borrow_tax_rate: 10, ..LiquidityConfig::default() },
+            Pubkey::default(),
+            CollateralConfig::default(),
+            RateModel {
+                offset: 10_000_000_000_000_000,
+                optimal: 100_000_000_000_000_000,
+                kink: 80,
+                max: 1_000_000_000_000_000_000,
+                smoothing_factor: 0,
+            },
+        );
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        reserve.accrue_interest(&Pubkey::default(), &Clock { slot: 1_000, ..Clock::default() }).unwrap();
+        reserve.last_update.update_slot(1_000, false);
+        reserve.oracle_info.price = Decimal::from(3u64);
+
+        reserve
+    }
+
+    #[test]
+    fn sotoken_priced_by_source_reserve() {
+        let reserve = new_source_reserve();
+        let clock = Clock { slot: 1_000, ..Clock::default() };
+
+        // liquidity per sotoken grows with interest
+        let total_liquidity = Decimal::from(reserve.liquidity_info.available)
+            .try_add(reserve.liquidity_info.borrowed_amount_wads).unwrap()
+            .try_sub(reserve.liquidity_info.insurance_wads).unwrap()
+            .try_sub(reserve.liquidity_info.staker_fee_wads).unwrap();
+        let expect = Decimal::from(3u64)
+            .try_mul(total_liquidity).unwrap()
+            .try_div(reserve.collateral_info.total_mint).unwrap();
+
+        let price = calculate_sotoken_price(&reserve, &clock).unwrap();
+        assert!(price > Decimal::from(3u64));
+        let diff = if price > expect { price.try_sub(expect) } else { expect.try_sub(price) }.unwrap();
+        assert!(diff < Decimal::from_scaled_val(1_000_000));
+    }
+
+    #[test]
+    fn sotoken_source_must_be_fresh_and_not_sotoken() {
+        let mut reserve = new_source_reserve();
+        let clock = Clock { slot: 1_100, ..Clock::default() };
+        assert_eq!(
+            calculate_sotoken_price(&reserve, &clock).unwrap_err(),
+            LendingError::MarketReserveStale.into(),
+        );
+
+        reserve.oracle_info.config.oracle_type = OracleType::SoToken;
+        assert_eq!(
+            calculate_sotoken_price(&reserve, &Clock { slot: 1_000, ..Clock::default() }).unwrap_err(),
+            LendingError::InvalidPriceOracle.into(),
+        );
+    }
+
+    #[test]
+    fn sotoken_source_must_share_manager() {
+        let manager = Pubkey::new_unique();
+        let reserve = MarketReserve { manager, ..new_source_reserve() };
+
+        let key = Pubkey::new_unique();
+        let owner = crate::id();
+        let mut lamports = 0;
+        let mut data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(reserve, &mut data).unwrap();
+        let source_reserve_info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, Epoch::default());
+
+        assert_eq!(assert_sotoken_source_manager(&source_reserve_info, &manager), Ok(()));
+        assert_eq!(
+            assert_sotoken_source_manager(&source_reserve_info, &Pubkey::new_unique()),
+            Err(LendingError::InvalidPriceOracle.into()),
+        );
+    }
+}
@@ -4,13 +4,20 @@ use solana_program::{
     msg,
     clock::Clock,
     program_error::ProgramError,
+    pubkey::Pubkey,
     account_info::AccountInfo,
 };
 use switchboard_program::fast_parse_switchboard_result;
 
 use crate::{error::LendingError, math::{Decimal, TryDiv}};
+use super::OracleParams;
 
-pub fn get_switchboard_price(account_info: &AccountInfo, clock: &Clock) -> Result<Decimal, ProgramError> {
+#[cfg(feature = "devnet")]
+pub const SWITCHBOARD_PROGRAM_ID: Pubkey = solana_program::pubkey!("7azgmy1pFXHikv36q1zZASvFq5vFa39TT9NweVugKKTU");
+#[cfg(not(feature = "devnet"))]
+pub const SWITCHBOARD_PROGRAM_ID: Pubkey = solana_program::pubkey!("DtmE9D2CSB4L5D6A15mraeEjrGMm6auWVzgaD8hK2tZM");
+
+pub fn get_switchboard_price(account_info: &AccountInfo, clock: &Clock, params: &OracleParams) -> Result<Decimal, ProgramError> {
     const STALE_AFTER_SLOTS_ELAPSED: u64 = 60;
 
     let data = account_info.try_borrow_data()?;
@@ -19,7 +26,7 @@ pub fn get_switchboard_price(account_info: &AccountInfo, clock: &Clock) -> Resul
     let slots_eplased = clock.slot
         .checked_sub(result.round_open_slot)
         .ok_or(LendingError::MathOverflow)?;
-    if slots_eplased >= STALE_AFTER_SLOTS_ELAPSED {
+    if slots_eplased >= params.stale_after(STALE_AFTER_SLOTS_ELAPSED) {
         msg!("Switchboard oracle price is stale");
         return Err(LendingError::InvalidPriceOracle.into());
     }
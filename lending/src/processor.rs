@@ -4,9 +4,11 @@ use std::any::Any;
 use num_traits::FromPrimitive;
 use solana_program::{
     account_info::{AccountInfo, next_account_info},
+    clock::Slot,
     decode_error::DecodeError,
     entrypoint::ProgramResult,
     msg,
+    program::set_return_data,
     program_error::{PrintProgramError, ProgramError},
     program_option::COption,
     program_pack::Pack,
@@ -18,6 +20,9 @@ use spl_token::{state::{Mint, Account}, native_mint};
 use crate::{
     assert_rent_exempt,
     assert_uninitialized,
+    assert_fresh_sotoken_mint,
+    assert_supported_token_mint,
+    associated_token_address,
     handle_amount,
     Data,
     dex::{OrcaSwapContext, Swapper, RaydiumSwapContext, DexType, ORCA, RAYDIUM, ORCA_TWICE},
@@ -25,12 +30,14 @@ use crate::{
     instruction::LendingInstruction,
     invoker::*,
     state::*,
-    oracle::OracleConfig,
+    oracle::{OracleConfig, OracleType, DebtOracleConfig, assert_sotoken_source_manager, check_oracle_quote, get_pyth_quote_currency},
     get_rent,
     get_clock,
     create_manager,
     create_market_reserve,
     create_user_obligation,
+    create_median_oracle,
+    create_dex_oracle,
     get_manager,
     get_mut_manager,
     get_manager_authority,
@@ -44,6 +51,7 @@ use crate::{
     get_sotoken_mint,
     get_supply_account,
     get_receiver_program,
+    get_liquidation_fee_receiver,
 };
 #[cfg(feature = "unique-credit")]
 use crate::{
@@ -199,6 +207,54 @@ pub fn process_instruction(
             msg!("Instruction: Open Leverage Position By Raydium with Base In: max borrow {}, collateral {}", max_borrow_amount, collateral_amount);
             process_open_leverage_position_base_out::<RAYDIUM>(program_id, accounts, max_borrow_amount, collateral_amount)
         }
+        LendingInstruction::RepayAllAndRedeemAll(close_obligation) => {
+            msg!("Instruction: Repay All And Redeem All: close obligation {}", close_obligation);
+            process_repay_all_and_redeem_all(program_id, accounts, close_obligation)
+        }
+        LendingInstruction::ClaimStakerFee => {
+            msg!("Instruction: Claim Staker Fee");
+            process_claim_staker_fee(program_id, accounts)
+        }
+        LendingInstruction::RefreshAll => {
+            msg!("Instruction: Refresh All");
+            process_refresh_all(program_id, accounts)
+        }
+        LendingInstruction::ApplyPendingConfig => {
+            msg!("Instruction: Apply Pending Config");
+            process_apply_pending_config(program_id, accounts)
+        }
+        LendingInstruction::ApplyManagerPendingConfig => {
+            msg!("Instruction: Apply Manager Pending Config");
+            process_apply_manager_pending_config(program_id, accounts)
+        }
+        LendingInstruction::ExtendManager => {
+            msg!("Instruction: Extend Manager");
+            process_extend_manager(program_id, accounts)
+        }
+        LendingInstruction::RefreshUserObligationPartial => {
+            msg!("Instruction: Refresh User Obligation Partial");
+            process_refresh_user_obligation_partial(program_id, accounts)
+        }
+        LendingInstruction::RefreshUserObligationForBorrow(borrow_reserve) => {
+            msg!("Instruction: Refresh User Obligation For Borrow");
+            process_refresh_user_obligation_for_borrow(program_id, accounts, &borrow_reserve)
+        }
+        LendingInstruction::DepositPledgeBorrow(deposit_amount, borrow_amount) => {
+            msg!("Instruction: Deposit Pledge Borrow: {} {}", deposit_amount, borrow_amount);
+            process_deposit_pledge_borrow(program_id, accounts, deposit_amount, borrow_amount)
+        }
+        LendingInstruction::RepayFromPledgedCollateral(amount) => {
+            msg!("Instruction: Repay From Pledged Collateral: {}", amount);
+            process_repay_from_pledged_collateral(program_id, accounts, amount)
+        }
+        LendingInstruction::RefreshMarketReserveWithTip => {
+            msg!("Instruction: Refresh Market Reserve With Tip");
+            process_refresh_market_reserve_with_tip(program_id, accounts)
+        }
+        LendingInstruction::LiquidateDirect(amount) => {
+            msg!("Instruction: Liquidate Direct {}", amount);
+            process_liquidate_direct(program_id, accounts, amount)
+        }
         #[cfg(feature = "unique-credit")]
         LendingInstruction::InitUniqueCredit(authority, amount) => {
             msg!("Instruction: Init Unique Credit");
@@ -224,27 +280,35 @@ pub fn process_instruction(
         }
         LendingInstruction::ControlMarketReserveLiquidity(enable) => {
             msg!("Instruction: Control Market Reserve Liquidity");
-            process_operate_market_reserve(program_id, accounts, LiquidityControl(enable))
+            if enable {
+                process_update_market_reserve_config(program_id, accounts, LiquidityControl(enable), input)
+            } else {
+                process_operate_market_reserve(program_id, accounts, LiquidityControl(enable))
+            }
         }
         LendingInstruction::UpdateMarketReserveRateModel(model) => {
             msg!("Instruction: Update Market Reserve Rate Model");
-            process_operate_market_reserve(program_id, accounts, model)
+            process_update_market_reserve_config(program_id, accounts, model, input)
         }
         LendingInstruction::UpdateMarketReserveCollateralConfig(config) => {
             msg!("Instruction: Update Market Reserve Collateral Config");
-            process_operate_market_reserve(program_id, accounts, config)
+            process_update_market_reserve_config(program_id, accounts, config, input)
         }
         LendingInstruction::UpdateMarketReserveLiquidityConfig(config) => {
             msg!("Instruction: Update Market Reserve Liquidity Config");
-            process_operate_market_reserve(program_id, accounts, config)
+            process_update_market_reserve_config(program_id, accounts, config, input)
         }
         LendingInstruction::UpdateMarketReserveOracleConfig(config) => {
             msg!("Instruction: Update Market Reserve Price Oracle Config");
-            process_operate_market_reserve(program_id, accounts, config)
+            process_update_market_reserve_oracle_config::<false, _>(program_id, accounts, config, Some(config), input)
         }
         LendingInstruction::ReduceInsurance(amount) => {
             msg!("Instruction: Reduce Insurance {}", amount);
-            process_reduce_insurance(program_id, accounts, amount)
+            process_reduce_insurance(program_id, accounts, amount, input)
+        }
+        LendingInstruction::TransferInsurance(amount) => {
+            msg!("Instruction: Transfer Insurance {}", amount);
+            process_transfer_insurance(program_id, accounts, amount, input)
         }
         LendingInstruction::ChangeManagerOwner => {
             msg!("Instruction: Change Manager Owner");
@@ -255,6 +319,150 @@ pub fn process_instruction(
             msg!("Instruction: Update Unique Credit Limit: amount = {}", amount);
             process_update_unique_credit_limit(program_id, accounts, amount)
         }
+        LendingInstruction::BatchUpdateMarketReserveRateModel(model) => {
+            msg!("Instruction: Batch Update Market Reserve Rate Model");
+            process_batch_operate_market_reserve(program_id, accounts, model)
+        }
+        LendingInstruction::BatchUpdateMarketReserveCollateralConfig(config) => {
+            msg!("Instruction: Batch Update Market Reserve Collateral Config");
+            process_batch_operate_market_reserve(program_id, accounts, config)
+        }
+        LendingInstruction::BatchUpdateMarketReserveLiquidityConfig(config) => {
+            msg!("Instruction: Batch Update Market Reserve Liquidity Config");
+            process_batch_operate_market_reserve(program_id, accounts, config)
+        }
+        LendingInstruction::GetReserveParams => {
+            msg!("Instruction: Get Reserve Params");
+            process_get_reserve_params(program_id, accounts)
+        }
+        LendingInstruction::UpdateManagerMinLeverageOpenHealth(bps) => {
+            msg!("Instruction: Update Manager Min Leverage Open Health: bps = {}", bps);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateMarketReserveDebtOracleConfig(config) => {
+            msg!("Instruction: Update Market Reserve Debt Oracle Config");
+            process_update_market_reserve_oracle_config::<true, _>(program_id, accounts, DebtOracleConfig(config), config, input)
+        }
+        LendingInstruction::UpdateMarketReserveOracleParams(params) => {
+            msg!("Instruction: Update Market Reserve Oracle Params");
+            process_update_market_reserve_config(program_id, accounts, params, input)
+        }
+        LendingInstruction::UpdateManagerLiquidationFee(ratio, _) => {
+            msg!("Instruction: Update Manager Liquidation Fee: ratio = {}", ratio);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerFriendBorrowValueCap(cap) => {
+            msg!("Instruction: Update Manager Friend Borrow Value Cap: cap = {}", cap);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerBorrowMaxObligationAge(max_age_slots) => {
+            msg!("Instruction: Update Manager Borrow Max Obligation Age: slots = {}", max_age_slots);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::SweepStrayTokens => {
+            msg!("Instruction: Sweep Stray Tokens");
+            process_sweep_stray_tokens(program_id, accounts)
+        }
+        LendingInstruction::UpdateMarketReserveStakerFeeConfig(config) => {
+            msg!("Instruction: Update Market Reserve Staker Fee Config");
+            process_update_market_reserve_config(program_id, accounts, config, input)
+        }
+        LendingInstruction::UpdateManagerCollateralDustValue(dust_value) => {
+            msg!("Instruction: Update Manager Collateral Dust Value: value = {}", dust_value);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerFlashLoanRepayTolerance(tolerance) => {
+            msg!("Instruction: Update Manager Flash Loan Repay Tolerance: tolerance = {}", tolerance);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::SetManagerPaused(paused) => {
+            msg!("Instruction: Set Manager Paused: paused = {}", paused);
+            process_set_manager_paused(program_id, accounts, paused)
+        }
+        LendingInstruction::SetEmergencyLiquidation(enable) => {
+            msg!("Instruction: Set Emergency Liquidation: enable = {}", enable);
+            if enable {
+                process_update_manager_config(program_id, accounts, input)
+            } else {
+                process_set_emergency_liquidation(program_id, accounts, enable)
+            }
+        }
+        LendingInstruction::UpdateManagerConfigTimelock(timelock_slots) => {
+            msg!("Instruction: Update Manager Config Timelock: slots = {}", timelock_slots);
+            process_update_manager_config_timelock(program_id, accounts, timelock_slots)
+        }
+        LendingInstruction::UpdateMarketReserveSotokenRestriction(restricted) => {
+            msg!("Instruction: Update Market Reserve Sotoken Restriction: restricted = {}", restricted);
+            process_update_market_reserve_config(program_id, accounts, SotokenRestriction(restricted), input)
+        }
+        LendingInstruction::InitMedianOracle(quorum, sources) => {
+            msg!("Instruction: Init Median Oracle: quorum = {}, sources = {}", quorum, sources.len());
+            process_init_median_oracle(program_id, accounts, quorum, sources)
+        }
+        LendingInstruction::InitDexOracle(min_quote_liquidity) => {
+            msg!("Instruction: Init Dex Oracle: min quote liquidity = {}", min_quote_liquidity);
+            process_init_dex_oracle(program_id, accounts, min_quote_liquidity)
+        }
+        LendingInstruction::UpdateManagerMaxLeverage(max_leverage_x) => {
+            msg!("Instruction: Update Manager Max Leverage: max leverage = {}x", max_leverage_x);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerLiquidationBackstopCap(cap) => {
+            msg!("Instruction: Update Manager Liquidation Backstop Cap: cap = {}", cap);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerMaxLoansPerObligation(max_loans) => {
+            msg!("Instruction: Update Manager Max Loans Per Obligation: max loans = {}", max_loans);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerObligationExposureCap(max_collateral_value, max_loan_value) => {
+            msg!(
+                "Instruction: Update Manager Obligation Exposure Cap: collateral value = {}, loan value = {}",
+                max_collateral_value,
+                max_loan_value,
+            );
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::BootstrapReserve(amount) => {
+            msg!("Instruction: Bootstrap Reserve: amount = {}", amount);
+            process_bootstrap_reserve(program_id, accounts, amount)
+        }
+        LendingInstruction::InitDepositWhitelist(until_slot, depositors) => {
+            msg!("Instruction: Init Deposit Whitelist: until slot = {}, depositors = {}", until_slot, depositors.len());
+            process_init_deposit_whitelist(program_id, accounts, until_slot, depositors)
+        }
+        LendingInstruction::ExtendMarketReserve => {
+            msg!("Instruction: Extend Market Reserve");
+            process_extend_market_reserve(program_id, accounts)
+        }
+        LendingInstruction::UpdateMarketReserveLiquidationBackstopBudget(budget) => {
+            msg!("Instruction: Update Market Reserve Liquidation Backstop Budget: budget = {}", budget);
+            process_update_market_reserve_config(program_id, accounts, LiquidationBackstopBudget(budget), input)
+        }
+        LendingInstruction::UpdateManagerCollateralCooldown(cooldown_slots) => {
+            msg!("Instruction: Update Manager Collateral Cooldown: slots = {}", cooldown_slots);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateManagerQuoteCurrency(quote_currency) => {
+            msg!("Instruction: Update Manager Quote Currency: quote = {:?}", quote_currency);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::AllowObligation => {
+            msg!("Instruction: Allow Obligation");
+            process_operate_user_obligation(program_id, accounts, ObligationAllowance(true))
+        }
+        LendingInstruction::DisallowObligation => {
+            msg!("Instruction: Disallow Obligation");
+            process_operate_user_obligation(program_id, accounts, ObligationAllowance(false))
+        }
+        LendingInstruction::UpdateManagerKeeperTip(tip_value, min_gap_slots) => {
+            msg!("Instruction: Update Manager Keeper Tip: value = {}, min gap slots = {}", tip_value, min_gap_slots);
+            process_update_manager_config(program_id, accounts, input)
+        }
+        LendingInstruction::UpdateMarketReserveLiquidityStatusBands(bands) => {
+            msg!("Instruction: Update Market Reserve Liquidity Status Bands: {:?}", bands);
+            process_update_market_reserve_config(program_id, accounts, bands, input)
+        }
     }
 }
 
@@ -288,8 +496,7 @@ fn process_init_market_reserve(
     rate_model: RateModel,
 ) -> ProgramResult {
     // check config
-    collateral_config.assert_valid()?;
-    liquidity_config.assert_valid()?;
+    assert_valid_reserve_config(&oracle_config, &collateral_config, &liquidity_config, &rate_model)?;
 
     let account_info_iter = &mut accounts.iter();
     // 1
@@ -302,19 +509,29 @@ fn process_init_market_reserve(
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 5
     let supply_token_account_info = next_account_info(account_info_iter)?;
+    assert_uninitialized::<Account>(supply_token_account_info)?;
     // 6
     create_market_reserve!(market_reserve_info; account_info_iter, program_id, rent);
     // 7
     let token_mint_info = next_account_info(account_info_iter)?;
+    assert_supported_token_mint(token_mint_info)?;
     let token_decimals = get_token_decimals(token_mint_info)?;
+    if token_decimals > MAX_TOKEN_DECIMALS {
+        msg!("Token decimals {} exceeds max {}", token_decimals, MAX_TOKEN_DECIMALS);
+        return Err(LendingError::InvalidTokenDecimals.into());
+    }
     // 8
     let sotoken_mint_info = next_account_info(account_info_iter)?;
+    assert_uninitialized::<Mint>(sotoken_mint_info)?;
+    assert_fresh_sotoken_mint(sotoken_mint_info)?;
     // 9
     get_manager_owner!(manager_owner_info; account_info_iter, manager);
     // 10
     let token_program_info = next_account_info(account_info_iter)?;
+    // 11?, pyth product account to check quote currency, only if manager sets one
+    assert_oracle_quote(&manager, &oracle_config, account_info_iter.next())?;
 
-    let market_reserve = MarketReserve::new(
+    let mut market_reserve = MarketReserve::new(
         clock.slot,
         *manager_info.key,
         TokenConfig {
@@ -328,6 +545,9 @@ fn process_init_market_reserve(
         collateral_config,
         rate_model,
     );
+    market_reserve.oracle_info.quote_currency = manager.quote_currency;
+    // first liquidity comes from bootstrap only, so no deposit can set the initial rate
+    market_reserve.liquidity_info.awaiting_bootstrap = true;
     MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
 
     // init manager token account
@@ -353,37 +573,147 @@ fn process_refresh_market_reserves(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
 ) -> ProgramResult {
-    let (clock_account, accounts) = accounts
-        .split_first()
-        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let account_info_iter = &mut accounts.iter();
     // 1
-    let clock = &Clock::from_account_info(clock_account)?;
-    accounts
-        .chunks_exact(2)
-        .try_for_each(|accounts_info| {
-            // 2 + i * 2
-            let market_reserve_info = &accounts_info[0];
-            // 3 + i * 2
-            let price_oracle_info = &accounts_info[1];
-        
-            if market_reserve_info.owner != program_id {
-                msg!("Market reserve provided is not owned by the lending program");
-                return Err(LendingError::InvalidAccountOwner.into());
-            }
-            let mut market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
-        
-            if price_oracle_info.key != &market_reserve.oracle_info.config.oracle {
-                msg!("Oracle of market reserve is not matched with oracle provided");
-                return Err(LendingError::InvalidPriceOracle.into());
-            }
-        
-            // update
-            market_reserve.oracle_info.update_price(price_oracle_info, clock)?;
-            market_reserve.accrue_interest(clock.slot)?;
-            market_reserve.last_update.update_slot(clock.slot, false);
-            // pack
-            MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
-        })
+    let clock = &Clock::from_account_info(next_account_info(account_info_iter)?)?;
+    // reserve with debt oracle takes 3 accounts, otherwise 2
+    while let Some(market_reserve_info) = account_info_iter.next() {
+        refresh_market_reserve(program_id, clock, market_reserve_info, account_info_iter)?;
+    }
+
+    Ok(())
+}
+
+#[inline(never)]
+fn process_refresh_market_reserve_with_tip(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    let clock = &Clock::from_account_info(next_account_info(account_info_iter)?)?;
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    // 3
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4
+    let market_reserve_info = next_account_info(account_info_iter)?;
+    let last_update_slot = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?.last_update.slot;
+    // 5 ~
+    let mut market_reserve = refresh_market_reserve(program_id, clock, market_reserve_info, account_info_iter)?;
+    if &market_reserve.manager != manager_info.key {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 6 ~
+    get_supply_account!(supply_token_account_info; account_info_iter, market_reserve);
+    // 7 ~
+    let keeper_token_account_info = next_account_info(account_info_iter)?;
+    // 8 ~
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    // tip only when insurance covers it, refresh goes on anyway
+    let tip = market_reserve.amount_of_value(manager.keeper_tip(clock.slot.saturating_sub(last_update_slot)))?;
+    if tip == 0 || market_reserve.liquidity_info.reduce_insurance(tip).is_err() {
+        return Ok(());
+    }
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
+    // transfer
+    process_token_transfer(
+        token_program_info,
+        supply_token_account_info,
+        keeper_token_account_info,
+        manager_authority_info,
+        tip,
+        signer_seeds,
+    )
+}
+
+fn refresh_market_reserve<'a, 'b>(
+    program_id: &Pubkey,
+    clock: &Clock,
+    market_reserve_info: &'a AccountInfo<'b>,
+    account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+) -> Result<MarketReserve, ProgramError> {
+    // 2 + i * n
+    // 3 + i * n
+    let price_oracle_info = next_account_info(account_info_iter)?;
+
+    if market_reserve_info.owner != program_id {
+        msg!("Market reserve provided is not owned by the lending program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    let mut market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
+
+    if price_oracle_info.key != &market_reserve.oracle_info.config.oracle {
+        msg!("Oracle of market reserve is not matched with oracle provided");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+    if price_oracle_info.key == market_reserve_info.key {
+        msg!("Market reserve can not price its own sotoken");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+
+    // update
+    if market_reserve.oracle_info.config.oracle_type == OracleType::Median {
+        if price_oracle_info.owner != program_id {
+            msg!("Median oracle provided is not owned by the lending program");
+            return Err(LendingError::InvalidOracleOwner.into());
+        }
+        let median_oracle = MedianOracle::unpack(&price_oracle_info.try_borrow_data()?)?;
+        if median_oracle.manager != market_reserve.manager {
+            msg!("Median oracle manager is not matched with market reserve manager");
+            return Err(LendingError::InvalidPriceOracle.into());
+        }
+        // 4 + i * n + j, j in sources
+        let source_infos = (0..median_oracle.sources.len())
+            .map(|_| next_account_info(account_info_iter).map(Clone::clone))
+            .collect::<Result<Vec<_>, _>>()?;
+        market_reserve.oracle_info.update_median_price(&median_oracle, &source_infos, clock)?;
+    } else if market_reserve.oracle_info.config.oracle_type == OracleType::Dex {
+        OracleType::Dex.check_owner(price_oracle_info)?;
+        let dex_oracle = DexOracle::unpack(&price_oracle_info.try_borrow_data()?)?;
+        if dex_oracle.manager != market_reserve.manager {
+            msg!("Dex oracle manager is not matched with market reserve manager");
+            return Err(LendingError::InvalidPriceOracle.into());
+        }
+        // 4 + i * n + j, base pool, quote pool and quote reserve
+        let source_infos = (0..3)
+            .map(|_| next_account_info(account_info_iter).map(Clone::clone))
+            .collect::<Result<Vec<_>, _>>()?;
+        market_reserve.oracle_info.update_dex_price(
+            &dex_oracle,
+            &market_reserve.token_config.mint_pubkey,
+            market_reserve.token_config.decimal,
+            &source_infos,
+            clock,
+        )?;
+    } else {
+        if market_reserve.oracle_info.config.oracle_type == OracleType::SoToken {
+            assert_sotoken_source_manager(price_oracle_info, &market_reserve.manager)?;
+        }
+        market_reserve.oracle_info.update_price(price_oracle_info, clock)?;
+    }
+    // 4 + i * n?
+    if let Some(debt_config) = market_reserve.oracle_info.debt_config {
+        let debt_oracle_info = next_account_info(account_info_iter)?;
+        if debt_oracle_info.key != &debt_config.oracle {
+            msg!("Debt oracle of market reserve is not matched with oracle provided");
+            return Err(LendingError::InvalidPriceOracle.into());
+        }
+        if debt_config.oracle_type == OracleType::SoToken {
+            assert_sotoken_source_manager(debt_oracle_info, &market_reserve.manager)?;
+        }
+        market_reserve.oracle_info.update_debt_price(debt_oracle_info, clock)?;
+    }
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
+    market_reserve.last_update.update_slot(clock.slot, false);
+    // pack
+    MarketReserve::pack(market_reserve.clone(), &mut market_reserve_info.try_borrow_mut_data()?)?;
+
+    Ok(market_reserve)
 }
 
 #[inline(never)]
@@ -405,10 +735,15 @@ fn process_deposit_or_withdraw<const IS_DEPOSIT: bool>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // withdraw stays open so sotoken minted before restriction can still be redeemed
+    if IS_DEPOSIT {
+        market_reserve.collateral_info.assert_plain_deposit_allowed()?;
+    }
     // 5
     get_sotoken_mint!(sotoken_mint_info; account_info_iter, market_reserve);
     // 6
@@ -421,9 +756,20 @@ fn process_deposit_or_withdraw<const IS_DEPOSIT: bool>(
     let user_sotoken_account_info = next_account_info(account_info_iter)?;
     // 10
     let token_program_info = next_account_info(account_info_iter)?;
+    // 11?, deposit only
+    if IS_DEPOSIT {
+        assert_deposit_whitelisted(
+            program_id,
+            market_reserve_info.key,
+            &market_reserve,
+            account_info_iter.next(),
+            user_authority_info.key,
+            clock.slot,
+        )?;
+    }
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // deposit or withdraw
     if IS_DEPOSIT {
@@ -490,7 +836,8 @@ fn process_init_user_obligation(
     // 2
     get_clock!(clock_info, clock; account_info_iter);
     // 3
-    get_manager!(manager_info, _manager; account_info_iter, program_id);
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 4
     create_user_obligation!(user_obligation_info; account_info_iter, program_id, rent);
     // 5
@@ -513,10 +860,106 @@ fn process_refresh_user_obligation(
     // 1
     get_clock!(clock_info, clock; account_info_iter);
     // 2
-    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id);
-    let manager = user_obligation.manager;
-    // 3 + i
-    let reserves_vec = account_info_iter
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    // 4 + i
+    let reserves_vec = unpack_fresh_market_reserves(program_id, clock, manager_info.key, account_info_iter)?;
+    assert_reserves_quote(&manager, &reserves_vec)?;
+
+    // update
+    user_obligation.update_user_obligation(reserves_vec, manager.collateral_dust_value)?;
+    user_obligation.last_update.update_slot(clock.slot, false);
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)
+}
+
+// for obligations too large to refresh in one transaction, stays stale until all entries covered
+fn process_refresh_user_obligation_partial(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    // 4 + i
+    let reserves_vec = unpack_fresh_market_reserves(program_id, clock, manager_info.key, account_info_iter)?;
+    assert_reserves_quote(&manager, &reserves_vec)?;
+
+    // update
+    if let Some(slot) = user_obligation.update_user_obligation_partial(
+        reserves_vec,
+        clock.slot,
+        manager.collateral_dust_value,
+    )? {
+        user_obligation.last_update.update_slot(slot, false);
+    }
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)
+}
+
+// borrow only needs collateral reserves and the borrow reserve strictly fresh
+fn process_refresh_user_obligation_for_borrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    borrow_reserve: &Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    // 4 + i
+    let reserves_vec = unpack_market_reserves(
+        program_id,
+        manager_info.key,
+        account_info_iter,
+        |key, market_reserve| if user_obligation.needs_strict_reserve_for_borrow(key, borrow_reserve) {
+            market_reserve.last_update.is_strict_stale(clock.slot)
+        } else {
+            market_reserve.last_update.is_lax_stale(clock.slot)
+        },
+    )?;
+    assert_reserves_quote(&manager, &reserves_vec)?;
+
+    // update, every position must still be covered, but only fresh for this borrow
+    user_obligation.update_user_obligation(reserves_vec, manager.collateral_dust_value)?;
+    user_obligation.record_borrow_refresh(clock.slot, borrow_reserve);
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)
+}
+
+fn unpack_fresh_market_reserves<'a, 'b: 'a, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    program_id: &Pubkey,
+    clock: &Clock,
+    manager_key: &Pubkey,
+    account_info_iter: I,
+) -> Result<Vec<(&'a Pubkey, MarketReserve)>, ProgramError> {
+    unpack_market_reserves(
+        program_id,
+        manager_key,
+        account_info_iter,
+        |_, market_reserve| market_reserve.last_update.is_strict_stale(clock.slot),
+    )
+}
+
+fn unpack_market_reserves<'a, 'b: 'a, I, F>(
+    program_id: &Pubkey,
+    manager_key: &Pubkey,
+    account_info_iter: I,
+    is_stale: F,
+) -> Result<Vec<(&'a Pubkey, MarketReserve)>, ProgramError>
+where
+    I: Iterator<Item = &'a AccountInfo<'b>>,
+    F: Fn(&Pubkey, &MarketReserve) -> Result<bool, ProgramError>,
+{
+    account_info_iter
         .map(|market_reserve_info| {
             if market_reserve_info.owner != program_id {
                 msg!("Market reserve provided is not owned by the lending program");
@@ -524,20 +967,54 @@ fn process_refresh_user_obligation(
             }
 
             let market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
-            if market_reserve.manager != manager {
+            if &market_reserve.manager != manager_key {
                 msg!("User obligation manager provided is matched with market reserve provided");
                 return Err(LendingError::UnmatchedAccounts.into());
             }
-            if market_reserve.last_update.is_strict_stale(clock.slot)? {
+            if is_stale(market_reserve_info.key, &market_reserve)? {
                 Err(LendingError::MarketReserveStale.into())
             } else {
                 Ok((market_reserve_info.key, market_reserve))
             }
         })
-        .collect::<Result<Vec<_>, ProgramError>>()?;
+        .collect()
+}
+
+// reserves are refreshed before obligation, so obligation always uses fresh prices
+fn process_refresh_all(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // last
+    let (user_obligation_info, accounts) = accounts
+        .split_last()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3 + i * n
+    let mut reserves_vec = Vec::new();
+    while let Some(market_reserve_info) = account_info_iter.next() {
+        let market_reserve = refresh_market_reserve(program_id, clock, market_reserve_info, account_info_iter)?;
+        reserves_vec.push((market_reserve_info.key, market_reserve));
+    }
+    assert_reserves_quote(&manager, &reserves_vec)?;
+
+    if user_obligation_info.owner != program_id {
+        msg!("User obliagtion provided is not owned by the lending program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    let mut user_obligation = UserObligation::unpack(&user_obligation_info.try_borrow_data()?)?;
+    if &user_obligation.manager != manager_info.key {
+        msg!("Manager of user obligation is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
 
     // update
-    user_obligation.update_user_obligation(reserves_vec)?;
+    user_obligation.update_user_obligation(reserves_vec, manager.collateral_dust_value)?;
     user_obligation.last_update.update_slot(clock.slot, false);
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)
@@ -557,14 +1034,15 @@ fn process_bind_friend(
     get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
     // 4
     get_user_obligation_owner!(friend_authority_info; account_info_iter, friend_obligation);
-
-    if user_obligation_info.key == friend_obligation_info.key {
-        msg!("User obligation provided is not matched with friend obligation provided");
-        return Err(LendingError::ObligationInvalidFriend.into())
+    // 5
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    if manager_info.key != &user_obligation.manager {
+        msg!("Manager of user obligation is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
     }
 
-    user_obligation.bind_friend(*friend_obligation_info.key)?;
-    friend_obligation.bind_friend(*user_obligation_info.key)?;
+    user_obligation.bind_friend(user_obligation_info.key, &mut friend_obligation, friend_obligation_info.key)?;
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     UserObligation::pack(friend_obligation, &mut friend_obligation_info.try_borrow_mut_data()?)
@@ -590,6 +1068,13 @@ fn process_unbind_friend(
     get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
     // 5
     get_user_obligation_owner!(friend_authority_info; account_info_iter, friend_obligation);
+    // 6
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    if manager_info.key != &user_obligation.manager {
+        msg!("Manager of user obligation is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
 
     // unbind
     user_obligation.unbind_friend()?;
@@ -624,14 +1109,30 @@ fn process_pledge_collateral(
     let user_sotoken_account = Account::unpack(&user_sotoken_account_info.try_borrow_data()?)?;
     // 6
     let token_program_info = next_account_info(account_info_iter)?;
+    // 7
+    get_clock!(clock_info, clock; account_info_iter);
+    // 8
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    if manager_info.key != &market_reserve.manager {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
 
     // handle obligation
     let balance = get_available_balance(user_sotoken_account, user_authority_info.key);
     let amount = if let Ok(index) = user_obligation.find_collateral(market_reserve_info.key) {
-        user_obligation.pledge::<false>(balance, amount, index, &market_reserve)?
+        user_obligation.pledge::<false>(balance, amount, index, &market_reserve, manager.max_obligation_collateral_value)?
     } else {
-        user_obligation.new_pledge::<false>(balance, amount, *market_reserve_info.key, &market_reserve)?
+        user_obligation.new_pledge::<false>(
+            balance,
+            amount,
+            *market_reserve_info.key,
+            &market_reserve,
+            manager.max_obligation_collateral_value,
+        )?
     };
+    user_obligation.record_pledge(market_reserve_info.key, clock.slot);
     user_obligation.last_update.mark_stale();
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
@@ -668,38 +1169,79 @@ fn process_deposit_and_pledge(
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, &market_reserve.manager);
     // 5
     get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
-    // 6
+    // 6, user authority itself for native sol
     let user_token_account_info = next_account_info(account_info_iter)?;
-    let user_token_account = Account::unpack(&user_token_account_info.try_borrow_data()?)?;
+    let is_native = user_token_account_info.key == user_authority_info.key &&
+        market_reserve.token_config.mint_pubkey == native_mint::id();
+    let balance = if is_native {
+        // max amount would drain the wallet below rent exemption
+        if amount.is_none() {
+            msg!("Native sol deposit requires an explicit amount");
+            return Err(LendingError::InvalidAmount.into());
+        }
+        user_authority_info.lamports()
+    } else {
+        let user_token_account = Account::unpack(&user_token_account_info.try_borrow_data()?)?;
+        get_available_balance(user_token_account, user_authority_info.key)
+    };
     // 7
     let token_program_info = next_account_info(account_info_iter)?;
+    // 8
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    if manager_info.key != &market_reserve.manager {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    manager.assert_not_paused()?;
+    // 9?
+    assert_deposit_whitelisted(
+        program_id,
+        market_reserve_info.key,
+        &market_reserve,
+        account_info_iter.next(),
+        user_authority_info.key,
+        clock.slot,
+    )?;
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // deposit in reserve
-    let amount = calculate_amount(amount, get_available_balance(user_token_account, user_authority_info.key));
+    let amount = calculate_amount(amount, balance);
     let mint_amount = market_reserve.deposit(amount)?;
     // pledge in obligation
     let _ = if let Ok(index) = user_obligation.find_collateral(market_reserve_info.key) {
-        user_obligation.pledge::<false>(mint_amount, None, index, &market_reserve)?
+        user_obligation.pledge::<false>(mint_amount, None, index, &market_reserve, manager.max_obligation_collateral_value)?
     } else {
-        user_obligation.new_pledge::<false>(mint_amount, None, *market_reserve_info.key, &market_reserve)?
+        user_obligation.new_pledge::<false>(
+            mint_amount,
+            None,
+            *market_reserve_info.key,
+            &market_reserve,
+            manager.max_obligation_collateral_value,
+        )?
     };
+    user_obligation.record_pledge(market_reserve_info.key, clock.slot);
     user_obligation.last_update.mark_stale();
     // pack
     MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
 
-    // transfer token to manager
-    process_token_transfer(
-        token_program_info,
-        user_token_account_info,
-        supply_token_account_info,
-        user_authority_info,
-        amount,
-        &[],
-    )
+    if is_native {
+        // wrap into supply account
+        process_transfer(user_authority_info, supply_token_account_info, amount, &[])?;
+        process_token_sync_native(token_program_info, supply_token_account_info)
+    } else {
+        // transfer token to manager
+        process_token_transfer(
+            token_program_info,
+            user_token_account_info,
+            supply_token_account_info,
+            user_authority_info,
+            amount,
+            &[],
+        )
+    }
 }
 
 // must after update obligation
@@ -718,10 +1260,12 @@ fn process_redeem_collateral(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    market_reserve.collateral_info.assert_unrestricted()?;
     // 5
     get_sotoken_mint!(sotoken_mint_info; account_info_iter, market_reserve);
     // 6
@@ -769,6 +1313,7 @@ fn process_redeem_and_withdraw<const WITH_LOAN: bool>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
@@ -794,7 +1339,7 @@ fn process_redeem_and_withdraw<const WITH_LOAN: bool>(
         user_obligation.redeem_without_loan(amount, index, friend_obligation)?
     };
     // withdraw
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     let withdraw_amount = market_reserve.withdraw(amount)?;
     // pack
@@ -825,10 +1370,12 @@ fn process_redeem_collateral_without_loan(
     let account_info_iter = &mut accounts.iter();
     // 1
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 2
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 3
     get_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    market_reserve.collateral_info.assert_unrestricted()?;
     // 4
     get_sotoken_mint!(sotoken_mint_info; account_info_iter, market_reserve);
     // 5
@@ -876,10 +1423,12 @@ fn process_replace_collateral(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_market_reserve!(out_market_reserve_info, out_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    out_market_reserve.collateral_info.assert_unrestricted()?;
     // 5
     get_sotoken_mint!(out_sotoken_mint_info; account_info_iter, out_market_reserve);
     // 6
@@ -913,7 +1462,10 @@ fn process_replace_collateral(
         &out_market_reserve,
         &in_market_reserve,
         friend_obligation,
+        manager.max_obligation_collateral_value,
     )?;
+    // replacement collateral starts cooldown like a pledge
+    user_obligation.record_pledge(in_market_reserve_info.key, clock.slot);
     user_obligation.last_update.mark_stale();
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
@@ -955,6 +1507,7 @@ fn process_borrow_liquidity(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
@@ -962,7 +1515,9 @@ fn process_borrow_liquidity(
     // 5
     get_supply_account!(supply_account_info; account_info_iter, market_reserve);
     // 6
-    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    // borrow may require fresher obligation than other actions
+    user_obligation.take_fresh_for_borrow(clock.slot, market_reserve_info.key, manager.borrow_max_obligation_age_slots)?;
     // 7
     get_friend_obligation!(friend_obligation; account_info_iter, user_obligation, clock);
     // 7/8
@@ -973,26 +1528,36 @@ fn process_borrow_liquidity(
     let token_program_info = next_account_info(account_info_iter)?;
 
     // borrow
-    let amount = if let Ok(index) = user_obligation.find_loan(market_reserve_info.key) {
-        user_obligation.borrow_in(
+    let (amount, new_loan_fee) = if let Ok(index) = user_obligation.find_loan(market_reserve_info.key) {
+        (user_obligation.borrow_in(
             amount,
             index,
             &market_reserve,
             friend_obligation,
-        )?
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            clock.slot,
+            manager.collateral_cooldown_slots,
+        )?, 0)
     } else {
         user_obligation.new_borrow_in(
             amount,
             *market_reserve_info.key,
             &market_reserve,
             friend_obligation,
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            manager.max_loans_per_obligation,
+            clock.slot,
+            manager.collateral_cooldown_slots,
         )?
     };
     user_obligation.last_update.mark_stale();
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     market_reserve.liquidity_info.borrow_out(amount)?;
+    market_reserve.liquidity_info.charge_new_loan_fee(new_loan_fee)?;
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
@@ -1008,13 +1573,18 @@ fn process_borrow_liquidity(
     )
 }
 
+// refreshes both reserves, must after update obligation
 #[inline(never)]
-fn process_repay_loan(
+fn process_deposit_pledge_borrow(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    amount: u64,
+    deposit_amount: u64,
+    borrow_amount: u64,
 ) -> ProgramResult {
-    let amount = handle_amount(amount, || {
+    let deposit_amount = handle_amount(deposit_amount, || {
+        msg!("Liquidity amount provided cannot be zero");
+    })?;
+    let borrow_amount = handle_amount(borrow_amount, || {
         msg!("Liquidity amount provided cannot be zero");
     })?;
 
@@ -1022,10 +1592,199 @@ fn process_repay_loan(
     // 1
     get_clock!(clock_info, clock; account_info_iter);
     // 2
-    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id);
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
-    get_supply_account!(supply_account_info; account_info_iter, market_reserve);
-    // 4
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4, 5, 6?
+    let collateral_market_reserve_info = next_account_info(account_info_iter)?;
+    let mut collateral_market_reserve = refresh_market_reserve(program_id, clock, collateral_market_reserve_info, account_info_iter)?;
+    if &collateral_market_reserve.manager != manager_info.key {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    collateral_market_reserve.collateral_info.assert_unrestricted()?;
+    // 6 + d, d: debt oracles provided so far
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
+    // 7 + d, 8 + d, 9 + d?
+    let loan_market_reserve_info = next_account_info(account_info_iter)?;
+    if loan_market_reserve_info.key == collateral_market_reserve_info.key {
+        msg!("Collateral market reserve and loan market reserve should be different");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    let mut loan_market_reserve = refresh_market_reserve(program_id, clock, loan_market_reserve_info, account_info_iter)?;
+    if &loan_market_reserve.manager != manager_info.key {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 9 + d
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
+    // 10 + d
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    user_obligation.take_fresh_for_borrow(clock.slot, loan_market_reserve_info.key, manager.borrow_max_obligation_age_slots)?;
+    // 11 + d?
+    get_friend_obligation!(friend_obligation; account_info_iter, user_obligation, clock);
+    // 11/12 + d
+    get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
+    // 12/13 + d
+    let user_collateral_token_account_info = next_account_info(account_info_iter)?;
+    let user_collateral_token_account = Account::unpack(&user_collateral_token_account_info.try_borrow_data()?)?;
+    let balance = get_available_balance(user_collateral_token_account, user_authority_info.key);
+    // 13/14 + d
+    let user_loan_token_account_info = next_account_info(account_info_iter)?;
+    // 14/15 + d
+    let token_program_info = next_account_info(account_info_iter)?;
+    // 15/16 + d?
+    assert_deposit_whitelisted(
+        program_id,
+        collateral_market_reserve_info.key,
+        &collateral_market_reserve,
+        account_info_iter.next(),
+        user_authority_info.key,
+        clock.slot,
+    )?;
+
+    // deposit in reserve
+    let deposit_amount = calculate_amount(deposit_amount, balance);
+    let mint_amount = collateral_market_reserve.deposit(deposit_amount)?;
+    collateral_market_reserve.last_update.update_slot(clock.slot, true);
+    // pledge in obligation with fresh price
+    let _ = if let Ok(index) = user_obligation.find_collateral(collateral_market_reserve_info.key) {
+        user_obligation.pledge::<true>(mint_amount, None, index, &collateral_market_reserve, manager.max_obligation_collateral_value)?
+    } else {
+        user_obligation.new_pledge::<true>(
+            mint_amount,
+            None,
+            *collateral_market_reserve_info.key,
+            &collateral_market_reserve,
+            manager.max_obligation_collateral_value,
+        )?
+    };
+    user_obligation.record_pledge(collateral_market_reserve_info.key, clock.slot);
+    // borrow, health validated with pledged collateral
+    let (borrow_amount, new_loan_fee) = if let Ok(index) = user_obligation.find_loan(loan_market_reserve_info.key) {
+        (user_obligation.borrow_in(
+            borrow_amount,
+            index,
+            &loan_market_reserve,
+            friend_obligation,
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            clock.slot,
+            manager.collateral_cooldown_slots,
+        )?, 0)
+    } else {
+        user_obligation.new_borrow_in(
+            borrow_amount,
+            *loan_market_reserve_info.key,
+            &loan_market_reserve,
+            friend_obligation,
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            manager.max_loans_per_obligation,
+            clock.slot,
+            manager.collateral_cooldown_slots,
+        )?
+    };
+    user_obligation.last_update.mark_stale();
+    loan_market_reserve.last_update.update_slot(clock.slot, true);
+    loan_market_reserve.liquidity_info.borrow_out(borrow_amount)?;
+    loan_market_reserve.liquidity_info.charge_new_loan_fee(new_loan_fee)?;
+    // pack
+    MarketReserve::pack(collateral_market_reserve, &mut collateral_market_reserve_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
+
+    // transfer token to manager
+    process_token_transfer(
+        token_program_info,
+        user_collateral_token_account_info,
+        collateral_supply_account_info,
+        user_authority_info,
+        deposit_amount,
+        &[],
+    )?;
+    // transfer token to user
+    process_token_transfer(
+        token_program_info,
+        loan_supply_account_info,
+        user_loan_token_account_info,
+        manager_authority_info,
+        borrow_amount,
+        signer_seeds,
+    )
+}
+
+// must after update obligation
+#[inline(never)]
+fn process_repay_from_pledged_collateral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let amount = handle_amount(amount, || {
+        msg!("Liquidity amount provided cannot be zero");
+    })?;
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    // 3
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    // 4
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
+    // 5?
+    get_friend_obligation!(friend_obligation; account_info_iter, user_obligation, clock);
+    // 5/6
+    get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
+
+    // collateral and loan in one reserve share the underlying mint
+    let collateral_index = user_obligation.find_collateral(market_reserve_info.key)?;
+    let loan_index = user_obligation.find_loan(market_reserve_info.key)?;
+    // accrue interest
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
+    market_reserve.last_update.update_slot(clock.slot, true);
+    // net off in obligation
+    user_obligation.loans[loan_index].accrue_interest(&market_reserve)?;
+    let (burn_amount, settle) = user_obligation.repay_from_pledged_collateral(
+        amount,
+        collateral_index,
+        loan_index,
+        &market_reserve,
+        friend_obligation,
+    )?;
+    user_obligation.record_repay(market_reserve_info.key, settle.amount_decimal, clock.slot, &market_reserve)?;
+    user_obligation.last_update.mark_stale();
+    // net off in reserve, liquidity never leaves supply account
+    market_reserve.collateral_info.burn(burn_amount)?;
+    market_reserve.liquidity_info.repay(&settle)?;
+    market_reserve.liquidity_info.withdraw(settle.amount)?;
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
+}
+
+#[inline(never)]
+fn process_repay_loan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let amount = handle_amount(amount, || {
+        msg!("Liquidity amount provided cannot be zero");
+    })?;
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id);
+    // 3
+    get_supply_account!(supply_account_info; account_info_iter, market_reserve);
+    // 4
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, &market_reserve.manager);
     // 5
     let user_authority_info = next_account_info(account_info_iter)?;
@@ -1034,14 +1793,22 @@ fn process_repay_loan(
     let user_balance = Account::unpack(&user_token_account_info.try_borrow_data()?)?.amount;
     // 7
     let token_program_info = next_account_info(account_info_iter)?;    
+    // 8
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    if manager_info.key != &market_reserve.manager {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // repay in obligation
     let index = user_obligation.find_loan(market_reserve_info.key)?;
     user_obligation.loans[index].accrue_interest(&market_reserve)?;
     let settle = user_obligation.repay::<false>(amount, user_balance, index, &market_reserve)?;
+    user_obligation.record_repay(market_reserve_info.key, settle.amount_decimal, clock.slot, &market_reserve)?;
     user_obligation.last_update.mark_stale();
     // repay in reserve 
     market_reserve.liquidity_info.repay(&settle)?;
@@ -1076,14 +1843,21 @@ fn process_liquidate<const IS_COLLATERAL: bool>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
-    get_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    // seized sotoken goes to liquidator and fee receiver wallets, restricted reserves liquidate directly instead
+    collateral_market_reserve.collateral_info.assert_unrestricted()?;
     // 5
     get_sotoken_mint!(sotoken_mint_info; account_info_iter, collateral_market_reserve);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    if collateral_market_reserve_info.key == loan_market_reserve_info.key {
+        msg!("Collateral market reserve and loan market reserve should be different");
+        return Err(LendingError::CollateralLoanSameReserve.into());
+    }
     // 7
     get_supply_account!(supply_token_account_info; account_info_iter, loan_market_reserve);
     // 8
@@ -1098,23 +1872,38 @@ fn process_liquidate<const IS_COLLATERAL: bool>(
     let liquidator_sotoken_account_info = next_account_info(account_info_iter)?;
     // 12/13
     let token_program_info = next_account_info(account_info_iter)?;
+    // 13/14?
+    get_liquidation_fee_receiver!(liquidation_fee_receiver_info; account_info_iter, manager, sotoken_mint_info.key);
 
     // liquidate
     let collateral_index = user_obligation.find_collateral(collateral_market_reserve_info.key)?;
     let loan_index = user_obligation.find_loan(loan_market_reserve_info.key)?;
-    let (so_token_amount, settle) = user_obligation.liquidate::<IS_COLLATERAL>(
+    let (so_token_amount, settle, shortfall) = user_obligation.liquidate_in::<IS_COLLATERAL>(
         amount,
         collateral_index,
         loan_index,
         &collateral_market_reserve,
         &loan_market_reserve,
         friend_obligation,
+        manager.emergency_liquidation,
     )?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
     user_obligation.last_update.mark_stale();
+    // protocol share of seized collateral bonus
+    let repay_equivalent = calculate_repay_equivalent_sotoken(&settle, &collateral_market_reserve, &loan_market_reserve)?;
+    let (so_token_amount, liquidation_fee) = manager.split_liquidation_fee(so_token_amount, repay_equivalent)?;
     // repay in market reserve
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     loan_market_reserve.liquidity_info.repay(&settle)?;
+    // collateral short of the bonus, insurance pays the liquidator's part of repay
+    manager.assert_liquidation_backstop(shortfall)?;
+    loan_market_reserve.liquidity_info.cover_liquidation_shortfall(shortfall)?;
+    // no receiver, protocol share goes to insurance
+    if liquidation_fee > 0 && liquidation_fee_receiver_info.is_none() {
+        collateral_market_reserve.collect_sotoken_to_insurance(liquidation_fee)?;
+        MarketReserve::pack(collateral_market_reserve, &mut collateral_market_reserve_info.try_borrow_mut_data()?)?;
+    }
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
@@ -1125,10 +1914,24 @@ fn process_liquidate<const IS_COLLATERAL: bool>(
         liquidator_token_account_info,
         supply_token_account_info,
         liquidator_authority_info,
-        settle.amount,
+        settle.amount - shortfall,
         &[],
     )?;
 
+    // mint protocol share to receiver
+    if let Some(liquidation_fee_receiver_info) = liquidation_fee_receiver_info {
+        if liquidation_fee > 0 {
+            process_token_mint_to(
+                token_program_info,
+                sotoken_mint_info,
+                liquidation_fee_receiver_info,
+                manager_authority_info,
+                liquidation_fee,
+                signer_seeds,
+            )?;
+        }
+    }
+
     // mint to user
     process_token_mint_to(
         token_program_info,
@@ -1140,6 +1943,127 @@ fn process_liquidate<const IS_COLLATERAL: bool>(
     )
 }
 
+// keeper holding loan asset settles directly into collateral asset, no sotoken minted
+fn process_liquidate_direct(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let amount = handle_amount(amount, || {
+        msg!("Liquidity amount provided cannot be zero");
+    })?;
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    // 3
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4
+    get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    // 5
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
+    // 6
+    get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    if collateral_market_reserve_info.key == loan_market_reserve_info.key {
+        msg!("Collateral market reserve and loan market reserve should be different");
+        return Err(LendingError::CollateralLoanSameReserve.into());
+    }
+    // 7
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
+    // 8
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
+    // 9
+    get_friend_obligation!(friend_obligation; account_info_iter, user_obligation, clock);
+    // 9/10
+    let liquidator_authority_info = next_account_info(account_info_iter)?;
+    // 10/11
+    let liquidator_loan_token_account_info = next_account_info(account_info_iter)?;
+    // 11/12
+    let liquidator_collateral_token_account_info = next_account_info(account_info_iter)?;
+    // 12/13
+    let token_program_info = next_account_info(account_info_iter)?;
+    // 13/14?, 14/15? protocol share minted to receiver like liquidate, restricted sotoken never leaves the reserve
+    let liquidation_fee_receiver = if manager.liquidation_fee_receiver.is_some() && !collateral_market_reserve.collateral_info.restricted {
+        get_sotoken_mint!(sotoken_mint_info; account_info_iter, collateral_market_reserve);
+        get_liquidation_fee_receiver!(liquidation_fee_receiver_info; account_info_iter, manager, sotoken_mint_info.key);
+        liquidation_fee_receiver_info.map(|receiver_info| (sotoken_mint_info, receiver_info))
+    } else {
+        None
+    };
+
+    // liquidate, same health, close factor and bonus rules as liquidate by loan
+    let collateral_index = user_obligation.find_collateral(collateral_market_reserve_info.key)?;
+    let loan_index = user_obligation.find_loan(loan_market_reserve_info.key)?;
+    let (sotoken_amount, settle, shortfall) = user_obligation.liquidate_in::<false>(
+        amount,
+        collateral_index,
+        loan_index,
+        &collateral_market_reserve,
+        &loan_market_reserve,
+        friend_obligation,
+        manager.emergency_liquidation,
+    )?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
+    user_obligation.last_update.mark_stale();
+    // protocol share of seized collateral bonus
+    let repay_equivalent = calculate_repay_equivalent_sotoken(&settle, &collateral_market_reserve, &loan_market_reserve)?;
+    let (sotoken_amount, liquidation_fee) = manager.split_liquidation_fee(sotoken_amount, repay_equivalent)?;
+    // repay in loan reserve
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
+    loan_market_reserve.last_update.update_slot(clock.slot, true);
+    loan_market_reserve.liquidity_info.repay(&settle)?;
+    manager.assert_liquidation_backstop(shortfall)?;
+    loan_market_reserve.liquidity_info.cover_liquidation_shortfall(shortfall)?;
+    // seized sotoken withdrawn at once, protocol share goes to insurance without receiver
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
+    collateral_market_reserve.last_update.update_slot(clock.slot, true);
+    let collateral_amount = collateral_market_reserve.withdraw(sotoken_amount)?;
+    if liquidation_fee > 0 && liquidation_fee_receiver.is_none() {
+        collateral_market_reserve.collect_sotoken_to_insurance(liquidation_fee)?;
+    }
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(collateral_market_reserve, &mut collateral_market_reserve_info.try_borrow_mut_data()?)?;
+
+    // transfer loan token from liquidator
+    process_token_transfer(
+        token_program_info,
+        liquidator_loan_token_account_info,
+        loan_supply_account_info,
+        liquidator_authority_info,
+        settle.amount - shortfall,
+        &[],
+    )?;
+
+    // mint protocol share to receiver
+    if let Some((sotoken_mint_info, liquidation_fee_receiver_info)) = liquidation_fee_receiver {
+        if liquidation_fee > 0 {
+            process_token_mint_to(
+                token_program_info,
+                sotoken_mint_info,
+                liquidation_fee_receiver_info,
+                manager_authority_info,
+                liquidation_fee,
+                signer_seeds,
+            )?;
+        }
+    }
+
+    // transfer collateral token to liquidator
+    process_token_transfer(
+        token_program_info,
+        collateral_supply_account_info,
+        liquidator_collateral_token_account_info,
+        manager_authority_info,
+        collateral_amount,
+        signer_seeds,
+    )
+}
+
 // must after update market reserve
 #[inline(never)]
 fn process_flash_loan(
@@ -1157,6 +2081,7 @@ fn process_flash_loan(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
@@ -1171,7 +2096,7 @@ fn process_flash_loan(
     get_receiver_program!(receiver_program_id; account_info_iter, program_id);
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // flash loan borrow calculate
     let borrow_amount = calculate_amount(amount, market_reserve.liquidity_info.available);
@@ -1219,12 +2144,13 @@ fn process_flash_loan(
 
     // check balance
     let balance_after = Account::unpack(&supply_account_info.try_borrow_data()?)?.amount;
-    if balance_after < expect_balance_after_flash_loan {
-        return Err(LendingError::FlashLoanRepayInsufficient.into());
-    }
+    let shortfall = manager.flash_loan_repay_shortfall(balance_after, expect_balance_after_flash_loan, flash_loan_fee)?;
     // check if reserve changed during flash loan
     let mut market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
     market_reserve.liquidity_info.flash_loan_repay(borrow_amount, flash_loan_fee)?;
+    if shortfall > 0 {
+        market_reserve.liquidity_info.reduce_insurance(shortfall)?;
+    }
     // pack
     MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
 }
@@ -1245,16 +2171,17 @@ fn process_flash_liquidate<const IS_COLLATERAL: bool>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 5
-    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve);
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 7
-    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve);
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
     // 8
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
     // 9
@@ -1268,27 +2195,40 @@ fn process_flash_liquidate<const IS_COLLATERAL: bool>(
 
     let collateral_index = user_obligation.find_collateral(collateral_market_reserve_info.key)?;
     let loan_index = user_obligation.find_loan(loan_market_reserve_info.key)?;
-    let (sotoken_amount, settle) = user_obligation.liquidate::<IS_COLLATERAL>(
+    let (sotoken_amount, settle, shortfall) = user_obligation.liquidate_in::<IS_COLLATERAL>(
         amount,
         collateral_index,
         loan_index,
         &collateral_market_reserve,
         &loan_market_reserve,
         friend_obligation,
+        manager.emergency_liquidation,
     )?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
     user_obligation.last_update.mark_stale();
+    // protocol share of seized collateral bonus
+    let repay_equivalent = calculate_repay_equivalent_sotoken(&settle, &collateral_market_reserve, &loan_market_reserve)?;
+    let (sotoken_amount, liquidation_fee) = manager.split_liquidation_fee(sotoken_amount, repay_equivalent)?;
     // accure interest
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     // user flash borrow repaying-loan from reserve
     let (flash_loan_total_repay, flash_loan_fee) = loan_market_reserve.liquidity_info.flash_loan_borrow_out(settle.amount)?;
     // user repay in loan reserve
     loan_market_reserve.liquidity_info.repay(&settle)?;
+    // collateral short of the bonus, insurance pays that part of flash loan repay
+    manager.assert_liquidation_backstop(shortfall)?;
+    loan_market_reserve.liquidity_info.cover_liquidation_shortfall(shortfall)?;
+    let flash_loan_total_repay = flash_loan_total_repay - shortfall;
     // user got sotoken and withdraw immediately
     // remark: token mint + token burn are all omitted here!
-    collateral_market_reserve.accrue_interest(clock.slot)?;
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
     collateral_market_reserve.last_update.update_slot(clock.slot, true);
     let collateral_amount = collateral_market_reserve.withdraw(sotoken_amount)?;
+    // sotoken is never minted in flash liquidation, so protocol share always goes to insurance
+    if liquidation_fee > 0 {
+        collateral_market_reserve.collect_sotoken_to_insurance(liquidation_fee)?;
+    }
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
@@ -1366,16 +2306,17 @@ fn process_open_leverage_position_base_in<const DEX_TYPE: DexType>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 5
-    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve);
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 7
-    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve);
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
     // 8
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
     // 9?
@@ -1502,40 +2443,75 @@ fn process_open_leverage_position_base_in<const DEX_TYPE: DexType>(
         }
         _ => unreachable!("unexpected dex type"),
     };
-    
+    // trailing, deposit whitelist?
+    assert_deposit_whitelisted(
+        program_id,
+        collateral_market_reserve_info.key,
+        &collateral_market_reserve,
+        account_info_iter.next(),
+        user_authority_info.key,
+        clock.slot,
+    )?;
+
     // deposit
     // accure interest
-    collateral_market_reserve.accrue_interest(clock.slot)?;
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
     collateral_market_reserve.last_update.update_slot(clock.slot, true);
     let mint_amount = collateral_market_reserve.deposit(collateral_amount)?;
     // pledge in obligation
     let _ = if let Ok(index) = user_obligation.find_collateral(collateral_market_reserve_info.key) {
-        user_obligation.pledge::<true>(mint_amount, None, index, &collateral_market_reserve)?
+        user_obligation.pledge::<true>(mint_amount, None, index, &collateral_market_reserve, manager.max_obligation_collateral_value)?
     } else {
-        user_obligation.new_pledge::<true>(mint_amount, None, *collateral_market_reserve_info.key, &collateral_market_reserve)?
+        user_obligation.new_pledge::<true>(
+            mint_amount,
+            None,
+            *collateral_market_reserve_info.key,
+            &collateral_market_reserve,
+            manager.max_obligation_collateral_value,
+        )?
     };
+    user_obligation.record_pledge(collateral_market_reserve_info.key, clock.slot);
     // borrow
-    let borrow_amount = if let Ok(index) = user_obligation.find_loan(loan_market_reserve_info.key) {
-        user_obligation.borrow_in(
+    let (borrow_amount, new_loan_fee) = if let Ok(index) = user_obligation.find_loan(loan_market_reserve_info.key) {
+        (user_obligation.borrow_in(
             Some(borrow_amount),
             index,
             &loan_market_reserve,
-            friend_obligation,
-        )?
+            friend_obligation.clone(),
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            clock.slot,
+            manager.collateral_cooldown_slots,
+        )?, 0)
     } else {
         user_obligation.new_borrow_in(
             Some(borrow_amount),
             *loan_market_reserve_info.key,
             &loan_market_reserve,
-            friend_obligation,
+            friend_obligation.clone(),
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            manager.max_loans_per_obligation,
+            clock.slot,
+            manager.collateral_cooldown_slots,
         )?
     };
     user_obligation.last_update.mark_stale();
+    // keep a safety margin for newly opened position
+    user_obligation.validate_leverage_open_health(friend_obligation, manager.min_leverage_open_health_bps)?;
+    user_obligation.validate_max_leverage(
+        collateral_market_reserve_info.key,
+        &collateral_market_reserve,
+        loan_market_reserve_info.key,
+        &loan_market_reserve,
+        manager.max_leverage_x,
+    )?;
     // accure interest
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     // borrow in reserve
     loan_market_reserve.liquidity_info.borrow_out(borrow_amount)?;
+    loan_market_reserve.liquidity_info.charge_new_loan_fee(new_loan_fee)?;
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
@@ -1563,16 +2539,17 @@ fn process_open_leverage_position_base_out<const DEX_TYPE: DexType>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 5
-    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve);
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 7
-    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve);
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
     // 8
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
     // 9?
@@ -1654,40 +2631,75 @@ fn process_open_leverage_position_base_out<const DEX_TYPE: DexType>(
         }
         _ => unreachable!("unexpected dex type"),
     };
-    
+    // trailing, deposit whitelist?
+    assert_deposit_whitelisted(
+        program_id,
+        collateral_market_reserve_info.key,
+        &collateral_market_reserve,
+        account_info_iter.next(),
+        user_authority_info.key,
+        clock.slot,
+    )?;
+
     // accure interest
-    collateral_market_reserve.accrue_interest(clock.slot)?;
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
     collateral_market_reserve.last_update.update_slot(clock.slot, true);
     // deposit
     let mint_amount = collateral_market_reserve.deposit(collateral_amount)?;
     // pledge in obligation
     let _ = if let Ok(index) = user_obligation.find_collateral(collateral_market_reserve_info.key) {
-        user_obligation.pledge::<true>(mint_amount, None, index, &collateral_market_reserve)?
+        user_obligation.pledge::<true>(mint_amount, None, index, &collateral_market_reserve, manager.max_obligation_collateral_value)?
     } else {
-        user_obligation.new_pledge::<true>(mint_amount, None, *collateral_market_reserve_info.key, &collateral_market_reserve)?
+        user_obligation.new_pledge::<true>(
+            mint_amount,
+            None,
+            *collateral_market_reserve_info.key,
+            &collateral_market_reserve,
+            manager.max_obligation_collateral_value,
+        )?
     };
+    user_obligation.record_pledge(collateral_market_reserve_info.key, clock.slot);
     // borrow
-    let borrow_amount = if let Ok(index) = user_obligation.find_loan(loan_market_reserve_info.key) {
-        user_obligation.borrow_in(
+    let (borrow_amount, new_loan_fee) = if let Ok(index) = user_obligation.find_loan(loan_market_reserve_info.key) {
+        (user_obligation.borrow_in(
             Some(borrow_amount),
             index,
             &loan_market_reserve,
-            friend_obligation,
-        )?
+            friend_obligation.clone(),
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            clock.slot,
+            manager.collateral_cooldown_slots,
+        )?, 0)
     } else {
         user_obligation.new_borrow_in(
             Some(borrow_amount),
             *loan_market_reserve_info.key,
             &loan_market_reserve,
-            friend_obligation,
+            friend_obligation.clone(),
+            manager.friend_borrow_value_cap,
+            manager.max_obligation_loan_value,
+            manager.max_loans_per_obligation,
+            clock.slot,
+            manager.collateral_cooldown_slots,
         )?
     };
     user_obligation.last_update.mark_stale();
+    // keep a safety margin for newly opened position
+    user_obligation.validate_leverage_open_health(friend_obligation, manager.min_leverage_open_health_bps)?;
+    user_obligation.validate_max_leverage(
+        collateral_market_reserve_info.key,
+        &collateral_market_reserve,
+        loan_market_reserve_info.key,
+        &loan_market_reserve,
+        manager.max_leverage_x,
+    )?;
     // accure interest
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     // borrow in reserve
     loan_market_reserve.liquidity_info.borrow_out(borrow_amount)?;
+    loan_market_reserve.liquidity_info.charge_new_loan_fee(new_loan_fee)?;
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
     MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
@@ -1714,16 +2726,17 @@ fn process_easy_repay_base_in<const DEX_TYPE: DexType>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 5
-    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve);
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 7
-    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve);
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
     // 8
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
     // 9?
@@ -1739,7 +2752,7 @@ fn process_easy_repay_base_in<const DEX_TYPE: DexType>(
     // redeem without remove
     let sotoken_amount = user_obligation.redeem::<false, false>(sotoken_amount, collateral_index, &collateral_market_reserve, friend_obligation.clone())?;
     // accure interest
-    collateral_market_reserve.accrue_interest(clock.slot)?;
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
     collateral_market_reserve.last_update.update_slot(clock.slot, true);
     let collateral_amount = collateral_market_reserve.withdraw(sotoken_amount)?;
 
@@ -1864,14 +2877,16 @@ fn process_easy_repay_base_in<const DEX_TYPE: DexType>(
         loan_index,
         &loan_market_reserve,
     )?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
     // accrue interest
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     // user repay in loan reserve
     loan_market_reserve.liquidity_info.repay(&settle)?;
     
     // validate health
     user_obligation.validate_health(friend_obligation)?;
+    user_obligation.validate_companion_collateral()?;
     user_obligation.last_update.mark_stale();
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
@@ -1898,16 +2913,17 @@ fn process_easy_repay_base_out<const DEX_TYPE: DexType>(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
     get_mut_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 5
-    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve);
+    get_supply_account!(collateral_supply_account_info; account_info_iter, collateral_market_reserve, manager_authority_info.key);
     // 6
     get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
     // 7
-    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve);
+    get_supply_account!(loan_supply_account_info; account_info_iter, loan_market_reserve, manager_authority_info.key);
     // 8
     get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key, clock);
     // 9?
@@ -1923,15 +2939,16 @@ fn process_easy_repay_base_out<const DEX_TYPE: DexType>(
     // redeem
     let max_sotoken_amount = user_obligation.redeem::<false, false>(max_sotoken_amount, collateral_index, &collateral_market_reserve, friend_obligation.clone())?;
     // accure interest
-    collateral_market_reserve.accrue_interest(clock.slot)?;
+    collateral_market_reserve.accrue_interest(collateral_market_reserve_info.key, clock)?;
     collateral_market_reserve.last_update.update_slot(clock.slot, true);
     // withdraw
     let max_collateral_amount = collateral_market_reserve.withdraw(max_sotoken_amount)?;
     // repay in obligation
     let loan_index = user_obligation.find_loan(loan_market_reserve_info.key)?;
     let settle = user_obligation.repay::<true>(repay_amount, u64::MAX, loan_index, &loan_market_reserve)?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
     // accrue interest
-    loan_market_reserve.accrue_interest(clock.slot)?;
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
     loan_market_reserve.last_update.update_slot(clock.slot, true);
     // user repay in loan reserve
     loan_market_reserve.liquidity_info.repay(&settle)?;
@@ -2006,15 +3023,30 @@ fn process_easy_repay_base_out<const DEX_TYPE: DexType>(
     };
 
     if max_collateral_amount > collateral_amount {
-        // deposit back to collateral reserve
+        // deposit back to collateral reserve, trailing deposit whitelist?
+        assert_deposit_whitelisted(
+            program_id,
+            collateral_market_reserve_info.key,
+            &collateral_market_reserve,
+            account_info_iter.next(),
+            user_authority_info.key,
+            clock.slot,
+        )?;
         let mint_amount = collateral_market_reserve.deposit(max_collateral_amount - collateral_amount)?;
-        user_obligation.pledge::<true>(mint_amount, None, collateral_index, &collateral_market_reserve)?;
+        user_obligation.pledge::<true>(
+            mint_amount,
+            None,
+            collateral_index,
+            &collateral_market_reserve,
+            manager.max_obligation_collateral_value,
+        )?;
     } else {
         user_obligation.close_empty_collateral(collateral_index);
     }
     
     // validate health
     user_obligation.validate_health(friend_obligation)?;
+    user_obligation.validate_companion_collateral()?;
     user_obligation.last_update.mark_stale();
     // pack
     UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
@@ -2022,27 +3054,238 @@ fn process_easy_repay_base_out<const DEX_TYPE: DexType>(
     MarketReserve::pack(collateral_market_reserve, &mut collateral_market_reserve_info.try_borrow_mut_data()?)
 }
 
-// by manager
-#[cfg(feature = "unique-credit")]
-fn process_init_unique_credit(
+#[inline(never)]
+fn process_repay_all_and_redeem_all(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    credit_authority: Pubkey,
-    borrow_limit: u64,
+    close_obligation: bool,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     // 1
-    get_rent!(rent_info, rent; account_info_iter);
+    get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
-    get_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    get_market_reserve!(collateral_market_reserve_info, collateral_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    collateral_market_reserve.collateral_info.assert_unrestricted()?;
     // 5
-    create_unique_credit!(unique_credit_info; account_info_iter, program_id, rent);
+    get_sotoken_mint!(sotoken_mint_info; account_info_iter, collateral_market_reserve);
     // 6
-    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    get_mut_market_reserve!(loan_market_reserve_info, loan_market_reserve; account_info_iter, program_id, manager_info.key, clock);
+    // 7
+    get_supply_account!(supply_token_account_info; account_info_iter, loan_market_reserve);
+    // 8
+    get_mut_user_obligation!(user_obligation_info, user_obligation; account_info_iter, program_id, manager_info.key);
+    // 9?
+    get_friend_obligation!(friend_obligation; account_info_iter, user_obligation);
+    // 9/10
+    get_user_obligation_owner!(user_authority_info; account_info_iter, user_obligation);
+    // 10/11
+    let user_token_account_info = next_account_info(account_info_iter)?;
+    let user_balance = Account::unpack(&user_token_account_info.try_borrow_data()?)?.amount;
+    // 11/12
+    let user_sotoken_account_info = next_account_info(account_info_iter)?;
+    // 12/13
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    #[cfg(feature = "friend")]
+    {
+        if close_obligation && user_obligation.friend.is_some() {
+            msg!("User obligation in binding cannot be closed");
+            return Err(LendingError::ObligationAlreadyBound.into());
+        }
+    }
+
+    // accrue interest
+    loan_market_reserve.accrue_interest(loan_market_reserve_info.key, clock)?;
+    loan_market_reserve.last_update.update_slot(clock.slot, true);
+    // repay all and redeem all in obligation
+    let loan_index = user_obligation.find_loan(loan_market_reserve_info.key)?;
+    let collateral_index = user_obligation.find_collateral(collateral_market_reserve_info.key)?;
+    user_obligation.loans[loan_index].accrue_interest(&loan_market_reserve)?;
+    let (settle, sotoken_amount) = user_obligation.repay_all_and_redeem_all(
+        user_balance,
+        loan_index,
+        collateral_index,
+        &loan_market_reserve,
+        friend_obligation,
+    )?;
+    user_obligation.record_repay(loan_market_reserve_info.key, settle.amount_decimal, clock.slot, &loan_market_reserve)?;
+    user_obligation.last_update.mark_stale();
+    // repay in reserve
+    loan_market_reserve.liquidity_info.repay(&settle)?;
+    // pack
+    UserObligation::pack(user_obligation, &mut user_obligation_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(loan_market_reserve, &mut loan_market_reserve_info.try_borrow_mut_data()?)?;
+
+    // transfer to manager
+    process_token_transfer(
+        token_program_info,
+        user_token_account_info,
+        supply_token_account_info,
+        user_authority_info,
+        settle.amount,
+        &[],
+    )?;
+
+    // mint to user
+    process_token_mint_to(
+        token_program_info,
+        sotoken_mint_info,
+        user_sotoken_account_info,
+        manager_authority_info,
+        sotoken_amount,
+        signer_seeds,
+    )?;
+
+    if close_obligation {
+        // 13/14
+        let rent_receiver_info = next_account_info(account_info_iter)?;
+
+        // reclaim rent and wipe obligation
+        let lamports = user_obligation_info.lamports();
+        **rent_receiver_info.lamports.borrow_mut() = rent_receiver_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(LendingError::MathOverflow)?;
+        **user_obligation_info.lamports.borrow_mut() = 0;
+        user_obligation_info.try_borrow_mut_data()?.fill(0);
+    }
+
+    Ok(())
+}
+
+fn process_claim_staker_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
+    // 3
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 5
+    get_supply_account!(supply_token_account_info; account_info_iter, market_reserve);
+    // 6
+    let staker_fee_account_info = next_account_info(account_info_iter)?;
+    if staker_fee_account_info.key != &market_reserve.liquidity_info.staker_fee_config.account {
+        msg!("Staker fee account provided is not matched with market reserve config");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 7
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    // accrue interest
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
+    market_reserve.last_update.update_slot(clock.slot, true);
+    // claim
+    let amount = market_reserve.liquidity_info.claim_staker_fee()?;
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
+    // transfer
+    process_token_transfer(
+        token_program_info,
+        supply_token_account_info,
+        staker_fee_account_info,
+        manager_authority_info,
+        amount,
+        signer_seeds,
+    )
+}
+
+// by manager
+fn process_init_median_oracle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    quorum: u8,
+    sources: Vec<OracleConfig>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_rent!(rent_info, rent; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    create_median_oracle!(median_oracle_info; account_info_iter, program_id, rent);
+    // 4
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+
+    let median_oracle = MedianOracle::new(*manager_info.key, quorum, sources);
+    median_oracle.assert_valid()?;
+
+    MedianOracle::pack(median_oracle, &mut median_oracle_info.try_borrow_mut_data()?)
+}
+
+// by manager
+fn process_init_dex_oracle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    min_quote_liquidity: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_rent!(rent_info, rent; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    create_dex_oracle!(dex_oracle_info; account_info_iter, program_id, rent);
+    // 4
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 5
+    let base_pool_info = next_account_info(account_info_iter)?;
+    // 6
+    let quote_pool_info = next_account_info(account_info_iter)?;
+    if base_pool_info.owner != &spl_token::id() || quote_pool_info.owner != &spl_token::id() {
+        msg!("Dex pool accounts provided are not owned by the token program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    // 7
+    get_market_reserve!(quote_reserve_info, quote_reserve; account_info_iter, program_id, manager_info.key);
+    if Account::unpack(&quote_pool_info.try_borrow_data()?)?.mint != quote_reserve.token_config.mint_pubkey {
+        msg!("Quote pool mint is not matched with quote reserve token mint");
+        return Err(LendingError::InvalidPriceOracle.into());
+    }
+
+    let dex_oracle = DexOracle::new(
+        *manager_info.key,
+        *base_pool_info.key,
+        *quote_pool_info.key,
+        *quote_reserve_info.key,
+        min_quote_liquidity,
+    );
+    dex_oracle.assert_valid()?;
+
+    DexOracle::pack(dex_oracle, &mut dex_oracle_info.try_borrow_mut_data()?)
+}
+
+#[cfg(feature = "unique-credit")]
+fn process_init_unique_credit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    credit_authority: Pubkey,
+    borrow_limit: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_rent!(rent_info, rent; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4
+    get_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 5
+    create_unique_credit!(unique_credit_info; account_info_iter, program_id, rent);
+    // 6
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
 
     let authority_info = next_account_info(account_info_iter)?;
     if authority_info.key != &manager.owner {
@@ -2080,6 +3323,7 @@ fn process_borrow_liquidity_by_unique_credit(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
@@ -2094,7 +3338,7 @@ fn process_borrow_liquidity_by_unique_credit(
     let token_program_info = next_account_info(account_info_iter)?;
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // borrow in credit
     unique_credit.accrue_interest(&market_reserve)?;
@@ -2133,6 +3377,7 @@ fn process_repay_loan_by_unique_credit(
     get_clock!(clock_info, clock; account_info_iter);
     // 2
     get_manager!(manager_info, manager; account_info_iter, program_id);
+    manager.assert_not_paused()?;
     // 3
     get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
     // 4
@@ -2153,7 +3398,7 @@ fn process_repay_loan_by_unique_credit(
     let token_program_info = next_account_info(account_info_iter)?;
 
     // accrue interest
-    market_reserve.accrue_interest(clock.slot)?;
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
     market_reserve.last_update.update_slot(clock.slot, true);
     // repay in obligation
     unique_credit.accrue_interest(&market_reserve)?;
@@ -2213,52 +3458,102 @@ fn process_operate_market_reserve<P: Any + Param>(
     MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
 }
 
-// by manager
-#[inline(never)]
-fn process_reduce_insurance(
+// by manager, queued as pending config under manager timelock
+fn process_update_market_reserve_config<P: Any + Param>(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    amount: u64,
+    param: P,
+    input: &[u8],
 ) -> ProgramResult {
-    if amount == 0 {
-        msg!("Reduce insurance amount provided cannot be zero");
-        return Err(LendingError::InvalidAmount.into());
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 3
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 4
+    get_clock!(clock_info, clock; account_info_iter);
+
+    if manager.config_timelock_slots == 0 {
+        market_reserve.operate(param)?;
+    } else {
+        param.assert_valid()?;
+        market_reserve.set_pending_config(clock.slot, manager.config_timelock_slots, input)?;
     }
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
+}
 
+// by manager, like `process_update_market_reserve_config` with quote of a new pyth oracle checked
+fn process_update_market_reserve_oracle_config<const IS_DEBT: bool, P: Any + Param>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    param: P,
+    oracle_config: Option<OracleConfig>,
+    input: &[u8],
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     // 1
     get_manager!(manager_info, manager; account_info_iter, program_id);
     // 2
-    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
-    // 3
     get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
-    // 4
-    get_supply_account!(supply_token_account_info; account_info_iter, market_reserve);
-    // 5
+    // 3
     get_manager_owner!(manager_owner_info; account_info_iter, manager);
-    // 6
-    let receiver_token_account_info = next_account_info(account_info_iter)?;
-    // 7
-    let token_program_info = next_account_info(account_info_iter)?;
+    // 4
+    get_clock!(clock_info, clock; account_info_iter);
+    // 5?, pyth product account, only if manager sets quote currency
+    if let Some(oracle_config) = oracle_config {
+        assert_oracle_quote(&manager, &oracle_config, account_info_iter.next())?;
+    }
 
-    // reduce insurance
-    market_reserve.liquidity_info.reduce_insurance(amount)?;
+    if !IS_DEBT {
+        market_reserve.oracle_info.quote_currency = manager.quote_currency;
+    }
+    if manager.config_timelock_slots == 0 {
+        market_reserve.operate(param)?;
+    } else {
+        param.assert_valid()?;
+        market_reserve.set_pending_config(clock.slot, manager.config_timelock_slots, input)?;
+    }
     // pack
-    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
-    // transfer
-    process_token_transfer(
-        token_program_info,
-        supply_token_account_info,
-        receiver_token_account_info,
-        manager_authority_info,
-        amount,
-        signer_seeds,
-    )
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
 }
 
-fn process_change_manager_owner(
+fn process_apply_pending_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id);
+
+    let data = market_reserve.take_pending_config(clock.slot)?;
+    match LendingInstruction::unpack(&data)? {
+        LendingInstruction::UpdateMarketReserveRateModel(model) => market_reserve.operate(model)?,
+        LendingInstruction::UpdateMarketReserveCollateralConfig(config) => market_reserve.operate(config)?,
+        LendingInstruction::UpdateMarketReserveLiquidityConfig(config) => market_reserve.operate(config)?,
+        LendingInstruction::UpdateMarketReserveOracleConfig(config) => market_reserve.operate(config)?,
+        LendingInstruction::UpdateMarketReserveDebtOracleConfig(config) => market_reserve.operate(DebtOracleConfig(config))?,
+        LendingInstruction::UpdateMarketReserveOracleParams(params) => market_reserve.operate(params)?,
+        LendingInstruction::UpdateMarketReserveStakerFeeConfig(config) => market_reserve.operate(config)?,
+        LendingInstruction::UpdateMarketReserveSotokenRestriction(restricted) => market_reserve.operate(SotokenRestriction(restricted))?,
+        LendingInstruction::UpdateMarketReserveLiquidityStatusBands(bands) => market_reserve.operate(bands)?,
+        LendingInstruction::UpdateMarketReserveLiquidationBackstopBudget(budget) => market_reserve.operate(LiquidationBackstopBudget(budget))?,
+        LendingInstruction::ControlMarketReserveLiquidity(enable) => market_reserve.operate(LiquidityControl(enable))?,
+        _ => return Err(LendingError::InstructionUnpackError.into()),
+    }
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
+}
+
+// by manager, queued as pending config under manager timelock
+fn process_update_manager_config(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+    input: &[u8],
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     // 1
@@ -2266,59 +3561,652 @@ fn process_change_manager_owner(
     // 2
     get_manager_owner!(manager_owner_info; account_info_iter, manager);
     // 3
-    get_signer!(new_owner_info; account_info_iter);
+    get_clock!(clock_info, clock; account_info_iter);
 
-    manager.owner = *new_owner_info.key;
+    let instruction = LendingInstruction::unpack(input)?;
+    if manager.config_timelock_slots == 0 {
+        apply_manager_config(&mut manager, instruction)?;
+    } else {
+        // invalid config is rejected when queued rather than when applied
+        apply_manager_config(&mut manager.clone(), instruction)?;
+        manager.set_pending_config(clock.slot, input)?;
+    }
+    // pack
     Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
 }
 
-// by manager
-#[cfg(feature = "unique-credit")]
-fn process_update_unique_credit_limit(
+fn process_apply_manager_pending_config(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    amount: u64,
 ) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     // 1
-    get_manager!(manager_info, manager; account_info_iter, program_id);
+    get_clock!(clock_info, clock; account_info_iter);
     // 2
-    get_unique_credit!(unique_credit_info, unique_credit; account_info_iter, program_id, manager_info.key);
-    // 3
-    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    get_mut_manager!(manager_info, manager; account_info_iter, program_id);
 
-    // update borrow limit
-    unique_credit.borrow_limit = amount;
-    UniqueCredit::pack(unique_credit, &mut unique_credit_info.try_borrow_mut_data()?)
+    let data = manager.take_pending_config(clock.slot)?;
+    apply_manager_config(&mut manager, LendingInstruction::unpack(&data)?)?;
+    // pack
+    Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
 }
 
-#[inline(always)]
-fn get_available_balance(account: Account, authority_key: &Pubkey) -> u64 {
-    if let COption::Some(ref delegate) = account.delegate {
-        if delegate == authority_key {
-            return account.amount.min(account.delegated_amount);
+fn apply_manager_config(manager: &mut Manager, instruction: LendingInstruction) -> ProgramResult {
+    match instruction {
+        LendingInstruction::UpdateManagerMinLeverageOpenHealth(bps) => manager.min_leverage_open_health_bps = bps,
+        LendingInstruction::UpdateManagerLiquidationFee(ratio, receiver) => manager.set_liquidation_fee(ratio, receiver.into())?,
+        LendingInstruction::UpdateManagerFriendBorrowValueCap(cap) => manager.friend_borrow_value_cap = cap,
+        LendingInstruction::UpdateManagerBorrowMaxObligationAge(max_age_slots) => manager.borrow_max_obligation_age_slots = max_age_slots,
+        LendingInstruction::UpdateManagerCollateralDustValue(dust_value) => manager.collateral_dust_value = dust_value,
+        LendingInstruction::UpdateManagerFlashLoanRepayTolerance(tolerance) => manager.flash_loan_repay_tolerance = tolerance,
+        LendingInstruction::SetEmergencyLiquidation(enable) => manager.emergency_liquidation = enable,
+        LendingInstruction::UpdateManagerMaxLeverage(max_leverage_x) => manager.max_leverage_x = max_leverage_x,
+        LendingInstruction::UpdateManagerLiquidationBackstopCap(cap) => manager.liquidation_backstop_cap = cap,
+        LendingInstruction::UpdateManagerKeeperTip(tip_value, min_gap_slots) => manager.set_keeper_tip(tip_value, min_gap_slots)?,
+        LendingInstruction::UpdateManagerMaxLoansPerObligation(max_loans) => manager.max_loans_per_obligation = max_loans,
+        LendingInstruction::UpdateManagerObligationExposureCap(max_collateral_value, max_loan_value) => {
+            manager.max_obligation_collateral_value = max_collateral_value;
+            manager.max_obligation_loan_value = max_loan_value;
         }
+        LendingInstruction::UpdateManagerQuoteCurrency(quote_currency) => manager.quote_currency = quote_currency,
+        LendingInstruction::UpdateManagerCollateralCooldown(cooldown_slots) => manager.collateral_cooldown_slots = cooldown_slots,
+        _ => return Err(LendingError::InstructionUnpackError.into()),
     }
 
-    if &account.owner == authority_key {
-        account.amount
-    } else {
-        0
-    }
+    Ok(())
 }
 
-#[inline(always)]
-fn get_token_decimals(account_info: &AccountInfo) -> Result<u8, ProgramError> {
-    if account_info.key == &native_mint::id() {
-        Ok(native_mint::DECIMALS)
-    } else {
-        Ok(Mint::unpack(&account_info.try_borrow_data()?)?.decimals)
+// under manager timelock an insurance move is queued by its first call and executed by the same call once effective,
+// returns whether to move now
+fn queue_insurance_move(
+    manager: &Manager,
+    market_reserve: &mut MarketReserve,
+    clock: &Clock,
+    input: &[u8],
+    destination: &Pubkey,
+) -> Result<bool, ProgramError> {
+    if manager.config_timelock_slots == 0 {
+        return Ok(true);
     }
-}
 
-struct FlashLoanData {
-    tag: u8,
-    flash_loan_total_repay: u64,
+    let queued = [input, destination.as_ref()].concat();
+    if PendingConfig::is_queued(&market_reserve.pending_config, &queued) {
+        market_reserve.take_pending_config(clock.slot)?;
+        Ok(true)
+    } else {
+        market_reserve.set_pending_config(clock.slot, manager.config_timelock_slots, &queued)?;
+        Ok(false)
+    }
+}
+
+fn process_get_reserve_params(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id);
+
+    let mut data = [0; ReserveParams::LEN];
+    ReserveParams::pack(ReserveParams::from(&market_reserve), &mut data)?;
+    set_return_data(&data);
+
+    Ok(())
+}
+
+// by manager
+fn process_batch_operate_market_reserve<P: Any + Param + Copy>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    param: P,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    if manager.config_timelock_slots > 0 {
+        msg!("Batch update is unavailable under config timelock, update market reserves one by one");
+        return Err(LendingError::ConfigTimelockNotElapsed.into());
+    }
+    // 2
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 3 + i
+    let market_reserves = account_info_iter
+        .map(|market_reserve_info| {
+            if market_reserve_info.owner != program_id {
+                msg!("Market reserve provided is not owned by the lending program");
+                return Err(LendingError::InvalidAccountOwner.into());
+            }
+
+            let mut market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
+            if &market_reserve.manager != manager_info.key {
+                msg!("Manager of market reserve is not matched with manager provided");
+                return Err(LendingError::UnmatchedAccounts.into());
+            }
+            market_reserve.operate(param)?;
+
+            Ok((market_reserve_info, market_reserve))
+        })
+        .collect::<Result<Vec<_>, ProgramError>>()?;
+
+    // pack only after all reserves are operated
+    market_reserves
+        .into_iter()
+        .try_for_each(|(market_reserve_info, market_reserve)| {
+            MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
+        })
+}
+
+// by manager
+#[inline(never)]
+fn process_reduce_insurance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    input: &[u8],
+) -> ProgramResult {
+    if amount == 0 {
+        msg!("Reduce insurance amount provided cannot be zero");
+        return Err(LendingError::InvalidAmount.into());
+    }
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 3
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 4
+    get_supply_account!(supply_token_account_info; account_info_iter, market_reserve);
+    // 5
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 6
+    let receiver_token_account_info = next_account_info(account_info_iter)?;
+    // 7
+    let token_program_info = next_account_info(account_info_iter)?;
+    // 8
+    get_clock!(clock_info, clock; account_info_iter);
+
+    if !queue_insurance_move(&manager, &mut market_reserve, clock, input, receiver_token_account_info.key)? {
+        return MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?);
+    }
+    // reduce insurance
+    market_reserve.liquidity_info.reduce_insurance(amount)?;
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
+    // transfer
+    process_token_transfer(
+        token_program_info,
+        supply_token_account_info,
+        receiver_token_account_info,
+        manager_authority_info,
+        amount,
+        signer_seeds,
+    )
+}
+
+fn process_transfer_insurance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    input: &[u8],
+) -> ProgramResult {
+    if amount == 0 {
+        msg!("Transfer insurance amount provided cannot be zero");
+        return Err(LendingError::InvalidAmount.into());
+    }
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 3
+    get_mut_market_reserve!(from_market_reserve_info, from_market_reserve; account_info_iter, program_id, manager_info.key);
+    // 4
+    get_supply_account!(from_supply_token_account_info; account_info_iter, from_market_reserve);
+    // 5
+    get_mut_market_reserve!(to_market_reserve_info, to_market_reserve; account_info_iter, program_id, manager_info.key);
+    if from_market_reserve_info.key == to_market_reserve_info.key ||
+        from_market_reserve.token_config.mint_pubkey != to_market_reserve.token_config.mint_pubkey {
+        msg!("Insurance can only be transferred between different reserves of the same token");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 6
+    get_supply_account!(to_supply_token_account_info; account_info_iter, to_market_reserve);
+    // 7
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 8
+    let token_program_info = next_account_info(account_info_iter)?;
+    // 9
+    get_clock!(clock_info, clock; account_info_iter);
+
+    if !queue_insurance_move(&manager, &mut from_market_reserve, clock, input, to_market_reserve_info.key)? {
+        return MarketReserve::pack(from_market_reserve, &mut from_market_reserve_info.try_borrow_mut_data()?);
+    }
+    // move insurance, borrowed-out liquidity stays
+    from_market_reserve.liquidity_info.transfer_insurance(&mut to_market_reserve.liquidity_info, amount)?;
+    // pack
+    MarketReserve::pack(from_market_reserve, &mut from_market_reserve_info.try_borrow_mut_data()?)?;
+    MarketReserve::pack(to_market_reserve, &mut to_market_reserve_info.try_borrow_mut_data()?)?;
+    // transfer
+    process_token_transfer(
+        token_program_info,
+        from_supply_token_account_info,
+        to_supply_token_account_info,
+        manager_authority_info,
+        amount,
+        signer_seeds,
+    )
+}
+
+fn process_change_manager_owner(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_mut_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 3
+    get_signer!(new_owner_info; account_info_iter);
+
+    manager.owner = *new_owner_info.key;
+    Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
+}
+
+#[inline(never)]
+fn process_bootstrap_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    if amount == 0 {
+        msg!("Bootstrap amount provided cannot be zero");
+        return Err(LendingError::InvalidAmount.into());
+    }
+
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_clock!(clock_info, clock; account_info_iter);
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 4
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 5
+    get_sotoken_mint!(sotoken_mint_info; account_info_iter, market_reserve);
+    // 6
+    get_supply_account!(supply_token_account_info; account_info_iter, market_reserve);
+    // 7
+    let locked_sotoken_account_info = next_account_info(account_info_iter)?;
+    let locked_sotoken_account = Account::unpack(&locked_sotoken_account_info.try_borrow_data()?)?;
+    if &locked_sotoken_account.owner != manager_authority_info.key {
+        msg!("Locked sotoken account is not owned by manager authority");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 8
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 9
+    let owner_token_account_info = next_account_info(account_info_iter)?;
+    // 10
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    // accrue interest
+    market_reserve.accrue_interest(market_reserve_info.key, clock)?;
+    market_reserve.last_update.update_slot(clock.slot, true);
+    // seed liquidity
+    let mint_amount = market_reserve.bootstrap(amount)?;
+    // pack
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
+
+    // transfer from owner to manager
+    process_token_transfer(
+        token_program_info,
+        owner_token_account_info,
+        supply_token_account_info,
+        manager_owner_info,
+        amount,
+        &[],
+    )?;
+
+    // mint to manager authority, locked since sweep refuses sotoken
+    process_token_mint_to(
+        token_program_info,
+        sotoken_mint_info,
+        locked_sotoken_account_info,
+        manager_authority_info,
+        mint_amount,
+        signer_seeds,
+    )
+}
+
+fn process_init_deposit_whitelist(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    until_slot: Slot,
+    depositors: Vec<Pubkey>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    let rent_info = next_account_info(account_info_iter)?;
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    get_mut_market_reserve!(market_reserve_info, market_reserve; account_info_iter, program_id, manager_info.key);
+    // 4
+    let deposit_whitelist_info = next_account_info(account_info_iter)?;
+    let (deposit_whitelist_key, bump_seed) = DepositWhitelist::find_address(market_reserve_info.key, program_id);
+    if deposit_whitelist_info.key != &deposit_whitelist_key {
+        msg!("Deposit whitelist account is not derived from market reserve");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // 5
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 6
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    let deposit_whitelist = DepositWhitelist::new(*market_reserve_info.key, until_slot, depositors);
+    deposit_whitelist.assert_valid()?;
+
+    process_optimal_create_account(
+        rent_info,
+        deposit_whitelist_info,
+        manager_owner_info,
+        system_program_info,
+        program_id,
+        DepositWhitelist::LEN,
+        &[],
+        &[market_reserve_info.key.as_ref(), DEPOSIT_WHITELIST_SEED, &[bump_seed]],
+    )?;
+    assert_uninitialized::<DepositWhitelist>(deposit_whitelist_info)?;
+
+    // deposits without the whitelist account are rejected until then
+    market_reserve.liquidity_info.deposit_whitelist_until_slot = until_slot;
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)?;
+    DepositWhitelist::pack(deposit_whitelist, &mut deposit_whitelist_info.try_borrow_mut_data()?)
+}
+
+// by manager
+fn process_extend_market_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    let rent_info = next_account_info(account_info_iter)?;
+    // 2
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 3
+    let market_reserve_info = next_account_info(account_info_iter)?;
+    if market_reserve_info.owner != program_id {
+        msg!("Market reserve provided is not owned by the lending program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    if market_reserve_info.data_len() != MARKET_RESERVE_LEGACY_LEN {
+        msg!("Market reserve provided has been extended already");
+        return Err(LendingError::AlreadyInitialized.into());
+    }
+    // 4
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 5
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    process_optimal_realloc(rent_info, market_reserve_info, manager_owner_info, system_program_info, MarketReserve::LEN)?;
+    // legacy layout is a prefix of the extended one, appended fields start zeroed
+    let mut market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data()?)?;
+    if &market_reserve.manager != manager_info.key {
+        msg!("Manager of market reserve is not matched with manager provided");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // no liquidation backstop budget until manager sets one
+    market_reserve.liquidity_info.liquidation_backstop_budget = u64::MAX;
+
+    MarketReserve::pack(market_reserve, &mut market_reserve_info.try_borrow_mut_data()?)
+}
+
+fn process_extend_manager(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    let rent_info = next_account_info(account_info_iter)?;
+    // 2
+    let manager_info = next_account_info(account_info_iter)?;
+    if manager_info.owner != program_id {
+        msg!("Manager provided is not owned by the lending program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    if manager_info.data_len() != MANAGER_LEGACY_LEN {
+        msg!("Manager provided has been extended already");
+        return Err(LendingError::AlreadyInitialized.into());
+    }
+    // 3
+    get_signer!(manager_owner_info; account_info_iter);
+    // 4
+    let system_program_info = next_account_info(account_info_iter)?;
+
+    process_optimal_realloc(rent_info, manager_info, manager_owner_info, system_program_info, Manager::LEN)?;
+    // legacy layout is a prefix of the extended one, appended fields start zeroed
+    let manager = Manager::unpack(&manager_info.try_borrow_data()?)?;
+    if manager_owner_info.key != &manager.owner {
+        msg!("Only manager owner can extend manager");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+
+    Ok(())
+}
+
+// pyth oracle must quote in manager quote currency if one is set
+fn assert_oracle_quote(
+    manager: &Manager,
+    oracle_config: &OracleConfig,
+    pyth_product_info: Option<&AccountInfo>,
+) -> ProgramResult {
+    if manager.quote_currency == [0; 4] || oracle_config.oracle_type != OracleType::Pyth {
+        return Ok(());
+    }
+
+    let pyth_product_info = pyth_product_info.ok_or_else(|| {
+        msg!("Pyth product account is required to check quote currency");
+        ProgramError::NotEnoughAccountKeys
+    })?;
+    check_oracle_quote(&manager.quote_currency, &get_pyth_quote_currency(pyth_product_info, &oracle_config.oracle)?)
+}
+
+// reserves valuing an obligation must be checked against the current manager quote currency
+fn assert_reserves_quote(manager: &Manager, reserves: &[(&Pubkey, MarketReserve)]) -> ProgramResult {
+    if manager.quote_currency != [0; 4] &&
+        reserves.iter().any(|(_, market_reserve)| market_reserve.oracle_info.quote_currency != manager.quote_currency) {
+        msg!("Market reserve oracle is not checked against manager quote currency");
+        Err(LendingError::OracleQuoteMismatch.into())
+    } else {
+        Ok(())
+    }
+}
+
+// deposits are open once the whitelist window recorded in market reserve is over,
+// the whitelist account is an optional trailing account only needed within the window
+fn assert_deposit_whitelisted(
+    program_id: &Pubkey,
+    market_reserve_key: &Pubkey,
+    market_reserve: &MarketReserve,
+    deposit_whitelist_info: Option<&AccountInfo>,
+    depositor: &Pubkey,
+    slot: Slot,
+) -> ProgramResult {
+    if slot >= market_reserve.liquidity_info.deposit_whitelist_until_slot {
+        return Ok(());
+    }
+
+    let deposit_whitelist_info = deposit_whitelist_info.ok_or_else(|| {
+        msg!("Deposit whitelist account is required until slot {}", market_reserve.liquidity_info.deposit_whitelist_until_slot);
+        LendingError::DepositNotWhitelisted
+    })?;
+    let (deposit_whitelist_key, _bump_seed) = DepositWhitelist::find_address(market_reserve_key, program_id);
+    if deposit_whitelist_info.key != &deposit_whitelist_key {
+        msg!("Deposit whitelist account is not derived from market reserve");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    if deposit_whitelist_info.owner != program_id {
+        msg!("Deposit whitelist account is not owned by the lending program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+
+    DepositWhitelist::unpack(&deposit_whitelist_info.try_borrow_data()?)?.assert_depositor(depositor, slot)
+}
+
+fn process_set_manager_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    paused: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_mut_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+
+    manager.paused = paused;
+    Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
+}
+
+fn process_set_emergency_liquidation(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    enable: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_mut_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+
+    manager.emergency_liquidation = enable;
+    Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
+}
+
+fn process_update_manager_config_timelock(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    timelock_slots: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_mut_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+
+    manager.set_config_timelock(timelock_slots)?;
+    Manager::pack(manager, &mut manager_info.try_borrow_mut_data()?)
+}
+
+// only the associated token account of manager authority can be swept,
+// supply accounts are created by init and can never be an associated token account
+fn process_sweep_stray_tokens(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_manager_authority!(manager_authority_info, signer_seeds; account_info_iter, program_id, manager, manager_info);
+    // 3
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+    // 4
+    let stray_token_account_info = next_account_info(account_info_iter)?;
+    // 5
+    let stray_token_mint_info = next_account_info(account_info_iter)?;
+    // 6
+    let receiver_token_account_info = next_account_info(account_info_iter)?;
+    // 7
+    let token_program_info = next_account_info(account_info_iter)?;
+
+    if stray_token_account_info.owner != &spl_token::id() || stray_token_mint_info.owner != &spl_token::id() {
+        msg!("Stray token account or mint is not owned by the token program");
+        return Err(LendingError::InvalidAccountOwner.into());
+    }
+    let stray_token_account = Account::unpack(&stray_token_account_info.try_borrow_data()?)?;
+    if &stray_token_account.owner != manager_authority_info.key {
+        msg!("Stray token account is not owned by manager authority");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    if stray_token_account_info.key != &associated_token_address(manager_authority_info.key, &stray_token_account.mint) {
+        msg!("Only the associated token account of manager authority can be swept");
+        return Err(LendingError::CannotSweepSupplyAccount.into());
+    }
+    if stray_token_mint_info.key != &stray_token_account.mint {
+        msg!("Stray token mint is not matched with stray token account");
+        return Err(LendingError::UnmatchedAccounts.into());
+    }
+    // sotokens (incl. locked bootstrap liquidity) are minted by manager authority
+    let stray_token_mint = Mint::unpack(&stray_token_mint_info.try_borrow_data()?)?;
+    if stray_token_mint.mint_authority == COption::Some(*manager_authority_info.key) {
+        msg!("Sotoken held by manager authority can not be swept");
+        return Err(LendingError::CannotSweepSupplyAccount.into());
+    }
+
+    process_token_transfer(
+        token_program_info,
+        stray_token_account_info,
+        receiver_token_account_info,
+        manager_authority_info,
+        stray_token_account.amount,
+        signer_seeds,
+    )
+}
+
+// by manager
+#[cfg(feature = "unique-credit")]
+fn process_update_unique_credit_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    // 1
+    get_manager!(manager_info, manager; account_info_iter, program_id);
+    // 2
+    get_unique_credit!(unique_credit_info, unique_credit; account_info_iter, program_id, manager_info.key);
+    // 3
+    get_manager_owner!(manager_owner_info; account_info_iter, manager);
+
+    // update borrow limit
+    unique_credit.borrow_limit = amount;
+    UniqueCredit::pack(unique_credit, &mut unique_credit_info.try_borrow_mut_data()?)
+}
+
+#[inline(always)]
+fn get_available_balance(account: Account, authority_key: &Pubkey) -> u64 {
+    if let COption::Some(ref delegate) = account.delegate {
+        if delegate == authority_key {
+            return account.amount.min(account.delegated_amount);
+        }
+    }
+
+    if &account.owner == authority_key {
+        account.amount
+    } else {
+        0
+    }
+}
+
+#[inline(always)]
+fn get_token_decimals(account_info: &AccountInfo) -> Result<u8, ProgramError> {
+    if account_info.key == &native_mint::id() {
+        Ok(native_mint::DECIMALS)
+    } else {
+        Ok(Mint::unpack(&account_info.try_borrow_data()?)?.decimals)
+    }
+}
+
+struct FlashLoanData {
+    tag: u8,
+    flash_loan_total_repay: u64,
 }
 
 impl Data for FlashLoanData {
@@ -2355,4 +4243,1247 @@ impl PrintProgramError for LendingError {
     {
         msg!(&self.to_string());
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::clock::Epoch;
+    use spl_token::state::AccountState;
+    use crate::oracle::OracleType;
+
+    fn new_market_reserve(manager: Pubkey) -> Vec<u8> {
+        let market_reserve = MarketReserve::new(
+            0,
+            manager,
+            TokenConfig::default(),
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig::default(),
+            Pubkey::default(),
+            CollateralConfig {
+                borrow_value_ratio: 60,
+                liquidation_value_ratio: 80,
+                liquidation_penalty_ratio: 5,
+                full_liquidation_health_bps: 0,
+                borrower_liquidation_rebate_bps: 0,
+                max_post_liquidation_health_bps: 0,
+                permissioned: false,
+                max_concentration_ratio: 0,
+                bonus_by_collateral_bps: 0,
+                bonus_by_loan_bps: 0,
+                requires_companion_collateral: false,
+            },
+            RateModel::default(),
+        );
+        let mut data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(market_reserve, &mut data).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn batch_update_market_reserve_collateral_config() {
+        let program_id = crate::id();
+        let (manager_key, owner_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (reserve_key_0, reserve_key_1) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let config = CollateralConfig {
+            borrow_value_ratio: 70,
+            liquidation_value_ratio: 85,
+            liquidation_penalty_ratio: 6,
+            full_liquidation_health_bps: 9000,
+            borrower_liquidation_rebate_bps: 0,
+            max_post_liquidation_health_bps: 0,
+            permissioned: false,
+            max_concentration_ratio: 0,
+            bonus_by_collateral_bps: 0,
+            bonus_by_loan_bps: 0,
+            requires_companion_collateral: false,
+        };
+
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(0, owner_key), &mut manager_data).unwrap();
+        let mut reserve_data_0 = new_market_reserve(manager_key);
+        let mut reserve_data_1 = new_market_reserve(manager_key);
+        let foreign_reserve_key = Pubkey::new_unique();
+        let mut foreign_reserve_data = new_market_reserve(Pubkey::new_unique());
+        let mut owner_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3, mut lamports_4) = (0, 0, 0, 0, 0);
+        let accounts = vec![
+            AccountInfo::new(&manager_key, false, false, &mut lamports_0, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&owner_key, true, false, &mut lamports_1, &mut owner_data, &owner_key, false, Epoch::default()),
+            AccountInfo::new(&reserve_key_0, false, true, &mut lamports_2, &mut reserve_data_0, &program_id, false, Epoch::default()),
+            AccountInfo::new(&reserve_key_1, false, true, &mut lamports_3, &mut reserve_data_1, &program_id, false, Epoch::default()),
+            AccountInfo::new(&foreign_reserve_key, false, true, &mut lamports_4, &mut foreign_reserve_data, &program_id, false, Epoch::default()),
+        ];
+
+        // reserve of another manager reverts the whole batch
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &accounts,
+                &LendingInstruction::BatchUpdateMarketReserveCollateralConfig(config).pack(),
+            ),
+            Err(LendingError::UnmatchedAccounts.into()),
+        );
+        for market_reserve_info in &accounts[2..4] {
+            let market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data().unwrap()).unwrap();
+            assert_ne!(market_reserve.collateral_info.config, config);
+        }
+
+        process_instruction(
+            &program_id,
+            &accounts[..4],
+            &LendingInstruction::BatchUpdateMarketReserveCollateralConfig(config).pack(),
+        ).unwrap();
+
+        for market_reserve_info in &accounts[2..4] {
+            let market_reserve = MarketReserve::unpack(&market_reserve_info.try_borrow_data().unwrap()).unwrap();
+            assert_eq!(market_reserve.collateral_info.config, config);
+        }
+    }
+
+    #[test]
+    fn sweep_stray_tokens_rejects_supply_account() {
+        let program_id = crate::id();
+        let (manager_key, owner_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+        let (mint_key, receiver_key, supply_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let stray_key = associated_token_address(&manager_authority_key, &mint_key);
+        let token_program_id = spl_token::id();
+
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(bump_seed, owner_key), &mut manager_data).unwrap();
+        let mut stray_data = vec![0; Account::LEN];
+        Account::pack(
+            Account {
+                mint: mint_key,
+                owner: manager_authority_key,
+                amount: 100,
+                state: spl_token::state::AccountState::Initialized,
+                ..Account::default()
+            },
+            &mut stray_data,
+        ).unwrap();
+        let mut supply_data = stray_data.clone();
+        let mut mint_data = vec![0; Mint::LEN];
+        Mint::pack(Mint { is_initialized: true, ..Mint::default() }, &mut mint_data).unwrap();
+        let (mut authority_data, mut owner_data, mut receiver_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0], [u8; 0]) = ([], [], [], []);
+        let (mut l0, mut l1, mut l2, mut l3, mut l4, mut l5, mut l6, mut l7) = (0, 0, 0, 0, 0, 0, 0, 0);
+        let mut accounts = vec![
+            AccountInfo::new(&manager_key, false, false, &mut l0, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut l1, &mut authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&owner_key, true, false, &mut l2, &mut owner_data, &owner_key, false, Epoch::default()),
+            AccountInfo::new(&stray_key, false, true, &mut l3, &mut stray_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&mint_key, false, false, &mut l4, &mut mint_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&receiver_key, false, true, &mut l5, &mut receiver_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut l6, &mut token_program_data, &token_program_id, true, Epoch::default()),
+        ];
+
+        // associated token account of manager authority can be swept
+        process_instruction(&program_id, &accounts, &LendingInstruction::SweepStrayTokens.pack()).unwrap();
+
+        // sotoken minted by manager authority is locked
+        let mint_info = &accounts[4];
+        Mint::pack(
+            Mint { mint_authority: COption::Some(manager_authority_key), is_initialized: true, ..Mint::default() },
+            &mut mint_info.try_borrow_mut_data().unwrap(),
+        ).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::SweepStrayTokens.pack()),
+            Err(LendingError::CannotSweepSupplyAccount.into()),
+        );
+
+        // supply account owned by manager authority is never an associated token account
+        Mint::pack(Mint { is_initialized: true, ..Mint::default() }, &mut mint_info.try_borrow_mut_data().unwrap()).unwrap();
+        accounts[3] = AccountInfo::new(&supply_key, false, true, &mut l7, &mut supply_data, &token_program_id, false, Epoch::default());
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::SweepStrayTokens.pack()),
+            Err(LendingError::CannotSweepSupplyAccount.into()),
+        );
+    }
+
+    #[test]
+    fn restricted_reserve_rejects_deposit() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key, reserve_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(bump_seed, owner_key), &mut manager_data).unwrap();
+        let mut reserve_data = new_market_reserve(manager_key);
+        let mut market_reserve = MarketReserve::unpack(&reserve_data).unwrap();
+        market_reserve.operate(SotokenRestriction(true)).unwrap();
+        MarketReserve::pack(market_reserve, &mut reserve_data).unwrap();
+        let mut manager_authority_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let accounts = vec![
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut manager_authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&reserve_key, false, true, &mut lamports_3, &mut reserve_data, &program_id, false, Epoch::default()),
+        ];
+
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::Deposit(100).pack()).unwrap_err(),
+            LendingError::UseDepositAndPledge.into(),
+        );
+    }
+
+    #[test]
+    fn deposit_and_pledge_native_sol() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let system_program_id = solana_program::system_program::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, reserve_key, supply_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (obligation_key, user_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let market_reserve = MarketReserve::new(
+            0,
+            manager_key,
+            TokenConfig { mint_pubkey: native_mint::id(), supply_account: supply_key, decimal: native_mint::DECIMALS },
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig { max_deposit: u64::MAX, ..LiquidityConfig::default() },
+            Pubkey::default(),
+            CollateralConfig {
+                borrow_value_ratio: 60,
+                liquidation_value_ratio: 80,
+                liquidation_penalty_ratio: 5,
+                full_liquidation_health_bps: 0,
+                borrower_liquidation_rebate_bps: 0,
+                max_post_liquidation_health_bps: 0,
+                permissioned: false,
+                max_concentration_ratio: 0,
+                bonus_by_collateral_bps: 0,
+                bonus_by_loan_bps: 0,
+                requires_companion_collateral: false,
+            },
+            RateModel::default(),
+        );
+        let (deposit_whitelist_key, _bump_seed) = DepositWhitelist::find_address(&reserve_key, &program_id);
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(255, Pubkey::new_unique()), &mut manager_data).unwrap();
+        let mut reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(market_reserve, &mut reserve_data).unwrap();
+        let mut supply_data = vec![0; Account::LEN];
+        let mut obligation_data = vec![0; UserObligation::LEN];
+        UserObligation::pack(UserObligation::new(0, manager_key, user_key), &mut obligation_data).unwrap();
+        let (mut user_data, mut token_program_data, mut deposit_whitelist_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let (mut lamports_4, mut lamports_5, mut lamports_6, mut lamports_7) = (5_000_000_000, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let mut manager = Manager::unpack(&manager_data).unwrap();
+        manager.paused = true;
+        Manager::pack(manager, &mut manager_data).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, false, &mut lamports_6, &mut manager_data, &program_id, false, Epoch::default());
+        let reserve_info = AccountInfo::new(&reserve_key, false, true, &mut lamports_1, &mut reserve_data, &program_id, false, Epoch::default());
+        let supply_info = AccountInfo::new(&supply_key, false, true, &mut lamports_2, &mut supply_data, &token_program_id, false, Epoch::default());
+        let obligation_info = AccountInfo::new(&obligation_key, false, true, &mut lamports_3, &mut obligation_data, &program_id, false, Epoch::default());
+        let user_info = AccountInfo::new(&user_key, true, true, &mut lamports_4, &mut user_data, &system_program_id, false, Epoch::default());
+        let token_program_info = AccountInfo::new(&token_program_id, false, false, &mut lamports_5, &mut token_program_data, &token_program_id, true, Epoch::default());
+        // never created, deposits are open
+        let deposit_whitelist_info = AccountInfo::new(&deposit_whitelist_key, false, false, &mut lamports_7, &mut deposit_whitelist_data, &system_program_id, false, Epoch::default());
+
+        let accounts = [
+            clock_info,
+            reserve_info.clone(),
+            supply_info,
+            obligation_info.clone(),
+            user_info.clone(),
+            user_info,
+            token_program_info,
+            manager_info.clone(),
+            deposit_whitelist_info,
+        ];
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositAndPledge(1_000_000_000).pack()).unwrap_err(),
+            LendingError::ManagerPaused.into(),
+        );
+
+        let mut manager = Manager::unpack(&manager_info.try_borrow_data().unwrap()).unwrap();
+        manager.paused = false;
+        Manager::pack(manager, &mut manager_info.try_borrow_mut_data().unwrap()).unwrap();
+        process_instruction(&program_id, &accounts, &LendingInstruction::DepositAndPledge(1_000_000_000).pack()).unwrap();
+
+        let market_reserve = MarketReserve::unpack(&reserve_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(market_reserve.liquidity_info.available, 1_000_000_000);
+        let user_obligation = UserObligation::unpack(&obligation_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(user_obligation.collaterals.len(), 1);
+        assert_eq!(user_obligation.collaterals[0].reserve, reserve_key);
+        assert_eq!(user_obligation.collaterals[0].amount, 1_000_000_000);
+
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositAndPledge(u64::MAX).pack()).unwrap_err(),
+            LendingError::InvalidAmount.into(),
+        );
+    }
+
+    #[test]
+    fn pending_config_applies_after_timelock() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key, reserve_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let config = CollateralConfig {
+            borrow_value_ratio: 70,
+            liquidation_value_ratio: 85,
+            liquidation_penalty_ratio: 6,
+            full_liquidation_health_bps: 9000,
+            borrower_liquidation_rebate_bps: 0,
+            max_post_liquidation_health_bps: 0,
+            permissioned: false,
+            max_concentration_ratio: 0,
+            bonus_by_collateral_bps: 0,
+            bonus_by_loan_bps: 0,
+            requires_companion_collateral: false,
+        };
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager = Manager::new(0, owner_key);
+        manager.set_config_timelock(100).unwrap();
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager, &mut manager_data).unwrap();
+        let mut reserve_data = new_market_reserve(manager_key);
+        let mut owner_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock { slot: 10, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default());
+        let reserve_info = AccountInfo::new(&reserve_key, false, true, &mut lamports_2, &mut reserve_data, &program_id, false, Epoch::default());
+        let owner_info = AccountInfo::new(&owner_key, true, false, &mut lamports_3, &mut owner_data, &owner_key, false, Epoch::default());
+
+        process_instruction(
+            &program_id,
+            &[manager_info, reserve_info.clone(), owner_info, clock_info.clone()],
+            &LendingInstruction::UpdateMarketReserveCollateralConfig(config).pack(),
+        ).unwrap();
+        let market_reserve = MarketReserve::unpack(&reserve_info.try_borrow_data().unwrap()).unwrap();
+        assert_ne!(market_reserve.collateral_info.config, config);
+        assert_eq!(market_reserve.pending_config.unwrap().effective_slot, 110);
+
+        Clock { slot: 109, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[clock_info.clone(), reserve_info.clone()],
+                &LendingInstruction::ApplyPendingConfig.pack(),
+            ).unwrap_err(),
+            LendingError::ConfigTimelockNotElapsed.into(),
+        );
+
+        Clock { slot: 110, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        process_instruction(
+            &program_id,
+            &[clock_info, reserve_info.clone()],
+            &LendingInstruction::ApplyPendingConfig.pack(),
+        ).unwrap();
+        let market_reserve = MarketReserve::unpack(&reserve_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(market_reserve.collateral_info.config, config);
+        assert_eq!(market_reserve.pending_config, None);
+    }
+
+    #[test]
+    fn transfer_insurance_queued_under_timelock() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, owner_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (from_key, to_key, supply_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::default());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager = Manager::new(bump_seed, owner_key);
+        manager.set_config_timelock(100).unwrap();
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager, &mut manager_data).unwrap();
+        let (mut from_data, mut to_data) = (new_market_reserve(manager_key), new_market_reserve(manager_key));
+        let (mut authority_data, mut supply_data, mut owner_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0], [u8; 0]) = ([], [], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let (mut lamports_4, mut lamports_5, mut lamports_6, mut lamports_7) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock { slot: 10, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default());
+        let authority_info = AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut authority_data, &program_id, false, Epoch::default());
+        let from_info = AccountInfo::new(&from_key, false, true, &mut lamports_3, &mut from_data, &program_id, false, Epoch::default());
+        let to_info = AccountInfo::new(&to_key, false, true, &mut lamports_4, &mut to_data, &program_id, false, Epoch::default());
+        let supply_info = AccountInfo::new(&supply_key, false, true, &mut lamports_5, &mut supply_data, &token_program_id, false, Epoch::default());
+        let owner_info = AccountInfo::new(&owner_key, true, false, &mut lamports_6, &mut owner_data, &owner_key, false, Epoch::default());
+        let token_program_info = AccountInfo::new(&token_program_id, false, false, &mut lamports_7, &mut token_program_data, &token_program_id, true, Epoch::default());
+        let accounts = [
+            manager_info,
+            authority_info,
+            from_info.clone(),
+            supply_info.clone(),
+            to_info.clone(),
+            supply_info,
+            owner_info,
+            token_program_info,
+            clock_info.clone(),
+        ];
+
+        // first call only queues the move
+        process_instruction(&program_id, &accounts, &LendingInstruction::TransferInsurance(50).pack()).unwrap();
+        let from_reserve = MarketReserve::unpack(&from_info.try_borrow_data().unwrap()).unwrap();
+        let pending_config = from_reserve.pending_config.unwrap();
+        assert_eq!(pending_config.effective_slot, 110);
+        assert_eq!(&pending_config.data[9..41], to_key.as_ref());
+        assert_eq!(MarketReserve::unpack(&to_info.try_borrow_data().unwrap()).unwrap().liquidity_info.insurance_wads, Decimal::zero());
+
+        Clock { slot: 109, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::TransferInsurance(50).pack()).unwrap_err(),
+            LendingError::ConfigTimelockNotElapsed.into(),
+        );
+        // queued move is no reserve config
+        Clock { slot: 110, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &[clock_info, from_info], &LendingInstruction::ApplyPendingConfig.pack()).unwrap_err(),
+            LendingError::InstructionUnpackError.into(),
+        );
+    }
+
+    #[test]
+    fn manager_pending_config_applies_after_timelock() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager = Manager::new(255, owner_key);
+        manager.set_config_timelock(100).unwrap();
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager, &mut manager_data).unwrap();
+        let mut owner_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2) = (0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock { slot: 10, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, true, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default());
+        let owner_info = AccountInfo::new(&owner_key, true, false, &mut lamports_2, &mut owner_data, &owner_key, false, Epoch::default());
+
+        // invalid config is rejected when queued
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[manager_info.clone(), owner_info.clone(), clock_info.clone()],
+                &LendingInstruction::UpdateManagerLiquidationFee(100, None).pack(),
+            ).unwrap_err(),
+            LendingError::InvalidLiquidationFeeConfig.into(),
+        );
+        process_instruction(
+            &program_id,
+            &[manager_info.clone(), owner_info.clone(), clock_info.clone()],
+            &LendingInstruction::UpdateManagerFlashLoanRepayTolerance(5).pack(),
+        ).unwrap();
+        let manager = Manager::unpack(&manager_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(manager.flash_loan_repay_tolerance, 0);
+        assert_eq!(manager.pending_config.unwrap().effective_slot, 110);
+
+        Clock { slot: 109, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[clock_info.clone(), manager_info.clone()],
+                &LendingInstruction::ApplyManagerPendingConfig.pack(),
+            ).unwrap_err(),
+            LendingError::ConfigTimelockNotElapsed.into(),
+        );
+
+        Clock { slot: 110, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        process_instruction(
+            &program_id,
+            &[clock_info.clone(), manager_info.clone()],
+            &LendingInstruction::ApplyManagerPendingConfig.pack(),
+        ).unwrap();
+        let manager = Manager::unpack(&manager_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(manager.flash_loan_repay_tolerance, 5);
+        assert_eq!(manager.pending_config, None);
+
+        // pause stays instant under timelock
+        process_instruction(
+            &program_id,
+            &[manager_info.clone(), owner_info],
+            &LendingInstruction::SetManagerPaused(true).pack(),
+        ).unwrap();
+        assert!(Manager::unpack(&manager_info.try_borrow_data().unwrap()).unwrap().paused);
+    }
+
+    #[test]
+    fn liquidate_rejects_same_collateral_and_loan_reserve() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key, reserve_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+        let sotoken_mint_key = Pubkey::default();
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(bump_seed, owner_key), &mut manager_data).unwrap();
+        let mut reserve_data = new_market_reserve(manager_key);
+        let (mut manager_authority_data, mut sotoken_mint_data): ([u8; 0], [u8; 0]) = ([], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3, mut lamports_4) = (0, 0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let reserve_info = AccountInfo::new(&reserve_key, false, true, &mut lamports_3, &mut reserve_data, &program_id, false, Epoch::default());
+        let accounts = vec![
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut manager_authority_data, &program_id, false, Epoch::default()),
+            reserve_info.clone(),
+            AccountInfo::new(&sotoken_mint_key, false, true, &mut lamports_4, &mut sotoken_mint_data, &spl_token::id(), false, Epoch::default()),
+            reserve_info,
+        ];
+
+        for instruction in [LendingInstruction::LiquidateByCollateral(100), LendingInstruction::LiquidateByLoan(100)] {
+            assert_eq!(
+                process_instruction(&program_id, &accounts, &instruction.pack()).unwrap_err(),
+                LendingError::CollateralLoanSameReserve.into(),
+            );
+        }
+    }
+
+    #[test]
+    fn restricted_reserve_rejects_liquidation_mint() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key, reserve_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(bump_seed, owner_key), &mut manager_data).unwrap();
+        let mut reserve_data = new_market_reserve(manager_key);
+        let mut market_reserve = MarketReserve::unpack(&reserve_data).unwrap();
+        market_reserve.operate(SotokenRestriction(true)).unwrap();
+        MarketReserve::pack(market_reserve, &mut reserve_data).unwrap();
+        let mut manager_authority_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let accounts = vec![
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut manager_authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&reserve_key, false, true, &mut lamports_3, &mut reserve_data, &program_id, false, Epoch::default()),
+        ];
+
+        for instruction in [LendingInstruction::LiquidateByCollateral(100), LendingInstruction::LiquidateByLoan(100)] {
+            assert_eq!(
+                process_instruction(&program_id, &accounts, &instruction.pack()).unwrap_err(),
+                LendingError::SotokenRestricted.into(),
+            );
+        }
+    }
+
+    #[test]
+    fn paused_manager_blocks_deposit_but_not_config() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, owner_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(255, owner_key), &mut manager_data).unwrap();
+        let mut owner_data: [u8; 0] = [];
+        let (mut lamports_0, mut lamports_1, mut lamports_2) = (0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, true, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default());
+        let owner_info = AccountInfo::new(&owner_key, true, false, &mut lamports_2, &mut owner_data, &owner_key, false, Epoch::default());
+
+        process_instruction(
+            &program_id,
+            &[manager_info.clone(), owner_info.clone()],
+            &LendingInstruction::SetManagerPaused(true).pack(),
+        ).unwrap();
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[clock_info.clone(), manager_info.clone()],
+                &LendingInstruction::Deposit(100).pack(),
+            ).unwrap_err(),
+            LendingError::ManagerPaused.into(),
+        );
+
+        process_instruction(
+            &program_id,
+            &[manager_info.clone(), owner_info.clone(), clock_info],
+            &LendingInstruction::UpdateManagerCollateralDustValue(10).pack(),
+        ).unwrap();
+        process_instruction(
+            &program_id,
+            &[manager_info.clone(), owner_info],
+            &LendingInstruction::SetManagerPaused(false).pack(),
+        ).unwrap();
+
+        let manager = Manager::unpack(&manager_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(manager.collateral_dust_value, 10);
+        assert!(!manager.paused);
+    }
+
+    #[test]
+    fn paused_manager_blocks_pledge() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, user_key, sotoken_mint_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (market_reserve_key, obligation_key, user_sotoken_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut market_reserve = MarketReserve::unpack(&new_market_reserve(manager_key)).unwrap();
+        market_reserve.collateral_info.sotoken_mint_pubkey = sotoken_mint_key;
+        let mut market_reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(market_reserve, &mut market_reserve_data).unwrap();
+        let mut obligation_data = vec![0; UserObligation::LEN];
+        UserObligation::pack(UserObligation::new(0, manager_key, user_key), &mut obligation_data).unwrap();
+        let mut user_sotoken_data = vec![0; Account::LEN];
+        Account::pack(
+            Account { mint: sotoken_mint_key, owner: user_key, amount: 100, state: AccountState::Initialized, ..Account::default() },
+            &mut user_sotoken_data,
+        ).unwrap();
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        manager.paused = true;
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager, &mut manager_data).unwrap();
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let (mut sotoken_mint_data, mut user_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let (mut lamports_4, mut lamports_5, mut lamports_6, mut lamports_7) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let accounts = [
+            AccountInfo::new(&market_reserve_key, false, false, &mut lamports_1, &mut market_reserve_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&sotoken_mint_key, false, true, &mut lamports_2, &mut sotoken_mint_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&obligation_key, false, true, &mut lamports_3, &mut obligation_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&user_key, true, false, &mut lamports_4, &mut user_data, &user_key, false, Epoch::default()),
+            AccountInfo::new(&user_sotoken_key, false, true, &mut lamports_5, &mut user_sotoken_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut lamports_6, &mut token_program_data, &token_program_id, true, Epoch::default()),
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_7, &mut manager_data, &program_id, false, Epoch::default()),
+        ];
+
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::PledgeCollateral(100).pack()),
+            Err(LendingError::ManagerPaused.into()),
+        );
+        let obligation = UserObligation::unpack(&accounts[2].try_borrow_data().unwrap()).unwrap();
+        assert!(obligation.collaterals.is_empty());
+    }
+
+    #[test]
+    fn replaced_collateral_starts_cooldown() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, user_key, obligation_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+        let (out_key, out_mint_key, user_out_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (in_key, in_mint_key, user_in_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        let new_reserve = |sotoken_mint: Pubkey| {
+            let mut market_reserve = MarketReserve::unpack(&new_market_reserve(manager_key)).unwrap();
+            market_reserve.collateral_info.sotoken_mint_pubkey = sotoken_mint;
+            market_reserve.oracle_info.price = Decimal::one();
+            market_reserve.last_update.update_slot(5, false);
+
+            let mut data = vec![0; MarketReserve::LEN];
+            MarketReserve::pack(market_reserve, &mut data).unwrap();
+            data
+        };
+
+        // 100 out collateral backs 10 loans
+        let mut obligation = UserObligation::new(0, manager_key, user_key);
+        obligation.collaterals.push(Collateral {
+            reserve: out_key,
+            amount: 100,
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            pledged_slot: 0,
+        });
+        let loan_key = Pubkey::new_unique();
+        obligation.loans.push(Loan {
+            reserve: loan_key,
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(10u64),
+            close_ratio: 50,
+        });
+        obligation.collaterals_borrow_value = Decimal::from(60u64);
+        obligation.collaterals_liquidation_value = Decimal::from(80u64);
+        obligation.loans_value = Decimal::from(10u64);
+        obligation.last_update.update_slot(5, false);
+        let mut obligation_data = vec![0; UserObligation::LEN];
+        UserObligation::pack(obligation, &mut obligation_data).unwrap();
+
+        let mut manager = Manager::new(bump_seed, Pubkey::new_unique());
+        manager.collateral_cooldown_slots = 1;
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager.clone(), &mut manager_data).unwrap();
+        let mut out_data = new_reserve(out_mint_key);
+        let mut in_data = new_reserve(in_mint_key);
+        let mut user_in_data = vec![0; Account::LEN];
+        Account::pack(
+            Account { mint: in_mint_key, owner: user_key, amount: 100, state: AccountState::Initialized, ..Account::default() },
+            &mut user_in_data,
+        ).unwrap();
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let (mut authority_data, mut out_mint_data, mut in_mint_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut user_data, mut user_out_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3, mut lamports_4) = (0, 0, 0, 0, 0);
+        let (mut lamports_5, mut lamports_6, mut lamports_7, mut lamports_8, mut lamports_9) = (0, 0, 0, 0, 0);
+        let (mut lamports_10, mut lamports_11) = (0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock { slot: 5, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        let accounts = [
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&out_key, false, false, &mut lamports_3, &mut out_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&out_mint_key, false, true, &mut lamports_4, &mut out_mint_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&in_key, false, false, &mut lamports_5, &mut in_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&in_mint_key, false, true, &mut lamports_6, &mut in_mint_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&obligation_key, false, true, &mut lamports_7, &mut obligation_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&user_key, true, false, &mut lamports_8, &mut user_data, &user_key, false, Epoch::default()),
+            AccountInfo::new(&user_out_key, false, true, &mut lamports_9, &mut user_out_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&user_in_key, false, true, &mut lamports_10, &mut user_in_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut lamports_11, &mut token_program_data, &token_program_id, true, Epoch::default()),
+        ];
+
+        process_instruction(&program_id, &accounts, &LendingInstruction::ReplaceCollateral(u64::MAX).pack()).unwrap();
+        let mut obligation = UserObligation::unpack(&accounts[7].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(obligation.collaterals[0].reserve, in_key);
+        assert_eq!(obligation.collaterals[0].pledged_slot, 5);
+
+        // borrowing against the replacement in the same slot is rejected
+        let mut loan_reserve = MarketReserve::unpack(&new_market_reserve(manager_key)).unwrap();
+        loan_reserve.oracle_info.price = Decimal::one();
+        loan_reserve.liquidity_info.available = 1_000;
+        obligation.last_update.update_slot(5, false);
+        assert_eq!(
+            obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 5, manager.collateral_cooldown_slots).unwrap_err(),
+            LendingError::CollateralTooFresh.into(),
+        );
+        obligation.borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 6, manager.collateral_cooldown_slots).unwrap();
+    }
+
+    #[test]
+    fn reserve_config_preflight_matches_init() {
+        let oracle_config = OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Pyth };
+        let collateral_config = CollateralConfig {
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            liquidation_penalty_ratio: 5,
+            full_liquidation_health_bps: 0,
+            borrower_liquidation_rebate_bps: 0,
+            max_post_liquidation_health_bps: 0,
+            permissioned: false,
+            max_concentration_ratio: 0,
+            bonus_by_collateral_bps: 0,
+            bonus_by_loan_bps: 0,
+            requires_companion_collateral: false,
+        };
+        let liquidity_config = LiquidityConfig {
+            close_ratio: 50,
+            borrow_tax_rate: 10,
+            flash_loan_fee_rate: 1,
+            max_deposit: u64::MAX,
+            ..LiquidityConfig::default()
+        };
+        // kink out of range
+        let rate_model = RateModel { offset: 0, optimal: 1, kink: 100, max: 2, smoothing_factor: 0 };
+
+        let preflight = assert_valid_reserve_config(&oracle_config, &collateral_config, &liquidity_config, &rate_model)
+            .unwrap_err();
+        assert_eq!(preflight, LendingError::InvalidRateModel.into());
+
+        let result = process_instruction(
+            &crate::id(),
+            &[],
+            &LendingInstruction::InitMarketReserve(oracle_config, collateral_config, liquidity_config, rate_model).pack(),
+        );
+        assert_eq!(result.unwrap_err(), preflight);
+    }
+
+    #[test]
+    fn refresh_all_matches_refresh_user_obligation() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let (manager_key, obligation_key_0, obligation_key_1) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (collateral_key, collateral_source_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (loan_key, loan_source_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let clock = Clock { slot: 100, ..Clock::default() };
+
+        // reserves priced by sotoken of fresh source reserves, not refreshed since slot 0
+        let new_reserve = |oracle: Pubkey, oracle_type: OracleType, price: u64, slot: Slot| {
+            let mut market_reserve = MarketReserve::unpack(&new_market_reserve(manager_key)).unwrap();
+            market_reserve.oracle_info.config = OracleConfig { oracle, oracle_type };
+            market_reserve.oracle_info.price = Decimal::from(price);
+            market_reserve.last_update.update_slot(slot, false);
+
+            let mut data = vec![0; MarketReserve::LEN];
+            MarketReserve::pack(market_reserve, &mut data).unwrap();
+            data
+        };
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(255, Pubkey::new_unique()), &mut manager_data).unwrap();
+        let mut collateral_data = new_reserve(collateral_source_key, OracleType::SoToken, 0, 0);
+        let mut collateral_source_data = new_reserve(Pubkey::default(), OracleType::Pyth, 2, 100);
+        let mut loan_data = {
+            let mut market_reserve = MarketReserve::unpack(&new_reserve(loan_source_key, OracleType::SoToken, 0, 0)).unwrap();
+            market_reserve.liquidity_info.borrowed_amount_wads = Decimal::from(50u64);
+            let mut data = vec![0; MarketReserve::LEN];
+            MarketReserve::pack(market_reserve, &mut data).unwrap();
+            data
+        };
+        let mut loan_source_data = new_reserve(Pubkey::default(), OracleType::Pyth, 1, 100);
+        let mut obligation = UserObligation::new(0, manager_key, Pubkey::new_unique());
+        obligation.collaterals.push(Collateral {
+            reserve: collateral_key,
+            amount: 100,
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            pledged_slot: 0,
+        });
+        obligation.loans.push(Loan {
+            reserve: loan_key,
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(50u64),
+            close_ratio: 50,
+        });
+        let mut obligation_data_0 = vec![0; UserObligation::LEN];
+        UserObligation::pack(obligation, &mut obligation_data_0).unwrap();
+        let mut obligation_data_1 = obligation_data_0.clone();
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let (mut lamports_4, mut lamports_5, mut lamports_6, mut lamports_7) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        clock.to_account_info(&mut clock_info).unwrap();
+        let manager_info = AccountInfo::new(&manager_key, false, false, &mut lamports_3, &mut manager_data, &program_id, false, Epoch::default());
+        let obligation_info_0 = AccountInfo::new(&obligation_key_0, false, true, &mut lamports_1, &mut obligation_data_0, &program_id, false, Epoch::default());
+        let obligation_info_1 = AccountInfo::new(&obligation_key_1, false, true, &mut lamports_2, &mut obligation_data_1, &program_id, false, Epoch::default());
+        let collateral_info = AccountInfo::new(&collateral_key, false, true, &mut lamports_4, &mut collateral_data, &program_id, false, Epoch::default());
+        let collateral_source_info = AccountInfo::new(&collateral_source_key, false, false, &mut lamports_5, &mut collateral_source_data, &program_id, false, Epoch::default());
+        let loan_info = AccountInfo::new(&loan_key, false, true, &mut lamports_6, &mut loan_data, &program_id, false, Epoch::default());
+        let loan_source_info = AccountInfo::new(&loan_source_key, false, false, &mut lamports_7, &mut loan_source_data, &program_id, false, Epoch::default());
+
+        // stale reserves reject plain refresh
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[clock_info.clone(), manager_info.clone(), obligation_info_0.clone(), collateral_info.clone(), loan_info.clone()],
+                &LendingInstruction::RefreshUserObligation.pack(),
+            ),
+            Err(LendingError::MarketReserveStale.into()),
+        );
+
+        // refresh all prices reserves first, so plain refresh afterwards sees the same reserves
+        process_instruction(
+            &program_id,
+            &[
+                clock_info.clone(),
+                manager_info.clone(),
+                collateral_info.clone(),
+                collateral_source_info,
+                loan_info.clone(),
+                loan_source_info,
+                obligation_info_1.clone(),
+            ],
+            &LendingInstruction::RefreshAll.pack(),
+        ).unwrap();
+        process_instruction(
+            &program_id,
+            &[clock_info, manager_info, obligation_info_0.clone(), collateral_info.clone(), loan_info.clone()],
+            &LendingInstruction::RefreshUserObligation.pack(),
+        ).unwrap();
+
+        let collateral_reserve = MarketReserve::unpack(&collateral_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(collateral_reserve.oracle_info.price, Decimal::from(2u64));
+        assert_eq!(collateral_reserve.last_update, LastUpdate { slot: 100, stale: false });
+        let loan_reserve = MarketReserve::unpack(&loan_info.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(loan_reserve.oracle_info.price, Decimal::one());
+        assert_eq!(loan_reserve.last_update, LastUpdate { slot: 100, stale: false });
+
+        let obligation_0 = UserObligation::unpack(&obligation_info_0.try_borrow_data().unwrap()).unwrap();
+        let obligation_1 = UserObligation::unpack(&obligation_info_1.try_borrow_data().unwrap()).unwrap();
+        assert_eq!(obligation_1.last_update, LastUpdate { slot: 100, stale: false });
+        assert_eq!(obligation_1.collaterals_borrow_value, Decimal::from(120u64));
+        assert_eq!(obligation_1.collaterals_liquidation_value, Decimal::from(160u64));
+        assert_eq!(obligation_1.loans_value, Decimal::from(50u64));
+        assert_eq!(obligation_0, obligation_1);
+    }
+
+    #[test]
+    fn deposit_pledge_borrow_in_one_instruction() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, user_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+        let (collateral_key, collateral_source_key, collateral_supply_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (loan_key, loan_source_key, loan_supply_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (obligation_key, user_collateral_key, user_loan_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        // reserves priced by sotoken of fresh source reserves at 1 dollar
+        let new_reserve = |supply_account: Pubkey, oracle: Pubkey, oracle_type: OracleType, available: u64| {
+            let mut market_reserve = MarketReserve::new(
+                0,
+                manager_key,
+                TokenConfig { supply_account, ..TokenConfig::default() },
+                OracleConfig { oracle, oracle_type },
+                LiquidityConfig { max_deposit: u64::MAX, ..LiquidityConfig::default() },
+                Pubkey::default(),
+                CollateralConfig {
+                    borrow_value_ratio: 60,
+                    liquidation_value_ratio: 80,
+                    liquidation_penalty_ratio: 5,
+                    full_liquidation_health_bps: 0,
+                    borrower_liquidation_rebate_bps: 0,
+                    max_post_liquidation_health_bps: 0,
+                    permissioned: false,
+                    max_concentration_ratio: 0,
+                    bonus_by_collateral_bps: 0,
+                    bonus_by_loan_bps: 0,
+                    requires_companion_collateral: false,
+                },
+                RateModel::default(),
+            );
+            market_reserve.oracle_info.price = Decimal::one();
+            market_reserve.liquidity_info.available = available;
+            market_reserve.last_update.update_slot(0, false);
+
+            let mut data = vec![0; MarketReserve::LEN];
+            MarketReserve::pack(market_reserve, &mut data).unwrap();
+            data
+        };
+        let new_token_account = |owner: Pubkey, amount: u64| {
+            let mut data = vec![0; Account::LEN];
+            Account::pack(Account { owner, amount, state: AccountState::Initialized, ..Account::default() }, &mut data).unwrap();
+            data
+        };
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        let mut manager = Manager::new(bump_seed, Pubkey::new_unique());
+        manager.collateral_cooldown_slots = 1;
+        Manager::pack(manager, &mut manager_data).unwrap();
+        let mut collateral_data = new_reserve(collateral_supply_key, collateral_source_key, OracleType::SoToken, 0);
+        let mut collateral_source_data = new_reserve(Pubkey::default(), Pubkey::default(), OracleType::Pyth, 0);
+        let mut loan_data = new_reserve(loan_supply_key, loan_source_key, OracleType::SoToken, 1_000);
+        let mut loan_source_data = new_reserve(Pubkey::default(), Pubkey::default(), OracleType::Pyth, 0);
+        let mut obligation = UserObligation::new(0, manager_key, user_key);
+        obligation.last_update.update_slot(0, false);
+        let mut obligation_data = vec![0; UserObligation::LEN];
+        UserObligation::pack(obligation, &mut obligation_data).unwrap();
+        let mut user_collateral_data = new_token_account(user_key, 1_000);
+        let mut user_loan_data = new_token_account(user_key, 0);
+        let mut supply_data_0 = new_token_account(manager_authority_key, 0);
+        let mut supply_data_1 = new_token_account(Pubkey::new_unique(), 1_000);
+        let (mut authority_data, mut user_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3, mut lamports_4) = (0, 0, 0, 0, 0);
+        let (mut lamports_5, mut lamports_6, mut lamports_7, mut lamports_8, mut lamports_9) = (0, 0, 0, 0, 0);
+        let (mut lamports_10, mut lamports_11, mut lamports_12, mut lamports_13, mut lamports_14) = (0, 0, 0, 0, 0);
+        let system_program_id = solana_program::system_program::id();
+        let (deposit_whitelist_key, _bump_seed) = DepositWhitelist::find_address(&collateral_key, &program_id);
+        let mut deposit_whitelist_data: [u8; 0] = [];
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock { slot: 1, ..Clock::default() }.to_account_info(&mut clock_info).unwrap();
+        let accounts = [
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&collateral_key, false, true, &mut lamports_3, &mut collateral_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&collateral_source_key, false, false, &mut lamports_4, &mut collateral_source_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&collateral_supply_key, false, true, &mut lamports_5, &mut supply_data_0, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&loan_key, false, true, &mut lamports_6, &mut loan_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&loan_source_key, false, false, &mut lamports_7, &mut loan_source_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&loan_supply_key, false, true, &mut lamports_8, &mut supply_data_1, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&obligation_key, false, true, &mut lamports_9, &mut obligation_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&user_key, true, false, &mut lamports_10, &mut user_data, &user_key, false, Epoch::default()),
+            AccountInfo::new(&user_collateral_key, false, true, &mut lamports_11, &mut user_collateral_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&user_loan_key, false, true, &mut lamports_12, &mut user_loan_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut lamports_13, &mut token_program_data, &token_program_id, true, Epoch::default()),
+            AccountInfo::new(&deposit_whitelist_key, false, false, &mut lamports_14, &mut deposit_whitelist_data, &system_program_id, false, Epoch::default()),
+        ];
+
+        // loan supply account owned by another authority
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositPledgeBorrow(1_000, 600).pack()),
+            Err(LendingError::InvalidTokenAccountOwner.into()),
+        );
+        accounts[8].try_borrow_mut_data().unwrap().copy_from_slice(&new_token_account(manager_authority_key, 1_000));
+
+        // whitelist account is required within the whitelist window
+        let mut collateral_reserve = MarketReserve::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        collateral_reserve.liquidity_info.deposit_whitelist_until_slot = 10;
+        MarketReserve::pack(collateral_reserve.clone(), &mut accounts[3].try_borrow_mut_data().unwrap()).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts[..14], &LendingInstruction::DepositPledgeBorrow(1_000, 600).pack()),
+            Err(LendingError::DepositNotWhitelisted.into()),
+        );
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositPledgeBorrow(1_000, 600).pack()),
+            Err(LendingError::InvalidAccountOwner.into()),
+        );
+        collateral_reserve.liquidity_info.deposit_whitelist_until_slot = 0;
+        MarketReserve::pack(collateral_reserve, &mut accounts[3].try_borrow_mut_data().unwrap()).unwrap();
+
+        // collateral pledged in this slot is under cooldown
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositPledgeBorrow(1_000, 600).pack()),
+            Err(LendingError::CollateralTooFresh.into()),
+        );
+        let mut manager = Manager::unpack(&accounts[1].try_borrow_data().unwrap()).unwrap();
+        manager.collateral_cooldown_slots = 0;
+        Manager::pack(manager, &mut accounts[1].try_borrow_mut_data().unwrap()).unwrap();
+
+        // 1_000 deposited gives 600 borrow value
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::DepositPledgeBorrow(1_000, 601).pack()),
+            Err(LendingError::ObligationNotHealthy.into()),
+        );
+        process_instruction(&program_id, &accounts, &LendingInstruction::DepositPledgeBorrow(u64::MAX, 600).pack()).unwrap();
+
+        let collateral_reserve = MarketReserve::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(collateral_reserve.liquidity_info.available, 1_000);
+        let loan_reserve = MarketReserve::unpack(&accounts[6].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(loan_reserve.liquidity_info.available, 400);
+        let obligation = UserObligation::unpack(&accounts[9].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(obligation.collaterals[0].reserve, collateral_key);
+        assert_eq!(obligation.collaterals[0].amount, 1_000);
+        assert_eq!(obligation.collaterals[0].pledged_slot, 1);
+        assert_eq!(obligation.loans[0].reserve, loan_key);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(600u64));
+        assert!(obligation.last_update.stale);
+    }
+
+    #[test]
+    fn repay_max_amount_settles_only_the_loan() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, user_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (market_reserve_key, supply_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (obligation_key, user_token_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut market_reserve = MarketReserve::unpack(&new_market_reserve(manager_key)).unwrap();
+        market_reserve.token_config.supply_account = supply_key;
+        market_reserve.liquidity_info.borrowed_amount_wads = Decimal::from(51u64);
+        let mut market_reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(market_reserve, &mut market_reserve_data).unwrap();
+
+        let mut obligation = UserObligation::new(0, manager_key, user_key);
+        obligation.loans.push(Loan {
+            reserve: market_reserve_key,
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(51u64),
+            close_ratio: 50,
+        });
+        let mut obligation_data = vec![0; UserObligation::LEN];
+        UserObligation::pack(obligation, &mut obligation_data).unwrap();
+
+        // balance well above the loan
+        let mut user_token_data = vec![0; Account::LEN];
+        Account::pack(
+            Account { owner: user_key, amount: 1_000, state: AccountState::Initialized, ..Account::default() },
+            &mut user_token_data,
+        ).unwrap();
+
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(Manager::new(255, Pubkey::new_unique()), &mut manager_data).unwrap();
+        let (mut supply_data, mut user_data, mut token_program_data): ([u8; 0], [u8; 0], [u8; 0]) = ([], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3) = (0, 0, 0, 0);
+        let (mut lamports_4, mut lamports_5, mut lamports_6, mut lamports_7) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let accounts = [
+            clock_info,
+            AccountInfo::new(&market_reserve_key, false, true, &mut lamports_1, &mut market_reserve_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&supply_key, false, true, &mut lamports_2, &mut supply_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&obligation_key, false, true, &mut lamports_3, &mut obligation_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&user_key, true, false, &mut lamports_4, &mut user_data, &user_key, false, Epoch::default()),
+            AccountInfo::new(&user_token_key, false, true, &mut lamports_5, &mut user_token_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut lamports_6, &mut token_program_data, &token_program_id, true, Epoch::default()),
+            AccountInfo::new(&manager_key, false, false, &mut lamports_7, &mut manager_data, &program_id, false, Epoch::default()),
+        ];
+
+        process_instruction(&program_id, &accounts, &LendingInstruction::RepayLoan(u64::MAX).pack()).unwrap();
+
+        // only the loan is taken from the user
+        let market_reserve = MarketReserve::unpack(&accounts[1].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(market_reserve.liquidity_info.available, 51);
+        assert_eq!(market_reserve.liquidity_info.borrowed_amount_wads, Decimal::zero());
+        let obligation = UserObligation::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        assert!(obligation.loans.is_empty());
+    }
+
+    #[test]
+    fn liquidate_direct_splits_liquidation_fee() {
+        let program_id = crate::id();
+        let clock_key = solana_program::sysvar::clock::id();
+        let token_program_id = spl_token::id();
+        let (manager_key, user_key, liquidator_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (manager_authority_key, bump_seed) = Pubkey::find_program_address(&[manager_key.as_ref()], &program_id);
+        let (collateral_key, collateral_supply_key, sotoken_mint_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (loan_key, loan_supply_key, obligation_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (liquidator_loan_key, liquidator_collateral_key, receiver_owner_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let receiver_key = associated_token_address(&receiver_owner_key, &sotoken_mint_key);
+
+        let new_reserve = |supply_account: Pubkey, sotoken_mint: Pubkey| {
+            let mut market_reserve = MarketReserve::new(
+                0,
+                manager_key,
+                TokenConfig { supply_account, ..TokenConfig::default() },
+                OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+                LiquidityConfig { close_ratio: 50, ..LiquidityConfig::default() },
+                sotoken_mint,
+                CollateralConfig {
+                    borrow_value_ratio: 60,
+                    liquidation_value_ratio: 80,
+                    liquidation_penalty_ratio: 5,
+                    full_liquidation_health_bps: 0,
+                    borrower_liquidation_rebate_bps: 0,
+                    max_post_liquidation_health_bps: 0,
+                    permissioned: false,
+                    max_concentration_ratio: 0,
+                    bonus_by_collateral_bps: 0,
+                    bonus_by_loan_bps: 0,
+                    requires_companion_collateral: false,
+                },
+                RateModel::default(),
+            );
+            market_reserve.oracle_info.price = Decimal::one();
+            market_reserve.last_update.update_slot(0, false);
+            market_reserve
+        };
+        let new_token_account = |owner: Pubkey, mint: Pubkey| {
+            let mut data = vec![0; Account::LEN];
+            Account::pack(Account { mint, owner, amount: 1_000, state: AccountState::Initialized, ..Account::default() }, &mut data).unwrap();
+            data
+        };
+
+        // 1_000 pledged against 850 borrowed, liquidation value 800 is under water
+        let mut collateral_reserve = new_reserve(collateral_supply_key, sotoken_mint_key);
+        collateral_reserve.liquidity_info.available = 1_000;
+        collateral_reserve.collateral_info.total_mint = 1_000;
+        let mut loan_reserve = new_reserve(loan_supply_key, Pubkey::new_unique());
+        loan_reserve.liquidity_info.borrowed_amount_wads = Decimal::from(850u64);
+        let mut obligation = UserObligation::new(0, manager_key, user_key);
+        obligation.collaterals.push(Collateral {
+            reserve: collateral_key,
+            amount: 1_000,
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            pledged_slot: 0,
+        });
+        obligation.loans.push(Loan {
+            reserve: loan_key,
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(850u64),
+            close_ratio: 50,
+        });
+        obligation.collaterals_borrow_value = Decimal::from(600u64);
+        obligation.collaterals_liquidation_value = Decimal::from(800u64);
+        obligation.loans_value = Decimal::from(850u64);
+        obligation.last_update.update_slot(0, false);
+        let mut manager = Manager::new(bump_seed, Pubkey::new_unique());
+        manager.set_liquidation_fee(40, COption::None).unwrap();
+
+        let (seize_amount, settle, _) = obligation.clone()
+            .liquidate_in::<false>(Some(400), 0, 0, &collateral_reserve, &loan_reserve, None, false)
+            .unwrap();
+        let repay_equivalent = calculate_repay_equivalent_sotoken(&settle, &collateral_reserve, &loan_reserve).unwrap();
+        let (liquidator_amount, liquidation_fee) = manager.split_liquidation_fee(seize_amount, repay_equivalent).unwrap();
+        assert!(liquidation_fee > 0);
+
+        let (mut collateral_data, mut loan_data, mut obligation_data) = (vec![0; MarketReserve::LEN], vec![0; MarketReserve::LEN], vec![0; UserObligation::LEN]);
+        MarketReserve::pack(collateral_reserve, &mut collateral_data).unwrap();
+        MarketReserve::pack(loan_reserve, &mut loan_data).unwrap();
+        UserObligation::pack(obligation, &mut obligation_data).unwrap();
+        let (initial_collateral_data, initial_loan_data, initial_obligation_data) = (collateral_data.clone(), loan_data.clone(), obligation_data.clone());
+        let mut manager_data = vec![0; Manager::LEN];
+        Manager::pack(manager.clone(), &mut manager_data).unwrap();
+        let mut clock_data = vec![0; std::mem::size_of::<Clock>()];
+        let mut collateral_supply_data = new_token_account(manager_authority_key, Pubkey::default());
+        let mut loan_supply_data = new_token_account(manager_authority_key, Pubkey::default());
+        let mut liquidator_loan_data = new_token_account(liquidator_key, Pubkey::default());
+        let mut liquidator_collateral_data = new_token_account(liquidator_key, Pubkey::default());
+        let mut receiver_data = new_token_account(receiver_owner_key, sotoken_mint_key);
+        let (mut authority_data, mut liquidator_data, mut token_program_data, mut sotoken_mint_data): ([u8; 0], [u8; 0], [u8; 0], [u8; 0]) = ([], [], [], []);
+        let (mut lamports_0, mut lamports_1, mut lamports_2, mut lamports_3, mut lamports_4) = (0, 0, 0, 0, 0);
+        let (mut lamports_5, mut lamports_6, mut lamports_7, mut lamports_8, mut lamports_9) = (0, 0, 0, 0, 0);
+        let (mut lamports_10, mut lamports_11, mut lamports_12, mut lamports_13) = (0, 0, 0, 0);
+        let mut clock_info = AccountInfo::new(&clock_key, false, false, &mut lamports_0, &mut clock_data, &clock_key, false, Epoch::default());
+        Clock::default().to_account_info(&mut clock_info).unwrap();
+        let accounts = [
+            clock_info,
+            AccountInfo::new(&manager_key, false, false, &mut lamports_1, &mut manager_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&manager_authority_key, false, false, &mut lamports_2, &mut authority_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&collateral_key, false, true, &mut lamports_3, &mut collateral_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&collateral_supply_key, false, true, &mut lamports_4, &mut collateral_supply_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&loan_key, false, true, &mut lamports_5, &mut loan_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&loan_supply_key, false, true, &mut lamports_6, &mut loan_supply_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&obligation_key, false, true, &mut lamports_7, &mut obligation_data, &program_id, false, Epoch::default()),
+            AccountInfo::new(&liquidator_key, true, false, &mut lamports_8, &mut liquidator_data, &liquidator_key, false, Epoch::default()),
+            AccountInfo::new(&liquidator_loan_key, false, true, &mut lamports_9, &mut liquidator_loan_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&liquidator_collateral_key, false, true, &mut lamports_10, &mut liquidator_collateral_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&token_program_id, false, false, &mut lamports_11, &mut token_program_data, &token_program_id, true, Epoch::default()),
+            AccountInfo::new(&sotoken_mint_key, false, true, &mut lamports_12, &mut sotoken_mint_data, &token_program_id, false, Epoch::default()),
+            AccountInfo::new(&receiver_key, false, true, &mut lamports_13, &mut receiver_data, &token_program_id, false, Epoch::default()),
+        ];
+
+        // without receiver protocol share is burned into insurance
+        process_instruction(&program_id, &accounts[..12], &LendingInstruction::LiquidateDirect(400).pack()).unwrap();
+        let collateral_reserve = MarketReserve::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(collateral_reserve.collateral_info.total_mint, 1_000 - seize_amount);
+        assert_eq!(collateral_reserve.liquidity_info.liquidation_fee_insurance, liquidation_fee);
+        assert_eq!(collateral_reserve.liquidity_info.available, 1_000 - liquidator_amount);
+        let loan_reserve = MarketReserve::unpack(&accounts[5].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(loan_reserve.liquidity_info.available, 400);
+        let obligation = UserObligation::unpack(&accounts[7].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(obligation.collaterals[0].amount, 1_000 - seize_amount);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(450u64));
+
+        // with receiver set its accounts are required and its associated account takes the protocol share as sotoken
+        accounts[3].try_borrow_mut_data().unwrap().copy_from_slice(&initial_collateral_data);
+        accounts[5].try_borrow_mut_data().unwrap().copy_from_slice(&initial_loan_data);
+        accounts[7].try_borrow_mut_data().unwrap().copy_from_slice(&initial_obligation_data);
+        manager.set_liquidation_fee(40, COption::Some(receiver_owner_key)).unwrap();
+        Manager::pack(manager, &mut accounts[1].try_borrow_mut_data().unwrap()).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts[..12], &LendingInstruction::LiquidateDirect(400).pack()),
+            Err(ProgramError::NotEnoughAccountKeys),
+        );
+        process_instruction(&program_id, &accounts, &LendingInstruction::LiquidateDirect(400).pack()).unwrap();
+        let collateral_reserve = MarketReserve::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(collateral_reserve.collateral_info.total_mint, 1_000 - liquidator_amount);
+        assert_eq!(collateral_reserve.liquidity_info.liquidation_fee_insurance, 0);
+        assert_eq!(collateral_reserve.liquidity_info.available, 1_000 - liquidator_amount);
+
+        // receiver without associated account for this sotoken falls back to insurance
+        accounts[3].try_borrow_mut_data().unwrap().copy_from_slice(&initial_collateral_data);
+        accounts[5].try_borrow_mut_data().unwrap().copy_from_slice(&initial_loan_data);
+        accounts[7].try_borrow_mut_data().unwrap().copy_from_slice(&initial_obligation_data);
+        accounts[13].try_borrow_mut_data().unwrap().fill(0);
+        process_instruction(&program_id, &accounts, &LendingInstruction::LiquidateDirect(400).pack()).unwrap();
+        let collateral_reserve = MarketReserve::unpack(&accounts[3].try_borrow_data().unwrap()).unwrap();
+        assert_eq!(collateral_reserve.collateral_info.total_mint, 1_000 - seize_amount);
+        assert_eq!(collateral_reserve.liquidity_info.liquidation_fee_insurance, liquidation_fee);
+
+        // associated account of another wallet is rejected
+        accounts[3].try_borrow_mut_data().unwrap().copy_from_slice(&initial_collateral_data);
+        accounts[5].try_borrow_mut_data().unwrap().copy_from_slice(&initial_loan_data);
+        accounts[7].try_borrow_mut_data().unwrap().copy_from_slice(&initial_obligation_data);
+        let mut manager = Manager::unpack(&accounts[1].try_borrow_data().unwrap()).unwrap();
+        manager.set_liquidation_fee(40, COption::Some(Pubkey::new_unique())).unwrap();
+        Manager::pack(manager, &mut accounts[1].try_borrow_mut_data().unwrap()).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &LendingInstruction::LiquidateDirect(400).pack()),
+            Err(LendingError::UnmatchedAccounts.into()),
+        );
+    }
+}
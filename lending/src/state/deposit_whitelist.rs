@@ -0,0 +1,176 @@
+#![allow(missing_docs)]
+use super::*;
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    clock::Slot,
+    entrypoint::ProgramResult,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+
+///
+pub const MAX_WHITELISTED_DEPOSITORS: usize = 8;
+///
+pub const DEPOSIT_WHITELIST_SEED: &[u8] = b"deposit_whitelist";
+
+/// Depositors allowed into a market reserve before `until_slot`, lives at the address derived from the reserve
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DepositWhitelist {
+    pub version: u8,
+    pub market_reserve: Pubkey,
+    /// deposits are open to everyone from this slot on
+    pub until_slot: Slot,
+    pub depositors: Vec<Pubkey>,
+}
+
+impl DepositWhitelist {
+    ///
+    pub fn new(market_reserve: Pubkey, until_slot: Slot, depositors: Vec<Pubkey>) -> Self {
+        Self {
+            version: PROGRAM_VERSION,
+            market_reserve,
+            until_slot,
+            depositors,
+        }
+    }
+    ///
+    pub fn find_address(market_reserve: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[market_reserve.as_ref(), DEPOSIT_WHITELIST_SEED], program_id)
+    }
+    ///
+    pub fn assert_depositor(&self, depositor: &Pubkey, slot: Slot) -> ProgramResult {
+        if slot >= self.until_slot || self.depositors.contains(depositor) {
+            Ok(())
+        } else {
+            msg!("Deposits are whitelisted until slot {}", self.until_slot);
+            Err(LendingError::DepositNotWhitelisted.into())
+        }
+    }
+}
+
+impl Param for DepositWhitelist {
+    fn assert_valid(&self) -> ProgramResult {
+        if self.depositors.len() <= MAX_WHITELISTED_DEPOSITORS {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidAmount.into())
+        }
+    }
+}
+
+impl Sealed for DepositWhitelist {}
+impl IsInitialized for DepositWhitelist {
+    fn is_initialized(&self) -> bool {
+        self.version != UNINITIALIZED_VERSION
+    }
+}
+
+const DEPOSIT_WHITELIST_PADDING_LEN: usize = 32;
+const DEPOSIT_WHITELIST_LEN: usize = 330;
+
+impl Pack for DepositWhitelist {
+    const LEN: usize = DEPOSIT_WHITELIST_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, DEPOSIT_WHITELIST_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            market_reserve,
+            until_slot,
+            depositors_len,
+            data_flatten,
+            _padding,
+        ) = mut_array_refs![
+            output,
+            1,
+            PUBKEY_BYTES,
+            8,
+            1,
+            PUBKEY_BYTES * MAX_WHITELISTED_DEPOSITORS,
+            DEPOSIT_WHITELIST_PADDING_LEN
+        ];
+
+        *version = self.version.to_le_bytes();
+        market_reserve.copy_from_slice(self.market_reserve.as_ref());
+        *until_slot = self.until_slot.to_le_bytes();
+        *depositors_len = (self.depositors.len() as u8).to_le_bytes();
+
+        data_flatten
+            .chunks_exact_mut(PUBKEY_BYTES)
+            .zip(self.depositors.iter())
+            .for_each(|(data, depositor)| data.copy_from_slice(depositor.as_ref()));
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, DEPOSIT_WHITELIST_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            market_reserve,
+            until_slot,
+            depositors_len,
+            data_flatten,
+            _padding,
+        ) = array_refs![
+            input,
+            1,
+            PUBKEY_BYTES,
+            8,
+            1,
+            PUBKEY_BYTES * MAX_WHITELISTED_DEPOSITORS,
+            DEPOSIT_WHITELIST_PADDING_LEN
+        ];
+
+        let version = u8::from_le_bytes(*version);
+        if version > PROGRAM_VERSION {
+            msg!("DepositWhitelist version does not match lending program version");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let depositors_len = u8::from_le_bytes(*depositors_len) as usize;
+        if depositors_len > MAX_WHITELISTED_DEPOSITORS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let depositors = data_flatten[..depositors_len * PUBKEY_BYTES]
+            .chunks_exact(PUBKEY_BYTES)
+            .map(|data| Pubkey::new_from_array(*array_ref![data, 0, PUBKEY_BYTES]))
+            .collect();
+
+        Ok(Self {
+            version,
+            market_reserve: Pubkey::new_from_array(*market_reserve),
+            until_slot: Slot::from_le_bytes(*until_slot),
+            depositors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deposit_whitelist_window() {
+        let depositor = Pubkey::new_unique();
+        let outsider = Pubkey::new_unique();
+        let deposit_whitelist = DepositWhitelist::new(Pubkey::new_unique(), 1_000, vec![depositor]);
+        assert_eq!(deposit_whitelist.assert_valid(), Ok(()));
+
+        assert_eq!(deposit_whitelist.assert_depositor(&depositor, 999), Ok(()));
+        assert_eq!(
+            deposit_whitelist.assert_depositor(&outsider, 999),
+            Err(LendingError::DepositNotWhitelisted.into()),
+        );
+        // window closed, open to everyone
+        assert_eq!(deposit_whitelist.assert_depositor(&outsider, 1_000), Ok(()));
+
+        let mut data = vec![0; DepositWhitelist::LEN];
+        DepositWhitelist::pack(deposit_whitelist.clone(), &mut data).unwrap();
+        assert_eq!(DepositWhitelist::unpack(&data).unwrap(), deposit_whitelist);
+
+        let too_many = DepositWhitelist { depositors: vec![depositor; MAX_WHITELISTED_DEPOSITORS + 1], ..deposit_whitelist };
+        assert_eq!(too_many.assert_valid(), Err(LendingError::InvalidAmount.into()));
+    }
+}
@@ -0,0 +1,164 @@
+#![allow(missing_docs)]
+use super::*;
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    entrypoint::ProgramResult,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+
+/// Pool token accounts of an amm priced against a quote reserve, referenced by market reserve oracle config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DexOracle {
+    pub version: u8,
+    pub manager: Pubkey,
+    /// pool token account holding the priced asset
+    pub base_pool: Pubkey,
+    /// pool token account holding the quote asset
+    pub quote_pool: Pubkey,
+    /// market reserve of the quote asset, its price values the quote side
+    pub quote_reserve: Pubkey,
+    /// in quote token, shallower pools are never used
+    pub min_quote_liquidity: u64,
+}
+
+impl DexOracle {
+    ///
+    pub fn new(
+        manager: Pubkey,
+        base_pool: Pubkey,
+        quote_pool: Pubkey,
+        quote_reserve: Pubkey,
+        min_quote_liquidity: u64,
+    ) -> Self {
+        Self {
+            version: PROGRAM_VERSION,
+            manager,
+            base_pool,
+            quote_pool,
+            quote_reserve,
+            min_quote_liquidity,
+        }
+    }
+}
+
+impl Param for DexOracle {
+    fn assert_valid(&self) -> ProgramResult {
+        if self.min_quote_liquidity > 0 && self.base_pool != self.quote_pool {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidOracleParams.into())
+        }
+    }
+}
+
+impl Sealed for DexOracle {}
+impl IsInitialized for DexOracle {
+    fn is_initialized(&self) -> bool {
+        self.version != UNINITIALIZED_VERSION
+    }
+}
+
+const DEX_ORACLE_PADDING_LEN: usize = 64;
+const DEX_ORACLE_LEN: usize = 201;
+
+impl Pack for DexOracle {
+    const LEN: usize = DEX_ORACLE_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, DEX_ORACLE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            manager,
+            base_pool,
+            quote_pool,
+            quote_reserve,
+            min_quote_liquidity,
+            _padding,
+        ) = mut_array_refs![
+            output,
+            1,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            8,
+            DEX_ORACLE_PADDING_LEN
+        ];
+
+        *version = self.version.to_le_bytes();
+        manager.copy_from_slice(self.manager.as_ref());
+        base_pool.copy_from_slice(self.base_pool.as_ref());
+        quote_pool.copy_from_slice(self.quote_pool.as_ref());
+        quote_reserve.copy_from_slice(self.quote_reserve.as_ref());
+        *min_quote_liquidity = self.min_quote_liquidity.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, DEX_ORACLE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            manager,
+            base_pool,
+            quote_pool,
+            quote_reserve,
+            min_quote_liquidity,
+            _padding,
+        ) = array_refs![
+            input,
+            1,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            PUBKEY_BYTES,
+            8,
+            DEX_ORACLE_PADDING_LEN
+        ];
+
+        let version = u8::from_le_bytes(*version);
+        if version > PROGRAM_VERSION {
+            msg!("DexOracle version does not match lending program version");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Self {
+            version,
+            manager: Pubkey::new_from_array(*manager),
+            base_pool: Pubkey::new_from_array(*base_pool),
+            quote_pool: Pubkey::new_from_array(*quote_pool),
+            quote_reserve: Pubkey::new_from_array(*quote_reserve),
+            min_quote_liquidity: u64::from_le_bytes(*min_quote_liquidity),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dex_oracle_config_validation() {
+        let dex_oracle = DexOracle::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10_000_000_000,
+        );
+        assert_eq!(dex_oracle.assert_valid(), Ok(()));
+
+        let mut data = vec![0; DexOracle::LEN];
+        DexOracle::pack(dex_oracle.clone(), &mut data).unwrap();
+        assert_eq!(DexOracle::unpack(&data).unwrap(), dex_oracle);
+
+        let invalid = [
+            DexOracle { min_quote_liquidity: 0, ..dex_oracle.clone() },
+            DexOracle { quote_pool: dex_oracle.base_pool, ..dex_oracle },
+        ];
+        for dex_oracle in invalid {
+            assert_eq!(dex_oracle.assert_valid(), Err(LendingError::InvalidOracleParams.into()));
+        }
+    }
+}
@@ -100,4 +100,34 @@ impl LastUpdate {
     pub fn is_lax_stale(&self, slot: Slot) -> Result<bool, ProgramError> {
         Ok(self.stale || self.slots_elapsed(slot)? > STALE_AFTER_SLOTS_ELAPSED)
     }
+
+    /// Check if marked stale or older than max age, which can only tighten lax stale
+    pub fn is_stale_after(&self, slot: Slot, max_age_slots: u64) -> Result<bool, ProgramError> {
+        if max_age_slots == 0 {
+            self.is_lax_stale(slot)
+        } else {
+            let max_age_slots = max_age_slots.min(STALE_AFTER_SLOTS_ELAPSED);
+            Ok(self.stale || self.slots_elapsed(slot)? > max_age_slots)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strict_max_age_is_stale_before_lax() {
+        let last_update = LastUpdate { slot: 100, stale: false };
+
+        // fresh enough for redeem, too stale for borrow with max age 5
+        assert!(!last_update.is_lax_stale(110).unwrap());
+        assert!(last_update.is_stale_after(110, 5).unwrap());
+        assert!(!last_update.is_stale_after(105, 5).unwrap());
+        // unset falls back to lax stale
+        assert!(!last_update.is_stale_after(110, 0).unwrap());
+        assert!(last_update.is_stale_after(116, 0).unwrap());
+        // looser max age is capped by lax stale
+        assert!(last_update.is_stale_after(116, 100).unwrap());
+    }
 }
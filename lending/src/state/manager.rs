@@ -2,8 +2,11 @@
 ///
 use super::*;
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use crate::{error::LendingError, math::Rate};
 use solana_program::{
+    clock::Slot,
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::{Pubkey, PUBKEY_BYTES}
 };
@@ -14,6 +17,46 @@ pub struct Manager {
     pub version: u8,
     pub bump_seed: u8,
     pub owner: Pubkey,
+    pub min_leverage_open_health_bps: u16,
+    /// protocol share (percent) of seized collateral in liquidation
+    pub liquidation_fee_ratio: u8,
+    /// wallet whose associated sotoken accounts receive protocol share, goes to insurance if unset
+    pub liquidation_fee_receiver: COption<Pubkey>,
+    /// max combined loans value of a friend pair, zero for no cap
+    pub friend_borrow_value_cap: u64,
+    /// max obligation age (slots) for borrow, zero for lax stale default
+    pub borrow_max_obligation_age_slots: u64,
+    /// collateral valued below this counts as zero in obligation valuation, zero for none
+    pub collateral_dust_value: u64,
+    /// flash loan repay shortfall accepted and drawn from insurance, zero for strict
+    pub flash_loan_repay_tolerance: u64,
+    /// halts user operations, manager owner instructions still available
+    pub paused: bool,
+    /// delay (slots) before config updates and insurance moves apply, zero for instant,
+    /// exempt are pause, timelock growth, owner change, stray token sweep and disabling liquidity or emergency liquidation
+    pub config_timelock_slots: u64,
+    /// max leverage multiple of a newly opened leverage position, zero for no cap
+    pub max_leverage_x: u8,
+    /// max liquidation shortfall drawn from insurance per liquidation, zero for no cap
+    pub liquidation_backstop_cap: u64,
+    /// value paid from insurance to keeper refreshing a long stale reserve, zero to disable
+    pub keeper_tip_value: u64,
+    /// min slots since last reserve update before keeper tip is paid
+    pub keeper_tip_min_gap_slots: u64,
+    /// max distinct loan reserves of an obligation, zero for no cap
+    pub max_loans_per_obligation: u8,
+    /// liquidation may repay the full loan regardless of close factor, for cascades
+    pub emergency_liquidation: bool,
+    /// max collateral borrow value of an obligation, zero for no cap
+    pub max_obligation_collateral_value: u64,
+    /// max loans value of an obligation, zero for no cap
+    pub max_obligation_loan_value: u64,
+    /// quote currency every pyth oracle must price in, zero for unchecked
+    pub quote_currency: [u8; 4],
+    /// slots a user pledge must age before borrowing against it, zero to disable
+    pub collateral_cooldown_slots: u8,
+    /// manager config update waiting for config timelock
+    pub pending_config: Option<PendingConfig>,
 }
 
 impl Manager {
@@ -23,6 +66,109 @@ impl Manager {
             version: PROGRAM_VERSION,
             bump_seed,
             owner,
+            min_leverage_open_health_bps: 0,
+            liquidation_fee_ratio: 0,
+            liquidation_fee_receiver: COption::None,
+            friend_borrow_value_cap: 0,
+            borrow_max_obligation_age_slots: 0,
+            collateral_dust_value: 0,
+            flash_loan_repay_tolerance: 0,
+            paused: false,
+            config_timelock_slots: 0,
+            max_leverage_x: 0,
+            liquidation_backstop_cap: 0,
+            keeper_tip_value: 0,
+            keeper_tip_min_gap_slots: 0,
+            max_loans_per_obligation: 0,
+            emergency_liquidation: false,
+            max_obligation_collateral_value: 0,
+            max_obligation_loan_value: 0,
+            quote_currency: [0; 4],
+            collateral_cooldown_slots: 0,
+            pending_config: None,
+        }
+    }
+    ///
+    pub fn set_liquidation_fee(&mut self, ratio: u8, receiver: COption<Pubkey>) -> ProgramResult {
+        if ratio >= 100 {
+            return Err(LendingError::InvalidLiquidationFeeConfig.into());
+        }
+        self.liquidation_fee_ratio = ratio;
+        self.liquidation_fee_receiver = receiver;
+
+        Ok(())
+    }
+    /// returns (liquidator amount, protocol fee), fee taken on the bonus above repay equivalent only
+    pub fn split_liquidation_fee(&self, seize_amount: u64, repay_equivalent: u64) -> Result<(u64, u64), ProgramError> {
+        let bonus = seize_amount.saturating_sub(repay_equivalent);
+        let fee = amount_mul_rate(bonus, Rate::from_percent(self.liquidation_fee_ratio))?;
+        let amount = seize_amount
+            .checked_sub(fee)
+            .ok_or(LendingError::MathOverflow)?;
+
+        Ok((amount, fee))
+    }
+    /// replaces any previous pending config
+    pub fn set_pending_config(&mut self, slot: Slot, instruction_data: &[u8]) -> ProgramResult {
+        self.pending_config = Some(PendingConfig::new(slot, self.config_timelock_slots, instruction_data)?);
+
+        Ok(())
+    }
+    ///
+    pub fn take_pending_config(&mut self, slot: Slot) -> Result<[u8; PENDING_CONFIG_DATA_LEN], ProgramError> {
+        PendingConfig::take(&mut self.pending_config, slot)
+    }
+    /// only grows, so a leaked owner key cannot skip the timelock
+    pub fn set_config_timelock(&mut self, timelock_slots: u64) -> ProgramResult {
+        if timelock_slots < self.config_timelock_slots {
+            return Err(LendingError::ConfigTimelockDecrease.into());
+        }
+        self.config_timelock_slots = timelock_slots;
+
+        Ok(())
+    }
+    ///
+    pub fn assert_not_paused(&self) -> ProgramResult {
+        if self.paused {
+            Err(LendingError::ManagerPaused.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// returns shortfall within tolerance, never more than the flash loan fee so insurance covers no principal
+    pub fn flash_loan_repay_shortfall(&self, balance_after: u64, expect_balance_after: u64, fee: u64) -> Result<u64, ProgramError> {
+        let shortfall = expect_balance_after.saturating_sub(balance_after);
+        if shortfall > self.flash_loan_repay_tolerance.min(fee) {
+            Err(LendingError::FlashLoanRepayInsufficient.into())
+        } else {
+            Ok(shortfall)
+        }
+    }
+    /// shortfall above the cap can not be backstopped by insurance
+    pub fn assert_liquidation_backstop(&self, shortfall: u64) -> ProgramResult {
+        if self.liquidation_backstop_cap > 0 && shortfall > self.liquidation_backstop_cap {
+            Err(LendingError::InsuranceDepleted.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// keeper tip value due for refreshing a reserve stale for `gap_slots`
+    /// tip must not be paid for refreshing reserves that are still lax fresh
+    pub fn set_keeper_tip(&mut self, tip_value: u64, min_gap_slots: u64) -> ProgramResult {
+        if tip_value > 0 && min_gap_slots < STALE_AFTER_SLOTS_ELAPSED {
+            return Err(LendingError::InvalidKeeperTipConfig.into());
+        }
+        self.keeper_tip_value = tip_value;
+        self.keeper_tip_min_gap_slots = min_gap_slots;
+
+        Ok(())
+    }
+    ///
+    pub fn keeper_tip(&self, gap_slots: u64) -> u64 {
+        if gap_slots >= self.keeper_tip_min_gap_slots {
+            self.keeper_tip_value
+        } else {
+            0
         }
     }
 }
@@ -34,8 +180,10 @@ impl IsInitialized for Manager {
     }
 }
 
-const MANAGER_PADDING_LEN: usize = 128;
-const MANAGER_LEN: usize = 162;
+/// size of managers created before growth, extended by `ExtendManager`
+pub const MANAGER_LEGACY_LEN: usize = 162;
+const MANAGER_PADDING_LEN: usize = 119 - PENDING_CONFIG_DATA_LEN;
+const MANAGER_LEN: usize = 290;
 
 impl Pack for Manager {
     const LEN: usize = MANAGER_LEN;
@@ -47,18 +195,89 @@ impl Pack for Manager {
             version,
             bump_seed,
             owner,
+            min_leverage_open_health_bps,
+            liquidation_fee_ratio,
+            liquidation_fee_receiver,
+            friend_borrow_value_cap,
+            borrow_max_obligation_age_slots,
+            collateral_dust_value,
+            flash_loan_repay_tolerance,
+            paused,
+            config_timelock_slots,
+            max_leverage_x,
+            liquidation_backstop_cap,
+            keeper_tip_value,
+            keeper_tip_min_gap_slots,
+            max_loans_per_obligation,
+            emergency_liquidation,
+            max_obligation_collateral_value,
+            max_obligation_loan_value,
+            quote_currency,
+            collateral_cooldown_slots,
+            pending_config_enable,
+            pending_config_effective_slot,
+            pending_config_data,
             _padding,
         ) = mut_array_refs![
             output,
             1,
             1,
             PUBKEY_BYTES,
+            2,
+            1,
+            COPTION_LEN + PUBKEY_BYTES,
+            8,
+            8,
+            8,
+            8,
+            1,
+            8,
+            1,
+            8,
+            8,
+            8,
+            1,
+            1,
+            8,
+            8,
+            4,
+            1,
+            1,
+            8,
+            PENDING_CONFIG_DATA_LEN,
             MANAGER_PADDING_LEN
         ];
 
         *version = self.version.to_le_bytes();
         *bump_seed = self.bump_seed.to_le_bytes();
         owner.copy_from_slice(self.owner.as_ref());
+        *min_leverage_open_health_bps = self.min_leverage_open_health_bps.to_le_bytes();
+        *liquidation_fee_ratio = self.liquidation_fee_ratio.to_le_bytes();
+        pack_coption_pubkey(&self.liquidation_fee_receiver, liquidation_fee_receiver);
+        *friend_borrow_value_cap = self.friend_borrow_value_cap.to_le_bytes();
+        *borrow_max_obligation_age_slots = self.borrow_max_obligation_age_slots.to_le_bytes();
+        *collateral_dust_value = self.collateral_dust_value.to_le_bytes();
+        *flash_loan_repay_tolerance = self.flash_loan_repay_tolerance.to_le_bytes();
+        pack_bool(self.paused, paused);
+        *config_timelock_slots = self.config_timelock_slots.to_le_bytes();
+        *max_leverage_x = self.max_leverage_x.to_le_bytes();
+        *liquidation_backstop_cap = self.liquidation_backstop_cap.to_le_bytes();
+        *keeper_tip_value = self.keeper_tip_value.to_le_bytes();
+        *keeper_tip_min_gap_slots = self.keeper_tip_min_gap_slots.to_le_bytes();
+        *max_loans_per_obligation = self.max_loans_per_obligation.to_le_bytes();
+        pack_bool(self.emergency_liquidation, emergency_liquidation);
+        *max_obligation_collateral_value = self.max_obligation_collateral_value.to_le_bytes();
+        *max_obligation_loan_value = self.max_obligation_loan_value.to_le_bytes();
+        quote_currency.copy_from_slice(&self.quote_currency);
+        *collateral_cooldown_slots = self.collateral_cooldown_slots.to_le_bytes();
+        pack_bool(self.pending_config.is_some(), pending_config_enable);
+        if let Some(pending_config) = self.pending_config {
+            *pending_config_effective_slot = pending_config.effective_slot.to_le_bytes();
+            *pending_config_data = pending_config.data;
+        } else {
+            *pending_config_effective_slot = 0u64.to_le_bytes();
+            *pending_config_data = [0; PENDING_CONFIG_DATA_LEN];
+        }
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
@@ -68,12 +287,56 @@ impl Pack for Manager {
             version,
             bump_seed,
             owner,
+            min_leverage_open_health_bps,
+            liquidation_fee_ratio,
+            liquidation_fee_receiver,
+            friend_borrow_value_cap,
+            borrow_max_obligation_age_slots,
+            collateral_dust_value,
+            flash_loan_repay_tolerance,
+            paused,
+            config_timelock_slots,
+            max_leverage_x,
+            liquidation_backstop_cap,
+            keeper_tip_value,
+            keeper_tip_min_gap_slots,
+            max_loans_per_obligation,
+            emergency_liquidation,
+            max_obligation_collateral_value,
+            max_obligation_loan_value,
+            quote_currency,
+            collateral_cooldown_slots,
+            pending_config_enable,
+            pending_config_effective_slot,
+            pending_config_data,
             _padding,
         ) = array_refs![
             input,
             1,
             1,
             PUBKEY_BYTES,
+            2,
+            1,
+            COPTION_LEN + PUBKEY_BYTES,
+            8,
+            8,
+            8,
+            8,
+            1,
+            8,
+            1,
+            8,
+            8,
+            8,
+            1,
+            1,
+            8,
+            8,
+            4,
+            1,
+            1,
+            8,
+            PENDING_CONFIG_DATA_LEN,
             MANAGER_PADDING_LEN
         ];
 
@@ -87,6 +350,127 @@ impl Pack for Manager {
             version,
             bump_seed: u8::from_le_bytes(*bump_seed),
             owner: Pubkey::new_from_array(*owner),
+            min_leverage_open_health_bps: u16::from_le_bytes(*min_leverage_open_health_bps),
+            liquidation_fee_ratio: u8::from_le_bytes(*liquidation_fee_ratio),
+            liquidation_fee_receiver: unpack_coption_pubkey(liquidation_fee_receiver)?,
+            friend_borrow_value_cap: u64::from_le_bytes(*friend_borrow_value_cap),
+            borrow_max_obligation_age_slots: u64::from_le_bytes(*borrow_max_obligation_age_slots),
+            collateral_dust_value: u64::from_le_bytes(*collateral_dust_value),
+            flash_loan_repay_tolerance: u64::from_le_bytes(*flash_loan_repay_tolerance),
+            paused: unpack_bool(paused)?,
+            config_timelock_slots: u64::from_le_bytes(*config_timelock_slots),
+            max_leverage_x: u8::from_le_bytes(*max_leverage_x),
+            liquidation_backstop_cap: u64::from_le_bytes(*liquidation_backstop_cap),
+            keeper_tip_value: u64::from_le_bytes(*keeper_tip_value),
+            keeper_tip_min_gap_slots: u64::from_le_bytes(*keeper_tip_min_gap_slots),
+            max_loans_per_obligation: u8::from_le_bytes(*max_loans_per_obligation),
+            emergency_liquidation: unpack_bool(emergency_liquidation)?,
+            max_obligation_collateral_value: u64::from_le_bytes(*max_obligation_collateral_value),
+            max_obligation_loan_value: u64::from_le_bytes(*max_obligation_loan_value),
+            quote_currency: *quote_currency,
+            collateral_cooldown_slots: u8::from_le_bytes(*collateral_cooldown_slots),
+            pending_config: if unpack_bool(pending_config_enable)? {
+                Some(PendingConfig {
+                    effective_slot: u64::from_le_bytes(*pending_config_effective_slot),
+                    data: *pending_config_data,
+                })
+            } else {
+                None
+            },
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn liquidation_fee_split_and_pack() {
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        assert_eq!(manager.split_liquidation_fee(1_000, 900).unwrap(), (1_000, 0));
+
+        let receiver = Pubkey::new_unique();
+        assert_eq!(
+            manager.set_liquidation_fee(100, COption::Some(receiver)).unwrap_err(),
+            LendingError::InvalidLiquidationFeeConfig.into(),
+        );
+        manager.set_liquidation_fee(10, COption::Some(receiver)).unwrap();
+        assert_eq!(manager.split_liquidation_fee(1_105, 1_000).unwrap(), (1_095, 10));
+        // no bonus left when seize falls short of repay
+        assert_eq!(manager.split_liquidation_fee(950, 1_000).unwrap(), (950, 0));
+
+        let mut data = vec![0; Manager::LEN];
+        Manager::pack(manager.clone(), &mut data).unwrap();
+        assert_eq!(Manager::unpack(&data).unwrap(), manager);
+    }
+
+    #[test]
+    fn extended_legacy_manager_has_no_pending_config() {
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        manager.set_config_timelock(10).unwrap();
+        manager.set_pending_config(5, &[126, 1]).unwrap();
+        assert_eq!(manager.pending_config.unwrap().effective_slot, 15);
+        let mut data = vec![0; Manager::LEN];
+        Manager::pack(manager.clone(), &mut data).unwrap();
+        assert_eq!(Manager::unpack(&data).unwrap(), manager);
+
+        // bytes past the legacy size are zero right after realloc
+        data[MANAGER_LEGACY_LEN..].iter_mut().for_each(|byte| *byte = 0);
+        manager.pending_config = None;
+        assert_eq!(Manager::unpack(&data).unwrap(), manager);
+        assert!(Manager::unpack(&data[..MANAGER_LEGACY_LEN]).is_err());
+    }
+
+    #[test]
+    fn flash_loan_repay_tolerance_boundary() {
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        assert_eq!(manager.flash_loan_repay_shortfall(1_000, 1_000, 3).unwrap(), 0);
+        assert_eq!(
+            manager.flash_loan_repay_shortfall(999, 1_000, 3).unwrap_err(),
+            LendingError::FlashLoanRepayInsufficient.into(),
+        );
+
+        manager.flash_loan_repay_tolerance = 2;
+        assert_eq!(manager.flash_loan_repay_shortfall(1_001, 1_000, 3).unwrap(), 0);
+        assert_eq!(manager.flash_loan_repay_shortfall(998, 1_000, 3).unwrap(), 2);
+        assert_eq!(
+            manager.flash_loan_repay_shortfall(997, 1_000, 3).unwrap_err(),
+            LendingError::FlashLoanRepayInsufficient.into(),
+        );
+        // shortfall beyond the fee eats principal
+        assert_eq!(
+            manager.flash_loan_repay_shortfall(998, 1_000, 1).unwrap_err(),
+            LendingError::FlashLoanRepayInsufficient.into(),
+        );
+    }
+
+    #[test]
+    fn keeper_tip_after_staleness_gap() {
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        assert_eq!(manager.keeper_tip(1_000), 0);
+
+        assert_eq!(manager.set_keeper_tip(5, 14), Err(LendingError::InvalidKeeperTipConfig.into()));
+        assert_eq!(manager.set_keeper_tip(0, 0), Ok(()));
+        manager.set_keeper_tip(5, 150).unwrap();
+        assert_eq!(manager.keeper_tip(0), 0);
+        assert_eq!(manager.keeper_tip(149), 0);
+        assert_eq!(manager.keeper_tip(150), 5);
+        assert_eq!(manager.keeper_tip(10_000), 5);
+
+        let mut data = vec![0; Manager::LEN];
+        Manager::pack(manager.clone(), &mut data).unwrap();
+        assert_eq!(Manager::unpack(&data).unwrap(), manager);
+    }
+
+    #[test]
+    fn emergency_liquidation_pack() {
+        let mut manager = Manager::new(255, Pubkey::new_unique());
+        assert!(!manager.emergency_liquidation);
+        manager.emergency_liquidation = true;
+
+        let mut data = vec![0; Manager::LEN];
+        Manager::pack(manager, &mut data).unwrap();
+        assert!(Manager::unpack(&data).unwrap().emergency_liquidation);
+    }
+}
@@ -2,12 +2,13 @@
 use super::*;
 use crate::{
     error::LendingError,
+    instruction::MAX_TIMELOCKED_DATA_LEN,
     math::{Rate, TryDiv, TrySub, WAD},
-    oracle::{OracleInfo, OracleConfig, OracleType},
+    oracle::{OracleInfo, OracleConfig, OracleType, OracleParams, DebtOracleConfig},
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
-    clock::Slot, 
+    clock::{Clock, Slot, UnixTimestamp}, 
     entrypoint::ProgramResult, 
     msg, 
     program_error::ProgramError,
@@ -24,12 +25,62 @@ pub struct TokenConfig {
     pub decimal: u8,
 }
 
+/// liquidator keeps at least half of the bonus
+pub const MAX_BORROWER_LIQUIDATION_REBATE_BPS: u16 = 5000;
+/// rounding gains are accounted in 1e-9 token, finer residual stays in exchange rate
+pub const ROUNDING_GAIN_NANOS_PER_TOKEN: u64 = 1_000_000_000;
+
 ///
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct CollateralConfig {
     pub borrow_value_ratio: u8,
     pub liquidation_value_ratio: u8,
     pub liquidation_penalty_ratio: u8,
+    pub full_liquidation_health_bps: u16,
+    /// share (bps) of liquidation bonus credited to borrower debt, at most half
+    pub borrower_liquidation_rebate_bps: u16,
+    /// health ceiling (bps) a partial liquidation may restore, zero for none
+    pub max_post_liquidation_health_bps: u16,
+    /// only whitelisted obligations may pledge
+    pub permissioned: bool,
+    /// max share (percent) of this collateral in an obligation with loans, zero for none
+    pub max_concentration_ratio: u8,
+    /// bonus (bps) of liquidation by collateral amount, zero for `liquidation_penalty_ratio`
+    pub bonus_by_collateral_bps: u16,
+    /// bonus (bps) of liquidation by loan amount, zero for `liquidation_penalty_ratio`
+    pub bonus_by_loan_bps: u16,
+    /// can not be borrowed against unless pledged alongside collateral without this flag
+    pub requires_companion_collateral: bool,
+}
+
+impl CollateralConfig {
+    /// seized value per repaid value, bonus of the liquidation direction included
+    pub fn liquidation_seize_rate(&self, is_collateral: bool) -> Result<Rate, ProgramError> {
+        let bonus_bps = if is_collateral { self.bonus_by_collateral_bps } else { self.bonus_by_loan_bps };
+        let bonus_rate = if bonus_bps > 0 {
+            Rate::from_bps(bonus_bps)
+        } else {
+            Rate::from_percent(self.liquidation_penalty_ratio)
+        };
+
+        Rate::one().try_add(bonus_rate)
+    }
+    /// health ceiling must stay above seized liquidation value per repaid value in both directions
+    fn assert_valid_health_ceiling(&self) -> ProgramResult {
+        if self.max_post_liquidation_health_bps == 0 {
+            return Ok(());
+        }
+
+        let ceiling = Rate::from_bps(self.max_post_liquidation_health_bps);
+        let liquidation_value_ratio = Rate::from_percent(self.liquidation_value_ratio);
+        for &is_collateral in [true, false].iter() {
+            if self.liquidation_seize_rate(is_collateral)?.try_mul(liquidation_value_ratio)? >= ceiling {
+                return Err(LendingError::InvalidCollateralConfig.into());
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Param for CollateralConfig {
@@ -38,8 +89,14 @@ impl Param for CollateralConfig {
             self.liquidation_penalty_ratio > 0 &&
             self.borrow_value_ratio < self.liquidation_value_ratio &&
             self.liquidation_value_ratio < 100 && 
-            self.liquidation_penalty_ratio < 100 {
-            Ok(())
+            self.liquidation_penalty_ratio < 100 &&
+            self.bonus_by_collateral_bps < 10000 &&
+            self.bonus_by_loan_bps < 10000 &&
+            self.full_liquidation_health_bps < 10000 &&
+            self.borrower_liquidation_rebate_bps <= MAX_BORROWER_LIQUIDATION_REBATE_BPS &&
+            self.max_concentration_ratio <= 100 &&
+            (self.max_post_liquidation_health_bps == 0 || self.max_post_liquidation_health_bps > 10000) {
+            self.assert_valid_health_ceiling()
         } else {
             Err(LendingError::InvalidCollateralConfig.into())
         }
@@ -52,9 +109,29 @@ pub struct CollateralInfo {
     pub sotoken_mint_pubkey: Pubkey,
     pub total_mint: u64,
     pub config: CollateralConfig,
+    /// sotoken only lives pledged in obligations, never minted to user wallets
+    pub restricted: bool,
 }
 
 impl CollateralInfo {
+    ///
+    pub fn assert_unrestricted(&self) -> ProgramResult {
+        if self.restricted {
+            msg!("Sotoken of market reserve is restricted and cannot be minted to a wallet");
+            Err(LendingError::SotokenRestricted.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// plain deposit would mint sotoken to wallet, restricted reserves take collateral by pledge only
+    pub fn assert_plain_deposit_allowed(&self) -> ProgramResult {
+        if self.restricted {
+            msg!("Market reserve takes collateral by pledge only, use deposit and pledge instead");
+            Err(LendingError::UseDepositAndPledge.into())
+        } else {
+            Ok(())
+        }
+    }
     ///
     pub fn mint(&mut self, amount: u64) -> ProgramResult {
         self.total_mint = self.total_mint
@@ -80,6 +157,14 @@ pub struct LiquidityConfig {
     pub borrow_tax_rate: u8,
     pub flash_loan_fee_rate: u64,
     pub max_deposit: u64,
+    /// fee charged for each new loan entry beyond the first, zero to disable
+    pub new_loan_fee: u64,
+    /// slots to wait before borrowing again after a large repay, zero to disable
+    pub borrow_cooldown_slots: u64,
+    /// repay percent of loan considered large
+    pub large_repay_ratio: u8,
+    /// compound interest over wall clock seconds instead of slots
+    pub compound_by_seconds: bool,
 }
 
 impl Param for LiquidityConfig {
@@ -90,6 +175,7 @@ impl Param for LiquidityConfig {
             self.max_deposit > 0 &&
             self.close_ratio < 100 &&
             self.borrow_tax_rate < 100 &&
+            self.large_repay_ratio <= 100 &&
             self.flash_loan_fee_rate < WAD {
             Ok(())
         } else {
@@ -98,6 +184,123 @@ impl Param for LiquidityConfig {
     }
 }
 
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StakerFeeConfig {
+    /// percent of reserve factor interest going to stakers, rest goes to insurance
+    pub ratio: u8,
+    pub account: Pubkey,
+}
+
+impl Param for StakerFeeConfig {
+    fn assert_valid(&self) -> ProgramResult {
+        if self.ratio <= 100 && (self.ratio == 0 || self.account != Pubkey::default()) {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidStakerFeeConfig.into())
+        }
+    }
+}
+
+///
+pub const DEFAULT_CRITICAL_UTILIZATION_BPS: u16 = 9500;
+
+/// Utilization bands of liquidity status, zero keeps the default
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LiquidityStatusBands {
+    /// defaults to kink of rate model
+    pub tight_bps: u16,
+    /// defaults to `DEFAULT_CRITICAL_UTILIZATION_BPS`
+    pub critical_bps: u16,
+}
+
+impl Param for LiquidityStatusBands {
+    fn assert_valid(&self) -> ProgramResult {
+        if self.critical_bps <= 10000 &&
+            (self.tight_bps == 0 || self.critical_bps == 0 || self.tight_bps < self.critical_bps) {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidLiquidityStatusBands.into())
+        }
+    }
+}
+
+/// Utilization category of market reserve for UI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LiquidityStatus {
+    Healthy,
+    Tight,
+    Critical,
+}
+
+/// same checks as init market reserve, usable off-chain before building the transaction
+pub fn assert_valid_reserve_config(
+    oracle_config: &OracleConfig,
+    collateral_config: &CollateralConfig,
+    liquidity_config: &LiquidityConfig,
+    rate_model: &RateModel,
+) -> ProgramResult {
+    oracle_config.assert_valid()?;
+    collateral_config.assert_valid()?;
+    liquidity_config.assert_valid()?;
+    rate_model.assert_valid()
+}
+
+pub const PENDING_CONFIG_DATA_LEN: usize = MAX_TIMELOCKED_DATA_LEN;
+
+/// packed config update instruction waiting for manager config timelock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PendingConfig {
+    pub effective_slot: Slot,
+    pub data: [u8; PENDING_CONFIG_DATA_LEN],
+}
+
+impl PendingConfig {
+    ///
+    pub fn new(slot: Slot, timelock_slots: u64, instruction_data: &[u8]) -> Result<Self, ProgramError> {
+        if instruction_data.len() > PENDING_CONFIG_DATA_LEN {
+            return Err(LendingError::InstructionUnpackError.into());
+        }
+        let mut data = [0; PENDING_CONFIG_DATA_LEN];
+        data[..instruction_data.len()].copy_from_slice(instruction_data);
+
+        Ok(Self {
+            effective_slot: slot
+                .checked_add(timelock_slots)
+                .ok_or(LendingError::MathOverflow)?,
+            data,
+        })
+    }
+    /// data of the pending config once effective, leaves it untouched otherwise
+    pub fn take(pending_config: &mut Option<Self>, slot: Slot) -> Result<[u8; PENDING_CONFIG_DATA_LEN], ProgramError> {
+        let config = pending_config.ok_or(LendingError::PendingConfigNotFound)?;
+        if slot < config.effective_slot {
+            msg!("Pending config is effective at slot {}", config.effective_slot);
+            return Err(LendingError::ConfigTimelockNotElapsed.into());
+        }
+        *pending_config = None;
+
+        Ok(config.data)
+    }
+    /// whether `instruction_data` is what has been queued
+    pub fn is_queued(pending_config: &Option<Self>, instruction_data: &[u8]) -> bool {
+        pending_config.map_or(false, |config| {
+            config.data[..instruction_data.len()] == *instruction_data &&
+                config.data[instruction_data.len()..].iter().all(|byte| *byte == 0)
+        })
+    }
+}
+
+/// source of protocol funds held by market reserve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsuranceBucket {
+    /// reserve factor share of interest, rest of insurance
+    Interest,
+    BorrowFee,
+    LiquidationFee,
+    FlashLoanFee,
+}
+
 ///
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct LiquidityInfo {
@@ -107,7 +310,28 @@ pub struct LiquidityInfo {
     pub acc_borrow_rate_wads: Decimal,
     pub borrowed_amount_wads: Decimal,
     pub insurance_wads: Decimal,
+    pub borrow_rate: Rate,
+    /// cumulative origination, never decreased by repay
+    pub total_borrows_originated: u128,
+    /// staker share of reserve factor, claimable to staker fee account
+    pub staker_fee_wads: Decimal,
+    pub staker_fee_config: StakerFeeConfig,
     pub config: LiquidityConfig,
+    /// part of insurance from new loan fee
+    pub borrow_fee_insurance: u64,
+    /// part of insurance from protocol share of liquidation
+    pub liquidation_fee_insurance: u64,
+    pub status_bands: LiquidityStatusBands,
+    /// cumulative repay rounding gain moved to insurance, in 1e-9 token
+    pub rounding_gain_nanos: u64,
+    /// unix timestamp of last interest accrual, zero before the first one
+    pub last_accrue_timestamp: UnixTimestamp,
+    /// insurance left for liquidation backstop, set by manager, `u64::MAX` for no budget
+    pub liquidation_backstop_budget: u64,
+    /// deposits need the whitelist account before this slot, zero if no whitelist
+    pub deposit_whitelist_until_slot: Slot,
+    /// set by init, deposits are rejected until manager bootstraps the reserve
+    pub awaiting_bootstrap: bool,
 }
 
 impl LiquidityInfo {
@@ -131,6 +355,9 @@ impl LiquidityInfo {
         if !self.enable {
             return Err(LendingError::MarketReserveDisabled.into());
         }
+        if self.awaiting_bootstrap {
+            return Err(LendingError::MarketReserveNotBootstrapped.into());
+        }
 
         self.available = self.available
             .checked_add(amount)
@@ -164,6 +391,22 @@ impl LiquidityInfo {
             .checked_sub(amount)
             .ok_or(LendingError::MarketReserveInsufficentLiquidity)?;
         self.borrowed_amount_wads = self.borrowed_amount_wads.try_add(Decimal::from(amount))?;
+        self.total_borrows_originated = self.total_borrows_originated
+            .checked_add(amount as u128)
+            .ok_or(LendingError::MathOverflow)?;
+
+        Ok(())
+    }
+    ///
+    // fee is added to debt and goes to insurance
+    pub fn charge_new_loan_fee(&mut self, fee: u64) -> ProgramResult {
+        if fee > 0 {
+            self.borrowed_amount_wads = self.borrowed_amount_wads.try_add(Decimal::from(fee))?;
+            self.insurance_wads = self.insurance_wads.try_add(Decimal::from(fee))?;
+            self.borrow_fee_insurance = self.borrow_fee_insurance
+                .checked_add(fee)
+                .ok_or(LendingError::MathOverflow)?;
+        }
 
         Ok(())
     }
@@ -199,8 +442,26 @@ impl LiquidityInfo {
             .ok_or(LendingError::MathOverflow)?;
         self.borrowed_amount_wads = self.borrowed_amount_wads.try_sub(settle.amount_decimal)?;
 
+        // repay rounded up is protocol gain, goes to insurance instead of exchange rate
+        let gain_nanos = Decimal::from(settle.amount)
+            .max(settle.amount_decimal)
+            .try_sub(settle.amount_decimal)?
+            .try_mul(ROUNDING_GAIN_NANOS_PER_TOKEN)?
+            .try_floor_u64()?;
+        if gain_nanos > 0 {
+            self.insurance_wads = self.insurance_wads
+                .try_add(Decimal::from(gain_nanos).try_div(ROUNDING_GAIN_NANOS_PER_TOKEN)?)?;
+            self.rounding_gain_nanos = self.rounding_gain_nanos
+                .checked_add(gain_nanos)
+                .ok_or(LendingError::MathOverflow)?;
+        }
+
         Ok(())
     }
+    /// cumulative rounding gain moved to insurance, never decreased by insurance reduction
+    pub fn rounding_gains(&self) -> Result<Decimal, ProgramError> {
+        Decimal::from(self.rounding_gain_nanos).try_div(ROUNDING_GAIN_NANOS_PER_TOKEN)
+    }
     ///
     pub fn flash_loan_repay(&mut self, amount: u64, fee: u64) -> ProgramResult {
         if !self.enable {
@@ -218,14 +479,125 @@ impl LiquidityInfo {
         Ok(())
     }
     ///
+    pub fn insurance_of(&self, bucket: InsuranceBucket) -> Result<Decimal, ProgramError> {
+        match bucket {
+            InsuranceBucket::Interest => self.insurance_wads
+                .try_sub(Decimal::from(self.borrow_fee_insurance))?
+                .try_sub(Decimal::from(self.liquidation_fee_insurance)),
+            InsuranceBucket::BorrowFee => Ok(Decimal::from(self.borrow_fee_insurance)),
+            InsuranceBucket::LiquidationFee => Ok(Decimal::from(self.liquidation_fee_insurance)),
+            InsuranceBucket::FlashLoanFee => Ok(Decimal::from(self.flash_loan_fee)),
+        }
+    }
+    ///
+    // flash loan fee first, then interest, borrow fee and liquidation fee in turn
     pub fn reduce_insurance(&mut self, amount: u64) -> ProgramResult {
-        if amount <= self.flash_loan_fee {
+        self.reduce_insurance_in(amount, None)
+    }
+    /// insurance backs the whole liquidation shortfall, errors once insurance or budget is depleted
+    pub fn cover_liquidation_shortfall(&mut self, shortfall: u64) -> ProgramResult {
+        if shortfall == 0 {
+            return Ok(());
+        }
+        if shortfall > self.liquidation_backstop_budget {
+            return Err(LendingError::InsuranceDepleted.into());
+        }
+        self.reduce_insurance_checked(shortfall)?;
+        if self.liquidation_backstop_budget != u64::MAX {
+            self.liquidation_backstop_budget -= shortfall;
+        }
+
+        Ok(())
+    }
+    /// like `reduce_insurance`, but errors instead of going below zero
+    pub fn reduce_insurance_checked(&mut self, amount: u64) -> ProgramResult {
+        let insurance = self.insurance_wads.try_add(Decimal::from(self.flash_loan_fee))?;
+        if Decimal::from(amount) > insurance {
+            return Err(LendingError::InsuranceDepleted.into());
+        }
+
+        self.reduce_insurance(amount)
+    }
+    /// insurance moved in from another reserve into the same bucket, exchange rate unchanged
+    pub fn add_insurance(&mut self, amount: u64, bucket: InsuranceBucket) -> ProgramResult {
+        match bucket {
+            InsuranceBucket::FlashLoanFee => {
+                self.flash_loan_fee = self.flash_loan_fee
+                    .checked_add(amount)
+                    .ok_or(LendingError::MathOverflow)?;
+                return Ok(());
+            }
+            InsuranceBucket::BorrowFee => self.borrow_fee_insurance = self.borrow_fee_insurance
+                .checked_add(amount)
+                .ok_or(LendingError::MathOverflow)?,
+            InsuranceBucket::LiquidationFee => self.liquidation_fee_insurance = self.liquidation_fee_insurance
+                .checked_add(amount)
+                .ok_or(LendingError::MathOverflow)?,
+            InsuranceBucket::Interest => {}
+        }
+        self.available = self.available
+            .checked_add(amount)
+            .ok_or(LendingError::MathOverflow)?;
+        self.insurance_wads = self.insurance_wads.try_add(Decimal::from(amount))?;
+
+        Ok(())
+    }
+    /// moves insurance bucket by bucket in `reduce_insurance` order, so fees are not credited as interest share
+    pub fn transfer_insurance(&mut self, to: &mut Self, amount: u64) -> ProgramResult {
+        let mut rest = amount;
+        for bucket in &[
+            InsuranceBucket::FlashLoanFee,
+            InsuranceBucket::Interest,
+            InsuranceBucket::BorrowFee,
+            InsuranceBucket::LiquidationFee,
+        ] {
+            let part = rest.min(self.insurance_of(*bucket)?.try_floor_u64()?);
+            if part > 0 {
+                self.reduce_insurance_in(part, Some(*bucket))?;
+                to.add_insurance(part, *bucket)?;
+                rest -= part;
+            }
+        }
+
+        if rest > 0 {
+            Err(LendingError::InsuranceDepleted.into())
+        } else {
+            Ok(())
+        }
+    }
+    ///
+    pub fn reduce_insurance_in(&mut self, amount: u64, bucket: Option<InsuranceBucket>) -> ProgramResult {
+        if let Some(bucket) = bucket {
+            if Decimal::from(amount) > self.insurance_of(bucket)? {
+                return Err(LendingError::InsuranceBucketInsufficient.into());
+            }
+
+            match bucket {
+                InsuranceBucket::FlashLoanFee => {
+                    self.flash_loan_fee -= amount;
+                    return Ok(());
+                }
+                InsuranceBucket::BorrowFee => self.borrow_fee_insurance -= amount,
+                InsuranceBucket::LiquidationFee => self.liquidation_fee_insurance -= amount,
+                InsuranceBucket::Interest => {}
+            }
+            self.insurance_wads = self.insurance_wads.try_sub(Decimal::from(amount))?;
+            self.available = self.available
+                .checked_sub(amount)
+                .ok_or(LendingError::MarketReserveInsufficentLiquidity)?;
+        } else if amount <= self.flash_loan_fee {
             self.flash_loan_fee = self.flash_loan_fee
                 .checked_sub(amount)
                 .ok_or(LendingError::MathOverflow)?;
         } else {
             let amount = amount - self.flash_loan_fee;
             self.flash_loan_fee = 0;
+
+            let rest = amount.saturating_sub(self.insurance_of(InsuranceBucket::Interest)?.try_floor_u64()?);
+            let from_borrow_fee = rest.min(self.borrow_fee_insurance);
+            self.borrow_fee_insurance -= from_borrow_fee;
+            self.liquidation_fee_insurance = self.liquidation_fee_insurance.saturating_sub(rest - from_borrow_fee);
+
             self.insurance_wads = self.insurance_wads.try_sub(Decimal::from(amount))?;
             self.available = self.available
                 .checked_sub(amount)
@@ -234,6 +606,16 @@ impl LiquidityInfo {
         
         Ok(())
     }
+    ///
+    pub fn claim_staker_fee(&mut self) -> Result<u64, ProgramError> {
+        let amount = self.staker_fee_wads.try_floor_u64()?.min(self.available);
+        self.staker_fee_wads = self.staker_fee_wads.try_sub(Decimal::from(amount))?;
+        self.available = self.available
+            .checked_sub(amount)
+            .ok_or(LendingError::MathOverflow)?;
+
+        Ok(amount)
+    }
 }
 
 /// Lending market reserve state
@@ -247,6 +629,7 @@ pub struct MarketReserve {
     pub collateral_info: CollateralInfo,
     pub liquidity_info: LiquidityInfo,
     pub rate_model: RateModel,
+    pub pending_config: Option<PendingConfig>,
 }
 
 impl MarketReserve {
@@ -254,7 +637,8 @@ impl MarketReserve {
     fn supply_without_insurance(&self) -> Result<Decimal, ProgramError> {
         self.liquidity_info
             .total_supply()?
-            .try_sub(self.liquidity_info.insurance_wads)
+            .try_sub(self.liquidity_info.insurance_wads)?
+            .try_sub(self.liquidity_info.staker_fee_wads)
     }
     ///
     #[allow(clippy::too_many_arguments)]
@@ -275,7 +659,13 @@ impl MarketReserve {
             token_config,
             oracle_info: OracleInfo {
                 price: Decimal::default(),
+                price_slot: 0,
                 config: oracle_config,
+                debt_price: Decimal::default(),
+                debt_price_slot: 0,
+                debt_config: None,
+                params: OracleParams::default(),
+                quote_currency: [0; 4],
             },
             liquidity_info: LiquidityInfo {
                 enable: true,
@@ -284,17 +674,50 @@ impl MarketReserve {
                 acc_borrow_rate_wads: Decimal::one(),
                 borrowed_amount_wads: Decimal::zero(),
                 insurance_wads: Decimal::zero(),
+                borrow_rate: Rate::zero(),
+                total_borrows_originated: 0,
+                staker_fee_wads: Decimal::zero(),
+                staker_fee_config: StakerFeeConfig::default(),
                 config: liquidity_config,
+                borrow_fee_insurance: 0,
+                liquidation_fee_insurance: 0,
+                status_bands: LiquidityStatusBands::default(),
+                rounding_gain_nanos: 0,
+                last_accrue_timestamp: 0,
+                liquidation_backstop_budget: u64::MAX,
+                deposit_whitelist_until_slot: 0,
+                awaiting_bootstrap: false,
             },
             collateral_info: CollateralInfo {
                 sotoken_mint_pubkey,
                 total_mint: 0,
                 config: collateral_config,
+                restricted: false,
             },
             rate_model,
+            pending_config: None,
         }
     }
+    /// replaces any previous pending config
+    pub fn set_pending_config(&mut self, slot: Slot, timelock_slots: u64, instruction_data: &[u8]) -> ProgramResult {
+        self.pending_config = Some(PendingConfig::new(slot, timelock_slots, instruction_data)?);
+
+        Ok(())
+    }
     ///
+    pub fn take_pending_config(&mut self, slot: Slot) -> Result<[u8; PENDING_CONFIG_DATA_LEN], ProgramError> {
+        PendingConfig::take(&mut self.pending_config, slot)
+    }
+    /// liquidity per sotoken, one when nothing is minted
+    pub fn exchange_rate(&self) -> Result<Decimal, ProgramError> {
+        let total_supply = self.supply_without_insurance()?;
+        if total_supply == Decimal::zero() || self.collateral_info.total_mint == 0 {
+            Ok(Decimal::one())
+        } else {
+            total_supply.try_div(Decimal::from(self.collateral_info.total_mint))
+        }
+    }
+    /// inverse of `exchange_rate`, divided directly so deposits round in favor of reserve
     pub fn liquidity_to_collateral_rate(&self) -> Result<Rate, ProgramError> {
         let total_supply = self.supply_without_insurance()?;
         if total_supply == Decimal::zero() || self.collateral_info.total_mint == 0 {
@@ -307,13 +730,73 @@ impl MarketReserve {
     }
     ///
     pub fn collateral_to_liquidity_rate(&self) -> Result<Rate, ProgramError> {
-        let total_supply = self.supply_without_insurance()?;
-        if total_supply == Decimal::zero() || self.collateral_info.total_mint == 0 {
-            Ok(Rate::one())
+        self.exchange_rate()?.try_into()
+    }
+    /// borrow value provided by sotoken amount at current price, needs refresh before
+    pub fn collateral_capacity(&self, sotoken_amount: u64) -> Result<Decimal, ProgramError> {
+        calculate_effective_value(
+            self.oracle_info.price,
+            amount_mul_rate(sotoken_amount, self.collateral_to_liquidity_rate()?)?,
+            calculate_decimals(self.token_config.decimal)?,
+            Rate::from_percent(self.collateral_info.config.borrow_value_ratio),
+        )
+    }
+    /// token amount worth `value` at current price, needs refresh before
+    pub fn amount_of_value(&self, value: u64) -> Result<u64, ProgramError> {
+        Decimal::from(value)
+            .try_mul(calculate_decimals(self.token_config.decimal)?)?
+            .try_div(self.oracle_info.price)?
+            .try_floor_u64()
+    }
+    /// sotoken amount whose collateral value is `target_value` at current price and exchange rate, needs refresh before
+    pub fn sotoken_for_value(&self, target_value: Decimal) -> Result<u64, ProgramError> {
+        target_value
+            .try_mul(calculate_decimals(self.token_config.decimal)?)?
+            .try_div(self.oracle_info.price)?
+            .try_div(self.collateral_to_liquidity_rate()?)?
+            .try_floor_u64()
+    }
+    /// distance between utilization and kink of rate model, true if over-utilized
+    pub fn utilization_gap(&self) -> Result<(Decimal, bool), ProgramError> {
+        let utilization = self.liquidity_info.utilization_rate()?;
+        let kink_utilization = Rate::from_percent(self.rate_model.kink);
+
+        if utilization >= kink_utilization {
+            Ok((Decimal::from(utilization.try_sub(kink_utilization)?), true))
         } else {
-            total_supply
-                .try_div(Decimal::from(self.collateral_info.total_mint))?
-                .try_into()
+            Ok((Decimal::from(kink_utilization.try_sub(utilization)?), false))
+        }
+    }
+    ///
+    pub fn liquidity_status(&self) -> Result<LiquidityStatus, ProgramError> {
+        let bands = self.liquidity_info.status_bands;
+        let tight = if bands.tight_bps == 0 {
+            Rate::from_percent(self.rate_model.kink)
+        } else {
+            Rate::from_bps(bands.tight_bps)
+        };
+        let critical = Rate::from_bps(if bands.critical_bps == 0 {
+            DEFAULT_CRITICAL_UTILIZATION_BPS
+        } else {
+            bands.critical_bps
+        });
+
+        let utilization = self.liquidity_info.utilization_rate()?;
+        if utilization >= critical {
+            Ok(LiquidityStatus::Critical)
+        } else if utilization >= tight {
+            Ok(LiquidityStatus::Tight)
+        } else {
+            Ok(LiquidityStatus::Healthy)
+        }
+    }
+    /// max amount borrowable now after reserve gates, zero if disabled or stale,
+    /// `UserObligation::borrow_available` adds manager and obligation gates
+    pub fn borrow_available(&self, clock: &Clock) -> Result<u64, ProgramError> {
+        if !self.liquidity_info.enable || self.last_update.is_lax_stale(clock.slot)? {
+            Ok(0)
+        } else {
+            Ok(self.liquidity_info.available)
         }
     }
     /// 
@@ -326,39 +809,96 @@ impl MarketReserve {
     // m = m + d_m
     // fee = fee + d_fee
     // -----------------------------------------------------------------
-    pub fn accrue_interest(&mut self, slot: Slot) -> ProgramResult {
-        let elapsed = self.last_update.slots_elapsed(slot)?;
+    pub fn accrue_interest(&mut self, key: &Pubkey, clock: &Clock) -> Result<Option<AccrueInterestEvent>, ProgramError> {
+        let elapsed = self.last_update.slots_elapsed(clock.slot)?;
         if elapsed > 0 {
-            let compounded_interest_rate = Rate::one()
-                .try_add(self.rate_model.calculate_borrow_rate(self.liquidity_info.utilization_rate()?)?)?
-                .try_pow(elapsed)?;
+            let utilization_rate = self.liquidity_info.utilization_rate()?;
+            let borrow_index = self.liquidity_info.acc_borrow_rate_wads;
+            let target_borrow_rate = self.rate_model.calculate_borrow_rate(utilization_rate)?;
+            let borrow_rate = self.rate_model.smooth_borrow_rate(self.liquidity_info.borrow_rate, target_borrow_rate, elapsed)?;
+            self.liquidity_info.borrow_rate = borrow_rate;
+            // first accrual after enabling has no recorded start, falls back to slots once
+            let last_accrue_timestamp = self.liquidity_info.last_accrue_timestamp;
+            let compounded_interest_rate = if self.liquidity_info.config.compound_by_seconds && last_accrue_timestamp > 0 {
+                compound_over_seconds(borrow_rate, clock.unix_timestamp.saturating_sub(last_accrue_timestamp))?
+            } else {
+                Rate::one()
+                    .try_add(borrow_rate)?
+                    .try_pow(elapsed)?
+            };
+            // never moves back on clock skew, or the skewed span would accrue twice
+            self.liquidity_info.last_accrue_timestamp = last_accrue_timestamp.max(clock.unix_timestamp);
             let fee_interest_rate = compounded_interest_rate
                 .try_sub(Rate::one())?
                 .try_mul(Rate::from_percent(self.liquidity_info.config.borrow_tax_rate))?;
-            let insurance_wads = self.liquidity_info.borrowed_amount_wads.try_mul(fee_interest_rate)?;
+            let fee_wads = self.liquidity_info.borrowed_amount_wads.try_mul(fee_interest_rate)?;
+            let staker_fee_wads = fee_wads.try_mul(Rate::from_percent(self.liquidity_info.staker_fee_config.ratio))?;
+            let insurance_wads = fee_wads.try_sub(staker_fee_wads)?;
 
             self.liquidity_info.insurance_wads = self.liquidity_info.insurance_wads.try_add(insurance_wads)?;
+            self.liquidity_info.staker_fee_wads = self.liquidity_info.staker_fee_wads.try_add(staker_fee_wads)?;
             self.liquidity_info.acc_borrow_rate_wads = self.liquidity_info.acc_borrow_rate_wads.try_mul(compounded_interest_rate)?;
             self.liquidity_info.borrowed_amount_wads = self.liquidity_info.borrowed_amount_wads.try_mul(compounded_interest_rate)?;
-        }
 
-        Ok(())
+            let event = AccrueInterestEvent {
+                reserve: *key,
+                borrow_rate,
+                new_borrow_index: self.liquidity_info.acc_borrow_rate_wads,
+                new_supply_index: self.collateral_to_liquidity_rate()?,
+                slot: clock.slot,
+                // borrowed liquidity not accruing over slots hints a rate model giving zero rate
+                index_stalled: utilization_rate > Rate::zero() && self.liquidity_info.acc_borrow_rate_wads <= borrow_index,
+            };
+            event.emit();
+
+            Ok(Some(event))
+        } else {
+            Ok(None)
+        }
+    }
+    ///
+    pub fn deposit_preview(&self, amount: u64) -> Result<u64, ProgramError> {
+        amount_mul_rate(amount, self.liquidity_to_collateral_rate()?)
+    }
+    ///
+    pub fn withdraw_preview(&self, amount: u64) -> Result<u64, ProgramError> {
+        if amount == self.collateral_info.total_mint {
+            Ok(self.liquidity_info.available)
+        } else {
+            amount_mul_rate(amount, self.collateral_to_liquidity_rate()?)
+        }
     }
     ///
     pub fn deposit(&mut self, amount: u64) -> Result<u64, ProgramError> {
-        let mint_amount = amount_mul_rate(amount, self.liquidity_to_collateral_rate()?)?;
+        let mint_amount = self.deposit_preview(amount)?;
         self.collateral_info.mint(mint_amount)?;
         self.liquidity_info.deposit(amount)?;
 
         Ok(mint_amount)
     }
+    /// seed an empty reserve at 1:1, minted sotoken stays locked with manager authority
+    pub fn bootstrap(&mut self, amount: u64) -> Result<u64, ProgramError> {
+        if self.collateral_info.total_mint > 0 {
+            return Err(LendingError::MarketReserveNotEmpty.into());
+        }
+
+        self.liquidity_info.awaiting_bootstrap = false;
+        self.deposit(amount)
+    }
+    /// move liquidity backing `amount` sotoken into insurance
+    pub fn collect_sotoken_to_insurance(&mut self, amount: u64) -> ProgramResult {
+        let liquidity_amount = amount_mul_rate(amount, self.collateral_to_liquidity_rate()?)?;
+        self.collateral_info.burn(amount)?;
+        self.liquidity_info.insurance_wads = self.liquidity_info.insurance_wads.try_add(Decimal::from(liquidity_amount))?;
+        self.liquidity_info.liquidation_fee_insurance = self.liquidity_info.liquidation_fee_insurance
+            .checked_add(liquidity_amount)
+            .ok_or(LendingError::MathOverflow)?;
+
+        Ok(())
+    }
     ///
     pub fn withdraw(&mut self, amount: u64) -> Result<u64, ProgramError> {
-        let withdraw_amount = if amount == self.collateral_info.total_mint {
-              self.liquidity_info.available
-        } else {
-            amount_mul_rate(amount, self.collateral_to_liquidity_rate()?)?
-        };
+        let withdraw_amount = self.withdraw_preview(amount)?;
         self.collateral_info.burn(amount)?;
         self.liquidity_info.withdraw(withdraw_amount)?;
 
@@ -373,8 +913,15 @@ impl IsInitialized for MarketReserve {
     }
 }
 
-const MARKET_RESERVE_PADDING_LEN: usize = 256;
-const MARKET_RESERVE_LEN: usize = 571;
+// permissioned and requires companion collateral share one byte, old accounts hold 0 or 1
+const COLLATERAL_PERMISSIONED_FLAG: u8 = 1;
+const COLLATERAL_REQUIRES_COMPANION_FLAG: u8 = 1 << 1;
+
+/// size of reserves created before growth, extended by `ExtendMarketReserve`
+pub const MARKET_RESERVE_LEGACY_LEN: usize = 571;
+// pending config data is sized by the largest timelocked instruction, padding takes the rest
+const MARKET_RESERVE_PADDING_LEN: usize = 255 - PENDING_CONFIG_DATA_LEN;
+const MARKET_RESERVE_LEN: usize = 827;
 
 impl Pack for MarketReserve {
     const LEN: usize = MARKET_RESERVE_LEN;
@@ -411,6 +958,46 @@ impl Pack for MarketReserve {
             optimal,
             kink,
             max,
+            full_liquidation_health_bps,
+            new_loan_fee,
+            borrow_rate,
+            smoothing_factor,
+            debt_oracle_enable,
+            debt_oracle,
+            debt_oracle_type,
+            debt_price,
+            total_borrows_originated,
+            stale_threshold,
+            max_confidence_bps,
+            max_deviation_bps,
+            borrow_cooldown_slots,
+            large_repay_ratio,
+            staker_fee_wads,
+            staker_fee_ratio,
+            staker_fee_account,
+            pending_config_enable,
+            pending_config_effective_slot,
+            pending_config_data,
+            borrower_liquidation_rebate_bps,
+            sotoken_restricted,
+            borrow_fee_insurance,
+            liquidation_fee_insurance,
+            max_post_liquidation_health_bps,
+            status_tight_bps,
+            status_critical_bps,
+            rounding_gain_nanos,
+            collateral_flags,
+            max_concentration_ratio,
+            bonus_by_collateral_bps,
+            bonus_by_loan_bps,
+            compound_by_seconds,
+            last_accrue_timestamp,
+            price_slot,
+            debt_price_slot,
+            liquidation_backstop_budget,
+            deposit_whitelist_until_slot,
+            awaiting_bootstrap,
+            quote_currency,
             _padding,
         ) = mut_array_refs![
             output,
@@ -442,6 +1029,46 @@ impl Pack for MarketReserve {
             8,
             1,
             16,
+            2,
+            8,
+            16,
+            1,
+            1,
+            PUBKEY_BYTES,
+            1,
+            16,
+            16,
+            8,
+            2,
+            2,
+            8,
+            1,
+            16,
+            1,
+            PUBKEY_BYTES,
+            1,
+            8,
+            PENDING_CONFIG_DATA_LEN,
+            2,
+            1,
+            8,
+            8,
+            2,
+            2,
+            2,
+            8,
+            1,
+            1,
+            2,
+            2,
+            1,
+            8,
+            8,
+            8,
+            8,
+            8,
+            1,
+            4,
             MARKET_RESERVE_PADDING_LEN
         ];
 
@@ -481,6 +1108,70 @@ impl Pack for MarketReserve {
         *optimal = self.rate_model.optimal.to_le_bytes();
         *kink = self.rate_model.kink.to_le_bytes();
         *max = self.rate_model.max.to_le_bytes();
+
+        *full_liquidation_health_bps = self.collateral_info.config.full_liquidation_health_bps.to_le_bytes();
+        *new_loan_fee = self.liquidity_info.config.new_loan_fee.to_le_bytes();
+        *borrow_rate = self.liquidity_info.borrow_rate.to_scaled_val().to_le_bytes();
+        *smoothing_factor = self.rate_model.smoothing_factor.to_le_bytes();
+
+        pack_bool(self.oracle_info.debt_config.is_some(), debt_oracle_enable);
+        if let Some(config) = self.oracle_info.debt_config {
+            debt_oracle.copy_from_slice(config.oracle.as_ref());
+            let debt_oracle_type_u8: u8 = config.oracle_type.into();
+            *debt_oracle_type = debt_oracle_type_u8.to_le_bytes();
+        } else {
+            debt_oracle.copy_from_slice(Pubkey::default().as_ref());
+            *debt_oracle_type = 0u8.to_le_bytes();
+        }
+        pack_decimal(self.oracle_info.debt_price, debt_price);
+        *total_borrows_originated = self.liquidity_info.total_borrows_originated.to_le_bytes();
+
+        *stale_threshold = self.oracle_info.params.stale_threshold.to_le_bytes();
+        *max_confidence_bps = self.oracle_info.params.max_confidence_bps.to_le_bytes();
+        *max_deviation_bps = self.oracle_info.params.max_deviation_bps.to_le_bytes();
+
+        *borrow_cooldown_slots = self.liquidity_info.config.borrow_cooldown_slots.to_le_bytes();
+        *large_repay_ratio = self.liquidity_info.config.large_repay_ratio.to_le_bytes();
+
+        pack_decimal(self.liquidity_info.staker_fee_wads, staker_fee_wads);
+        *staker_fee_ratio = self.liquidity_info.staker_fee_config.ratio.to_le_bytes();
+        staker_fee_account.copy_from_slice(self.liquidity_info.staker_fee_config.account.as_ref());
+
+        pack_bool(self.pending_config.is_some(), pending_config_enable);
+        if let Some(pending_config) = self.pending_config {
+            *pending_config_effective_slot = pending_config.effective_slot.to_le_bytes();
+            *pending_config_data = pending_config.data;
+        } else {
+            *pending_config_effective_slot = 0u64.to_le_bytes();
+            *pending_config_data = [0; PENDING_CONFIG_DATA_LEN];
+        }
+        *borrower_liquidation_rebate_bps = self.collateral_info.config.borrower_liquidation_rebate_bps.to_le_bytes();
+        pack_bool(self.collateral_info.restricted, sotoken_restricted);
+        *borrow_fee_insurance = self.liquidity_info.borrow_fee_insurance.to_le_bytes();
+        *liquidation_fee_insurance = self.liquidity_info.liquidation_fee_insurance.to_le_bytes();
+        *max_post_liquidation_health_bps = self.collateral_info.config.max_post_liquidation_health_bps.to_le_bytes();
+        *status_tight_bps = self.liquidity_info.status_bands.tight_bps.to_le_bytes();
+        *status_critical_bps = self.liquidity_info.status_bands.critical_bps.to_le_bytes();
+        *rounding_gain_nanos = self.liquidity_info.rounding_gain_nanos.to_le_bytes();
+        let mut flags = 0u8;
+        if self.collateral_info.config.permissioned {
+            flags |= COLLATERAL_PERMISSIONED_FLAG;
+        }
+        if self.collateral_info.config.requires_companion_collateral {
+            flags |= COLLATERAL_REQUIRES_COMPANION_FLAG;
+        }
+        *collateral_flags = flags.to_le_bytes();
+        *max_concentration_ratio = self.collateral_info.config.max_concentration_ratio.to_le_bytes();
+        *bonus_by_collateral_bps = self.collateral_info.config.bonus_by_collateral_bps.to_le_bytes();
+        *bonus_by_loan_bps = self.collateral_info.config.bonus_by_loan_bps.to_le_bytes();
+        pack_bool(self.liquidity_info.config.compound_by_seconds, compound_by_seconds);
+        *last_accrue_timestamp = self.liquidity_info.last_accrue_timestamp.to_le_bytes();
+        *price_slot = self.oracle_info.price_slot.to_le_bytes();
+        *debt_price_slot = self.oracle_info.debt_price_slot.to_le_bytes();
+        *liquidation_backstop_budget = self.liquidity_info.liquidation_backstop_budget.to_le_bytes();
+        *deposit_whitelist_until_slot = self.liquidity_info.deposit_whitelist_until_slot.to_le_bytes();
+        pack_bool(self.liquidity_info.awaiting_bootstrap, awaiting_bootstrap);
+        *quote_currency = self.oracle_info.quote_currency;
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
@@ -515,6 +1206,46 @@ impl Pack for MarketReserve {
             optimal,
             kink,
             max,
+            full_liquidation_health_bps,
+            new_loan_fee,
+            borrow_rate,
+            smoothing_factor,
+            debt_oracle_enable,
+            debt_oracle,
+            debt_oracle_type,
+            debt_price,
+            total_borrows_originated,
+            stale_threshold,
+            max_confidence_bps,
+            max_deviation_bps,
+            borrow_cooldown_slots,
+            large_repay_ratio,
+            staker_fee_wads,
+            staker_fee_ratio,
+            staker_fee_account,
+            pending_config_enable,
+            pending_config_effective_slot,
+            pending_config_data,
+            borrower_liquidation_rebate_bps,
+            sotoken_restricted,
+            borrow_fee_insurance,
+            liquidation_fee_insurance,
+            max_post_liquidation_health_bps,
+            status_tight_bps,
+            status_critical_bps,
+            rounding_gain_nanos,
+            collateral_flags,
+            max_concentration_ratio,
+            bonus_by_collateral_bps,
+            bonus_by_loan_bps,
+            compound_by_seconds,
+            last_accrue_timestamp,
+            price_slot,
+            debt_price_slot,
+            liquidation_backstop_budget,
+            deposit_whitelist_until_slot,
+            awaiting_bootstrap,
+            quote_currency,
             _padding,
         ) = array_refs![
             input,
@@ -546,6 +1277,46 @@ impl Pack for MarketReserve {
             8,
             1,
             16,
+            2,
+            8,
+            16,
+            1,
+            1,
+            PUBKEY_BYTES,
+            1,
+            16,
+            16,
+            8,
+            2,
+            2,
+            8,
+            1,
+            16,
+            1,
+            PUBKEY_BYTES,
+            1,
+            8,
+            PENDING_CONFIG_DATA_LEN,
+            2,
+            1,
+            8,
+            8,
+            2,
+            2,
+            2,
+            8,
+            1,
+            1,
+            2,
+            2,
+            1,
+            8,
+            8,
+            8,
+            8,
+            8,
+            1,
+            4,
             MARKET_RESERVE_PADDING_LEN
         ];
 
@@ -554,6 +1325,7 @@ impl Pack for MarketReserve {
             msg!("MarketReserve version does not match lending program version");
             return Err(ProgramError::InvalidAccountData);
         }
+        let collateral_flags = u8::from_le_bytes(*collateral_flags);
 
         Ok(Self {
             version,
@@ -566,10 +1338,27 @@ impl Pack for MarketReserve {
             },
             oracle_info: OracleInfo {
                 price: unpack_decimal(price),
+                price_slot: u64::from_le_bytes(*price_slot),
                 config: OracleConfig {
                     oracle: Pubkey::new_from_array(*oracle),
                     oracle_type: OracleType::from(u8::from_le_bytes(*oracle_type)),
                 },
+                debt_price: unpack_decimal(debt_price),
+                debt_price_slot: u64::from_le_bytes(*debt_price_slot),
+                debt_config: if unpack_bool(debt_oracle_enable)? {
+                    Some(OracleConfig {
+                        oracle: Pubkey::new_from_array(*debt_oracle),
+                        oracle_type: OracleType::from(u8::from_le_bytes(*debt_oracle_type)),
+                    })
+                } else {
+                    None
+                },
+                params: OracleParams {
+                    stale_threshold: u64::from_le_bytes(*stale_threshold),
+                    max_confidence_bps: u16::from_le_bytes(*max_confidence_bps),
+                    max_deviation_bps: u16::from_le_bytes(*max_deviation_bps),
+                },
+                quote_currency: *quote_currency,
             },
             collateral_info: CollateralInfo {
                 sotoken_mint_pubkey: Pubkey::new_from_array(*sotoken_mint_pubkey),
@@ -578,7 +1367,16 @@ impl Pack for MarketReserve {
                     borrow_value_ratio: u8::from_le_bytes(*borrow_value_ratio),
                     liquidation_value_ratio: u8::from_le_bytes(*liquidation_value_ratio),
                     liquidation_penalty_ratio: u8::from_le_bytes(*liquidation_penalty_ratio),
+                    full_liquidation_health_bps: u16::from_le_bytes(*full_liquidation_health_bps),
+                    borrower_liquidation_rebate_bps: u16::from_le_bytes(*borrower_liquidation_rebate_bps),
+                    max_post_liquidation_health_bps: u16::from_le_bytes(*max_post_liquidation_health_bps),
+                    permissioned: collateral_flags & COLLATERAL_PERMISSIONED_FLAG != 0,
+                    max_concentration_ratio: u8::from_le_bytes(*max_concentration_ratio),
+                    bonus_by_collateral_bps: u16::from_le_bytes(*bonus_by_collateral_bps),
+                    bonus_by_loan_bps: u16::from_le_bytes(*bonus_by_loan_bps),
+                    requires_companion_collateral: collateral_flags & COLLATERAL_REQUIRES_COMPANION_FLAG != 0,
                 },
+                restricted: unpack_bool(sotoken_restricted)?,
             },
             liquidity_info: LiquidityInfo {
                 enable: unpack_bool(enable)?,
@@ -587,23 +1385,152 @@ impl Pack for MarketReserve {
                 acc_borrow_rate_wads: unpack_decimal(acc_borrow_rate_wads),
                 borrowed_amount_wads: unpack_decimal(borrowed_amount_wads),
                 insurance_wads: unpack_decimal(insurance_wads),
+                borrow_rate: Rate::from_raw_val(u128::from_le_bytes(*borrow_rate)),
+                total_borrows_originated: u128::from_le_bytes(*total_borrows_originated),
+                staker_fee_wads: unpack_decimal(staker_fee_wads),
+                staker_fee_config: StakerFeeConfig {
+                    ratio: u8::from_le_bytes(*staker_fee_ratio),
+                    account: Pubkey::new_from_array(*staker_fee_account),
+                },
                 config: LiquidityConfig {
                     close_ratio: u8::from_le_bytes(*close_ratio),
                     borrow_tax_rate: u8::from_le_bytes(*borrow_tax_rate),
                     flash_loan_fee_rate: u64::from_le_bytes(*flash_loan_fee_rate),
                     max_deposit: u64::from_le_bytes(*max_deposit),
+                    new_loan_fee: u64::from_le_bytes(*new_loan_fee),
+                    borrow_cooldown_slots: u64::from_le_bytes(*borrow_cooldown_slots),
+                    large_repay_ratio: u8::from_le_bytes(*large_repay_ratio),
+                    compound_by_seconds: unpack_bool(compound_by_seconds)?,
                 },
+                borrow_fee_insurance: u64::from_le_bytes(*borrow_fee_insurance),
+                liquidation_fee_insurance: u64::from_le_bytes(*liquidation_fee_insurance),
+                status_bands: LiquidityStatusBands {
+                    tight_bps: u16::from_le_bytes(*status_tight_bps),
+                    critical_bps: u16::from_le_bytes(*status_critical_bps),
+                },
+                rounding_gain_nanos: u64::from_le_bytes(*rounding_gain_nanos),
+                last_accrue_timestamp: i64::from_le_bytes(*last_accrue_timestamp),
+                liquidation_backstop_budget: u64::from_le_bytes(*liquidation_backstop_budget),
+                deposit_whitelist_until_slot: Slot::from_le_bytes(*deposit_whitelist_until_slot),
+                awaiting_bootstrap: unpack_bool(awaiting_bootstrap)?,
             },
             rate_model: RateModel {
                 offset: u64::from_le_bytes(*offset),
                 optimal: u64::from_le_bytes(*optimal),
                 kink: u8::from_le_bytes(*kink),
                 max: u128::from_le_bytes(*max),
-            }
+                smoothing_factor: u8::from_le_bytes(*smoothing_factor),
+            },
+            pending_config: if unpack_bool(pending_config_enable)? {
+                Some(PendingConfig {
+                    effective_slot: u64::from_le_bytes(*pending_config_effective_slot),
+                    data: *pending_config_data,
+                })
+            } else {
+                None
+            },
         })
     }
 }
 
+/// Compact risk params of market reserve, returned by `GetReserveParams`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReserveParams {
+    pub borrow_value_ratio: u8,
+    pub liquidation_value_ratio: u8,
+    pub close_factor: u8,
+    pub price: Decimal,
+}
+
+impl From<&MarketReserve> for ReserveParams {
+    fn from(reserve: &MarketReserve) -> Self {
+        Self {
+            borrow_value_ratio: reserve.collateral_info.config.borrow_value_ratio,
+            liquidation_value_ratio: reserve.collateral_info.config.liquidation_value_ratio,
+            close_factor: reserve.liquidity_info.config.close_ratio,
+            price: reserve.oracle_info.price,
+        }
+    }
+}
+
+impl Sealed for ReserveParams {}
+
+const RESERVE_PARAMS_LEN: usize = 19;
+
+impl Pack for ReserveParams {
+    const LEN: usize = RESERVE_PARAMS_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, RESERVE_PARAMS_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            borrow_value_ratio,
+            liquidation_value_ratio,
+            close_factor,
+            price,
+        ) = mut_array_refs![output, 1, 1, 1, 16];
+
+        *borrow_value_ratio = self.borrow_value_ratio.to_le_bytes();
+        *liquidation_value_ratio = self.liquidation_value_ratio.to_le_bytes();
+        *close_factor = self.close_factor.to_le_bytes();
+        pack_decimal(self.price, price);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, RESERVE_PARAMS_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            borrow_value_ratio,
+            liquidation_value_ratio,
+            close_factor,
+            price,
+        ) = array_refs![input, 1, 1, 1, 16];
+
+        Ok(Self {
+            borrow_value_ratio: u8::from_le_bytes(*borrow_value_ratio),
+            liquidation_value_ratio: u8::from_le_bytes(*liquidation_value_ratio),
+            close_factor: u8::from_le_bytes(*close_factor),
+            price: unpack_decimal(price),
+        })
+    }
+}
+
+/// Emitted by `accrue_interest` whenever interest actually accrues.
+///
+/// Logged as a single line:
+/// `AccrueInterestEvent: <reserve> <borrow_rate> <new_borrow_index> <new_supply_index> <slot>`
+/// - `borrow_rate`: per-slot borrow rate, 18-decimal fixed point
+/// - `new_borrow_index`: accumulated borrow rate, 18-decimal fixed point
+/// - `new_supply_index`: sotoken to liquidity rate, 18-decimal fixed point
+///
+/// Followed by a warning line when the borrow index did not grow despite nonzero utilization.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccrueInterestEvent {
+    pub reserve: Pubkey,
+    pub borrow_rate: Rate,
+    pub new_borrow_index: Decimal,
+    pub new_supply_index: Rate,
+    pub slot: Slot,
+    pub index_stalled: bool,
+}
+
+impl AccrueInterestEvent {
+    ///
+    pub fn emit(&self) {
+        msg!(
+            "AccrueInterestEvent: {} {} {} {} {}",
+            self.reserve,
+            self.borrow_rate,
+            self.new_borrow_index,
+            self.new_supply_index,
+            self.slot,
+        );
+        if self.index_stalled {
+            msg!("Warning: borrow index of market reserve {} did not grow, check rate model", self.reserve);
+        }
+    }
+}
+
 /// All Operations due MarketReserve
 impl<P: Any + Param> Operator<P> for MarketReserve {
     fn operate_unchecked(&mut self, param: P) -> ProgramResult {
@@ -612,6 +1539,11 @@ impl<P: Any + Param> Operator<P> for MarketReserve {
             return Ok(())
         }
 
+        if let Some(restriction) = <dyn Any>::downcast_ref::<SotokenRestriction>(&param) {
+            self.collateral_info.restricted = restriction.0;
+            return Ok(())
+        }
+
         if let Some(config) = <dyn Any>::downcast_ref::<CollateralConfig>(&param) {
             self.collateral_info.config = *config;
             return Ok(());
@@ -632,6 +1564,35 @@ impl<P: Any + Param> Operator<P> for MarketReserve {
             return Ok(());
         }
 
+        if let Some(config) = <dyn Any>::downcast_ref::<DebtOracleConfig>(&param) {
+            // stale until refresh reads the new debt price
+            self.oracle_info.debt_config = config.0;
+            self.oracle_info.debt_price = Decimal::zero();
+            self.oracle_info.debt_price_slot = 0;
+            self.last_update.mark_stale();
+            return Ok(());
+        }
+
+        if let Some(params) = <dyn Any>::downcast_ref::<OracleParams>(&param) {
+            self.oracle_info.params = *params;
+            return Ok(());
+        }
+
+        if let Some(config) = <dyn Any>::downcast_ref::<StakerFeeConfig>(&param) {
+            self.liquidity_info.staker_fee_config = *config;
+            return Ok(());
+        }
+
+        if let Some(bands) = <dyn Any>::downcast_ref::<LiquidityStatusBands>(&param) {
+            self.liquidity_info.status_bands = *bands;
+            return Ok(());
+        }
+
+        if let Some(budget) = <dyn Any>::downcast_ref::<LiquidationBackstopBudget>(&param) {
+            self.liquidity_info.liquidation_backstop_budget = budget.0;
+            return Ok(());
+        }
+
         unreachable!("unexpected param type {}", std::any::type_name::<P>());
     }
 }
@@ -645,3 +1606,797 @@ impl Param for LiquidityControl {
         Ok(())
     }
 }
+
+///
+#[derive(Clone, Debug)]
+pub struct SotokenRestriction(pub bool);
+
+impl Param for SotokenRestriction {
+    fn assert_valid(&self) -> ProgramResult {
+        Ok(())
+    }
+}
+
+/// total insurance liquidation backstop may spend for market reserve, `u64::MAX` for no budget
+#[derive(Clone, Debug)]
+pub struct LiquidationBackstopBudget(pub u64);
+
+impl Param for LiquidationBackstopBudget {
+    fn assert_valid(&self) -> ProgramResult {
+        Ok(())
+    }
+}
+
+/// reserves a keeper should refresh, strict for obligation updates, lax otherwise
+pub fn stale_reserves(reserves: &[(Pubkey, MarketReserve)], clock: &Clock, strict: bool) -> Result<Vec<Pubkey>, ProgramError> {
+    let mut keys = Vec::new();
+    for (key, reserve) in reserves {
+        let stale = if strict {
+            reserve.last_update.is_strict_stale(clock.slot)?
+        } else {
+            reserve.last_update.is_lax_stale(clock.slot)?
+        };
+        if stale {
+            keys.push(*key);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// sotoken mint of reserve, for integrations mapping underlying to sotoken
+#[cfg(not(target_arch = "bpf"))]
+pub fn sotoken_mint_for_reserve(reserve: &MarketReserve) -> Pubkey {
+    reserve.collateral_info.sotoken_mint_pubkey
+}
+
+/// (reserve, underlying mint) whose sotoken mint is `sotoken_mint`
+#[cfg(not(target_arch = "bpf"))]
+pub fn reserve_for_sotoken_mint(reserves: &[(Pubkey, MarketReserve)], sotoken_mint: &Pubkey) -> Option<(Pubkey, Pubkey)> {
+    reserves
+        .iter()
+        .find(|(_, reserve)| &reserve.collateral_info.sotoken_mint_pubkey == sotoken_mint)
+        .map(|(key, reserve)| (*key, reserve.token_config.mint_pubkey))
+}
+
+/// sum of (available + borrowed) * price over reserves, in quote currency
+#[cfg(not(target_arch = "bpf"))]
+pub fn compute_tvl(reserves_with_prices: &[(MarketReserve, Decimal)]) -> Result<Decimal, ProgramError> {
+    reserves_with_prices
+        .iter()
+        .try_fold(Decimal::zero(), |acc, (reserve, price)| {
+            reserve.liquidity_info
+                .total_supply()?
+                .try_mul(*price)?
+                .try_div(calculate_decimals(reserve.token_config.decimal)?)?
+                .try_add(acc)
+        })
+}
+
+/// borrowed value over supplied value across reserves, value weighted
+#[cfg(not(target_arch = "bpf"))]
+pub fn protocol_utilization(reserves_with_prices: &[(MarketReserve, Decimal)]) -> Result<Decimal, ProgramError> {
+    let total_supply_value = compute_tvl(reserves_with_prices)?;
+    if total_supply_value == Decimal::zero() {
+        return Ok(Decimal::zero());
+    }
+
+    reserves_with_prices
+        .iter()
+        .try_fold(Decimal::zero(), |acc, (reserve, price)| {
+            reserve.liquidity_info.borrowed_amount_wads
+                .try_mul(*price)?
+                .try_div(calculate_decimals(reserve.token_config.decimal)?)?
+                .try_add(acc)
+        })?
+        .try_div(total_supply_value)
+}
+
+/// (field name, before, after) of every field that changed, for operators
+#[cfg(not(target_arch = "bpf"))]
+pub fn diff_reserves(before: &MarketReserve, after: &MarketReserve) -> Vec<(String, String, String)> {
+    let mut diffs = Vec::new();
+
+    macro_rules! diff_field {
+        ($($field:ident).+) => {
+            if before.$($field).+ != after.$($field).+ {
+                diffs.push((
+                    stringify!($($field).+).replace(' ', ""),
+                    format!("{:?}", before.$($field).+),
+                    format!("{:?}", after.$($field).+),
+                ));
+            }
+        };
+    }
+
+    diff_field!(version);
+    diff_field!(last_update);
+    diff_field!(manager);
+    diff_field!(token_config);
+    diff_field!(oracle_info.price);
+    diff_field!(oracle_info.config);
+    diff_field!(oracle_info.debt_price);
+    diff_field!(oracle_info.debt_config);
+    diff_field!(oracle_info.params);
+    diff_field!(collateral_info.sotoken_mint_pubkey);
+    diff_field!(collateral_info.total_mint);
+    diff_field!(collateral_info.config);
+    diff_field!(collateral_info.restricted);
+    diff_field!(liquidity_info.enable);
+    diff_field!(liquidity_info.available);
+    diff_field!(liquidity_info.flash_loan_fee);
+    diff_field!(liquidity_info.acc_borrow_rate_wads);
+    diff_field!(liquidity_info.borrowed_amount_wads);
+    diff_field!(liquidity_info.insurance_wads);
+    diff_field!(liquidity_info.borrow_rate);
+    diff_field!(liquidity_info.total_borrows_originated);
+    diff_field!(liquidity_info.staker_fee_wads);
+    diff_field!(liquidity_info.staker_fee_config);
+    diff_field!(liquidity_info.config);
+    diff_field!(liquidity_info.borrow_fee_insurance);
+    diff_field!(liquidity_info.liquidation_fee_insurance);
+    diff_field!(liquidity_info.rounding_gain_nanos);
+    diff_field!(liquidity_info.status_bands);
+    diff_field!(rate_model);
+    diff_field!(pending_config);
+
+    diffs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_reserve() -> MarketReserve {
+        MarketReserve::new(
+            0,
+            Pubkey::default(),
+            TokenConfig::default(),
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig { max_deposit: u64::MAX, ..LiquidityConfig::default() },
+            Pubkey::default(),
+            CollateralConfig::default(),
+            RateModel {
+                offset: 10_000_000_000_000_000,
+                optimal: 100_000_000_000_000_000,
+                kink: 80,
+                max: 1_000_000_000_000_000_000,
+                smoothing_factor: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn accrue_interest_emits_event_only_when_slots_elapsed() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+
+        // same slot is a no-op
+        assert_eq!(reserve.accrue_interest(&key, &Clock { slot: 0, ..Clock::default() }).unwrap(), None);
+
+        let event = reserve.accrue_interest(&key, &Clock { slot: 100, ..Clock::default() }).unwrap().unwrap();
+        assert_eq!(event.reserve, key);
+        assert_eq!(event.slot, 100);
+        assert_eq!(event.new_borrow_index, reserve.liquidity_info.acc_borrow_rate_wads);
+        assert!(event.new_borrow_index > Decimal::one());
+        assert!(event.new_supply_index > Rate::one());
+        assert!(!event.index_stalled);
+    }
+
+    #[test]
+    fn zero_rate_model_flags_stalled_index() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.rate_model = RateModel { offset: 0, optimal: 0, kink: 80, max: 0, smoothing_factor: 0 };
+        reserve.deposit(1_000_000).unwrap();
+
+        // no borrow, nothing expected to accrue
+        assert!(!reserve.accrue_interest(&key, &Clock { slot: 100, ..Clock::default() }).unwrap().unwrap().index_stalled);
+
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        let event = reserve.accrue_interest(&key, &Clock { slot: 200, ..Clock::default() }).unwrap().unwrap();
+        assert_eq!(event.new_borrow_index, Decimal::one());
+        assert!(event.index_stalled);
+    }
+
+    #[test]
+    fn smoothed_borrow_rate_lags_utilization_spike() {
+        let key = Pubkey::new_unique();
+        let mut instant = new_reserve();
+        instant.deposit(1_000_000).unwrap();
+        instant.liquidity_info.borrow_out(100_000).unwrap();
+        let previous = instant.rate_model.calculate_borrow_rate(instant.liquidity_info.utilization_rate().unwrap()).unwrap();
+        instant.liquidity_info.borrow_rate = previous;
+
+        let mut smoothed = instant.clone();
+        smoothed.rate_model.smoothing_factor = 50;
+
+        // utilization spike from 10% to 80%
+        instant.liquidity_info.borrow_out(700_000).unwrap();
+        smoothed.liquidity_info.borrow_out(700_000).unwrap();
+
+        let instant_rate = instant.accrue_interest(&key, &Clock { slot: 1, ..Clock::default() }).unwrap().unwrap().borrow_rate;
+        let smoothed_rate = smoothed.accrue_interest(&key, &Clock { slot: 1, ..Clock::default() }).unwrap().unwrap().borrow_rate;
+        assert!(previous < smoothed_rate && smoothed_rate < instant_rate);
+        assert_eq!(smoothed_rate, smoothed.rate_model.smooth_borrow_rate(previous, instant_rate, 1).unwrap());
+        assert_eq!(smoothed.liquidity_info.borrow_rate, smoothed_rate);
+    }
+
+    #[test]
+    fn deposit_and_withdraw_previews_match() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+
+        // empty reserve
+        let preview = reserve.deposit_preview(1_000_000).unwrap();
+        assert_eq!(reserve.deposit(1_000_000).unwrap(), preview);
+
+        // borrowed and accrued reserve
+        reserve.liquidity_info.borrow_out(700_000).unwrap();
+        reserve.accrue_interest(&key, &Clock { slot: 1_000_000, ..Clock::default() }).unwrap();
+        reserve.last_update.update_slot(1_000_000, false);
+        for amount in [1, 333_333, 2_000_000] {
+            let preview = reserve.deposit_preview(amount).unwrap();
+            assert_eq!(reserve.clone().deposit(amount).unwrap(), preview);
+        }
+        for amount in [1, 123_456, 250_000] {
+            let preview = reserve.withdraw_preview(amount).unwrap();
+            assert_eq!(reserve.clone().withdraw(amount).unwrap(), preview);
+        }
+
+        // withdraw all remaining sotoken
+        let borrowed_amount_wads = reserve.liquidity_info.borrowed_amount_wads;
+        reserve.liquidity_info.repay(&RepaySettle {
+            amount: borrowed_amount_wads.try_ceil_u64().unwrap(),
+            amount_decimal: borrowed_amount_wads,
+        }).unwrap();
+        let total_mint = reserve.collateral_info.total_mint;
+        let preview = reserve.withdraw_preview(total_mint).unwrap();
+        assert_eq!(reserve.withdraw(total_mint).unwrap(), preview);
+    }
+
+    #[test]
+    fn reserve_params_match_packed_reserve() {
+        let mut reserve = new_reserve();
+        reserve.collateral_info.config = CollateralConfig {
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            liquidation_penalty_ratio: 5,
+            full_liquidation_health_bps: 0,
+            borrower_liquidation_rebate_bps: 0,
+            max_post_liquidation_health_bps: 0,
+            permissioned: false,
+            max_concentration_ratio: 0,
+            bonus_by_collateral_bps: 0,
+            bonus_by_loan_bps: 0,
+            requires_companion_collateral: false,
+        };
+        reserve.liquidity_info.config.close_ratio = 50;
+        reserve.oracle_info.price = Decimal::from(12345u64);
+
+        let mut reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(reserve, &mut reserve_data).unwrap();
+        let reserve = MarketReserve::unpack(&reserve_data).unwrap();
+
+        let mut return_data = vec![0; ReserveParams::LEN];
+        ReserveParams::pack(ReserveParams::from(&reserve), &mut return_data).unwrap();
+        let params = ReserveParams::unpack_from_slice(&return_data).unwrap();
+
+        assert_eq!(params.borrow_value_ratio, 60);
+        assert_eq!(params.liquidation_value_ratio, 80);
+        assert_eq!(params.close_factor, 50);
+        assert_eq!(params.price, Decimal::from(12345u64));
+    }
+
+    #[test]
+    fn debt_oracle_packs_and_falls_back() {
+        let mut reserve = new_reserve();
+        reserve.oracle_info.price = Decimal::from(2u64);
+        assert_eq!(reserve.oracle_info.price_for_debt(), Decimal::from(2u64));
+
+        let debt_config = OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Switchboard };
+        reserve.last_update.update_slot(0, false);
+        reserve.operate(DebtOracleConfig(Some(debt_config))).unwrap();
+        assert!(reserve.last_update.stale);
+        assert_eq!(reserve.oracle_info.debt_price, Decimal::zero());
+        reserve.oracle_info.debt_price = Decimal::from(3u64);
+        assert_eq!(reserve.oracle_info.price_for_debt(), Decimal::from(3u64));
+
+        let mut reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(reserve.clone(), &mut reserve_data).unwrap();
+        assert_eq!(MarketReserve::unpack(&reserve_data).unwrap(), reserve);
+
+        reserve.operate(DebtOracleConfig(None)).unwrap();
+        assert_eq!(reserve.oracle_info.price_for_debt(), Decimal::from(2u64));
+    }
+
+    #[test]
+    fn total_borrows_originated_is_cumulative() {
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000_000).unwrap();
+
+        reserve.liquidity_info.borrow_out(100_000).unwrap();
+        reserve.liquidity_info.repay(&RepaySettle { amount: 100_000, amount_decimal: Decimal::from(100_000u64) }).unwrap();
+        reserve.liquidity_info.borrow_out(200_000).unwrap();
+        reserve.liquidity_info.repay(&RepaySettle { amount: 50_000, amount_decimal: Decimal::from(50_000u64) }).unwrap();
+        reserve.liquidity_info.borrow_out(300_000).unwrap();
+
+        assert_eq!(reserve.liquidity_info.total_borrows_originated, 600_000);
+        assert_eq!(reserve.liquidity_info.borrowed_amount_wads, Decimal::from(450_000u64));
+    }
+
+    #[test]
+    fn update_oracle_params_keeps_oracle() {
+        let mut reserve = new_reserve();
+        let oracle_config = OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Pyth };
+        reserve.operate(oracle_config).unwrap();
+
+        reserve.operate(OracleParams { stale_threshold: 60, ..OracleParams::default() }).unwrap();
+        assert_eq!(reserve.oracle_info.config, oracle_config);
+        assert_eq!(reserve.oracle_info.params.stale_threshold, 60);
+
+        let mut reserve_data = vec![0; MarketReserve::LEN];
+        MarketReserve::pack(reserve.clone(), &mut reserve_data).unwrap();
+        assert_eq!(MarketReserve::unpack(&reserve_data).unwrap(), reserve);
+
+        // confidence band wider than allowed deviation
+        assert_eq!(
+            reserve.operate(OracleParams { stale_threshold: 0, max_confidence_bps: 200, max_deviation_bps: 100 }).unwrap_err(),
+            LendingError::InvalidOracleParams.into(),
+        );
+        assert_eq!(reserve.oracle_info.params.stale_threshold, 60);
+    }
+
+    #[test]
+    fn collect_sotoken_to_insurance_keeps_exchange_rate() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        reserve.accrue_interest(&key, &Clock { slot: 1_000_000, ..Clock::default() }).unwrap();
+
+        let rate = reserve.collateral_to_liquidity_rate().unwrap();
+        let insurance_wads = reserve.liquidity_info.insurance_wads;
+        reserve.collect_sotoken_to_insurance(10_000).unwrap();
+        assert_eq!(reserve.collateral_info.total_mint, 990_000);
+        assert!(reserve.liquidity_info.insurance_wads > insurance_wads);
+        assert!(reserve.collateral_to_liquidity_rate().unwrap() >= rate);
+    }
+
+    #[test]
+    fn insurance_split_by_fee_type() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.liquidity_info.config.borrow_tax_rate = 10;
+        reserve.deposit(1_000_000).unwrap();
+
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        reserve.liquidity_info.charge_new_loan_fee(300).unwrap();
+        assert_eq!(reserve.liquidity_info.insurance_of(InsuranceBucket::BorrowFee).unwrap(), Decimal::from(300u64));
+
+        reserve.accrue_interest(&key, &Clock { slot: 1_000_000, ..Clock::default() }).unwrap();
+        let interest = reserve.liquidity_info.insurance_of(InsuranceBucket::Interest).unwrap();
+        assert!(interest > Decimal::zero());
+
+        let liquidation_fee = amount_mul_rate(10_000, reserve.collateral_to_liquidity_rate().unwrap()).unwrap();
+        reserve.collect_sotoken_to_insurance(10_000).unwrap();
+        assert_eq!(reserve.liquidity_info.liquidation_fee_insurance, liquidation_fee);
+        assert_eq!(reserve.liquidity_info.insurance_of(InsuranceBucket::Interest).unwrap(), interest);
+
+        reserve.liquidity_info.config.flash_loan_fee_rate = 7_000_000_000_000_000;
+        let (_, fee) = reserve.liquidity_info.flash_loan_borrow_out(1_000).unwrap();
+        reserve.liquidity_info.flash_loan_repay(1_000, fee).unwrap();
+        assert_eq!(reserve.liquidity_info.insurance_of(InsuranceBucket::FlashLoanFee).unwrap(), Decimal::from(7u64));
+
+        // targeted reduce only touches its bucket
+        assert_eq!(
+            reserve.liquidity_info.reduce_insurance_in(301, Some(InsuranceBucket::BorrowFee)).unwrap_err(),
+            LendingError::InsuranceBucketInsufficient.into(),
+        );
+        reserve.liquidity_info.reduce_insurance_in(100, Some(InsuranceBucket::BorrowFee)).unwrap();
+        assert_eq!(reserve.liquidity_info.borrow_fee_insurance, 200);
+        assert_eq!(reserve.liquidity_info.flash_loan_fee, 7);
+        assert_eq!(reserve.liquidity_info.insurance_of(InsuranceBucket::Interest).unwrap(), interest);
+
+        // untargeted drains flash loan fee and interest before borrow fee
+        let interest_floor = interest.try_floor_u64().unwrap();
+        reserve.liquidity_info.reduce_insurance(7 + interest_floor + 50).unwrap();
+        assert_eq!(reserve.liquidity_info.flash_loan_fee, 0);
+        assert_eq!(reserve.liquidity_info.borrow_fee_insurance, 150);
+        assert_eq!(reserve.liquidity_info.liquidation_fee_insurance, liquidation_fee);
+    }
+
+    #[test]
+    fn diff_reserves_reports_available_liquidity() {
+        let before = new_reserve();
+        assert!(diff_reserves(&before, &before).is_empty());
+
+        let mut after = before.clone();
+        after.liquidity_info.available = 1_000;
+
+        assert_eq!(
+            diff_reserves(&before, &after),
+            vec![("liquidity_info.available".to_string(), "0".to_string(), "1000".to_string())],
+        );
+    }
+
+    #[test]
+    fn reserve_factor_split_between_insurance_and_stakers() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.liquidity_info.config.borrow_tax_rate = 10;
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        let mut split_reserve = reserve.clone();
+        split_reserve.operate(StakerFeeConfig { ratio: 30, account: Pubkey::new_unique() }).unwrap();
+
+        reserve.accrue_interest(&key, &Clock { slot: 1_000_000, ..Clock::default() }).unwrap();
+        split_reserve.accrue_interest(&key, &Clock { slot: 1_000_000, ..Clock::default() }).unwrap();
+
+        // default goes all to insurance
+        let fee_wads = reserve.liquidity_info.insurance_wads;
+        assert_eq!(reserve.liquidity_info.staker_fee_wads, Decimal::zero());
+        assert_eq!(split_reserve.liquidity_info.staker_fee_wads, fee_wads.try_mul(Rate::from_percent(30)).unwrap());
+        assert_eq!(
+            split_reserve.liquidity_info.insurance_wads.try_add(split_reserve.liquidity_info.staker_fee_wads).unwrap(),
+            fee_wads,
+        );
+
+        let staker_fee = split_reserve.liquidity_info.staker_fee_wads.try_floor_u64().unwrap();
+        assert_eq!(split_reserve.liquidity_info.claim_staker_fee().unwrap(), staker_fee);
+        assert_eq!(split_reserve.liquidity_info.available, 500_000 - staker_fee);
+        assert!(split_reserve.liquidity_info.staker_fee_wads < Decimal::one());
+
+        assert_eq!(
+            split_reserve.operate(StakerFeeConfig { ratio: 101, account: Pubkey::new_unique() }).unwrap_err(),
+            LendingError::InvalidStakerFeeConfig.into(),
+        );
+    }
+
+    #[test]
+    fn tvl_sums_reserves_at_price() {
+        // 1_000 tokens (decimal 6) at 2, half borrowed
+        let mut reserve_0 = new_reserve();
+        reserve_0.token_config.decimal = 6;
+        reserve_0.liquidity_info.deposit(1_000_000_000).unwrap();
+        reserve_0.liquidity_info.borrow_out(500_000_000).unwrap();
+        // 3 tokens (decimal 9) at 100
+        let mut reserve_1 = new_reserve();
+        reserve_1.token_config.decimal = 9;
+        reserve_1.liquidity_info.deposit(3_000_000_000).unwrap();
+
+        let tvl = compute_tvl(&[
+            (reserve_0, Decimal::from(2u64)),
+            (reserve_1, Decimal::from(100u64)),
+        ]).unwrap();
+        assert_eq!(tvl, Decimal::from(2_300u64));
+        assert_eq!(compute_tvl(&[]).unwrap(), Decimal::zero());
+    }
+
+    #[test]
+    fn protocol_utilization_weighted_by_value() {
+        // 1_000 tokens (decimal 6) at 2, half borrowed: 1_000 of 2_000
+        let mut reserve_0 = new_reserve();
+        reserve_0.token_config.decimal = 6;
+        reserve_0.liquidity_info.deposit(1_000_000_000).unwrap();
+        reserve_0.liquidity_info.borrow_out(500_000_000).unwrap();
+        // 3 tokens (decimal 9) at 100, one borrowed: 100 of 300
+        let mut reserve_1 = new_reserve();
+        reserve_1.token_config.decimal = 9;
+        reserve_1.liquidity_info.deposit(3_000_000_000).unwrap();
+        reserve_1.liquidity_info.borrow_out(1_000_000_000).unwrap();
+
+        let utilization = protocol_utilization(&[
+            (reserve_0, Decimal::from(2u64)),
+            (reserve_1, Decimal::from(100u64)),
+        ]).unwrap();
+        assert_eq!(utilization, Decimal::from(1_100u64).try_div(2_300u64).unwrap());
+        assert_eq!(protocol_utilization(&[]).unwrap(), Decimal::zero());
+    }
+
+    #[test]
+    fn collateral_capacity_at_price() {
+        let mut reserve = new_reserve();
+        reserve.token_config.decimal = 6;
+        reserve.oracle_info.price = Decimal::from(2u64);
+        reserve.collateral_info.config.borrow_value_ratio = 60;
+        assert_eq!(reserve.collateral_capacity(0).unwrap(), Decimal::zero());
+        assert_eq!(reserve.collateral_capacity(100_000_000).unwrap(), Decimal::from(120u64));
+
+        // sotoken worth 1.5 liquidity after interest
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.available = 1_500_000;
+        assert_eq!(reserve.collateral_capacity(1_000_000).unwrap(), Decimal::from_scaled_val(1_800_000_000_000_000_000));
+    }
+
+    #[test]
+    fn utilization_gap_around_kink() {
+        // kink at 80%
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        assert_eq!(reserve.utilization_gap().unwrap(), (Decimal::from_percent(30), false));
+
+        reserve.liquidity_info.borrow_out(400_000).unwrap();
+        assert_eq!(reserve.utilization_gap().unwrap(), (Decimal::from_percent(10), true));
+    }
+
+    #[test]
+    fn liquidity_status_bands() {
+        // kink at 80%
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        assert_eq!(reserve.liquidity_status().unwrap(), LiquidityStatus::Healthy);
+        reserve.liquidity_info.borrow_out(300_000).unwrap();
+        assert_eq!(reserve.liquidity_status().unwrap(), LiquidityStatus::Tight);
+        reserve.liquidity_info.borrow_out(150_000).unwrap();
+        assert_eq!(reserve.liquidity_status().unwrap(), LiquidityStatus::Critical);
+
+        let bands = LiquidityStatusBands { tight_bps: 4000, critical_bps: 6000 };
+        reserve.operate(bands).unwrap();
+        reserve.liquidity_info.repay(&RepaySettle { amount: 450_000, amount_decimal: Decimal::from(450_000u64) }).unwrap();
+        assert_eq!(reserve.liquidity_status().unwrap(), LiquidityStatus::Tight);
+
+        assert_eq!(
+            reserve.operate(LiquidityStatusBands { tight_bps: 6000, critical_bps: 4000 }),
+            Err(LendingError::InvalidLiquidityStatusBands.into()),
+        );
+    }
+
+    #[test]
+    fn repay_rounding_gain_to_insurance() {
+        let mut reserve = new_reserve();
+        reserve.deposit(1_000).unwrap();
+        reserve.liquidity_info.borrow_out(100).unwrap();
+
+        // each full repay settles 0.3 borrowed by 1 token
+        for _ in 0..10 {
+            reserve.liquidity_info.repay(&RepaySettle {
+                amount: 1,
+                amount_decimal: Decimal::from_scaled_val(300_000_000_000_000_000),
+            }).unwrap();
+        }
+        assert_eq!(reserve.liquidity_info.insurance_wads, Decimal::from(7u64));
+        assert_eq!(reserve.liquidity_info.rounding_gains().unwrap(), Decimal::from(7u64));
+        assert_eq!(reserve.liquidity_info.borrowed_amount_wads, Decimal::from(97u64));
+
+        // exact repay and residual finer than 1e-9 token gain nothing
+        reserve.liquidity_info.repay(&RepaySettle { amount: 1, amount_decimal: Decimal::one() }).unwrap();
+        reserve.liquidity_info.repay(&RepaySettle {
+            amount: 1,
+            amount_decimal: Decimal::from_scaled_val(999_999_999_999_999_999),
+        }).unwrap();
+        assert_eq!(reserve.liquidity_info.rounding_gain_nanos, 7 * ROUNDING_GAIN_NANOS_PER_TOKEN);
+
+        // survives pack
+        let mut data = [0u8; MarketReserve::LEN];
+        MarketReserve::pack(reserve.clone(), &mut data).unwrap();
+        assert_eq!(MarketReserve::unpack(&data).unwrap().liquidity_info.rounding_gain_nanos, 7 * ROUNDING_GAIN_NANOS_PER_TOKEN);
+    }
+
+    #[test]
+    fn borrow_available_after_gates() {
+        let mut reserve = new_reserve();
+        reserve.liquidity_info.deposit(1_000).unwrap();
+        reserve.last_update.update_slot(100, false);
+        let clock = Clock { slot: 100, ..Clock::default() };
+        assert_eq!(reserve.borrow_available(&clock).unwrap(), 1_000);
+
+        reserve.operate(LiquidityControl(false)).unwrap();
+        assert_eq!(reserve.borrow_available(&clock).unwrap(), 0);
+        reserve.operate(LiquidityControl(true)).unwrap();
+
+        reserve.last_update.mark_stale();
+        assert_eq!(reserve.borrow_available(&clock).unwrap(), 0);
+        reserve.last_update.update_slot(100, false);
+
+        reserve.liquidity_info.borrow_out(400).unwrap();
+        assert_eq!(reserve.borrow_available(&clock).unwrap(), 600);
+    }
+
+    #[test]
+    fn transfer_insurance_between_reserves() {
+        let mut from = new_reserve();
+        from.liquidity_info.deposit(1_000).unwrap();
+        from.liquidity_info.insurance_wads = Decimal::from(100u64);
+        let mut to = new_reserve();
+        to.liquidity_info.deposit(500).unwrap();
+        to.collateral_info.total_mint = 400;
+        let to_rate = to.collateral_to_liquidity_rate().unwrap();
+
+        from.liquidity_info.borrow_fee_insurance = 30;
+        from.liquidity_info.flash_loan_fee = 5;
+
+        from.liquidity_info.transfer_insurance(&mut to.liquidity_info, 85).unwrap();
+        assert_eq!(from.liquidity_info.insurance_wads, Decimal::from(20u64));
+        assert_eq!(from.liquidity_info.borrow_fee_insurance, 20);
+        assert_eq!(from.liquidity_info.flash_loan_fee, 0);
+        assert_eq!(from.liquidity_info.available, 920);
+        // fees stay fees, only the interest share lands in interest
+        assert_eq!(to.liquidity_info.insurance_wads, Decimal::from(80u64));
+        assert_eq!(to.liquidity_info.insurance_of(InsuranceBucket::Interest).unwrap(), Decimal::from(70u64));
+        assert_eq!(to.liquidity_info.borrow_fee_insurance, 10);
+        assert_eq!(to.liquidity_info.flash_loan_fee, 5);
+        assert_eq!(to.liquidity_info.available, 580);
+        assert_eq!(to.collateral_to_liquidity_rate().unwrap(), to_rate);
+
+        let mut other = new_reserve();
+        assert_eq!(
+            from.liquidity_info.clone().transfer_insurance(&mut other.liquidity_info, 30).unwrap_err(),
+            LendingError::InsuranceDepleted.into(),
+        );
+        // insurance lent out stays in reserve
+        from.liquidity_info.borrow_out(910).unwrap();
+        assert_eq!(
+            from.liquidity_info.transfer_insurance(&mut other.liquidity_info, 20).unwrap_err(),
+            LendingError::MarketReserveInsufficentLiquidity.into(),
+        );
+    }
+
+    #[test]
+    fn sotoken_mint_maps_both_ways() {
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let reserves: Vec<(Pubkey, MarketReserve)> = keys
+            .iter()
+            .map(|key| {
+                let mut reserve = new_reserve();
+                reserve.token_config.mint_pubkey = Pubkey::new_unique();
+                reserve.collateral_info.sotoken_mint_pubkey = Pubkey::new_unique();
+                let mut reserve_data = vec![0; MarketReserve::LEN];
+                MarketReserve::pack(reserve, &mut reserve_data).unwrap();
+                (*key, MarketReserve::unpack(&reserve_data).unwrap())
+            })
+            .collect();
+
+        let sotoken_mint = sotoken_mint_for_reserve(&reserves[1].1);
+        assert_eq!(
+            reserve_for_sotoken_mint(&reserves, &sotoken_mint),
+            Some((keys[1], reserves[1].1.token_config.mint_pubkey)),
+        );
+        assert_eq!(reserve_for_sotoken_mint(&reserves, &Pubkey::new_unique()), None);
+    }
+
+    #[test]
+    fn sotoken_for_target_value() {
+        let mut reserve = new_reserve();
+        reserve.token_config.decimal = 6;
+        reserve.oracle_info.price = Decimal::from(4u64);
+        // sotoken worth 1.25 token
+        reserve.liquidity_info.deposit(1_250_000_000).unwrap();
+        reserve.collateral_info.total_mint = 1_000_000_000;
+
+        // 1000 / 4 / 1.25 = 200 token
+        let sotoken_amount = reserve.sotoken_for_value(Decimal::from(1_000u64)).unwrap();
+        assert_eq!(sotoken_amount, 200_000_000);
+        // worth exactly the target at full borrow value ratio
+        reserve.collateral_info.config.borrow_value_ratio = 100;
+        assert_eq!(reserve.collateral_capacity(sotoken_amount).unwrap(), Decimal::from(1_000u64));
+    }
+
+    #[test]
+    fn bootstrap_seeds_empty_reserve_one_to_one() {
+        let mut reserve = new_reserve();
+        // no deposit can front-run bootstrap of a fresh reserve
+        reserve.liquidity_info.awaiting_bootstrap = true;
+        assert_eq!(
+            reserve.deposit(1).unwrap_err(),
+            LendingError::MarketReserveNotBootstrapped.into(),
+        );
+        assert_eq!(reserve.bootstrap(1_000_000).unwrap(), 1_000_000);
+        assert_eq!(reserve.collateral_info.total_mint, 1_000_000);
+        assert_eq!(reserve.liquidity_info.available, 1_000_000);
+        assert_eq!(reserve.collateral_to_liquidity_rate().unwrap(), Rate::one());
+
+        assert_eq!(
+            reserve.bootstrap(1_000).unwrap_err(),
+            LendingError::MarketReserveNotEmpty.into(),
+        );
+        // later deposits keep the seeded rate
+        assert_eq!(reserve.deposit(500).unwrap(), 500);
+    }
+
+    #[test]
+    fn exchange_rate_through_reserve_states() {
+        let mut reserve = new_reserve();
+        assert_eq!(reserve.exchange_rate().unwrap(), Decimal::one());
+
+        reserve.deposit(1_000).unwrap();
+        assert_eq!(reserve.exchange_rate().unwrap(), Decimal::one());
+
+        // interest accrued lifts 1_000 sotoken to 1_100 liquidity
+        reserve.liquidity_info.borrow_out(100).unwrap();
+        reserve.liquidity_info.borrowed_amount_wads = Decimal::from(200u64);
+        assert_eq!(reserve.exchange_rate().unwrap(), Decimal::from(11u64).try_div(10u64).unwrap());
+        assert_eq!(reserve.withdraw_preview(100).unwrap(), 110);
+        assert_eq!(reserve.deposit_preview(110).unwrap(), 100);
+    }
+
+    #[test]
+    fn stale_reserves_for_keeper() {
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut fresh = new_reserve();
+        fresh.last_update.update_slot(100, false);
+        let mut old = new_reserve();
+        old.last_update.update_slot(99, false);
+        let marked = new_reserve();
+        let reserves = [(keys[0], fresh), (keys[1], old), (keys[2], marked)];
+        let clock = Clock { slot: 100, ..Clock::default() };
+
+        assert_eq!(stale_reserves(&reserves, &clock, true).unwrap(), vec![keys[1], keys[2]]);
+        assert_eq!(stale_reserves(&reserves, &clock, false).unwrap(), vec![keys[2]]);
+    }
+
+    #[test]
+    fn extended_legacy_reserve_reads_zero_bonus() {
+        let mut reserve = new_reserve();
+        reserve.collateral_info.config.bonus_by_loan_bps = 300;
+        let mut data = vec![0u8; MarketReserve::LEN];
+        MarketReserve::pack(reserve.clone(), &mut data).unwrap();
+        assert_eq!(MarketReserve::unpack(&data).unwrap(), reserve);
+
+        // bytes past the legacy size are zero right after realloc
+        data[MARKET_RESERVE_LEGACY_LEN..].iter_mut().for_each(|byte| *byte = 0);
+        reserve.collateral_info.config.bonus_by_loan_bps = 0;
+        reserve.liquidity_info.liquidation_backstop_budget = 0;
+        assert_eq!(MarketReserve::unpack(&data).unwrap(), reserve);
+        assert!(MarketReserve::unpack(&data[..MARKET_RESERVE_LEGACY_LEN]).is_err());
+    }
+
+    #[test]
+    fn health_ceiling_above_seized_liquidation_value() {
+        let mut config = CollateralConfig {
+            borrow_value_ratio: 70,
+            liquidation_value_ratio: 80,
+            liquidation_penalty_ratio: 5,
+            max_post_liquidation_health_bps: 11000,
+            ..CollateralConfig::default()
+        };
+        assert!(config.assert_valid().is_ok());
+
+        // 1.4 * 0.8 = 1.12 by loan reaches the ceiling
+        config.bonus_by_loan_bps = 4000;
+        assert_eq!(config.assert_valid(), Err(LendingError::InvalidCollateralConfig.into()));
+        config.max_post_liquidation_health_bps = 11300;
+        assert!(config.assert_valid().is_ok());
+        config.bonus_by_collateral_bps = 4200;
+        assert_eq!(config.assert_valid(), Err(LendingError::InvalidCollateralConfig.into()));
+    }
+
+    #[test]
+    fn compound_by_seconds_follows_wall_clock() {
+        let key = Pubkey::new_unique();
+        let mut reserve = new_reserve();
+        reserve.liquidity_info.config.compound_by_seconds = true;
+        reserve.deposit(1_000_000).unwrap();
+        reserve.liquidity_info.borrow_out(500_000).unwrap();
+        let mut by_slots = reserve.clone();
+
+        // no recorded start yet, first accrual goes by slots
+        reserve.accrue_interest(&key, &Clock { slot: 100, unix_timestamp: 1_000, ..Clock::default() }).unwrap();
+        assert_eq!(reserve.liquidity_info.last_accrue_timestamp, 1_000);
+        reserve.last_update.update_slot(100, false);
+        let borrow_index = reserve.liquidity_info.acc_borrow_rate_wads;
+
+        // 100 slots took 100 seconds instead of 40
+        let event = reserve.accrue_interest(&key, &Clock { slot: 200, unix_timestamp: 1_100, ..Clock::default() }).unwrap().unwrap();
+        let compounded = compound_over_seconds(event.borrow_rate, 100).unwrap();
+        assert_eq!(event.new_borrow_index, borrow_index.try_mul(compounded).unwrap());
+        assert!(compounded > Rate::one().try_add(event.borrow_rate).unwrap().try_pow(100).unwrap());
+
+        // clock skew accrues nothing and keeps the recorded start
+        reserve.last_update.update_slot(200, false);
+        let borrow_index = reserve.liquidity_info.acc_borrow_rate_wads;
+        reserve.accrue_interest(&key, &Clock { slot: 201, unix_timestamp: 1_050, ..Clock::default() }).unwrap();
+        assert_eq!(reserve.liquidity_info.acc_borrow_rate_wads, borrow_index);
+        assert_eq!(reserve.liquidity_info.last_accrue_timestamp, 1_100);
+
+        // flag off ignores the recorded start
+        by_slots.liquidity_info.config.compound_by_seconds = false;
+        by_slots.liquidity_info.last_accrue_timestamp = 1_000;
+        let event = by_slots.accrue_interest(&key, &Clock { slot: 100, unix_timestamp: 1_100, ..Clock::default() }).unwrap().unwrap();
+        assert_eq!(event.new_borrow_index, Decimal::one().try_mul(Rate::one().try_add(event.borrow_rate).unwrap().try_pow(100).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,184 @@
+#![allow(missing_docs)]
+use super::*;
+use crate::oracle::{OracleConfig, OracleType};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    entrypoint::ProgramResult,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+
+///
+pub const MAX_MEDIAN_SOURCES: usize = 5;
+/// fewer fresh prices can not outvote one outlier
+pub const MIN_MEDIAN_QUORUM: u8 = 3;
+
+/// Price feeds of an asset combined by median, referenced by market reserve oracle config
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MedianOracle {
+    pub version: u8,
+    pub manager: Pubkey,
+    /// min fresh sources to produce a price
+    pub quorum: u8,
+    pub sources: Vec<OracleConfig>,
+}
+
+impl MedianOracle {
+    ///
+    pub fn new(manager: Pubkey, quorum: u8, sources: Vec<OracleConfig>) -> Self {
+        Self {
+            version: PROGRAM_VERSION,
+            manager,
+            quorum,
+            sources,
+        }
+    }
+}
+
+impl Param for MedianOracle {
+    fn assert_valid(&self) -> ProgramResult {
+        // sources must be single feed accounts
+        let valid_sources = self.sources
+            .iter()
+            .all(|source| matches!(
+                source.oracle_type,
+                OracleType::Pyth | OracleType::ChainLink | OracleType::Switchboard,
+            ));
+
+        if valid_sources &&
+            self.sources.len() <= MAX_MEDIAN_SOURCES &&
+            self.quorum >= MIN_MEDIAN_QUORUM &&
+            self.quorum as usize <= self.sources.len() {
+            Ok(())
+        } else {
+            Err(LendingError::InvalidOracleParams.into())
+        }
+    }
+}
+
+impl Sealed for MedianOracle {}
+impl IsInitialized for MedianOracle {
+    fn is_initialized(&self) -> bool {
+        self.version != UNINITIALIZED_VERSION
+    }
+}
+
+const ORACLE_CONFIG_LEN: usize = PUBKEY_BYTES + 1;
+const MEDIAN_ORACLE_PADDING_LEN: usize = 64;
+const MEDIAN_ORACLE_LEN: usize = 264;
+
+impl Pack for MedianOracle {
+    const LEN: usize = MEDIAN_ORACLE_LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, MEDIAN_ORACLE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            manager,
+            quorum,
+            sources_len,
+            data_flatten,
+            _padding,
+        ) = mut_array_refs![
+            output,
+            1,
+            PUBKEY_BYTES,
+            1,
+            1,
+            ORACLE_CONFIG_LEN * MAX_MEDIAN_SOURCES,
+            MEDIAN_ORACLE_PADDING_LEN
+        ];
+
+        *version = self.version.to_le_bytes();
+        manager.copy_from_slice(self.manager.as_ref());
+        *quorum = self.quorum.to_le_bytes();
+        *sources_len = (self.sources.len() as u8).to_le_bytes();
+
+        data_flatten
+            .chunks_exact_mut(ORACLE_CONFIG_LEN)
+            .zip(self.sources.iter())
+            .for_each(|(data, source)| {
+                data[..PUBKEY_BYTES].copy_from_slice(source.oracle.as_ref());
+                data[PUBKEY_BYTES] = source.oracle_type.into();
+            });
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, MEDIAN_ORACLE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            version,
+            manager,
+            quorum,
+            sources_len,
+            data_flatten,
+            _padding,
+        ) = array_refs![
+            input,
+            1,
+            PUBKEY_BYTES,
+            1,
+            1,
+            ORACLE_CONFIG_LEN * MAX_MEDIAN_SOURCES,
+            MEDIAN_ORACLE_PADDING_LEN
+        ];
+
+        let version = u8::from_le_bytes(*version);
+        if version > PROGRAM_VERSION {
+            msg!("MedianOracle version does not match lending program version");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let sources_len = u8::from_le_bytes(*sources_len) as usize;
+        if sources_len > MAX_MEDIAN_SOURCES {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let sources = data_flatten[..sources_len * ORACLE_CONFIG_LEN]
+            .chunks_exact(ORACLE_CONFIG_LEN)
+            .map(|data| OracleConfig {
+                oracle: Pubkey::new_from_array(*array_ref![data, 0, PUBKEY_BYTES]),
+                oracle_type: OracleType::from(data[PUBKEY_BYTES]),
+            })
+            .collect();
+
+        Ok(Self {
+            version,
+            manager: Pubkey::new_from_array(*manager),
+            quorum: u8::from_le_bytes(*quorum),
+            sources,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn median_oracle_config_validation() {
+        let source = |oracle_type| OracleConfig { oracle: Pubkey::new_unique(), oracle_type };
+        let median_oracle = MedianOracle::new(
+            Pubkey::new_unique(),
+            3,
+            vec![source(OracleType::Pyth), source(OracleType::ChainLink), source(OracleType::Switchboard)],
+        );
+        assert_eq!(median_oracle.assert_valid(), Ok(()));
+
+        let mut data = vec![0; MedianOracle::LEN];
+        MedianOracle::pack(median_oracle.clone(), &mut data).unwrap();
+        assert_eq!(MedianOracle::unpack(&data).unwrap(), median_oracle);
+
+        let invalid = [
+            MedianOracle { quorum: 0, ..median_oracle.clone() },
+            MedianOracle { quorum: 2, ..median_oracle.clone() },
+            MedianOracle { quorum: 4, ..median_oracle.clone() },
+            MedianOracle { sources: vec![source(OracleType::SoToken)], quorum: 1, ..median_oracle.clone() },
+            MedianOracle { sources: vec![source(OracleType::Pyth); MAX_MEDIAN_SOURCES + 1], ..median_oracle },
+        ];
+        for median_oracle in invalid {
+            assert_eq!(median_oracle.assert_valid(), Err(LendingError::InvalidOracleParams.into()));
+        }
+    }
+}
@@ -5,6 +5,9 @@ mod last_update;
 mod market_reserve;
 mod user_obligation;
 mod rate_model;
+mod median_oracle;
+mod dex_oracle;
+mod deposit_whitelist;
 #[cfg(feature = "unique-credit")]
 mod unique_credit;
 
@@ -15,6 +18,9 @@ pub use last_update::*;
 pub use user_obligation::*;
 pub use market_reserve::*;
 pub use rate_model::*;
+pub use median_oracle::*;
+pub use dex_oracle::*;
+pub use deposit_whitelist::*;
 #[cfg(feature = "unique-credit")]
 pub use unique_credit::*;
 
@@ -142,11 +148,17 @@ fn unpack_bool(src: &[u8; 1]) -> Result<bool, ProgramError> {
     }
 }
 
+/// Max token decimals supported by market reserve
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
 #[inline(always)]
-pub fn calculate_decimals(decimal: u8) -> Result<u64, ProgramError> {
-    10u64
+pub fn calculate_decimals(decimal: u8) -> Result<u128, ProgramError> {
+    10u128
         .checked_pow(decimal as u32)
-        .ok_or(LendingError::MathOverflow.into())
+        .ok_or_else(|| {
+            msg!("Token decimals {} is out of range", decimal);
+            LendingError::MathOverflow.into()
+        })
 }
 
 #[inline(always)]
@@ -171,7 +183,7 @@ fn calculate_amount_and_decimal(amount: Option<u64>, max: Decimal) -> Result<(u6
 }
 
 #[inline(always)]
-pub fn calculate_effective_value(price: Decimal, amount: u64, decimals: u64, ratio: Rate) -> Result<Decimal, ProgramError> {
+pub fn calculate_effective_value(price: Decimal, amount: u64, decimals: u128, ratio: Rate) -> Result<Decimal, ProgramError> {
     price
         .try_mul(amount)?
         .try_div(decimals)?
@@ -187,13 +199,13 @@ pub struct RepaySettle {
     pub amount_decimal: Decimal,
 }
 
-pub struct ReservesRefVec<'a, 'b>(Vec<&'b(&'a Pubkey, MarketReserve)>);
+pub struct ReservesRefVec<'a, 'b>(Vec<(&'a Pubkey, &'b MarketReserve)>);
 
 impl<'a, 'b> ReservesRefVec<'a, 'b> {
     pub fn find_and_remove<E>(&mut self, reserve: &Pubkey, e: E) -> Result<&'b MarketReserve, E> {
         let index = self.0
             .iter()
-            .position(|(key, _)| key == &reserve)
+            .position(|(key, _)| *key == reserve)
             .ok_or(e)?;
 
         let (_, market_reserve) = self.0.remove(index);
@@ -201,3 +213,16 @@ impl<'a, 'b> ReservesRefVec<'a, 'b> {
         Ok(market_reserve)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn calculate_decimals_range() {
+        assert_eq!(calculate_decimals(0).unwrap(), 1);
+        assert_eq!(calculate_decimals(9).unwrap(), 1_000_000_000);
+        assert_eq!(calculate_decimals(MAX_TOKEN_DECIMALS).unwrap(), 1_000_000_000_000_000_000);
+        assert_eq!(calculate_decimals(39).unwrap_err(), LendingError::MathOverflow.into());
+    }
+}
@@ -3,7 +3,7 @@
 use std::convert::TryInto;
 use crate::error::LendingError;
 use solana_program::{
-    clock::{DEFAULT_TICKS_PER_SECOND, DEFAULT_TICKS_PER_SLOT, SECONDS_PER_DAY},
+    clock::{DEFAULT_TICKS_PER_SECOND, DEFAULT_TICKS_PER_SLOT, SECONDS_PER_DAY, Slot, UnixTimestamp},
     entrypoint::ProgramResult, 
     program_error::ProgramError,
 };
@@ -17,13 +17,16 @@ pub struct RateModel {
     pub optimal: u64,
     pub kink: u8,
     pub max: u128,
+    /// weight (percent) kept from previous borrow rate per elapsed slot, zero to disable smoothing
+    pub smoothing_factor: u8,
 }
 
 impl Param for RateModel {
     fn assert_valid(&self) -> ProgramResult {
         if self.optimal > self.offset &&
             self.max > self.optimal as u128 &&
-            self.kink > 0 && self.kink < 100 {
+            self.kink > 0 && self.kink < 100 &&
+            self.smoothing_factor < 100 {
             Ok(())
         } else {
             Err(LendingError::InvalidRateModel.into())
@@ -55,4 +58,69 @@ impl RateModel {
 
         borrow_rate_per_year.try_div(SLOTS_PER_YEAR)
     }
+    ///
+    // kept = factor ^ elapsed, smoothed = previous * kept + target * (1 - kept)
+    pub fn smooth_borrow_rate(&self, previous: Rate, target: Rate, elapsed: Slot) -> Result<Rate, ProgramError> {
+        if self.smoothing_factor == 0 {
+            Ok(target)
+        } else {
+            let kept = Rate::from_percent(self.smoothing_factor).try_pow(elapsed)?;
+            previous
+                .try_mul(kept)?
+                .try_add(target.try_mul(Rate::one().try_sub(kept)?)?)
+        }
+    }
+}
+
+/// per slot borrow rate compounded over wall clock seconds, calendar accurate whatever the slot time,
+/// zero or negative span (clock skew) accrues nothing
+pub fn compound_over_seconds(borrow_rate_per_slot: Rate, elapsed_seconds: UnixTimestamp) -> Result<Rate, ProgramError> {
+    if elapsed_seconds <= 0 {
+        return Ok(Rate::one());
+    }
+
+    let borrow_rate_per_second = borrow_rate_per_slot
+        .try_mul(DEFAULT_TICKS_PER_SECOND)?
+        .try_div(DEFAULT_TICKS_PER_SLOT)?;
+    Rate::one()
+        .try_add(borrow_rate_per_second)?
+        .try_pow(elapsed_seconds as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compound_over_calendar_year() {
+        // 10% a year, continuously compounded: e^0.1 ≈ 1.10517
+        let borrow_rate_per_slot = Rate::from_percent(10).try_div(SLOTS_PER_YEAR).unwrap();
+        let seconds_per_year = (SECONDS_PER_DAY * 365) as UnixTimestamp;
+        let compounded = compound_over_seconds(borrow_rate_per_slot, seconds_per_year).unwrap();
+        assert!(compounded > Rate::from_scaled_val(1_105_170_000_000_000_000));
+        assert!(compounded < Rate::from_scaled_val(1_105_171_000_000_000_000));
+
+        assert_eq!(compound_over_seconds(borrow_rate_per_slot, 0).unwrap(), Rate::one());
+        assert_eq!(compound_over_seconds(borrow_rate_per_slot, -5).unwrap(), Rate::one());
+    }
+
+    #[test]
+    fn smoothing_scales_with_elapsed_slots() {
+        let model = RateModel { smoothing_factor: 50, ..RateModel::default() };
+        let (previous, target) = (Rate::from_percent(10), Rate::from_percent(50));
+
+        assert_eq!(model.smooth_borrow_rate(previous, target, 1).unwrap(), Rate::from_percent(30));
+        // two slots at once match two single slot updates
+        assert_eq!(model.smooth_borrow_rate(previous, target, 2).unwrap(), Rate::from_percent(40));
+        assert_eq!(
+            model.smooth_borrow_rate(model.smooth_borrow_rate(previous, target, 1).unwrap(), target, 1).unwrap(),
+            Rate::from_percent(40),
+        );
+        // long idle span converges to target
+        let converged = model.smooth_borrow_rate(previous, target, 1_000).unwrap();
+        assert!(target.try_sub(converged).unwrap() < Rate::from_scaled_val(1_000));
+        // disabled
+        let model = RateModel { smoothing_factor: 0, ..model };
+        assert_eq!(model.smooth_borrow_rate(previous, target, 1).unwrap(), target);
+    }
 }
@@ -6,8 +6,9 @@ use crate::{
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
-    clock::Slot,
+    clock::{Clock, Slot},
     entrypoint::ProgramResult,
+    hash::hash,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::{Pubkey, PUBKEY_BYTES}
@@ -31,6 +32,8 @@ pub struct Collateral {
     pub amount: u64,
     pub borrow_value_ratio: u8,
     pub liquidation_value_ratio: u8,
+    /// slot of the last user pledge, zero for never
+    pub pledged_slot: Slot,
 }
 
 impl Collateral {
@@ -44,7 +47,7 @@ impl Collateral {
 
 impl Sealed for Collateral {}
 
-const COLLATERAL_PADDING_LEN: usize = 32;
+const COLLATERAL_PADDING_LEN: usize = 24;
 const COLLATERAL_LEN: usize = 74;
 
 impl Pack for Collateral {
@@ -58,6 +61,7 @@ impl Pack for Collateral {
             amount,
             borrow_value_ratio,
             liquidation_value_ratio,
+            pledged_slot,
             _padding,
         ) = mut_array_refs![
             output,
@@ -65,6 +69,7 @@ impl Pack for Collateral {
             8,
             1,
             1,
+            8,
             COLLATERAL_PADDING_LEN
         ];
 
@@ -72,6 +77,7 @@ impl Pack for Collateral {
         *amount = self.amount.to_le_bytes();
         *borrow_value_ratio = self.borrow_value_ratio.to_le_bytes();
         *liquidation_value_ratio = self.liquidation_value_ratio.to_le_bytes();
+        *pledged_slot = self.pledged_slot.to_le_bytes();
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
@@ -82,6 +88,7 @@ impl Pack for Collateral {
             amount,
             borrow_value_ratio,
             liquidation_value_ratio,
+            pledged_slot,
             _padding,
         ) = array_refs![
             input,
@@ -89,6 +96,7 @@ impl Pack for Collateral {
             8,
             1,
             1,
+            8,
             COLLATERAL_PADDING_LEN
         ];
 
@@ -97,6 +105,7 @@ impl Pack for Collateral {
             amount: u64::from_le_bytes(*amount),
             borrow_value_ratio: u8::from_le_bytes(*borrow_value_ratio),
             liquidation_value_ratio: u8::from_le_bytes(*liquidation_value_ratio),
+            pledged_slot: Slot::from_le_bytes(*pledged_slot),
         })
     }
 }
@@ -130,7 +139,7 @@ impl Loan {
     }
     ///
     fn calculate_loan_value(&self, reserve: &MarketReserve) -> Result<Decimal, ProgramError> {
-        reserve.oracle_info.price
+        reserve.oracle_info.price_for_debt()
             .try_mul(self.borrowed_amount_wads.try_ceil_u64()?)?
             .try_div(calculate_decimals(reserve.token_config.decimal)?)
     }
@@ -208,6 +217,43 @@ pub struct UserObligation {
     pub collaterals_liquidation_value: Decimal,
     pub loans: Vec<Loan>,
     pub loans_value: Decimal,
+    pub refresh_progress: Option<RefreshProgress>,
+    /// on manager allowlist, may pledge into permissioned reserves
+    pub whitelisted: bool,
+    /// part of `collaterals_borrow_value` from reserves not requiring companion collateral
+    pub companion_borrow_value: Decimal,
+    /// slot of the last refresh for borrow, 0 if none
+    pub borrow_refresh_slot: Slot,
+    /// hash of the borrow reserve that refresh was for
+    pub borrow_refresh_reserve_hash: u64,
+    /// slot of last large repay, kept when the loan is closed, for reserve borrow cooldown
+    pub last_repay_slot: Slot,
+}
+
+/// accumulated values of a refresh split across transactions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RefreshProgress {
+    /// slot when the first chunk was refreshed
+    pub slot: Slot,
+    /// hash of obligation entries when the refresh started
+    pub entries_hash: u64,
+    pub collaterals_mask: u16,
+    pub loans_mask: u16,
+    pub collaterals_borrow_value: Decimal,
+    pub collaterals_liquidation_value: Decimal,
+    pub loans_value: Decimal,
+    pub companion_borrow_value: Decimal,
+}
+
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObligationValues {
+    pub collaterals_value: Decimal,
+    pub collaterals_borrow_value: Decimal,
+    pub collaterals_liquidation_value: Decimal,
+    pub loans_value: Decimal,
+    /// borrow value of collaterals not requiring companion collateral
+    pub companion_borrow_value: Decimal,
 }
 
 impl UserObligation {
@@ -216,7 +262,7 @@ impl UserObligation {
         &self,
         other: Option<Self>,
         collateral_index: usize,
-    ) -> Result<Rate, ProgramError> {
+    ) -> Result<(Rate, Decimal, Decimal), ProgramError> {
         let (collaterals_liquidation_value, loans_value) = if let Some(other) = other {
             let collaterals_liquidation_value = self.collaterals_liquidation_value
                 .try_add(other.collaterals_liquidation_value)?;
@@ -245,7 +291,9 @@ impl UserObligation {
             //    ==================================================================
             let seize_rate = collaterals_liquidation_value
                 .try_div(loans_value.try_mul(Rate::from_percent(self.collaterals[collateral_index].liquidation_value_ratio))?)?;
-            Rate::try_from(seize_rate).map_err(|_| LendingError::LiquidationForbidden.into())
+            let seize_rate = Rate::try_from(seize_rate).map_err(|_| LendingError::LiquidationForbidden)?;
+
+            Ok((seize_rate, collaterals_liquidation_value, loans_value))
         } else {
             Err(LendingError::LiquidationNotAvailable.into())
         }
@@ -281,6 +329,112 @@ impl UserObligation {
             collaterals_liquidation_value: Decimal::zero(),
             loans: Vec::new(),
             loans_value: Decimal::zero(),
+            refresh_progress: None,
+            whitelisted: false,
+            companion_borrow_value: Decimal::zero(),
+            borrow_refresh_slot: 0,
+            borrow_refresh_reserve_hash: 0,
+            last_repay_slot: 0,
+        }
+    }
+    ///
+    pub fn assert_pledge_permitted(&self, reserve: &MarketReserve) -> ProgramResult {
+        if reserve.collateral_info.config.permissioned && !self.whitelisted {
+            Err(LendingError::ObligationNotWhitelisted.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// borrow value share of collateral at `index` after a pledge of `pledged`,
+    // a sole collateral is never capped, with other collaterals a pledge always raises the share
+    fn assert_collateral_concentration<const WITH_UPDATE_VALUE: bool>(
+        &self,
+        pledged: u64,
+        index: usize,
+        reserve: &MarketReserve,
+    ) -> ProgramResult {
+        let max_concentration_ratio = reserve.collateral_info.config.max_concentration_ratio;
+        if max_concentration_ratio == 0 || self.loans.is_empty() || self.collaterals.len() == 1 {
+            return Ok(());
+        }
+
+        let collateral_value = self.collateral_borrow_value_of(self.collaterals[index].amount, index, reserve)?;
+        let collaterals_value = self.collaterals_borrow_value_after::<WITH_UPDATE_VALUE>(pledged, index, reserve)?;
+
+        if collateral_value > collaterals_value.try_mul(Rate::from_percent(max_concentration_ratio))? {
+            Err(LendingError::CollateralConcentrationExceeded.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// collateral borrow value after a pledge of `pledged` against the manager cap
+    fn assert_collateral_exposure<const WITH_UPDATE_VALUE: bool>(
+        &self,
+        pledged: u64,
+        index: usize,
+        reserve: &MarketReserve,
+        max_collateral_value: u64,
+    ) -> ProgramResult {
+        if max_collateral_value == 0 {
+            return Ok(());
+        }
+
+        let collaterals_value = self.collaterals_borrow_value_after::<WITH_UPDATE_VALUE>(pledged, index, reserve)?;
+        if collaterals_value > Decimal::from(max_collateral_value) {
+            Err(LendingError::ObligationExposureCapExceeded.into())
+        } else {
+            Ok(())
+        }
+    }
+    ///
+    fn collateral_borrow_value_of(&self, amount: u64, index: usize, reserve: &MarketReserve) -> Result<Decimal, ProgramError> {
+        calculate_effective_value(
+            reserve.oracle_info.price,
+            amount_mul_rate(amount, reserve.collateral_to_liquidity_rate()?)?,
+            calculate_decimals(reserve.token_config.decimal)?,
+            Rate::from_percent(self.collaterals[index].borrow_value_ratio),
+        )
+    }
+    /// recorded values already include the pledge if `WITH_UPDATE_VALUE`
+    fn collaterals_borrow_value_after<const WITH_UPDATE_VALUE: bool>(
+        &self,
+        pledged: u64,
+        index: usize,
+        reserve: &MarketReserve,
+    ) -> Result<Decimal, ProgramError> {
+        if WITH_UPDATE_VALUE {
+            Ok(self.collaterals_borrow_value)
+        } else {
+            self.collaterals_borrow_value.try_add(self.collateral_borrow_value_of(pledged, index, reserve)?)
+        }
+    }
+    /// collateral reserves and the borrow reserve decide borrow health, other loan reserves may be lax fresh
+    pub fn needs_strict_reserve_for_borrow(&self, key: &Pubkey, borrow_reserve: &Pubkey) -> bool {
+        key == borrow_reserve || self.collaterals.iter().any(|collateral| &collateral.reserve == key)
+    }
+    ///
+    fn reserve_hash(key: &Pubkey) -> u64 {
+        u64::from_le_bytes(*array_ref![hash(key.as_ref()).to_bytes(), 0, 8])
+    }
+    /// values refreshed for borrowing from `borrow_reserve` only, stale for any other action
+    pub fn record_borrow_refresh(&mut self, slot: Slot, borrow_reserve: &Pubkey) {
+        self.last_update.update_slot(slot, true);
+        self.borrow_refresh_slot = slot;
+        self.borrow_refresh_reserve_hash = Self::reserve_hash(borrow_reserve);
+    }
+    /// full refresh within max age, or refresh for borrowing from `borrow_reserve` in this slot,
+    /// which is consumed by the borrow
+    pub fn take_fresh_for_borrow(&mut self, slot: Slot, borrow_reserve: &Pubkey, max_age_slots: u64) -> ProgramResult {
+        let borrow_refreshed = self.borrow_refresh_slot != 0 &&
+            self.borrow_refresh_slot == slot &&
+            self.borrow_refresh_reserve_hash == Self::reserve_hash(borrow_reserve);
+        self.borrow_refresh_slot = 0;
+        self.borrow_refresh_reserve_hash = 0;
+
+        if borrow_refreshed || !self.last_update.is_stale_after(slot, max_age_slots)? {
+            Ok(())
+        } else {
+            Err(LendingError::ObligationStale.into())
         }
     }
     ///
@@ -298,14 +452,30 @@ impl UserObligation {
             .ok_or(LendingError::ObligationCollateralNotFound.into())
     }
     ///
+    // interest accrued since loan's last accrual, rounded up
+    pub fn loan_accrued_interest(&self, index: usize, reserve: &MarketReserve) -> Result<u64, ProgramError> {
+        let loan = self.loans.get(index).ok_or(LendingError::ObligationLoanNotFound)?;
+        let mut accrued_loan = loan.clone();
+        accrued_loan.accrue_interest(reserve)?;
+
+        accrued_loan.borrowed_amount_wads
+            .try_sub(loan.borrowed_amount_wads)?
+            .try_ceil_u64()
+    }
+    ///
     #[cfg(feature = "friend")]
-    pub fn bind_friend(&mut self, other: Pubkey) -> ProgramResult {
-        if self.friend.is_none() {
-            self.friend = COption::Some(other);
+    pub fn bind_friend(&mut self, key: &Pubkey, other: &mut Self, other_key: &Pubkey) -> ProgramResult {
+        if key == other_key {
+            return Err(LendingError::ObligationInvalidFriend.into());
+        }
+
+        if self.friend.is_none() && other.friend.is_none() {
+            self.friend = COption::Some(*other_key);
+            other.friend = COption::Some(*key);
 
             Ok(())
         } else {
-            Err(LendingError::AlreadyBindFriend.into())
+            Err(LendingError::ObligationAlreadyBound.into())
         }
     }
     ///
@@ -321,57 +491,382 @@ impl UserObligation {
         }
     }
     ///
-    // need refresh reserves before
-    pub fn update_user_obligation(&mut self, reserves_vec: Vec<(&Pubkey, MarketReserve)>) -> ProgramResult {
-        let mut reserves_ref_vec = ReservesRefVec(reserves_vec.iter().collect());
+    /// health = collaterals borrow value / loans value, zero min health disables the check
+    pub fn validate_leverage_open_health(&self, other: Option<Self>, min_health_bps: u16) -> ProgramResult {
+        if min_health_bps == 0 {
+            return Ok(());
+        }
 
-        let (collaterals_borrow_value, collaterals_liquidation_value) = self.collaterals
+        let (collaterals_borrow_value, loans_value) = if let Some(other) = other {
+            let collaterals_borrow_value = self.collaterals_borrow_value
+                .try_add(other.collaterals_borrow_value)?;
+            let loans_value = self.loans_value.try_add(other.loans_value)?;
+
+            (collaterals_borrow_value, loans_value)
+        } else {
+            (self.collaterals_borrow_value, self.loans_value)
+        };
+
+        if collaterals_borrow_value >= loans_value.try_mul(Rate::from_bps(min_health_bps))? {
+            Ok(())
+        } else {
+            Err(LendingError::LeverageOpenTooRisky.into())
+        }
+    }
+    /// leverage of the opened pair = collateral value / (collateral value - loan value),
+    /// zero max leverage disables the check
+    pub fn validate_max_leverage(
+        &self,
+        collateral_key: &Pubkey,
+        collateral_reserve: &MarketReserve,
+        loan_key: &Pubkey,
+        loan_reserve: &MarketReserve,
+        max_leverage_x: u8,
+    ) -> ProgramResult {
+        if max_leverage_x == 0 {
+            return Ok(());
+        }
+
+        let collateral_value = self.collaterals[self.find_collateral(collateral_key)?]
+            .calculate_collateral_value(collateral_reserve)?;
+        let loan_value = self.loans[self.find_loan(loan_key)?]
+            .calculate_loan_value(loan_reserve)?;
+
+        if collateral_value > loan_value &&
+            collateral_value <= collateral_value.try_sub(loan_value)?.try_mul(max_leverage_x as u64)? {
+            Ok(())
+        } else {
+            Err(LendingError::MaxLeverageExceeded.into())
+        }
+    }
+    /// borrow from a reserve with cooldown is blocked for a while after a large repay
+    fn validate_borrow_cooldown(&self, slot: Slot, reserve: &MarketReserve) -> ProgramResult {
+        let cooldown_slots = reserve.liquidity_info.config.borrow_cooldown_slots;
+        if cooldown_slots > 0 && self.last_repay_slot > 0 && slot < self.last_repay_slot.saturating_add(cooldown_slots) {
+            msg!("Borrow is cooling down until slot {}", self.last_repay_slot.saturating_add(cooldown_slots));
+            Err(LendingError::BorrowCooldown.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// collateral pledged within `cooldown_slots` can not be borrowed against yet
+    pub fn validate_collateral_age(&self, slot: Slot, cooldown_slots: u8) -> ProgramResult {
+        if cooldown_slots == 0 {
+            return Ok(());
+        }
+
+        let fresh = self.collaterals
             .iter()
-            .try_fold((Decimal::zero(), Decimal::zero()),
-                |(acc_0, acc_1), collateral| -> Result<_, ProgramError> {
+            .any(|collateral| collateral.pledged_slot > 0 && slot < collateral.pledged_slot.saturating_add(cooldown_slots as Slot));
+        if fresh {
+            Err(LendingError::CollateralTooFresh.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// with collateral of reserves requiring companion collateral pledged,
+    /// the rest of collaterals must cover loans on its own
+    pub fn validate_companion_collateral(&self) -> ProgramResult {
+        if self.companion_borrow_value < self.collaterals_borrow_value && self.companion_borrow_value < self.loans_value {
+            Err(LendingError::RequiresCompanionCollateral.into())
+        } else {
+            Ok(())
+        }
+    }
+    /// part of collateral borrow value counted as companion, flag read from reserve
+    fn companion_value_of(borrow_value: Decimal, reserve: &MarketReserve) -> Decimal {
+        if reserve.collateral_info.config.requires_companion_collateral {
+            Decimal::zero()
+        } else {
+            borrow_value
+        }
+    }
+    ///
+    pub fn validate_loan_exposure(&self, max_loan_value: u64) -> ProgramResult {
+        if max_loan_value > 0 && self.loans_value > Decimal::from(max_loan_value) {
+            Err(LendingError::ObligationExposureCapExceeded.into())
+        } else {
+            Ok(())
+        }
+    }
+    ///
+    pub fn validate_friend_borrow_cap(&self, other: &Option<Self>, friend_borrow_value_cap: u64) -> ProgramResult {
+        if let Some(other) = other {
+            if friend_borrow_value_cap > 0 &&
+                self.loans_value.try_add(other.loans_value)? > Decimal::from(friend_borrow_value_cap) {
+                return Err(LendingError::FriendLeverageCapExceeded.into());
+            }
+        }
+
+        Ok(())
+    }
+    // need refresh reserves before, pure computation without mutating obligation,
+    // collateral valued below dust counts as zero but its entry is not deleted
+    pub fn compute_values(
+        &self,
+        reserves: &[(&Pubkey, &MarketReserve)],
+        collateral_dust_value: u64,
+    ) -> Result<ObligationValues, ProgramError> {
+        if reserves.iter().any(|(_, reserve)| reserve.manager != self.manager) {
+            msg!("User obligation manager provided is not matched with market reserve provided");
+            return Err(LendingError::UnmatchedAccounts.into());
+        }
+
+        let mut reserves_ref_vec = ReservesRefVec(reserves.to_vec());
+
+        let (collaterals_value, collaterals_borrow_value, collaterals_liquidation_value, companion_borrow_value) = self.collaterals
+            .iter()
+            .try_fold((Decimal::zero(), Decimal::zero(), Decimal::zero(), Decimal::zero()),
+                |(acc_0, acc_1, acc_2, acc_3), collateral| -> Result<_, ProgramError> {
                 let reserve = reserves_ref_vec
                     .find_and_remove(&collateral.reserve, LendingError::ObligationCollateralNotFound)?;
 
                 let collateral_value = collateral.calculate_collateral_value(reserve)?;
-                let borrow_effective_value = collateral_value
-                    .try_mul(Rate::from_percent(collateral.borrow_value_ratio))?
-                    .try_add(acc_0)?;
+                if collateral_value < Decimal::from(collateral_dust_value) {
+                    return Ok((acc_0, acc_1, acc_2, acc_3));
+                }
+                let borrow_value = collateral_value.try_mul(Rate::from_percent(collateral.borrow_value_ratio))?;
                 let liquidation_effective_value = collateral_value
                     .try_mul(Rate::from_percent(collateral.liquidation_value_ratio))?
-                    .try_add(acc_1)?;
-
-                Ok((borrow_effective_value, liquidation_effective_value))
+                    .try_add(acc_2)?;
+
+                Ok((
+                    collateral_value.try_add(acc_0)?,
+                    borrow_value.try_add(acc_1)?,
+                    liquidation_effective_value,
+                    Self::companion_value_of(borrow_value, reserve).try_add(acc_3)?,
+                ))
             })?;
 
-        let mut reserves_ref_vec = ReservesRefVec(reserves_vec.iter().collect());
+        let mut reserves_ref_vec = ReservesRefVec(reserves.to_vec());
 
-        self.collaterals_borrow_value = collaterals_borrow_value;
-        self.collaterals_liquidation_value = collaterals_liquidation_value;
-        self.loans_value = self.loans
-            .iter_mut()
+        let loans_value = self.loans
+            .iter()
             .try_fold(Decimal::zero(), |acc, loan| {
                 let reserve = reserves_ref_vec
                     .find_and_remove(&loan.reserve, LendingError::ObligationLoanNotFound)?;
 
+                let mut loan = loan.clone();
                 loan.accrue_interest(reserve)?;
                 loan
                     .calculate_loan_value(reserve)?
                     .try_add(acc)
             })?;
 
+        Ok(ObligationValues {
+            collaterals_value,
+            collaterals_borrow_value,
+            collaterals_liquidation_value,
+            loans_value,
+            companion_borrow_value,
+        })
+    }
+    /// values each entry adds to `compute_values`, collaterals first then loans in obligation order,
+    /// collateral entries fill borrow and liquidation value, loan entries fill loans value
+    #[cfg(not(target_arch = "bpf"))]
+    pub fn compute_contributions(
+        &self,
+        reserves: &[(&Pubkey, &MarketReserve)],
+        collateral_dust_value: u64,
+    ) -> Result<Vec<(Pubkey, ObligationValues)>, ProgramError> {
+        let collaterals = self.collaterals
+            .iter()
+            .map(|collateral| {
+                let entry = Self {
+                    collaterals: vec![collateral.clone()],
+                    loans: vec![],
+                    ..self.clone()
+                };
+                Ok((collateral.reserve, entry.compute_values(reserves, collateral_dust_value)?))
+            });
+        let loans = self.loans
+            .iter()
+            .map(|loan| {
+                let entry = Self {
+                    collaterals: vec![],
+                    loans: vec![loan.clone()],
+                    ..self.clone()
+                };
+                Ok((loan.reserve, entry.compute_values(reserves, collateral_dust_value)?))
+            });
+
+        collaterals.chain(loans).collect()
+    }
+    /// price of the collateral at which obligation becomes liquidatable, other prices fixed,
+    /// None if no loan or collateral has no liquidation value, zero if never liquidated by this price
+    pub fn liquidation_price(
+        &self,
+        collateral_index: usize,
+        reserves: &[(&Pubkey, &MarketReserve)],
+    ) -> Result<Option<Decimal>, ProgramError> {
+        if self.loans.is_empty() {
+            return Ok(None);
+        }
+
+        let collateral = self.collaterals
+            .get(collateral_index)
+            .ok_or(LendingError::ObligationCollateralNotFound)?;
+        let values = self.compute_values(reserves, 0)?;
+        let (_, reserve) = reserves
+            .iter()
+            .find(|(key, _)| *key == &collateral.reserve)
+            .ok_or(LendingError::ObligationCollateralNotFound)?;
+
+        let liquidation_value = collateral
+            .calculate_collateral_value(reserve)?
+            .try_mul(Rate::from_percent(collateral.liquidation_value_ratio))?;
+        if liquidation_value == Decimal::zero() {
+            return Ok(None);
+        }
+
+        let others_liquidation_value = values.collaterals_liquidation_value.try_sub(liquidation_value)?;
+        if others_liquidation_value >= values.loans_value {
+            return Ok(Some(Decimal::zero()));
+        }
+
+        // price * (loans value - others) / this collateral liquidation value
+        reserve.oracle_info.price
+            .try_mul(values.loans_value.try_sub(others_liquidation_value)?)?
+            .try_div(liquidation_value)
+            .map(Some)
+    }
+    ///
+    // need refresh reserves before
+    pub fn update_user_obligation(
+        &mut self,
+        reserves_vec: Vec<(&Pubkey, MarketReserve)>,
+        collateral_dust_value: u64,
+    ) -> ProgramResult {
+        let reserves = reserves_vec
+            .iter()
+            .map(|(key, reserve)| (*key, reserve))
+            .collect::<Vec<_>>();
+        let values = self.compute_values(&reserves, collateral_dust_value)?;
+
+        let mut reserves_ref_vec = ReservesRefVec(reserves);
+        for loan in self.loans.iter_mut() {
+            let reserve = reserves_ref_vec
+                .find_and_remove(&loan.reserve, LendingError::ObligationLoanNotFound)?;
+            loan.accrue_interest(reserve)?;
+        }
+
+        self.collaterals_borrow_value = values.collaterals_borrow_value;
+        self.collaterals_liquidation_value = values.collaterals_liquidation_value;
+        self.loans_value = values.loans_value;
+        self.companion_borrow_value = values.companion_borrow_value;
+        self.refresh_progress = None;
+        self.borrow_refresh_slot = 0;
+        self.borrow_refresh_reserve_hash = 0;
+
         Ok(())
     }
     ///
+    // collaterals (reserve and amount) and loan reserves, loan amounts excluded since covered loans accrue
+    fn entries_hash(&self) -> u64 {
+        let mut data = Vec::with_capacity(2 + MAX_OBLIGATION_RESERVES * (PUBKEY_BYTES + 8));
+        data.push(self.collaterals.len() as u8);
+        data.push(self.loans.len() as u8);
+        for collateral in self.collaterals.iter() {
+            data.extend_from_slice(collateral.reserve.as_ref());
+            data.extend_from_slice(&collateral.amount.to_le_bytes());
+        }
+        for loan in self.loans.iter() {
+            data.extend_from_slice(loan.reserve.as_ref());
+        }
+
+        u64::from_le_bytes(*array_ref![hash(&data).to_bytes(), 0, 8])
+    }
+    ///
+    // need refresh reserves before, accumulates entries of provided reserves only,
+    // returns slot the refresh started at once all entries are covered
+    pub fn update_user_obligation_partial(
+        &mut self,
+        reserves_vec: Vec<(&Pubkey, MarketReserve)>,
+        slot: Slot,
+        collateral_dust_value: u64,
+    ) -> Result<Option<Slot>, ProgramError> {
+        if reserves_vec.iter().any(|(_, reserve)| reserve.manager != self.manager) {
+            msg!("User obligation manager provided is not matched with market reserve provided");
+            return Err(LendingError::UnmatchedAccounts.into());
+        }
+
+        let entries_hash = self.entries_hash();
+        let mut progress = match self.refresh_progress {
+            Some(progress) if progress.entries_hash == entries_hash &&
+                slot.saturating_sub(progress.slot) <= STALE_AFTER_SLOTS_ELAPSED => progress,
+            _ => RefreshProgress {
+                slot,
+                entries_hash,
+                ..RefreshProgress::default()
+            },
+        };
+        self.last_update.mark_stale();
+
+        for (key, reserve) in reserves_vec.iter() {
+            if let Ok(index) = self.find_collateral(key) {
+                if progress.collaterals_mask & (1 << index) == 0 {
+                    let collateral = &self.collaterals[index];
+                    let collateral_value = collateral.calculate_collateral_value(reserve)?;
+                    if collateral_value >= Decimal::from(collateral_dust_value) {
+                        let borrow_value = collateral_value.try_mul(Rate::from_percent(collateral.borrow_value_ratio))?;
+                        progress.collaterals_borrow_value = progress.collaterals_borrow_value.try_add(borrow_value)?;
+                        progress.companion_borrow_value = progress.companion_borrow_value
+                            .try_add(Self::companion_value_of(borrow_value, reserve))?;
+                        progress.collaterals_liquidation_value = collateral_value
+                            .try_mul(Rate::from_percent(collateral.liquidation_value_ratio))?
+                            .try_add(progress.collaterals_liquidation_value)?;
+                    }
+                    progress.collaterals_mask |= 1 << index;
+                }
+            }
+
+            if let Ok(index) = self.find_loan(key) {
+                if progress.loans_mask & (1 << index) == 0 {
+                    let loan = &mut self.loans[index];
+                    loan.accrue_interest(reserve)?;
+                    progress.loans_value = loan
+                        .calculate_loan_value(reserve)?
+                        .try_add(progress.loans_value)?;
+                    progress.loans_mask |= 1 << index;
+                }
+            }
+        }
+
+        let collaterals_all = (1u16 << self.collaterals.len()) - 1;
+        let loans_all = (1u16 << self.loans.len()) - 1;
+        if progress.collaterals_mask == collaterals_all && progress.loans_mask == loans_all {
+            self.collaterals_borrow_value = progress.collaterals_borrow_value;
+            self.collaterals_liquidation_value = progress.collaterals_liquidation_value;
+            self.loans_value = progress.loans_value;
+            self.companion_borrow_value = progress.companion_borrow_value;
+            self.refresh_progress = None;
+
+            Ok(Some(progress.slot))
+        } else {
+            self.refresh_progress = Some(progress);
+
+            Ok(None)
+        }
+    }
+    ///
     // need refresh obligation before
+    #[allow(clippy::too_many_arguments)]
     pub fn borrow_in(
         &mut self,
         amount: Option<u64>,
         index: usize,
         reserve: &MarketReserve,
         other: Option<Self>,
+        friend_borrow_value_cap: u64,
+        max_loan_value: u64,
+        slot: Slot,
+        collateral_cooldown_slots: u8,
     ) -> Result<u64, ProgramError> {
+        self.validate_collateral_age(slot, collateral_cooldown_slots)?;
+        self.validate_borrow_cooldown(slot, reserve)?;
+
         let amount = calculate_amount(amount, reserve.liquidity_info.available);
-        let value = reserve.oracle_info.price
+        let value = reserve.oracle_info.price_for_debt()
             .try_mul(amount)?
             .try_div(calculate_decimals(reserve.token_config.decimal)?)?;
         self.loans_value = self.loans_value.try_add(value)?;
@@ -380,28 +875,122 @@ impl UserObligation {
             return Err(LendingError::BorrowTooSmall.into());
         }
         
+        self.validate_loan_exposure(max_loan_value)?;
+        self.validate_friend_borrow_cap(&other, friend_borrow_value_cap)?;
         self.validate_health(other)?;
+        self.validate_companion_collateral()?;
 
         self.loans[index].borrowed_amount_wads = self.loans[index].borrowed_amount_wads.try_add(Decimal::from(amount))?;
 
         Ok(amount)
     }
+    // charge fee for each new loan beyond the first
+    fn new_loan_fee(&self, reserve: &MarketReserve) -> u64 {
+        if self.loans.is_empty() { 0 } else { reserve.liquidity_info.config.new_loan_fee }
+    }
+    /// (fee, received) of a borrow from reserve `key`, fee is added to debt and received is never reduced
+    pub fn borrow_preview(&self, amount: Option<u64>, key: &Pubkey, reserve: &MarketReserve) -> (u64, u64) {
+        let received = calculate_amount(amount, reserve.liquidity_info.available);
+        let fee = if self.find_loan(key).is_ok() { 0 } else { self.new_loan_fee(reserve) };
+
+        (fee, received)
+    }
+    /// max amount of reserve `key` borrowable now after reserve gates and the same checks `borrow_in` runs,
+    /// zero if any gate rejects, obligation must be refreshed before
+    pub fn borrow_available(
+        &self,
+        key: &Pubkey,
+        reserve: &MarketReserve,
+        other: Option<Self>,
+        manager: &Manager,
+        clock: &Clock,
+    ) -> Result<u64, ProgramError> {
+        let available = reserve.borrow_available(clock)?;
+        if manager.paused ||
+            available == 0 ||
+            self.last_update.is_stale_after(clock.slot, manager.borrow_max_obligation_age_slots)? {
+            return Ok(0);
+        }
+
+        let try_borrow = |amount| {
+            let mut obligation = self.clone();
+            if let Ok(index) = obligation.find_loan(key) {
+                obligation.borrow_in(
+                    Some(amount),
+                    index,
+                    reserve,
+                    other.clone(),
+                    manager.friend_borrow_value_cap,
+                    manager.max_obligation_loan_value,
+                    clock.slot,
+                    manager.collateral_cooldown_slots,
+                ).map(|_| ())
+            } else {
+                obligation.new_borrow_in(
+                    Some(amount),
+                    *key,
+                    reserve,
+                    other.clone(),
+                    manager.friend_borrow_value_cap,
+                    manager.max_obligation_loan_value,
+                    manager.max_loans_per_obligation,
+                    clock.slot,
+                    manager.collateral_cooldown_slots,
+                ).map(|_| ())
+            }
+        };
+        // too small is the only rejection easing with amount, the others hold for any amount or grow with it
+        let fits = |amount| match try_borrow(amount) {
+            Ok(()) => true,
+            Err(err) => err == ProgramError::from(LendingError::BorrowTooSmall),
+        };
+
+        if !fits(1) {
+            return Ok(0);
+        }
+        let (mut low, mut high) = (1, available);
+        while low < high {
+            let mid = low + (high - low + 1) / 2;
+            if fits(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        Ok(if try_borrow(low).is_ok() { low } else { 0 })
+    }
     ///
     // need refresh obligation before
+    #[allow(clippy::too_many_arguments)]
     pub fn new_borrow_in(
         &mut self,
         amount: Option<u64>,
         key: Pubkey,
         reserve: &MarketReserve,
         other: Option<Self>,
-    ) -> Result<u64, ProgramError> {
+        friend_borrow_value_cap: u64,
+        max_loan_value: u64,
+        max_loans: u8,
+        slot: Slot,
+        collateral_cooldown_slots: u8,
+    ) -> Result<(u64, u64), ProgramError> {
+        self.validate_collateral_age(slot, collateral_cooldown_slots)?;
+        self.validate_borrow_cooldown(slot, reserve)?;
         if self.collaterals.len() + self.loans.len() >= MAX_OBLIGATION_RESERVES {
             return Err(LendingError::ObligationReservesFull.into());
         }
+        if max_loans > 0 && self.loans.len() >= max_loans as usize {
+            return Err(LendingError::LoanDiversityCapExceeded.into());
+        }
 
         let amount = calculate_amount(amount, reserve.liquidity_info.available);
-        let value = reserve.oracle_info.price
-            .try_mul(amount)?
+        let fee = self.new_loan_fee(reserve);
+        let debt = amount
+            .checked_add(fee)
+            .ok_or(LendingError::MathOverflow)?;
+        let value = reserve.oracle_info.price_for_debt()
+            .try_mul(debt)?
             .try_div(calculate_decimals(reserve.token_config.decimal)?)?;
         self.loans_value = self.loans_value.try_add(value)?;
 
@@ -409,16 +998,19 @@ impl UserObligation {
             return Err(LendingError::BorrowTooSmall.into());
         }
 
+        self.validate_loan_exposure(max_loan_value)?;
+        self.validate_friend_borrow_cap(&other, friend_borrow_value_cap)?;
         self.validate_health(other)?;
+        self.validate_companion_collateral()?;
 
         self.loans.push(Loan{
             reserve: key,
             acc_borrow_rate_wads: reserve.liquidity_info.acc_borrow_rate_wads,
-            borrowed_amount_wads: Decimal::from(amount),
+            borrowed_amount_wads: Decimal::from(debt),
             close_ratio: reserve.liquidity_info.config.close_ratio,
         });
 
-        Ok(amount)
+        Ok((amount, fee))
     }
     ///
     // need accure reserve and obligation interest before
@@ -429,8 +1021,10 @@ impl UserObligation {
         index: usize,
         reserve: &MarketReserve,
     ) -> Result<RepaySettle, ProgramError> {
-        let (amount, amount_decimal) =
-            calculate_amount_and_decimal(amount, self.loans[index].borrowed_amount_wads.min(Decimal::from(balance)))?;
+        // amount covering the whole loan or balance is settled as max, the excess is never transferred
+        let max = self.loans[index].borrowed_amount_wads.min(Decimal::from(balance));
+        let amount = amount.filter(|amount| Decimal::from(*amount) < max);
+        let (amount, amount_decimal) = calculate_amount_and_decimal(amount, max)?;
 
         self.loans[index].borrowed_amount_wads = self.loans[index].borrowed_amount_wads
             .try_sub(amount_decimal)
@@ -441,7 +1035,7 @@ impl UserObligation {
         }
 
         if WITH_UPDATE_VALUE {
-            let value = reserve.oracle_info.price
+            let value = reserve.oracle_info.price_for_debt()
                 .try_mul(amount)?
                 .try_div(calculate_decimals(reserve.token_config.decimal)?)?;
             self.loans_value = self.loans_value.try_sub(value)?;
@@ -452,6 +1046,56 @@ impl UserObligation {
             amount_decimal
         })
     }
+    /// net off pledged collateral against loan of the same reserve,
+    /// returns (sotoken amount burned, repay settle)
+    pub fn repay_from_pledged_collateral(
+        &mut self,
+        amount: Option<u64>,
+        collateral_index: usize,
+        loan_index: usize,
+        reserve: &MarketReserve,
+        other: Option<Self>,
+    ) -> Result<(u64, RepaySettle), ProgramError> {
+        let collateral_liquidity = reserve.withdraw_preview(self.collaterals[collateral_index].amount)?;
+        let settle = self.repay::<true>(amount, collateral_liquidity, loan_index, reserve)?;
+        if settle.amount == 0 {
+            return Err(LendingError::InvalidAmount.into());
+        }
+
+        // round burn up so the reserve never loses
+        let burn_amount = Decimal::from(settle.amount)
+            .try_mul(reserve.liquidity_to_collateral_rate()?)?
+            .try_ceil_u64()?
+            .min(self.collaterals[collateral_index].amount);
+        let burn_amount = self.redeem::<true, false>(Some(burn_amount), collateral_index, reserve, None)?;
+        self.validate_health(other)?;
+
+        Ok((burn_amount, settle))
+    }
+    /// call after a user pledge, starts collateral cooldown
+    pub fn record_pledge(&mut self, key: &Pubkey, slot: Slot) {
+        if let Ok(index) = self.find_collateral(key) {
+            self.collaterals[index].pledged_slot = slot;
+        }
+    }
+    /// call after any repay incl. liquidation, a fully repaid loan is closed and always counts as large
+    pub fn record_repay(&mut self, key: &Pubkey, repaid: Decimal, slot: Slot, reserve: &MarketReserve) -> ProgramResult {
+        if reserve.liquidity_info.config.borrow_cooldown_slots == 0 {
+            return Ok(());
+        }
+
+        let large = if let Ok(index) = self.find_loan(key) {
+            let borrowed_before = self.loans[index].borrowed_amount_wads.try_add(repaid)?;
+            repaid >= borrowed_before.try_mul(Rate::from_percent(reserve.liquidity_info.config.large_repay_ratio))?
+        } else {
+            true
+        };
+        if large {
+            self.last_repay_slot = slot;
+        }
+
+        Ok(())
+    }
     /// mark stale later
     pub fn pledge<const WITH_UPDATE_VALUE: bool>(
         &mut self,
@@ -459,7 +1103,9 @@ impl UserObligation {
         amount: Option<u64>,
         index: usize,
         reserve: &MarketReserve,
+        max_collateral_value: u64,
     ) -> Result<u64, ProgramError> {
+        self.assert_pledge_permitted(reserve)?;
         let amount = calculate_amount(amount, balance);
         self.collaterals[index].amount = self.collaterals[index].amount
             .checked_add(amount)
@@ -475,7 +1121,10 @@ impl UserObligation {
             )?;
             // update value
             self.collaterals_borrow_value = self.collaterals_borrow_value.try_add(changed_value)?;
+            self.companion_borrow_value = self.companion_borrow_value.try_add(Self::companion_value_of(changed_value, reserve))?;
         }
+        self.assert_collateral_concentration::<WITH_UPDATE_VALUE>(amount, index, reserve)?;
+        self.assert_collateral_exposure::<WITH_UPDATE_VALUE>(amount, index, reserve, max_collateral_value)?;
 
         Ok(amount)
     }
@@ -486,7 +1135,9 @@ impl UserObligation {
         amount: Option<u64>,
         key: Pubkey,
         reserve: &MarketReserve,
+        max_collateral_value: u64,
     ) -> Result<u64, ProgramError> {
+        self.assert_pledge_permitted(reserve)?;
         if self.collaterals.len() + self.loans.len() >= MAX_OBLIGATION_RESERVES {
             Err(LendingError::ObligationReservesFull.into())
         } else {
@@ -496,6 +1147,7 @@ impl UserObligation {
                 amount,
                 borrow_value_ratio: reserve.collateral_info.config.borrow_value_ratio,
                 liquidation_value_ratio: reserve.collateral_info.config.liquidation_value_ratio,
+                pledged_slot: 0,
             });
 
             if WITH_UPDATE_VALUE {
@@ -508,7 +1160,11 @@ impl UserObligation {
                 )?;
                 // update value
                 self.collaterals_borrow_value = self.collaterals_borrow_value.try_add(changed_value)?;
+                self.companion_borrow_value = self.companion_borrow_value.try_add(Self::companion_value_of(changed_value, reserve))?;
             }
+            let index = self.collaterals.len() - 1;
+            self.assert_collateral_concentration::<WITH_UPDATE_VALUE>(amount, index, reserve)?;
+            self.assert_collateral_exposure::<WITH_UPDATE_VALUE>(amount, index, reserve, max_collateral_value)?;
 
             Ok(amount)
         }
@@ -542,9 +1198,11 @@ impl UserObligation {
         )?;
         // update value
         self.collaterals_borrow_value = self.collaterals_borrow_value.try_sub(changed_borrow_value)?;
+        self.companion_borrow_value = self.companion_borrow_value.try_sub(Self::companion_value_of(changed_borrow_value, reserve))?;
 
         if WITH_VALIDATE {
             self.validate_health(other)?;
+            self.validate_companion_collateral()?;
         }
 
         Ok(amount)
@@ -584,6 +1242,33 @@ impl UserObligation {
         }
     }
     ///
+    // need accure reserve and obligation interest before
+    pub fn repay_all_and_redeem_all(
+        &mut self,
+        balance: u64,
+        loan_index: usize,
+        collateral_index: usize,
+        loan_reserve: &MarketReserve,
+        other: Option<Self>,
+    ) -> Result<(RepaySettle, u64), ProgramError> {
+        if self.loans.len() != 1 || self.collaterals.len() != 1 {
+            return Err(LendingError::ObligationNotSinglePair.into());
+        }
+
+        let settle = self.repay::<false>(None, balance, loan_index, loan_reserve)?;
+        if !self.loans.is_empty() {
+            return Err(LendingError::ObligationHasDept.into());
+        }
+        let amount = self.redeem_without_loan(None, collateral_index, other)?;
+
+        self.collaterals_borrow_value = Decimal::zero();
+        self.collaterals_liquidation_value = Decimal::zero();
+        self.loans_value = Decimal::zero();
+        self.companion_borrow_value = Decimal::zero();
+
+        Ok((settle, amount))
+    }
+    ///
     // need refresh obligation before
     #[allow(clippy::too_many_arguments)]
     pub fn replace_collateral(
@@ -595,7 +1280,9 @@ impl UserObligation {
         out_reserve: &MarketReserve,
         in_reserve: &MarketReserve,
         other: Option<Self>,
+        max_collateral_value: u64,
     ) -> Result<(u64, u64), ProgramError> {
+        self.assert_pledge_permitted(in_reserve)?;
         let in_amount = calculate_amount(in_amount, balance);
         let out_amount = self.collaterals[out_index].amount;
         let out_borrow_value_ratio = Rate::from_percent(self.collaterals[out_index].borrow_value_ratio);
@@ -606,6 +1293,7 @@ impl UserObligation {
             amount: in_amount,
             borrow_value_ratio: in_reserve.collateral_info.config.borrow_value_ratio,
             liquidation_value_ratio: in_reserve.collateral_info.config.liquidation_value_ratio,
+            pledged_slot: 0,
         });
 
         let out_borrow_value = calculate_effective_value(
@@ -624,12 +1312,20 @@ impl UserObligation {
         self.collaterals_borrow_value = self.collaterals_borrow_value
             .try_sub(out_borrow_value)?
             .try_add(in_borrow_value)?;
-
+        self.companion_borrow_value = self.companion_borrow_value
+            .try_sub(Self::companion_value_of(out_borrow_value, out_reserve))?
+            .try_add(Self::companion_value_of(in_borrow_value, in_reserve))?;
+
+        // recorded values already include the replacement
+        let in_index = self.collaterals.len() - 1;
+        self.assert_collateral_concentration::<true>(in_amount, in_index, in_reserve)?;
+        self.assert_collateral_exposure::<true>(in_amount, in_index, in_reserve, max_collateral_value)?;
         self.validate_health(other)?;
+        self.validate_companion_collateral()?;
 
         Ok((in_amount, out_amount))
     }
-    ///
+    /// returns (seize amount, repay settle, shortfall)
     // need refresh obligation before
     #[allow(clippy::too_many_arguments)]
     pub fn liquidate<const IS_COLLATERAL: bool>(
@@ -640,18 +1336,99 @@ impl UserObligation {
         collateral_reserve: &MarketReserve,
         loan_reserve: &MarketReserve,
         other: Option<Self>,
-    ) -> Result<(u64, RepaySettle), ProgramError> {
+    ) -> Result<(u64, RepaySettle, u64), ProgramError> {
+        self.liquidate_in::<IS_COLLATERAL>(amount, collateral_index, loan_index, collateral_reserve, loan_reserve, other, false)
+    }
+    /// emergency lets repay reach the full loan regardless of close factor
+    #[allow(clippy::too_many_arguments)]
+    pub fn liquidate_in<const IS_COLLATERAL: bool>(
+        &mut self,
+        amount: Option<u64>,
+        collateral_index: usize,
+        loan_index: usize,
+        collateral_reserve: &MarketReserve,
+        loan_reserve: &MarketReserve,
+        other: Option<Self>,
+        emergency: bool,
+    ) -> Result<(u64, RepaySettle, u64), ProgramError> {
         // check valid
-        let seize_rate = self.validate_liquidation(other, collateral_index)?;
+        let (seize_rate, collaterals_liquidation_value, loans_value) = self.validate_liquidation(other, collateral_index)?;
+        let health_factor = collaterals_liquidation_value.try_div(loans_value)?;
         // get optimal seize rate
-        let optimal_seize_rate = Rate::from_percent(collateral_reserve.collateral_info.config.liquidation_penalty_ratio)
-            .try_add(Rate::one())?
-            .min(seize_rate);
+        let bonus_seize_rate = collateral_reserve.collateral_info.config.liquidation_seize_rate(IS_COLLATERAL)?;
+        let optimal_seize_rate = bonus_seize_rate.min(seize_rate);
+        // part of full bonus credited to borrower as debt reduction, paid by liquidator on top of repay
+        let rebate_rate = Rate::from_bps(collateral_reserve.collateral_info.config.borrower_liquidation_rebate_bps);
+        let debt_reduction_rate = if optimal_seize_rate == bonus_seize_rate && optimal_seize_rate > Rate::one() {
+            optimal_seize_rate
+                .try_sub(Rate::one())?
+                .try_mul(rebate_rate)?
+                .try_add(Rate::one())?
+        } else {
+            Rate::one()
+        };
+        // close factor escalates to 100% for deeply underwater positions
+        let full_liquidation_health_bps = collateral_reserve.collateral_info.config.full_liquidation_health_bps;
+        let close_ratio = if emergency || (full_liquidation_health_bps > 0 &&
+            health_factor < Decimal::from(Rate::from_bps(full_liquidation_health_bps))) {
+            Rate::one()
+        } else {
+            Rate::from_percent(loan_reserve.liquidity_info.config.close_ratio)
+        };
+        // partial liquidation stops once borrower is back at the health ceiling, full close bypasses it
+        let max_post_health_bps = collateral_reserve.collateral_info.config.max_post_liquidation_health_bps;
+        let ceiling_repay_amount_decimal = if close_ratio < Rate::one() && max_post_health_bps > 0 {
+            // c: health ceiling   a_k, κ, m, n as in `validate_liquidation`, v: repay value   d: debt reduction rate
+            // (∑ (a_i * m_i) - v * κ * a_k) / (∑ n_i - v * d) <= c  =>  v <= (c * ∑ n_i - ∑ (a_i * m_i)) / (c * d - κ * a_k)
+            let ceiling = Decimal::from(Rate::from_bps(max_post_health_bps));
+            let seized_liquidation_rate = Decimal::from(optimal_seize_rate
+                .try_mul(Rate::from_percent(self.collaterals[collateral_index].liquidation_value_ratio))?);
+            let ceiling_debt_reduction = ceiling.try_mul(Decimal::from(debt_reduction_rate))?;
+            // c * d <= κ * a_k: every repay lowers health, the ceiling is never reached
+            if ceiling_debt_reduction > seized_liquidation_rate {
+                let repay_value = ceiling
+                    .try_mul(loans_value)?
+                    .try_sub(collaterals_liquidation_value)?
+                    .try_div(ceiling_debt_reduction.try_sub(seized_liquidation_rate)?)?;
+
+                Some(repay_value
+                    .try_mul(calculate_decimals(loan_reserve.token_config.decimal)?)?
+                    .try_div(loan_reserve.oracle_info.price_for_debt())?)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        let seize_amount_of = |repay_amount_decimal: Decimal| -> Result<u64, ProgramError> {
+            loan_reserve.oracle_info.price_for_debt()
+                .try_mul(repay_amount_decimal)?
+                .try_div(calculate_decimals(loan_reserve.token_config.decimal)?)?
+                .try_mul(optimal_seize_rate)?
+                .try_mul(calculate_decimals(collateral_reserve.token_config.decimal)?)?
+                .try_div(collateral_reserve.oracle_info.price)?
+                .try_div(collateral_reserve.collateral_to_liquidity_rate()?)?
+                .try_floor_u64()
+        };
 
-        if IS_COLLATERAL {
+        let (seize_amount, settle) = if IS_COLLATERAL {
             // input amount represents collateral
             let seize_amount = calculate_amount(amount, self.collaterals[collateral_index].amount);
 
+            // calculate repay amount
+            let repay_amount_decimal = collateral_reserve.oracle_info.price
+                .try_mul(amount_mul_rate(seize_amount, collateral_reserve.collateral_to_liquidity_rate()?)?)?
+                .try_div(calculate_decimals(collateral_reserve.token_config.decimal)?)?
+                .try_div(optimal_seize_rate)?
+                .try_mul(calculate_decimals(loan_reserve.token_config.decimal)?)?
+                .try_div(loan_reserve.oracle_info.price_for_debt())?;
+            // clamp to health ceiling, borrower keeps the rest
+            let (seize_amount, repay_amount_decimal) = match ceiling_repay_amount_decimal {
+                Some(ceiling_repay_amount_decimal) if repay_amount_decimal > ceiling_repay_amount_decimal =>
+                    (seize_amount_of(ceiling_repay_amount_decimal)?, ceiling_repay_amount_decimal),
+                _ => (seize_amount, repay_amount_decimal),
+            };
+
             // update collteral amount
             self.collaterals[collateral_index].amount = self.collaterals[collateral_index].amount
                 .checked_sub(seize_amount)
@@ -660,20 +1437,12 @@ impl UserObligation {
                 self.collaterals.remove(collateral_index);
             }
 
-            // calculate repay amount
-            let repay_amount_decimal = collateral_reserve.oracle_info.price
-                .try_mul(amount_mul_rate(seize_amount, collateral_reserve.collateral_to_liquidity_rate()?)?)?
-                .try_div(calculate_decimals(collateral_reserve.token_config.decimal)?)?
-                .try_div(optimal_seize_rate)?
-                .try_mul(calculate_decimals(loan_reserve.token_config.decimal)?)?
-                .try_div(loan_reserve.oracle_info.price)?;
-
             // repay amount check
             if repay_amount_decimal == Decimal::zero() {
                 return Err(LendingError::LiquidationRepayTooSmall.into());
             }
             let max_repay_amount_decimal = self.loans[loan_index].borrowed_amount_wads
-                .try_mul(Rate::from_percent(loan_reserve.liquidity_info.config.close_ratio))?;
+                .try_mul(close_ratio)?;
             if repay_amount_decimal > max_repay_amount_decimal {
                 return Err(LendingError::LiquidationRepayTooMuch.into());
             }
@@ -681,15 +1450,23 @@ impl UserObligation {
             // update loans
             self.loans[loan_index].borrowed_amount_wads = self.loans[loan_index].borrowed_amount_wads.try_sub(repay_amount_decimal)?;
 
-            Ok((seize_amount, RepaySettle {
+            (seize_amount, RepaySettle {
                 amount: repay_amount_decimal.try_ceil_u64()?,
                 amount_decimal: repay_amount_decimal,
-            }))
+            })
         } else {
             // input amount represents loan
             // calculate repay amount
             let max_repay_amount_decimal = self.loans[loan_index].borrowed_amount_wads
-                .try_mul(Rate::from_percent(loan_reserve.liquidity_info.config.close_ratio))?;
+                .try_mul(close_ratio)?;
+            // clamp to health ceiling, borrower keeps the rest
+            let (amount, max_repay_amount_decimal) = match ceiling_repay_amount_decimal {
+                Some(ceiling_repay_amount_decimal) if ceiling_repay_amount_decimal < max_repay_amount_decimal => {
+                    let ceiling_repay_amount = ceiling_repay_amount_decimal.try_floor_u64()?;
+                    (amount.map(|amount| amount.min(ceiling_repay_amount)), ceiling_repay_amount_decimal)
+                }
+                _ => (amount, max_repay_amount_decimal),
+            };
             let (repay_amount, repay_amount_decimal) = calculate_amount_and_decimal(amount, max_repay_amount_decimal)?;
             if repay_amount_decimal > max_repay_amount_decimal {
                 return Err(LendingError::LiquidationRepayTooMuch.into());
@@ -702,14 +1479,7 @@ impl UserObligation {
             self.loans[loan_index].borrowed_amount_wads = self.loans[loan_index].borrowed_amount_wads.try_sub(repay_amount_decimal)?;
 
             // calculate seize amount
-            let seize_amount = loan_reserve.oracle_info.price
-                .try_mul(repay_amount)?
-                .try_div(calculate_decimals(loan_reserve.token_config.decimal)?)?
-                .try_mul(optimal_seize_rate)?
-                .try_mul(calculate_decimals(collateral_reserve.token_config.decimal)?)?
-                .try_div(collateral_reserve.oracle_info.price)?
-                .try_div(collateral_reserve.collateral_to_liquidity_rate()?)?
-                .try_floor_u64()?;
+            let seize_amount = seize_amount_of(Decimal::from(repay_amount))?;
             if seize_amount == 0 {
                 return Err(LendingError::LiquidationSeizeTooSmall.into());
             }
@@ -722,14 +1492,60 @@ impl UserObligation {
                 self.collaterals.remove(collateral_index);
             }
 
-            Ok((seize_amount, RepaySettle {
+            (seize_amount, RepaySettle {
                 amount: repay_amount,
                 amount_decimal: repay_amount_decimal,
-            }))
-        }
+            })
+        };
+
+        // collateral worth less than the repaid loan after the bonus,
+        // shortfall is the repay not paid back by seized collateral at the full bonus
+        let shortfall = if optimal_seize_rate < bonus_seize_rate {
+            let covered_amount_decimal = settle.amount_decimal
+                .try_mul(optimal_seize_rate)?
+                .try_div(bonus_seize_rate)?;
+            Decimal::from(settle.amount)
+                .try_sub(covered_amount_decimal)?
+                .try_floor_u64()?
+        } else {
+            0
+        };
+
+        // rebate only comes with the full bonus, so it never meets a shortfall
+        let settle = if debt_reduction_rate > Rate::one() {
+            let rebate_amount_decimal = settle.amount_decimal
+                .try_mul(debt_reduction_rate.try_sub(Rate::one())?)?
+                .min(self.loans[loan_index].borrowed_amount_wads);
+            self.loans[loan_index].borrowed_amount_wads = self.loans[loan_index].borrowed_amount_wads.try_sub(rebate_amount_decimal)?;
+            let amount_decimal = settle.amount_decimal.try_add(rebate_amount_decimal)?;
+
+            RepaySettle {
+                amount: amount_decimal.try_ceil_u64()?,
+                amount_decimal,
+            }
+        } else {
+            settle
+        };
+
+        Ok((seize_amount, settle, shortfall))
     }
 }
 
+/// sotoken of collateral worth the repaid loan, the seized amount above it is liquidation bonus
+pub fn calculate_repay_equivalent_sotoken(
+    settle: &RepaySettle,
+    collateral_reserve: &MarketReserve,
+    loan_reserve: &MarketReserve,
+) -> Result<u64, ProgramError> {
+    loan_reserve.oracle_info.price_for_debt()
+        .try_mul(settle.amount_decimal)?
+        .try_div(calculate_decimals(loan_reserve.token_config.decimal)?)?
+        .try_mul(calculate_decimals(collateral_reserve.token_config.decimal)?)?
+        .try_div(collateral_reserve.oracle_info.price)?
+        .try_div(collateral_reserve.collateral_to_liquidity_rate()?)?
+        .try_ceil_u64()
+}
+
 impl Sealed for UserObligation {}
 impl IsInitialized for UserObligation {
     fn is_initialized(&self) -> bool {
@@ -739,7 +1555,7 @@ impl IsInitialized for UserObligation {
 
 // const MAX_PADDING_LEN: usize = max(COLLATERAL_LEN, LOAN_LEN);
 const MAX_COLLATERAL_OR_LOAN_LEN: usize = LOAN_LEN;
-const USER_OBLIGATITION_PADDING_LEN: usize = 128;
+const USER_OBLIGATITION_PADDING_LEN: usize = 2;
 const USER_OBLIGATITION_LEN: usize = 1452;
 
 impl Pack for UserObligation {
@@ -760,6 +1576,20 @@ impl Pack for UserObligation {
             collaterals_len,
             loans_len,
             data_flatten,
+            refresh_progress_enable,
+            refresh_progress_slot,
+            refresh_progress_entries_hash,
+            refresh_progress_collaterals_mask,
+            refresh_progress_loans_mask,
+            refresh_progress_collaterals_borrow_value,
+            refresh_progress_collaterals_liquidation_value,
+            refresh_progress_loans_value,
+            whitelisted,
+            companion_borrow_value,
+            refresh_progress_companion_borrow_value,
+            borrow_refresh_slot,
+            borrow_refresh_reserve_hash,
+            last_repay_slot,
             _padding,
         ) = mut_array_refs![
             output,
@@ -774,6 +1604,20 @@ impl Pack for UserObligation {
             1,
             1,
             MAX_COLLATERAL_OR_LOAN_LEN * MAX_OBLIGATION_RESERVES,
+            1,
+            8,
+            8,
+            2,
+            2,
+            16,
+            16,
+            16,
+            1,
+            16,
+            16,
+            8,
+            8,
+            8,
             USER_OBLIGATITION_PADDING_LEN
         ];
 
@@ -799,6 +1643,22 @@ impl Pack for UserObligation {
             .chunks_exact_mut(LOAN_LEN)
             .zip(self.loans.iter())
             .for_each(|(data, loan)| loan.pack_into_slice(data));
+
+        let progress = self.refresh_progress.unwrap_or_default();
+        pack_bool(self.refresh_progress.is_some(), refresh_progress_enable);
+        *refresh_progress_slot = progress.slot.to_le_bytes();
+        *refresh_progress_entries_hash = progress.entries_hash.to_le_bytes();
+        *refresh_progress_collaterals_mask = progress.collaterals_mask.to_le_bytes();
+        *refresh_progress_loans_mask = progress.loans_mask.to_le_bytes();
+        pack_decimal(progress.collaterals_borrow_value, refresh_progress_collaterals_borrow_value);
+        pack_decimal(progress.collaterals_liquidation_value, refresh_progress_collaterals_liquidation_value);
+        pack_decimal(progress.loans_value, refresh_progress_loans_value);
+        pack_bool(self.whitelisted, whitelisted);
+        pack_decimal(self.companion_borrow_value, companion_borrow_value);
+        pack_decimal(progress.companion_borrow_value, refresh_progress_companion_borrow_value);
+        *borrow_refresh_slot = self.borrow_refresh_slot.to_le_bytes();
+        *borrow_refresh_reserve_hash = self.borrow_refresh_reserve_hash.to_le_bytes();
+        *last_repay_slot = self.last_repay_slot.to_le_bytes();
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
@@ -816,6 +1676,20 @@ impl Pack for UserObligation {
             collaterals_len,
             loans_len,
             data_flatten,
+            refresh_progress_enable,
+            refresh_progress_slot,
+            refresh_progress_entries_hash,
+            refresh_progress_collaterals_mask,
+            refresh_progress_loans_mask,
+            refresh_progress_collaterals_borrow_value,
+            refresh_progress_collaterals_liquidation_value,
+            refresh_progress_loans_value,
+            whitelisted,
+            companion_borrow_value,
+            refresh_progress_companion_borrow_value,
+            borrow_refresh_slot,
+            borrow_refresh_reserve_hash,
+            last_repay_slot,
             _padding,
         ) = array_refs![
             input,
@@ -830,6 +1704,20 @@ impl Pack for UserObligation {
             1,
             1,
             MAX_COLLATERAL_OR_LOAN_LEN * MAX_OBLIGATION_RESERVES,
+            1,
+            8,
+            8,
+            2,
+            2,
+            16,
+            16,
+            16,
+            1,
+            16,
+            16,
+            8,
+            8,
+            8,
             USER_OBLIGATITION_PADDING_LEN
         ];
 
@@ -863,6 +1751,25 @@ impl Pack for UserObligation {
             collaterals_liquidation_value: unpack_decimal(collaterals_liquidation_value),
             loans,
             loans_value: unpack_decimal(loans_value),
+            refresh_progress: if unpack_bool(refresh_progress_enable)? {
+                Some(RefreshProgress {
+                    slot: Slot::from_le_bytes(*refresh_progress_slot),
+                    entries_hash: u64::from_le_bytes(*refresh_progress_entries_hash),
+                    collaterals_mask: u16::from_le_bytes(*refresh_progress_collaterals_mask),
+                    loans_mask: u16::from_le_bytes(*refresh_progress_loans_mask),
+                    collaterals_borrow_value: unpack_decimal(refresh_progress_collaterals_borrow_value),
+                    collaterals_liquidation_value: unpack_decimal(refresh_progress_collaterals_liquidation_value),
+                    loans_value: unpack_decimal(refresh_progress_loans_value),
+                    companion_borrow_value: unpack_decimal(refresh_progress_companion_borrow_value),
+                })
+            } else {
+                None
+            },
+            whitelisted: unpack_bool(whitelisted)?,
+            companion_borrow_value: unpack_decimal(companion_borrow_value),
+            borrow_refresh_slot: Slot::from_le_bytes(*borrow_refresh_slot),
+            borrow_refresh_reserve_hash: u64::from_le_bytes(*borrow_refresh_reserve_hash),
+            last_repay_slot: Slot::from_le_bytes(*last_repay_slot),
         })
     }
 }
@@ -888,6 +1795,12 @@ impl<P: Any + Param> Operator<P> for UserObligation {
             return Ok(());
         }
 
+        if let Some(allowance) = <dyn Any>::downcast_ref::<ObligationAllowance>(&param) {
+            self.whitelisted = allowance.0;
+
+            return Ok(());
+        }
+
         unreachable!("unexpected param type");
     }
 }
@@ -930,3 +1843,1308 @@ impl Param for IndexedLoanConfig {
         }
     }
 }
+
+/// manager allowlist membership for permissioned reserves
+#[derive(Clone, Debug)]
+pub struct ObligationAllowance(pub bool);
+
+impl Param for ObligationAllowance {
+    fn assert_valid(&self) -> ProgramResult {
+        Ok(())
+    }
+}
+
+/// (obligation, health factor, liquidatable) of packed obligations for liquidation scanners,
+/// health factor is liquidation value over loans value, max for no loan, needs refresh reserves before
+#[cfg(not(target_arch = "bpf"))]
+pub fn obligations_health(
+    obligations: &[(Pubkey, &[u8])],
+    reserves: &[(&Pubkey, &MarketReserve)],
+    collateral_dust_value: u64,
+) -> Result<Vec<(Pubkey, Decimal, bool)>, ProgramError> {
+    obligations
+        .iter()
+        .map(|(key, data)| {
+            let values = UserObligation::unpack(data)?.compute_values(reserves, collateral_dust_value)?;
+            if values.loans_value == Decimal::zero() {
+                Ok((*key, Decimal::max_value(), false))
+            } else {
+                let health_factor = values.collaterals_liquidation_value.try_div(values.loans_value)?;
+                Ok((*key, health_factor, values.loans_value >= values.collaterals_liquidation_value))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::oracle::{OracleConfig, OracleType};
+
+    fn new_reserve(close_ratio: u8, full_liquidation_health_bps: u16) -> MarketReserve {
+        let mut reserve = MarketReserve::new(
+            0,
+            Pubkey::default(),
+            TokenConfig::default(),
+            OracleConfig { oracle: Pubkey::default(), oracle_type: OracleType::Pyth },
+            LiquidityConfig { close_ratio, ..LiquidityConfig::default() },
+            Pubkey::default(),
+            CollateralConfig {
+                borrow_value_ratio: 60,
+                liquidation_value_ratio: 80,
+                liquidation_penalty_ratio: 5,
+                full_liquidation_health_bps,
+                borrower_liquidation_rebate_bps: 0,
+                max_post_liquidation_health_bps: 0,
+                permissioned: false,
+                max_concentration_ratio: 0,
+                bonus_by_collateral_bps: 0,
+                bonus_by_loan_bps: 0,
+                requires_companion_collateral: false,
+            },
+            RateModel::default(),
+        );
+        reserve.oracle_info.price = Decimal::one();
+
+        reserve
+    }
+
+    fn new_obligation(collateral_amount: u64, borrowed_amount: u64) -> UserObligation {
+        let mut obligation = UserObligation::new(0, Pubkey::default(), Pubkey::default());
+        obligation.collaterals.push(Collateral {
+            reserve: Pubkey::new_unique(),
+            amount: collateral_amount,
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            pledged_slot: 0,
+        });
+        obligation.loans.push(Loan {
+            reserve: Pubkey::new_unique(),
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(borrowed_amount),
+            close_ratio: 50,
+        });
+        obligation.collaterals_borrow_value = Decimal::from(collateral_amount).try_mul(Rate::from_percent(60)).unwrap();
+        obligation.collaterals_liquidation_value = Decimal::from(collateral_amount).try_mul(Rate::from_percent(80)).unwrap();
+        obligation.loans_value = Decimal::from(borrowed_amount);
+
+        obligation
+    }
+
+    #[test]
+    fn mildly_underwater_liquidation_is_partial() {
+        let collateral_reserve = new_reserve(50, 9500);
+        let loan_reserve = new_reserve(50, 9500);
+
+        // health factor: 80 / 82 ≈ 0.975
+        let mut obligation = new_obligation(100, 82);
+        assert_eq!(
+            obligation.clone().liquidate::<false>(Some(42), 0, 0, &collateral_reserve, &loan_reserve, None).unwrap_err(),
+            LendingError::LiquidationRepayTooMuch.into(),
+        );
+
+        let (_, settle, _) = obligation.liquidate::<false>(Some(41), 0, 0, &collateral_reserve, &loan_reserve, None).unwrap();
+        assert_eq!(settle.amount, 41);
+    }
+
+    #[test]
+    fn liquidation_seizes_collateral_never_borrowed() {
+        // collateral A at price 2 is only pledged, loan B is only borrowed
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.oracle_info.price = Decimal::from(2u64);
+        let loan_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(1_000, 2_200);
+        obligation.collaterals.insert(0, Collateral {
+            reserve: Pubkey::new_unique(),
+            amount: 500,
+            borrow_value_ratio: 60,
+            liquidation_value_ratio: 80,
+            pledged_slot: 0,
+        });
+        // liquidation value: 500 * 0.8 + 1000 * 2 * 0.8 = 2000 against 2200 loans
+        obligation.collaterals_liquidation_value = Decimal::from(2_000u64);
+        assert!(obligation.loans.iter().all(|loan| loan.reserve != obligation.collaterals[1].reserve));
+
+        // repay 100 of B seizes 100 * 1.05 / 2 of A
+        let mut by_loan = obligation.clone();
+        let (seize_amount, settle, shortfall) = by_loan
+            .liquidate::<false>(Some(100), 1, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount, shortfall), (52, 100, 0));
+        assert_eq!(by_loan.collaterals[0].amount, 500);
+        assert_eq!(by_loan.collaterals[1].amount, 948);
+        assert_eq!(by_loan.loans[0].borrowed_amount_wads, Decimal::from(2_100u64));
+
+        // seize 42 of A repays 42 * 2 / 1.05 of B
+        let mut by_collateral = obligation;
+        let (seize_amount, settle, _) = by_collateral
+            .liquidate::<true>(Some(42), 1, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (42, 80));
+        assert_eq!(by_collateral.collaterals[1].amount, 958);
+        assert_eq!(by_collateral.loans[0].borrowed_amount_wads, Decimal::from(2_120u64));
+    }
+
+    #[test]
+    fn direct_liquidation_matches_liquidate_and_redeem() {
+        // sotoken worth 1.25 token
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.liquidity_info.deposit(1_250).unwrap();
+        collateral_reserve.collateral_info.total_mint = 1_000;
+        let loan_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(1_000, 1_100);
+        obligation.collaterals_liquidation_value = Decimal::from(1_000u64);
+
+        // standard: liquidator is minted sotoken, then redeems it
+        let (seize_amount, settle, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (84, 100));
+        let redeemed = collateral_reserve.clone().withdraw(seize_amount).unwrap();
+
+        // direct: seized sotoken is withdrawn in the same step, paying 100 * 1.05 at par prices
+        let (direct_seize_amount, direct_settle, _) = obligation
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        let collateral_amount = collateral_reserve.withdraw(direct_seize_amount).unwrap();
+        assert_eq!(direct_settle.amount_decimal, settle.amount_decimal);
+        assert_eq!(collateral_amount, redeemed);
+        assert_eq!(collateral_amount, 105);
+        assert_eq!(collateral_reserve.collateral_info.total_mint, 916);
+    }
+
+    #[test]
+    fn emergency_liquidation_bypasses_close_factor() {
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+
+        let obligation = new_obligation(100, 82);
+        assert_eq!(
+            obligation.clone().liquidate_in::<false>(Some(82), 0, 0, &collateral_reserve, &loan_reserve, None, false).unwrap_err(),
+            LendingError::LiquidationRepayTooMuch.into(),
+        );
+
+        let mut liquidated = obligation.clone();
+        let (seize_amount, settle, _) = liquidated
+            .liquidate_in::<false>(Some(82), 0, 0, &collateral_reserve, &loan_reserve, None, true)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (86, 82));
+        assert_eq!(liquidated.loans[0].borrowed_amount_wads, Decimal::zero());
+        // bonus is the seize above repay equivalent
+        assert_eq!(calculate_repay_equivalent_sotoken(&settle, &collateral_reserve, &loan_reserve).unwrap(), 82);
+    }
+
+    #[test]
+    fn liquidation_bonus_by_direction() {
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.collateral_info.config.bonus_by_collateral_bps = 800;
+        collateral_reserve.collateral_info.config.bonus_by_loan_bps = 300;
+        let loan_reserve = new_reserve(50, 0);
+        let obligation = new_obligation(100, 82);
+
+        // by loan: 20 * 1.03
+        let (seize_amount, settle, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(20), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (20, 20));
+        let (seize_amount, _, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(40), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(seize_amount, 41);
+
+        // by collateral: 27 / 1.08
+        let (seize_amount, settle, _) = obligation
+            .clone()
+            .liquidate::<true>(Some(27), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (27, 25));
+
+        // unset falls back to the shared penalty: 21 / 1.05
+        collateral_reserve.collateral_info.config.bonus_by_collateral_bps = 0;
+        let (_, settle, _) = obligation
+            .clone()
+            .liquidate::<true>(Some(21), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(settle.amount, 20);
+    }
+
+    #[test]
+    fn liquidation_clamped_at_health_ceiling() {
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.collateral_info.config.max_post_liquidation_health_bps = 11000;
+        let loan_reserve = new_reserve(50, 0);
+
+        // health factor: 800 / 820 ≈ 0.975, ceiling repay (1.1 * 820 - 800) / (1.1 - 1.05 * 0.8) ≈ 392.3
+        let obligation = new_obligation(1_000, 820);
+        let (seize_amount, settle, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(410), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (411, 392));
+        // borrower left just healthy: 589 * 0.8 / 428 ≈ 1.1009
+        let (seize_amount, settle, _) = obligation
+            .clone()
+            .liquidate::<true>(Some(430), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(seize_amount, 411);
+        assert!(settle.amount_decimal < Decimal::from(393u64));
+
+        let (_, settle, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(300), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(settle.amount, 300);
+
+        // full close bypasses the ceiling
+        collateral_reserve.collateral_info.config.full_liquidation_health_bps = 9900;
+        let (_, settle, _) = obligation
+            .clone()
+            .liquidate::<false>(Some(410), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(settle.amount, 410);
+    }
+
+    #[test]
+    fn liquidation_rebate_reduces_borrower_debt() {
+        let mut collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+
+        // health factor: 800 / 820 ≈ 0.975, seize rate capped by 5% penalty
+        let mut obligation = new_obligation(1_000, 820);
+        let (_, settle, _) = obligation
+            .clone()
+            .liquidate::<true>(Some(105), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(settle.amount_decimal, Decimal::from(100u64));
+
+        // half of the bonus credited to borrower: 100 + 5 * 50% off the debt, seize unchanged
+        collateral_reserve.collateral_info.config.borrower_liquidation_rebate_bps = 5000;
+        let (seize_amount, settle, shortfall) = obligation
+            .liquidate::<true>(Some(105), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(seize_amount, 105);
+        assert_eq!(settle.amount_decimal, Decimal::from(1025u64).try_div(10u64).unwrap());
+        assert_eq!(settle.amount, 103);
+        assert_eq!(shortfall, 0);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(7175u64).try_div(10u64).unwrap());
+
+        collateral_reserve.collateral_info.config.borrower_liquidation_rebate_bps = 5001;
+        assert_eq!(
+            collateral_reserve.collateral_info.config.assert_valid().unwrap_err(),
+            LendingError::InvalidCollateralConfig.into(),
+        );
+    }
+
+    #[test]
+    fn deeply_underwater_liquidation_is_full() {
+        let collateral_reserve = new_reserve(50, 9500);
+        let loan_reserve = new_reserve(50, 9500);
+
+        // health factor: 80 / 100 = 0.8
+        let mut obligation = new_obligation(100, 100);
+        let (seize_amount, settle, _) = obligation
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(settle.amount, 100);
+        assert_eq!(seize_amount, 100);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::zero());
+
+        // disabled escalation keeps the close factor
+        let collateral_reserve = new_reserve(50, 0);
+        let mut obligation = new_obligation(100, 100);
+        assert_eq!(
+            obligation.liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None).unwrap_err(),
+            LendingError::LiquidationRepayTooMuch.into(),
+        );
+    }
+
+    #[test]
+    fn underwater_liquidation_shortfall() {
+        let collateral_reserve = new_reserve(50, 9500);
+        let loan_reserve = new_reserve(50, 9500);
+
+        // seize rate: 1_000 * 0.8 / (820 * 0.8) > 1.05, full bonus paid by collateral
+        let (_, _, shortfall) = new_obligation(1_000, 820)
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!(shortfall, 0);
+
+        // seize rate: 90 * 0.8 / (100 * 0.8) = 0.9, bonus exceeds collateral value
+        let mut obligation = new_obligation(90, 100);
+        let (seize_amount, settle, shortfall) = obligation
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount), (90, 100));
+        assert!(obligation.collaterals.is_empty());
+        // liquidator short of 100 - 90 / 1.05 ≈ 14.3
+        assert_eq!(shortfall, 14);
+
+        let mut loan_reserve = loan_reserve;
+        loan_reserve.liquidity_info.available = 1_000;
+        loan_reserve.liquidity_info.insurance_wads = Decimal::from(10u64);
+        // insurance short of the shortfall
+        assert_eq!(
+            loan_reserve.liquidity_info.clone().cover_liquidation_shortfall(shortfall).unwrap_err(),
+            LendingError::InsuranceDepleted.into(),
+        );
+        // no budget, whole shortfall drawn from insurance
+        loan_reserve.liquidity_info.insurance_wads = Decimal::from(30u64);
+        let mut liquidity_info = loan_reserve.liquidity_info.clone();
+        assert_eq!(liquidity_info.cover_liquidation_shortfall(shortfall), Ok(()));
+        assert_eq!(liquidity_info.insurance_wads, Decimal::from(16u64));
+        assert_eq!(liquidity_info.available, 986);
+        assert_eq!(liquidity_info.liquidation_backstop_budget, u64::MAX);
+        // split liquidations share one budget
+        loan_reserve.liquidity_info.liquidation_backstop_budget = 20;
+        assert_eq!(loan_reserve.liquidity_info.cover_liquidation_shortfall(shortfall), Ok(()));
+        assert_eq!(loan_reserve.liquidity_info.liquidation_backstop_budget, 6);
+        assert_eq!(
+            loan_reserve.liquidity_info.cover_liquidation_shortfall(shortfall).unwrap_err(),
+            LendingError::InsuranceDepleted.into(),
+        );
+    }
+
+    #[test]
+    fn repay_all_and_redeem_all_empties_obligation() {
+        let loan_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(100, 50);
+        assert_eq!(
+            obligation.clone().repay_all_and_redeem_all(49, 0, 0, &loan_reserve, None).unwrap_err(),
+            LendingError::ObligationHasDept.into(),
+        );
+
+        let (settle, amount) = obligation.repay_all_and_redeem_all(60, 0, 0, &loan_reserve, None).unwrap();
+        assert_eq!(settle.amount, 50);
+        assert_eq!(amount, 100);
+        assert!(obligation.collaterals.is_empty());
+        assert!(obligation.loans.is_empty());
+        assert_eq!(obligation.loans_value, Decimal::zero());
+    }
+
+    #[test]
+    fn compute_values_matches_refresh() {
+        let (collateral_key, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.oracle_info.price = Decimal::from(2u64);
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.acc_borrow_rate_wads = Decimal::from_scaled_val(1_100_000_000_000_000_000);
+
+        let mut obligation = new_obligation(100, 50);
+        obligation.collaterals[0].reserve = collateral_key;
+        obligation.loans[0].reserve = loan_key;
+
+        let values = obligation
+            .compute_values(&[(&loan_key, &loan_reserve), (&collateral_key, &collateral_reserve)], 0)
+            .unwrap();
+        assert_eq!(values, ObligationValues {
+            collaterals_value: Decimal::from(200u64),
+            collaterals_borrow_value: Decimal::from(120u64),
+            collaterals_liquidation_value: Decimal::from(160u64),
+            loans_value: Decimal::from(55u64),
+            companion_borrow_value: Decimal::from(120u64),
+        });
+        // snapshot leaves obligation untouched
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(50u64));
+
+        obligation
+            .update_user_obligation(vec![(&collateral_key, collateral_reserve), (&loan_key, loan_reserve)], 0)
+            .unwrap();
+        assert_eq!(obligation.collaterals_borrow_value, values.collaterals_borrow_value);
+        assert_eq!(obligation.collaterals_liquidation_value, values.collaterals_liquidation_value);
+        assert_eq!(obligation.loans_value, values.loans_value);
+        assert_eq!(obligation.companion_borrow_value, values.companion_borrow_value);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(55u64));
+    }
+
+    #[test]
+    fn contributions_sum_to_values() {
+        let (collateral_key_a, collateral_key_b, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut collateral_reserve_a = new_reserve(50, 0);
+        collateral_reserve_a.oracle_info.price = Decimal::from(2u64);
+        let collateral_reserve_b = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(100, 120);
+        obligation.collaterals[0].reserve = collateral_key_a;
+        obligation.collaterals.push(Collateral {
+            reserve: collateral_key_b,
+            amount: 50,
+            borrow_value_ratio: 50,
+            liquidation_value_ratio: 70,
+            pledged_slot: 0,
+        });
+        obligation.loans[0].reserve = loan_key;
+
+        let reserves = [
+            (&collateral_key_a, &collateral_reserve_a),
+            (&collateral_key_b, &collateral_reserve_b),
+            (&loan_key, &loan_reserve),
+        ];
+        let contributions = obligation.compute_contributions(&reserves, 0).unwrap();
+        assert_eq!(contributions.len(), 3);
+        assert_eq!(contributions[0], (collateral_key_a, ObligationValues {
+            collaterals_value: Decimal::from(200u64),
+            collaterals_borrow_value: Decimal::from(120u64),
+            collaterals_liquidation_value: Decimal::from(160u64),
+            loans_value: Decimal::zero(),
+            companion_borrow_value: Decimal::from(120u64),
+        }));
+        assert_eq!(contributions[1].1.collaterals_liquidation_value, Decimal::from(35u64));
+        assert_eq!(contributions[2], (loan_key, ObligationValues {
+            loans_value: Decimal::from(120u64),
+            ..ObligationValues::default()
+        }));
+
+        let sum = contributions
+            .iter()
+            .fold(ObligationValues::default(), |acc, (_, values)| ObligationValues {
+                collaterals_value: acc.collaterals_value.try_add(values.collaterals_value).unwrap(),
+                collaterals_borrow_value: acc.collaterals_borrow_value.try_add(values.collaterals_borrow_value).unwrap(),
+                collaterals_liquidation_value: acc.collaterals_liquidation_value.try_add(values.collaterals_liquidation_value).unwrap(),
+                loans_value: acc.loans_value.try_add(values.loans_value).unwrap(),
+                companion_borrow_value: acc.companion_borrow_value.try_add(values.companion_borrow_value).unwrap(),
+            });
+        assert_eq!(sum, obligation.compute_values(&reserves, 0).unwrap());
+    }
+
+    #[test]
+    fn obligations_health_for_scanner() {
+        let (collateral_key, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+        let reserves = [(&collateral_key, &collateral_reserve), (&loan_key, &loan_reserve)];
+
+        let pack = |collateral_amount, borrowed_amount| {
+            let mut obligation = new_obligation(collateral_amount, borrowed_amount);
+            obligation.collaterals[0].reserve = collateral_key;
+            obligation.loans[0].reserve = loan_key;
+            if borrowed_amount == 0 {
+                obligation.loans.clear();
+            }
+            let mut data = vec![0u8; UserObligation::LEN];
+            UserObligation::pack(obligation, &mut data).unwrap();
+            data
+        };
+        let (healthy, liquidatable, no_loan) = (pack(100, 50), pack(100, 90), pack(100, 0));
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+
+        let health = obligations_health(
+            &[(keys[0], &healthy[..]), (keys[1], &liquidatable[..]), (keys[2], &no_loan[..])],
+            &reserves,
+            0,
+        ).unwrap();
+        assert_eq!(health, vec![
+            (keys[0], Decimal::from_percent(160), false),
+            (keys[1], Decimal::from(80u64).try_div(Decimal::from(90u64)).unwrap(), true),
+            (keys[2], Decimal::max_value(), false),
+        ]);
+
+        // undecodable data fails the batch
+        assert!(obligations_health(&[(keys[0], &[0u8; 4][..])], &reserves, 0).is_err());
+    }
+
+    #[test]
+    fn dust_collateral_excluded_from_values() {
+        let (collateral_key, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.oracle_info.price = Decimal::from(2u64);
+        let loan_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(100, 50);
+        obligation.collaterals[0].reserve = collateral_key;
+        obligation.loans[0].reserve = loan_key;
+
+        let reserves = [(&loan_key, &loan_reserve), (&collateral_key, &collateral_reserve)];
+        let values = obligation.compute_values(&reserves, 200).unwrap();
+        assert_eq!(values.collaterals_borrow_value, Decimal::from(120u64));
+
+        let values = obligation.compute_values(&reserves, 201).unwrap();
+        assert_eq!(values.collaterals_value, Decimal::zero());
+        assert_eq!(values.collaterals_borrow_value, Decimal::zero());
+        assert_eq!(values.collaterals_liquidation_value, Decimal::zero());
+
+        obligation
+            .update_user_obligation(vec![(&collateral_key, collateral_reserve), (&loan_key, loan_reserve)], 201)
+            .unwrap();
+        assert_eq!(obligation.collaterals_liquidation_value, Decimal::zero());
+        // still tracked
+        assert_eq!(obligation.collaterals.len(), 1);
+        assert_eq!(obligation.collaterals[0].amount, 100);
+    }
+
+    #[test]
+    fn repay_never_exceeds_loan_or_balance() {
+        let reserve = new_reserve(50, 0);
+        let mut obligation = new_obligation(100, 50);
+        obligation.loans[0].borrowed_amount_wads = Decimal::from(50u64).try_add(Decimal::from_scaled_val(1)).unwrap();
+
+        let settle = obligation.clone().repay::<false>(None, 1_000, 0, &reserve).unwrap();
+        assert_eq!(settle.amount, 51);
+        let settle = obligation.clone().repay::<false>(Some(500), 1_000, 0, &reserve).unwrap();
+        assert_eq!(settle.amount, 51);
+        let settle = obligation.clone().repay::<false>(Some(500), 30, 0, &reserve).unwrap();
+        assert_eq!(settle.amount, 30);
+
+        let settle = obligation.repay::<false>(Some(20), 1_000, 0, &reserve).unwrap();
+        assert_eq!(settle.amount, 20);
+        assert_eq!(obligation.loans.len(), 1);
+    }
+
+    #[test]
+    fn repay_from_pledged_collateral_nets_off_same_reserve() {
+        let reserve = new_reserve(50, 0);
+        let mut obligation = new_obligation(1_000, 300);
+        obligation.loans[0].reserve = obligation.collaterals[0].reserve;
+
+        let (burn_amount, settle) = obligation
+            .repay_from_pledged_collateral(Some(200), 0, 0, &reserve, None)
+            .unwrap();
+        assert_eq!((burn_amount, settle.amount), (200, 200));
+        assert_eq!(obligation.collaterals[0].amount, 800);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(100u64));
+        assert_eq!(obligation.collaterals_borrow_value, Decimal::from(480u64));
+        assert_eq!(obligation.loans_value, Decimal::from(100u64));
+
+        // max settles the whole loan
+        let (burn_amount, settle) = obligation
+            .repay_from_pledged_collateral(None, 0, 0, &reserve, None)
+            .unwrap();
+        assert_eq!((burn_amount, settle.amount), (100, 100));
+        assert_eq!(obligation.collaterals[0].amount, 700);
+        assert!(obligation.loans.is_empty());
+    }
+
+    #[test]
+    fn partial_refresh_across_two_chunks() {
+        let (collateral_key, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut collateral_reserve = new_reserve(50, 0);
+        collateral_reserve.oracle_info.price = Decimal::from(2u64);
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.acc_borrow_rate_wads = Decimal::from(2u64);
+
+        let mut obligation = new_obligation(100, 50);
+        obligation.collaterals[0].reserve = collateral_key;
+        obligation.loans[0].reserve = loan_key;
+        obligation.last_update.update_slot(0, false);
+        let expect = obligation
+            .compute_values(&[(&collateral_key, &collateral_reserve), (&loan_key, &loan_reserve)], 0)
+            .unwrap();
+
+        let slot = obligation
+            .update_user_obligation_partial(vec![(&collateral_key, collateral_reserve.clone())], 10, 0)
+            .unwrap();
+        assert_eq!(slot, None);
+        assert!(obligation.last_update.stale);
+        assert!(obligation.refresh_progress.is_some());
+
+        // repeated chunk is not counted twice
+        obligation
+            .update_user_obligation_partial(vec![(&collateral_key, collateral_reserve)], 11, 0)
+            .unwrap();
+        let slot = obligation
+            .update_user_obligation_partial(vec![(&loan_key, loan_reserve)], 12, 0)
+            .unwrap();
+        assert_eq!(slot, Some(10));
+        assert_eq!(obligation.refresh_progress, None);
+        assert_eq!(obligation.collaterals_borrow_value, expect.collaterals_borrow_value);
+        assert_eq!(obligation.collaterals_liquidation_value, expect.collaterals_liquidation_value);
+        assert_eq!(obligation.loans_value, expect.loans_value);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(100u64));
+    }
+
+    #[test]
+    fn partial_refresh_restarts_on_changed_entries() {
+        let collateral_key = Pubkey::new_unique();
+        let collateral_reserve = new_reserve(50, 0);
+
+        let mut obligation = new_obligation(100, 50);
+        obligation.collaterals[0].reserve = collateral_key;
+        obligation
+            .update_user_obligation_partial(vec![(&collateral_key, collateral_reserve)], 10, 0)
+            .unwrap();
+        let progress = obligation.refresh_progress.unwrap();
+        assert_eq!(progress.collaterals_mask, 1);
+
+        obligation.collaterals[0].amount = 200;
+        let mut packed = [0u8; USER_OBLIGATITION_LEN];
+        obligation.pack_into_slice(&mut packed);
+        let mut obligation = UserObligation::unpack_from_slice(&packed).unwrap();
+        assert_eq!(obligation.refresh_progress, Some(progress));
+
+        obligation.update_user_obligation_partial(vec![], 11, 0).unwrap();
+        let restarted = obligation.refresh_progress.unwrap();
+        assert_eq!(restarted.slot, 11);
+        assert_eq!(restarted.collaterals_mask, 0);
+    }
+
+    #[test]
+    fn compute_values_requires_all_reserves() {
+        let obligation = new_obligation(100, 50);
+        let collateral_key = obligation.collaterals[0].reserve;
+        let reserve = new_reserve(50, 0);
+
+        assert_eq!(
+            obligation.compute_values(&[(&collateral_key, &reserve)], 0).unwrap_err(),
+            LendingError::ObligationLoanNotFound.into(),
+        );
+    }
+
+    #[test]
+    fn debt_oracle_values_loans_asymmetrically() {
+        let reserve_key = Pubkey::new_unique();
+        let mut reserve = new_reserve(50, 0);
+        reserve.oracle_info.price = Decimal::from(2u64);
+
+        let mut obligation = new_obligation(100, 50);
+        obligation.collaterals[0].reserve = reserve_key;
+        obligation.loans[0].reserve = reserve_key;
+
+        let values = obligation.compute_values(&[(&reserve_key, &reserve)], 0).unwrap();
+        assert_eq!(values.collaterals_value, Decimal::from(200u64));
+        assert_eq!(values.loans_value, Decimal::from(100u64));
+
+        // collateral keeps primary price, debt uses the higher debt feed
+        reserve.oracle_info.debt_config = Some(OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Pyth });
+        reserve.oracle_info.debt_price = Decimal::from(3u64);
+        let values = obligation.compute_values(&[(&reserve_key, &reserve)], 0).unwrap();
+        assert_eq!(values.collaterals_value, Decimal::from(200u64));
+        assert_eq!(values.loans_value, Decimal::from(150u64));
+    }
+
+    #[test]
+    fn liquidation_values_debt_with_debt_oracle() {
+        let collateral_reserve = new_reserve(50, 0);
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.oracle_info.debt_config = Some(OracleConfig { oracle: Pubkey::new_unique(), oracle_type: OracleType::Pyth });
+        loan_reserve.oracle_info.debt_price = Decimal::from(2u64);
+
+        // health factor: 800 / (410 * 2) ≈ 0.975, seize valued at debt price: 100 * 2 * 1.05
+        let mut obligation = new_obligation(1_000, 410);
+        obligation.loans_value = Decimal::from(820u64);
+        let (seize_amount, settle, shortfall) = obligation
+            .liquidate::<false>(Some(100), 0, 0, &collateral_reserve, &loan_reserve, None)
+            .unwrap();
+        assert_eq!((seize_amount, settle.amount, shortfall), (210, 100, 0));
+        assert_eq!(calculate_repay_equivalent_sotoken(&settle, &collateral_reserve, &loan_reserve).unwrap(), 200);
+    }
+
+    #[test]
+    fn compute_values_rejects_foreign_reserve() {
+        let mut obligation = new_obligation(100, 50);
+        let (collateral_key, loan_key) = (obligation.collaterals[0].reserve, obligation.loans[0].reserve);
+        let collateral_reserve = new_reserve(50, 0);
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.manager = Pubkey::new_unique();
+
+        assert_eq!(
+            obligation.compute_values(&[(&collateral_key, &collateral_reserve), (&loan_key, &loan_reserve)], 0).unwrap_err(),
+            LendingError::UnmatchedAccounts.into(),
+        );
+        assert_eq!(
+            obligation.update_user_obligation(vec![(&collateral_key, collateral_reserve), (&loan_key, loan_reserve)], 0).unwrap_err(),
+            LendingError::UnmatchedAccounts.into(),
+        );
+    }
+
+    #[test]
+    fn leverage_open_health_margin() {
+        // borrow value 60, min health 110%
+        assert!(new_obligation(100, 54).validate_leverage_open_health(None, 11000).is_ok());
+        assert_eq!(
+            new_obligation(100, 55).validate_leverage_open_health(None, 11000).unwrap_err(),
+            LendingError::LeverageOpenTooRisky.into(),
+        );
+        // disabled
+        assert!(new_obligation(100, 60).validate_leverage_open_health(None, 0).is_ok());
+    }
+
+    #[test]
+    fn max_leverage_at_open() {
+        let reserve = new_reserve(50, 0);
+        let leverage = |collateral_amount, borrowed_amount, max_leverage_x| {
+            let obligation = new_obligation(collateral_amount, borrowed_amount);
+            let (collateral_key, loan_key) = (obligation.collaterals[0].reserve, obligation.loans[0].reserve);
+            obligation.validate_max_leverage(&collateral_key, &reserve, &loan_key, &reserve, max_leverage_x)
+        };
+
+        // 300 collateral on 100 equity is 3x
+        assert_eq!(leverage(300, 200, 3), Ok(()));
+        // 400 collateral on 100 equity is 4x
+        assert_eq!(leverage(400, 300, 3), Err(LendingError::MaxLeverageExceeded.into()));
+        assert_eq!(leverage(300, 300, 3), Err(LendingError::MaxLeverageExceeded.into()));
+        // disabled
+        assert_eq!(leverage(400, 300, 0), Ok(()));
+    }
+
+    #[test]
+    fn loan_accrued_interest_matches_accrual() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.acc_borrow_rate_wads = Decimal::from_scaled_val(1_012_300_000_000_000_000);
+
+        let mut obligation = new_obligation(100, 50);
+        let interest = obligation.loan_accrued_interest(0, &loan_reserve).unwrap();
+        // 50 * 0.0123 = 0.615, rounded up
+        assert_eq!(interest, 1);
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(50u64));
+
+        let before = obligation.loans[0].borrowed_amount_wads;
+        obligation.loans[0].accrue_interest(&loan_reserve).unwrap();
+        let realized = obligation.loans[0].borrowed_amount_wads.try_sub(before).unwrap();
+        assert_eq!(realized.try_ceil_u64().unwrap(), interest);
+        // nothing left once accrued
+        assert_eq!(obligation.loan_accrued_interest(0, &loan_reserve).unwrap(), 0);
+        assert_eq!(
+            obligation.loan_accrued_interest(1, &loan_reserve).unwrap_err(),
+            LendingError::ObligationLoanNotFound.into(),
+        );
+    }
+
+    #[test]
+    fn permissioned_reserve_requires_whitelist() {
+        let mut reserve = new_reserve(50, 0);
+        reserve.collateral_info.config.permissioned = true;
+        let key = Pubkey::new_unique();
+
+        let mut obligation = UserObligation::new(0, Pubkey::default(), Pubkey::default());
+        assert_eq!(
+            obligation.new_pledge::<false>(100, None, key, &reserve, 0).unwrap_err(),
+            LendingError::ObligationNotWhitelisted.into(),
+        );
+
+        obligation.operate(ObligationAllowance(true)).unwrap();
+        obligation.new_pledge::<false>(100, None, key, &reserve, 0).unwrap();
+
+        let mut packed = vec![0; UserObligation::LEN];
+        UserObligation::pack(obligation.clone(), &mut packed).unwrap();
+        assert_eq!(UserObligation::unpack(&packed).unwrap(), obligation);
+
+        obligation.operate(ObligationAllowance(false)).unwrap();
+        assert_eq!(
+            obligation.pledge::<false>(100, None, 0, &reserve, 0).unwrap_err(),
+            LendingError::ObligationNotWhitelisted.into(),
+        );
+        reserve.collateral_info.config.permissioned = false;
+        assert_eq!(obligation.pledge::<false>(100, None, 0, &reserve, 0).unwrap(), 100);
+    }
+
+    #[test]
+    fn collateral_concentration_capped() {
+        let mut reserve = new_reserve(50, 0);
+        reserve.collateral_info.config.max_concentration_ratio = 60;
+        let key = Pubkey::new_unique();
+
+        // existing collateral borrow value 60
+        let mut obligation = new_obligation(100, 50);
+        obligation.new_pledge::<true>(100, None, key, &reserve, 0).unwrap();
+        // 120 of 180 over 60%
+        assert_eq!(
+            obligation.clone().pledge::<true>(100, None, 1, &reserve, 0).unwrap_err(),
+            LendingError::CollateralConcentrationExceeded.into(),
+        );
+        // 84 of 144 within 60%
+        obligation.clone().pledge::<true>(40, None, 1, &reserve, 0).unwrap();
+        // same limits when replacing the other collateral
+        assert_eq!(
+            obligation.clone().replace_collateral(200, None, 0, Pubkey::new_unique(), &reserve, &reserve, None, 0).unwrap_err(),
+            LendingError::CollateralConcentrationExceeded.into(),
+        );
+        obligation.clone().replace_collateral(140, None, 0, Pubkey::new_unique(), &reserve, &reserve, None, 0).unwrap();
+
+        // no loan, no cap
+        let mut no_loan = obligation.clone();
+        no_loan.loans.clear();
+        no_loan.pledge::<true>(100, None, 1, &reserve, 0).unwrap();
+
+        // sole collateral can always be topped up to cure health
+        obligation.collaterals.remove(0);
+        obligation.collaterals_borrow_value = Decimal::from(60u64);
+        obligation.pledge::<true>(100, None, 0, &reserve, 0).unwrap();
+    }
+
+    #[test]
+    fn borrow_refresh_with_lax_loan_reserves() {
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+        let mut obligation = new_obligation(1_000, 100);
+        let other_loan_key = Pubkey::new_unique();
+        obligation.loans.push(Loan {
+            reserve: other_loan_key,
+            acc_borrow_rate_wads: Decimal::one(),
+            borrowed_amount_wads: Decimal::from(100u64),
+            close_ratio: 50,
+        });
+        let (collateral_key, borrow_key) = (obligation.collaterals[0].reserve, obligation.loans[0].reserve);
+
+        assert!(obligation.needs_strict_reserve_for_borrow(&collateral_key, &borrow_key));
+        assert!(obligation.needs_strict_reserve_for_borrow(&borrow_key, &borrow_key));
+        assert!(!obligation.needs_strict_reserve_for_borrow(&other_loan_key, &borrow_key));
+
+        // every position still covered, values refreshed
+        obligation.update_user_obligation(
+            vec![(&collateral_key, collateral_reserve.clone()), (&borrow_key, loan_reserve.clone()), (&other_loan_key, loan_reserve.clone())],
+            0,
+        ).unwrap();
+        assert_eq!(obligation.loans_value, Decimal::from(200u64));
+        obligation.borrow_in(Some(100), 0, &loan_reserve, None, 0, 0, 0, 0).unwrap();
+
+        // partial refresh only serves a borrow from the recorded reserve in the same slot
+        obligation.record_borrow_refresh(10, &borrow_key);
+        assert!(obligation.last_update.is_lax_stale(10).unwrap());
+        assert_eq!(
+            obligation.clone().take_fresh_for_borrow(10, &other_loan_key, 0).unwrap_err(),
+            LendingError::ObligationStale.into(),
+        );
+        assert_eq!(
+            obligation.clone().take_fresh_for_borrow(11, &borrow_key, 0).unwrap_err(),
+            LendingError::ObligationStale.into(),
+        );
+        obligation.take_fresh_for_borrow(10, &borrow_key, 0).unwrap();
+        assert_eq!(
+            obligation.take_fresh_for_borrow(10, &borrow_key, 0).unwrap_err(),
+            LendingError::ObligationStale.into(),
+        );
+
+        // a missing collateral reserve is never relaxed
+        assert_eq!(
+            obligation.update_user_obligation(vec![(&borrow_key, loan_reserve.clone()), (&other_loan_key, loan_reserve)], 0).unwrap_err(),
+            LendingError::ObligationCollateralNotFound.into(),
+        );
+    }
+
+    #[test]
+    fn obligation_exposure_capped_at_boundary() {
+        let mut reserve = new_reserve(50, 0);
+        reserve.liquidity_info.available = 1000;
+
+        // collateral borrow value 60, each 100 sotoken adds 60
+        let obligation = new_obligation(100, 50);
+        obligation.clone().pledge::<false>(100, None, 0, &reserve, 120).unwrap();
+        assert_eq!(
+            obligation.clone().pledge::<false>(101, None, 0, &reserve, 120).unwrap_err(),
+            LendingError::ObligationExposureCapExceeded.into(),
+        );
+        let mut updated = obligation.clone();
+        updated.pledge::<true>(100, None, 0, &reserve, 120).unwrap();
+        assert_eq!(
+            updated.new_pledge::<true>(1, None, Pubkey::new_unique(), &reserve, 120).unwrap_err(),
+            LendingError::ObligationExposureCapExceeded.into(),
+        );
+        // no cap
+        obligation.clone().pledge::<false>(1000, None, 0, &reserve, 0).unwrap();
+        // replacing collateral is capped like pledging
+        obligation.clone().replace_collateral(200, None, 0, Pubkey::new_unique(), &reserve, &reserve, None, 120).unwrap();
+        assert_eq!(
+            obligation.clone().replace_collateral(201, None, 0, Pubkey::new_unique(), &reserve, &reserve, None, 120).unwrap_err(),
+            LendingError::ObligationExposureCapExceeded.into(),
+        );
+
+        // loans value 50
+        let obligation = new_obligation(1000, 50);
+        obligation.clone().borrow_in(Some(50), 0, &reserve, None, 0, 100, 0, 0).unwrap();
+        assert_eq!(
+            obligation.clone().borrow_in(Some(51), 0, &reserve, None, 0, 100, 0, 0).unwrap_err(),
+            LendingError::ObligationExposureCapExceeded.into(),
+        );
+        obligation.clone().new_borrow_in(Some(50), Pubkey::new_unique(), &reserve, None, 0, 100, 0, 0, 0).unwrap();
+        assert_eq!(
+            obligation.clone().new_borrow_in(Some(51), Pubkey::new_unique(), &reserve, None, 0, 100, 0, 0, 0).unwrap_err(),
+            LendingError::ObligationExposureCapExceeded.into(),
+        );
+        // no cap
+        obligation.clone().borrow_in(Some(200), 0, &reserve, None, 0, 0, 0, 0).unwrap();
+    }
+
+    #[test]
+    fn fresh_pledge_blocks_borrow_within_cooldown() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+
+        let mut obligation = new_obligation(1000, 10);
+        let collateral_key = obligation.collaterals[0].reserve;
+        obligation.record_pledge(&collateral_key, 100);
+
+        assert_eq!(
+            obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 100, 1).unwrap_err(),
+            LendingError::CollateralTooFresh.into(),
+        );
+        assert_eq!(
+            obligation.clone().new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 0, 100, 1).unwrap_err(),
+            LendingError::CollateralTooFresh.into(),
+        );
+        // no cooldown
+        obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 100, 0).unwrap();
+        // next slot
+        obligation.borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 101, 1).unwrap();
+    }
+
+    #[test]
+    fn borrow_preview_matches_realized_fee() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+        loan_reserve.liquidity_info.config.new_loan_fee = 3;
+
+        let mut obligation = new_obligation(1000, 10);
+        let key = Pubkey::new_unique();
+        let (fee, received) = obligation.borrow_preview(Some(10), &key, &loan_reserve);
+        let (amount, realized_fee) = obligation.new_borrow_in(Some(10), key, &loan_reserve, None, 0, 0, 0, 0, 0).unwrap();
+        assert_eq!((fee, received), (realized_fee, amount));
+        assert_eq!((fee, received), (3, 10));
+
+        // topping up an existing loan is free
+        assert_eq!(obligation.borrow_preview(Some(10), &key, &loan_reserve), (0, 10));
+        // borrow all takes what is available
+        assert_eq!(obligation.borrow_preview(None, &Pubkey::new_unique(), &loan_reserve), (3, 1000));
+    }
+
+    #[test]
+    fn borrow_available_runs_borrow_gates() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+        loan_reserve.last_update.update_slot(100, false);
+        let mut manager = Manager::new(255, Pubkey::default());
+        let clock = Clock { slot: 100, ..Clock::default() };
+
+        // borrow value 600 against 10 loans
+        let mut obligation = new_obligation(1000, 10);
+        obligation.last_update.update_slot(100, false);
+        let (loan_key, new_key) = (obligation.loans[0].reserve, Pubkey::new_unique());
+        assert_eq!(obligation.borrow_available(&loan_key, &loan_reserve, None, &manager, &clock).unwrap(), 590);
+        assert_eq!(obligation.borrow_available(&new_key, &loan_reserve, None, &manager, &clock).unwrap(), 590);
+        obligation.clone().borrow_in(Some(590), 0, &loan_reserve, None, 0, 0, 100, 0).unwrap();
+        // new loan fee counts against health
+        loan_reserve.liquidity_info.config.new_loan_fee = 5;
+        assert_eq!(obligation.borrow_available(&new_key, &loan_reserve, None, &manager, &clock).unwrap(), 585);
+        loan_reserve.liquidity_info.config.new_loan_fee = 0;
+        // reserve liquidity
+        loan_reserve.liquidity_info.available = 100;
+        assert_eq!(obligation.borrow_available(&loan_key, &loan_reserve, None, &manager, &clock).unwrap(), 100);
+        loan_reserve.liquidity_info.available = 1000;
+        // manager pause, obligation exposure cap, collateral cooldown
+        manager.paused = true;
+        assert_eq!(obligation.borrow_available(&loan_key, &loan_reserve, None, &manager, &clock).unwrap(), 0);
+        manager.paused = false;
+        manager.max_obligation_loan_value = 110;
+        assert_eq!(obligation.borrow_available(&loan_key, &loan_reserve, None, &manager, &clock).unwrap(), 100);
+        manager.max_obligation_loan_value = 0;
+        let mut fresh = obligation.clone();
+        let collateral_key = fresh.collaterals[0].reserve;
+        fresh.record_pledge(&collateral_key, 100);
+        manager.collateral_cooldown_slots = 1;
+        assert_eq!(fresh.borrow_available(&loan_key, &loan_reserve, None, &manager, &clock).unwrap(), 0);
+        manager.collateral_cooldown_slots = 0;
+        // stale obligation
+        let stale_clock = Clock { slot: 110, ..Clock::default() };
+        loan_reserve.last_update.update_slot(110, false);
+        manager.borrow_max_obligation_age_slots = 5;
+        assert_eq!(obligation.borrow_available(&loan_key, &loan_reserve, None, &manager, &stale_clock).unwrap(), 0);
+        manager.borrow_max_obligation_age_slots = 0;
+        loan_reserve.last_update.update_slot(100, false);
+
+        // whole reserve is below min borrow size
+        loan_reserve.token_config.decimal = 9;
+        let mut empty = new_obligation(1000, 0);
+        empty.last_update.update_slot(100, false);
+        assert_eq!(empty.borrow_available(&new_key, &loan_reserve, None, &manager, &clock).unwrap(), 0);
+    }
+
+    #[test]
+    fn companion_collateral_must_cover_loans() {
+        let (volatile_key, companion_key, loan_key) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut volatile_reserve = new_reserve(50, 0);
+        volatile_reserve.collateral_info.config.requires_companion_collateral = true;
+        let companion_reserve = new_reserve(50, 0);
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+
+        let mut obligation = new_obligation(1000, 10);
+        obligation.collaterals[0].reserve = volatile_key;
+        obligation.loans[0].reserve = loan_key;
+        // flag is read from reserve on refresh, collateral pledged before it was set included
+        obligation
+            .update_user_obligation(vec![(&volatile_key, volatile_reserve), (&loan_key, loan_reserve.clone())], 0)
+            .unwrap();
+        assert_eq!(obligation.companion_borrow_value, Decimal::zero());
+        assert_eq!(
+            obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 0, 0).unwrap_err(),
+            LendingError::RequiresCompanionCollateral.into(),
+        );
+        assert_eq!(
+            obligation.clone().new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 0, 0, 0).unwrap_err(),
+            LendingError::RequiresCompanionCollateral.into(),
+        );
+
+        // dust companion does not unlock borrowing: 10 * 0.6 < 20
+        obligation.new_pledge::<true>(10, None, companion_key, &companion_reserve, 0).unwrap();
+        assert_eq!(obligation.companion_borrow_value, Decimal::from(6u64));
+        assert_eq!(
+            obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 0, 0).unwrap_err(),
+            LendingError::RequiresCompanionCollateral.into(),
+        );
+        obligation.pledge::<true>(100, None, 1, &companion_reserve, 0).unwrap();
+        obligation.borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 0, 0).unwrap();
+
+        // companion can not be unpledged from under the loans: 66 - 60 < 20
+        assert_eq!(
+            obligation.clone().redeem::<true, true>(Some(100), 1, &companion_reserve, None).unwrap_err(),
+            LendingError::RequiresCompanionCollateral.into(),
+        );
+        obligation.redeem::<true, true>(Some(50), 1, &companion_reserve, None).unwrap();
+        assert_eq!(obligation.companion_borrow_value, Decimal::from(36u64));
+    }
+
+    #[test]
+    fn loan_diversity_capped() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+
+        let mut obligation = new_obligation(1000, 0);
+        obligation.loans.clear();
+        obligation.new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 2, 0, 0).unwrap();
+        obligation.new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 2, 0, 0).unwrap();
+        assert_eq!(
+            obligation.clone().new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 2, 0, 0).unwrap_err(),
+            LendingError::LoanDiversityCapExceeded.into(),
+        );
+        // no cap
+        assert!(obligation.clone().new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 0, 0, 0).is_ok());
+
+        // topping up existing loan is unaffected
+        obligation.borrow_in(Some(10), 1, &loan_reserve, None, 0, 0, 0, 0).unwrap();
+        assert_eq!(obligation.loans[1].borrowed_amount_wads, Decimal::from(20u64));
+    }
+
+    #[test]
+    fn new_loan_fee_charged_beyond_first_loan() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1000;
+        loan_reserve.liquidity_info.config.new_loan_fee = 2;
+
+        // first loan is free
+        let mut obligation = new_obligation(1000, 0);
+        obligation.loans.clear();
+        let (amount, fee) = obligation.new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 0, 0, 0).unwrap();
+        assert_eq!((amount, fee), (10, 0));
+        assert_eq!(obligation.loans[0].borrowed_amount_wads, Decimal::from(10u64));
+
+        // second distinct loan pays the fee
+        let (amount, fee) = obligation.new_borrow_in(Some(10), Pubkey::new_unique(), &loan_reserve, None, 0, 0, 0, 0, 0).unwrap();
+        assert_eq!((amount, fee), (10, 2));
+        assert_eq!(obligation.loans[1].borrowed_amount_wads, Decimal::from(12u64));
+        assert_eq!(obligation.loans_value, Decimal::from(22u64));
+
+        // topping up an existing loan is free
+        let amount = obligation.borrow_in(Some(5), 1, &loan_reserve, None, 0, 0, 0, 0).unwrap();
+        assert_eq!(amount, 5);
+        assert_eq!(obligation.loans[1].borrowed_amount_wads, Decimal::from(17u64));
+
+        loan_reserve.liquidity_info.borrow_out(10).unwrap();
+        loan_reserve.liquidity_info.charge_new_loan_fee(2).unwrap();
+        assert_eq!(loan_reserve.liquidity_info.available, 990);
+        assert_eq!(loan_reserve.liquidity_info.borrowed_amount_wads, Decimal::from(12u64));
+        assert_eq!(loan_reserve.liquidity_info.insurance_wads, Decimal::from(2u64));
+    }
+
+    #[test]
+    #[cfg(feature = "friend")]
+    fn bind_friend_keeps_strict_pair() {
+        let (key_a, key_b, key_c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut obligation_a = new_obligation(100, 50);
+        let mut obligation_b = new_obligation(100, 50);
+        let mut obligation_c = new_obligation(100, 50);
+
+        // self binding
+        let mut obligation_a_copy = obligation_a.clone();
+        assert_eq!(
+            obligation_a.bind_friend(&key_a, &mut obligation_a_copy, &key_a).unwrap_err(),
+            LendingError::ObligationInvalidFriend.into(),
+        );
+
+        obligation_a.bind_friend(&key_a, &mut obligation_b, &key_b).unwrap();
+        assert_eq!(obligation_a.friend, COption::Some(key_b));
+        assert_eq!(obligation_b.friend, COption::Some(key_a));
+
+        // binding an already bound obligation
+        assert_eq!(
+            obligation_c.bind_friend(&key_c, &mut obligation_b, &key_b).unwrap_err(),
+            LendingError::ObligationAlreadyBound.into(),
+        );
+        assert_eq!(
+            obligation_a.bind_friend(&key_a, &mut obligation_c, &key_c).unwrap_err(),
+            LendingError::ObligationAlreadyBound.into(),
+        );
+        assert_eq!(obligation_c.friend, COption::None);
+    }
+
+    #[cfg(feature = "friend")]
+    fn new_friend_pair(
+        collateral_a: u64,
+        borrowed_a: u64,
+        collateral_b: u64,
+        borrowed_b: u64,
+    ) -> (UserObligation, UserObligation) {
+        let (key_a, key_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut obligation_a = new_obligation(collateral_a, borrowed_a);
+        let mut obligation_b = new_obligation(collateral_b, borrowed_b);
+        if borrowed_a == 0 {
+            obligation_a.loans.clear();
+        }
+        if borrowed_b == 0 {
+            obligation_b.loans.clear();
+        }
+        obligation_a.bind_friend(&key_a, &mut obligation_b, &key_b).unwrap();
+
+        (obligation_a, obligation_b)
+    }
+
+    #[test]
+    #[cfg(feature = "friend")]
+    fn friend_borrow_and_redeem_use_combined_values() {
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+        let loan_key = Pubkey::new_unique();
+
+        // borrow value: a 60, b 60; loans value: a 0, b 30
+        let (mut obligation_a, mut obligation_b) = new_friend_pair(100, 0, 100, 30);
+
+        // a alone only supports 60
+        assert_eq!(
+            obligation_a.clone().new_borrow_in(Some(61), loan_key, &loan_reserve, None, 0, 0, 0, 0, 0).unwrap_err(),
+            LendingError::ObligationNotHealthy.into(),
+        );
+        // combined supports 120 - 30 = 90
+        assert_eq!(
+            obligation_a.clone().new_borrow_in(Some(91), loan_key, &loan_reserve, Some(obligation_b.clone()), 0, 0, 0, 0, 0).unwrap_err(),
+            LendingError::ObligationNotHealthy.into(),
+        );
+        obligation_a.new_borrow_in(Some(70), loan_key, &loan_reserve, Some(obligation_b.clone()), 0, 0, 0, 0, 0).unwrap();
+        assert_eq!(obligation_a.loans_value, Decimal::from(70u64));
+
+        // b collateral backs a's loan: combined loans 100, combined borrow value 120
+        assert_eq!(
+            obligation_b.clone().redeem::<true, true>(Some(34), 0, &collateral_reserve, Some(obligation_a.clone())).unwrap_err(),
+            LendingError::ObligationNotHealthy.into(),
+        );
+        obligation_b.redeem::<true, true>(Some(33), 0, &collateral_reserve, Some(obligation_a.clone())).unwrap();
+        assert_eq!(obligation_b.collaterals[0].amount, 67);
+        assert!(obligation_b.validate_health(Some(obligation_a.clone())).is_ok());
+        // a alone is now unhealthy, only the combined position holds
+        assert!(obligation_a.validate_health(None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "friend")]
+    fn friend_liquidation_uses_combined_values() {
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+
+        // liquidation value: a 80, b 160; loans value: a 170
+        let (obligation_a, obligation_b) = new_friend_pair(100, 170, 200, 0);
+        assert!(obligation_a.clone().liquidate::<false>(Some(20), 0, 0, &collateral_reserve, &loan_reserve, None).is_ok());
+        assert_eq!(
+            obligation_a.clone().liquidate::<false>(Some(20), 0, 0, &collateral_reserve, &loan_reserve, Some(obligation_b)).unwrap_err(),
+            LendingError::LiquidationNotAvailable.into(),
+        );
+
+        // liquidation value: a 80, b 80; loans value: a 170
+        let (mut obligation_a, obligation_b) = new_friend_pair(100, 170, 100, 0);
+        let (seize_amount, settle, _) = obligation_a
+            .liquidate::<false>(Some(20), 0, 0, &collateral_reserve, &loan_reserve, Some(obligation_b))
+            .unwrap();
+        // seize rate capped by liquidation penalty 5%
+        assert_eq!(settle.amount, 20);
+        assert_eq!(seize_amount, 21);
+        assert_eq!(obligation_a.collaterals[0].amount, 79);
+        assert_eq!(obligation_a.loans[0].borrowed_amount_wads, Decimal::from(150u64));
+        // close factor bounds repay by the liquidated obligation's own loan
+        let (obligation_a, obligation_b) = new_friend_pair(100, 170, 100, 0);
+        assert_eq!(
+            obligation_a.clone().liquidate::<false>(Some(86), 0, 0, &collateral_reserve, &loan_reserve, Some(obligation_b)).unwrap_err(),
+            LendingError::LiquidationRepayTooMuch.into(),
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "friend")]
+    fn friend_borrow_value_cap_limits_combined_loans() {
+        let loan_reserve = new_reserve(50, 0);
+        let loan_key = Pubkey::new_unique();
+
+        // borrow value: a 60, b 60; loans value: a 0, b 30
+        let (obligation_a, obligation_b) = new_friend_pair(100, 0, 100, 30);
+
+        // combined health allows 90, cap of 50 leaves 20
+        assert_eq!(
+            obligation_a.clone().new_borrow_in(Some(21), loan_key, &loan_reserve, Some(obligation_b.clone()), 50, 0, 0, 0, 0).unwrap_err(),
+            LendingError::FriendLeverageCapExceeded.into(),
+        );
+        assert!(obligation_a.clone().new_borrow_in(Some(20), loan_key, &loan_reserve, Some(obligation_b.clone()), 50, 0, 0, 0, 0).is_ok());
+        // cap only applies to friend pairs
+        assert!(obligation_a.clone().new_borrow_in(Some(60), loan_key, &loan_reserve, None, 50, 0, 0, 0, 0).is_ok());
+        assert!(obligation_a.new_borrow_in(Some(90), loan_key, &loan_reserve, Some(obligation_b), 0, 0, 0, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn borrow_cooldown_after_large_repay() {
+        let mut loan_reserve = new_reserve(50, 0);
+        loan_reserve.liquidity_info.available = 1_000;
+        loan_reserve.liquidity_info.config.borrow_cooldown_slots = 10;
+        loan_reserve.liquidity_info.config.large_repay_ratio = 50;
+        let mut obligation = new_obligation(100, 40);
+        let loan_key = obligation.loans[0].reserve;
+
+        // small repay keeps borrow available
+        let settle = obligation.repay::<true>(Some(10), u64::MAX, 0, &loan_reserve).unwrap();
+        obligation.record_repay(&loan_key, settle.amount_decimal, 100, &loan_reserve).unwrap();
+        assert_eq!(obligation.last_repay_slot, 0);
+
+        // repay 20 of 30 is large
+        let settle = obligation.repay::<true>(Some(20), u64::MAX, 0, &loan_reserve).unwrap();
+        obligation.record_repay(&loan_key, settle.amount_decimal, 100, &loan_reserve).unwrap();
+        assert_eq!(obligation.last_repay_slot, 100);
+
+        assert_eq!(
+            obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 109, 0).unwrap_err(),
+            LendingError::BorrowCooldown.into(),
+        );
+        obligation.clone().borrow_in(Some(10), 0, &loan_reserve, None, 0, 0, 110, 0).unwrap();
+
+        // closing the loan keeps the cooldown for a new loan
+        let settle = obligation.repay::<true>(None, u64::MAX, 0, &loan_reserve).unwrap();
+        assert!(obligation.loans.is_empty());
+        obligation.record_repay(&loan_key, settle.amount_decimal, 200, &loan_reserve).unwrap();
+        assert_eq!(
+            obligation.clone().new_borrow_in(Some(10), loan_key, &loan_reserve, None, 0, 0, 0, 209, 0).unwrap_err(),
+            LendingError::BorrowCooldown.into(),
+        );
+        obligation.new_borrow_in(Some(10), loan_key, &loan_reserve, None, 0, 0, 0, 210, 0).unwrap();
+    }
+
+    #[test]
+    fn liquidation_price_of_single_pair() {
+        let collateral_reserve = new_reserve(50, 0);
+        let loan_reserve = new_reserve(50, 0);
+        let mut obligation = new_obligation(100, 40);
+        let (collateral_key, loan_key) = (obligation.collaterals[0].reserve, obligation.loans[0].reserve);
+        let reserves = [(&collateral_key, &collateral_reserve), (&loan_key, &loan_reserve)];
+
+        // 100 * price * 80% == 40
+        assert_eq!(
+            obligation.liquidation_price(0, &reserves).unwrap(),
+            Some(Decimal::from_percent(50)),
+        );
+
+        assert_eq!(
+            obligation.liquidation_price(1, &reserves),
+            Err(LendingError::ObligationCollateralNotFound.into()),
+        );
+
+        obligation.loans.clear();
+        assert_eq!(obligation.liquidation_price(0, &reserves).unwrap(), None);
+    }
+}
@@ -9,7 +9,7 @@ use solana_program::{
     system_program,
 };
 use soda_lending::{
-    state::{CollateralConfig, LiquidityConfig, RateModel},
+    state::{CollateralConfig, DepositWhitelist, LiquidityConfig, RateModel},
     oracle::{OracleConfig, OracleType},
 };
 use spl_associated_token_account::get_associated_token_address;
@@ -162,8 +162,9 @@ impl ProxyInstruction {
         let (optimal, rest) = Self::unpack_u64(rest)?;
         let (kink, rest) = Self::unpack_u8(rest)?;
         let (max, rest) = Self::unpack_u128(rest)?;
+        let (smoothing_factor, rest) = Self::unpack_u8(rest)?;
 
-        Ok((RateModel { offset, optimal, kink, max }, rest))
+        Ok((RateModel { offset, optimal, kink, max, smoothing_factor }, rest))
     }
 
     fn unpack_oracle_config(input: &[u8]) -> Result<(OracleConfig, &[u8]), ProgramError> {
@@ -177,8 +178,30 @@ impl ProxyInstruction {
         let (borrow_value_ratio, rest) = Self::unpack_u8(input)?;
         let (liquidation_value_ratio, rest) = Self::unpack_u8(rest)?;
         let (liquidation_penalty_ratio, rest) = Self::unpack_u8(rest)?;
+        let (full_liquidation_health_bps, rest) = Self::unpack_u16(rest)?;
+        let (borrower_liquidation_rebate_bps, rest) = Self::unpack_u16(rest)?;
+        let (max_post_liquidation_health_bps, rest) = Self::unpack_u16(rest)?;
+        let (permissioned, rest) = Self::unpack_u8(rest)?;
+        let (max_concentration_ratio, rest) = Self::unpack_u8(rest)?;
+        let (bonus_by_collateral_bps, rest) = Self::unpack_u16(rest)?;
+        let (bonus_by_loan_bps, rest) = Self::unpack_u16(rest)?;
+        let (requires_companion_collateral, rest) = Self::unpack_u8(rest)?;
 
-        Ok((CollateralConfig { borrow_value_ratio, liquidation_value_ratio, liquidation_penalty_ratio }, rest))
+        Ok((
+            CollateralConfig {
+                borrow_value_ratio,
+                liquidation_value_ratio,
+                liquidation_penalty_ratio,
+                full_liquidation_health_bps,
+                borrower_liquidation_rebate_bps,
+                max_post_liquidation_health_bps,
+                permissioned: permissioned != 0,
+                max_concentration_ratio,
+                bonus_by_collateral_bps,
+                bonus_by_loan_bps,
+                requires_companion_collateral: requires_companion_collateral != 0,
+            }, rest
+        ))
     }
 
     fn unpack_liquidity_config(input: &[u8]) -> Result<(LiquidityConfig, &[u8]), ProgramError> {
@@ -186,6 +209,10 @@ impl ProxyInstruction {
         let (borrow_tax_rate, rest) = Self::unpack_u8(rest)?;
         let (flash_loan_fee_rate, rest) = Self::unpack_u64(rest)?;
         let (max_deposit, rest) = Self::unpack_u64(rest)?;
+        let (new_loan_fee, rest) = Self::unpack_u64(rest)?;
+        let (borrow_cooldown_slots, rest) = Self::unpack_u64(rest)?;
+        let (large_repay_ratio, rest) = Self::unpack_u8(rest)?;
+        let (compound_by_seconds, rest) = Self::unpack_u8(rest)?;
 
         Ok((
             LiquidityConfig {
@@ -193,6 +220,10 @@ impl ProxyInstruction {
                 borrow_tax_rate,
                 flash_loan_fee_rate,
                 max_deposit,
+                new_loan_fee,
+                borrow_cooldown_slots,
+                large_repay_ratio,
+                compound_by_seconds: compound_by_seconds != 0,
             }, rest
         ))
     }
@@ -235,6 +266,20 @@ impl ProxyInstruction {
         Ok((amount, rest))
     }
 
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        if input.len() < 2 {
+            msg!("u16 cannot be unpacked");
+            return Err(ProxyError::InstructionUnpackError.into());
+        }
+        let (amount, rest) = input.split_at(2);
+        let amount = amount
+            .get(..2)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u16::from_le_bytes)
+            .ok_or(ProxyError::InstructionUnpackError)?;
+        Ok((amount, rest))
+    }
+
     fn unpack_u8(input: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
         if input.is_empty() {
             msg!("u8 cannot be unpacked");
@@ -348,6 +393,7 @@ impl ProxyInstruction {
         buf.extend_from_slice(&model.optimal.to_le_bytes());
         buf.extend_from_slice(&model.kink.to_le_bytes());
         buf.extend_from_slice(&model.max.to_le_bytes());
+        buf.extend_from_slice(&model.smoothing_factor.to_le_bytes());
     }
 
     fn pack_oracle_config(config: OracleConfig, buf: &mut Vec<u8>) {
@@ -360,6 +406,14 @@ impl ProxyInstruction {
         buf.extend_from_slice(&config.borrow_value_ratio.to_le_bytes());
         buf.extend_from_slice(&config.liquidation_value_ratio.to_le_bytes());
         buf.extend_from_slice(&config.liquidation_penalty_ratio.to_le_bytes());
+        buf.extend_from_slice(&config.full_liquidation_health_bps.to_le_bytes());
+        buf.extend_from_slice(&config.borrower_liquidation_rebate_bps.to_le_bytes());
+        buf.extend_from_slice(&config.max_post_liquidation_health_bps.to_le_bytes());
+        buf.extend_from_slice(&(config.permissioned as u8).to_le_bytes());
+        buf.extend_from_slice(&config.max_concentration_ratio.to_le_bytes());
+        buf.extend_from_slice(&config.bonus_by_collateral_bps.to_le_bytes());
+        buf.extend_from_slice(&config.bonus_by_loan_bps.to_le_bytes());
+        buf.extend_from_slice(&(config.requires_companion_collateral as u8).to_le_bytes());
     }
 
     fn pack_liquidity_config(config: LiquidityConfig, buf: &mut Vec<u8>) {
@@ -367,6 +421,10 @@ impl ProxyInstruction {
         buf.extend_from_slice(&config.borrow_tax_rate.to_le_bytes());
         buf.extend_from_slice(&config.flash_loan_fee_rate.to_le_bytes());
         buf.extend_from_slice(&config.max_deposit.to_le_bytes());
+        buf.extend_from_slice(&config.new_loan_fee.to_le_bytes());
+        buf.extend_from_slice(&config.borrow_cooldown_slots.to_le_bytes());
+        buf.extend_from_slice(&config.large_repay_ratio.to_le_bytes());
+        buf.extend_from_slice(&(config.compound_by_seconds as u8).to_le_bytes());
     }
 }
 
@@ -399,6 +457,7 @@ pub fn create_market_reserve(
     collateral_config: CollateralConfig,
     liquidity_config: LiquidityConfig,
     rate_model: RateModel,
+    pyth_product_key: Option<Pubkey>,
 ) -> Instruction {
     let lending_id = soda_lending::id();
     let (manager_authority_key, _bump_seed) = Pubkey::find_program_address(
@@ -406,22 +465,27 @@ pub fn create_market_reserve(
         &lending_id,
     );
 
+    let mut accounts = vec![
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(manager_key, false),
+        AccountMeta::new_readonly(manager_authority_key, false),
+        AccountMeta::new(supply_token_account_key, true),
+        AccountMeta::new(market_reserve_key, true),
+        AccountMeta::new_readonly(token_mint_key, false),
+        AccountMeta::new(sotoken_mint_key, true),
+        AccountMeta::new_readonly(authority_key, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(lending_id, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if let Some(pyth_product_key) = pyth_product_key {
+        accounts.push(AccountMeta::new_readonly(pyth_product_key, false));
+    }
+
     Instruction {
         program_id: id(),
-        accounts: vec![
-            AccountMeta::new_readonly(sysvar::rent::id(), false),
-            AccountMeta::new_readonly(sysvar::clock::id(), false),
-            AccountMeta::new_readonly(manager_key, false),
-            AccountMeta::new_readonly(manager_authority_key, false),
-            AccountMeta::new(supply_token_account_key, true),
-            AccountMeta::new(market_reserve_key, true),
-            AccountMeta::new_readonly(token_mint_key, false),
-            AccountMeta::new(sotoken_mint_key, true),
-            AccountMeta::new_readonly(authority_key, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
-            AccountMeta::new_readonly(lending_id, false),
-            AccountMeta::new_readonly(system_program::id(), false),
-        ],
+        accounts,
         data: ProxyInstruction::CreateMarketReserve(oracle_config, collateral_config, liquidity_config, rate_model).pack(),
     }
 }
@@ -455,6 +519,7 @@ pub fn deposit_and_pledge_or_repay<const IS_REPAY: bool>(
         vec![
             AccountMeta::new_readonly(sysvar::rent::id(), false),
             AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(manager_key, false),
             AccountMeta::new(market_reserve_key, false),
             AccountMeta::new_readonly(supply_mint_key, false),
             AccountMeta::new(supply_token_account_key, false),
@@ -481,6 +546,7 @@ pub fn deposit_and_pledge_or_repay<const IS_REPAY: bool>(
             AccountMeta::new_readonly(lending_id, false),
             AccountMeta::new_readonly(system_program::id(), false),
             AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(DepositWhitelist::find_address(&market_reserve_key, &lending_id).0, false),
         ]
     };
 
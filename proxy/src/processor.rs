@@ -146,6 +146,8 @@ fn process_create_market_reserve(
     let token_program_info = next_account_info(account_info_iter)?;
     let lending_program_info = next_account_info(account_info_iter)?;
     let system_program_info = next_account_info(account_info_iter)?;
+    // pyth product account, forwarded to check quote currency
+    let pyth_product_info = account_info_iter.next();
 
     _process_create_account::<MarketReserve>(
         rent_info,
@@ -171,6 +173,23 @@ fn process_create_market_reserve(
         token_program_info.key,
     )?;
 
+    let mut account_infos = vec![
+        rent_info.clone(),
+        clock_info.clone(),
+        manager_info.clone(),
+        manager_authority_info.clone(),
+        supply_token_account_info.clone(),
+        market_reserve_info.clone(),
+        token_mint_info.clone(),
+        sotoken_mint_info.clone(),
+        authority_info.clone(),
+        token_program_info.clone(),
+        lending_program_info.clone(),
+    ];
+    if let Some(pyth_product_info) = pyth_product_info {
+        account_infos.push(pyth_product_info.clone());
+    }
+
     invoke(
         &lending_instruction::init_market_reserve(
             *manager_info.key,
@@ -183,20 +202,9 @@ fn process_create_market_reserve(
             collateral_config,
             liquidity_config,
             rate_model,
+            pyth_product_info.map(|info| *info.key),
         ),
-        &[
-            rent_info.clone(),
-            clock_info.clone(),
-            manager_info.clone(),
-            manager_authority_info.clone(),
-            supply_token_account_info.clone(),
-            market_reserve_info.clone(),
-            token_mint_info.clone(),
-            sotoken_mint_info.clone(),
-            authority_info.clone(),
-            token_program_info.clone(),
-            lending_program_info.clone(),
-        ],
+        &account_infos,
     )
 }
 
@@ -217,6 +225,7 @@ fn process_deposit_and_pledge(program_id: &Pubkey, accounts: &[AccountInfo], amo
     let lending_program_info = next_account_info(account_info_iter)?;
     let system_program_info = next_account_info(account_info_iter)?;
     let spl_associated_program_info = next_account_info(account_info_iter)?;
+    let deposit_whitelist_info = next_account_info(account_info_iter)?;
 
     _process_create_user_obligation(
         program_id,
@@ -242,6 +251,7 @@ fn process_deposit_and_pledge(program_id: &Pubkey, accounts: &[AccountInfo], amo
 
     invoke(
         &lending_instruction::deposit_and_pledge(
+            *manager_info.key,
             *market_reserve_info.key,
             *supply_token_account_info.key,
             *user_obligation_info.key,
@@ -257,6 +267,8 @@ fn process_deposit_and_pledge(program_id: &Pubkey, accounts: &[AccountInfo], amo
             authority_info.clone(),
             user_token_account_info.clone(),
             token_program_info.clone(),
+            manager_info.clone(),
+            deposit_whitelist_info.clone(),
             lending_program_info.clone(),
         ],
     )?;
@@ -275,6 +287,7 @@ fn process_repay(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
 
     let rent_info = next_account_info(account_info_iter)?;
     let clock_info = next_account_info(account_info_iter)?;
+    let manager_info = next_account_info(account_info_iter)?;
     let market_reserve_info = next_account_info(account_info_iter)?;
     let supply_mint_info = next_account_info(account_info_iter)?;
     let supply_token_account_info = next_account_info(account_info_iter)?;
@@ -299,6 +312,7 @@ fn process_repay(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
 
     invoke(
         &lending_instruction::repay_loan(
+            *manager_info.key,
             *market_reserve_info.key,
             *supply_token_account_info.key,
             *user_obligation_info.key,
@@ -314,6 +328,7 @@ fn process_repay(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
             authority_info.clone(),
             user_token_account_info.clone(),
             token_program_info.clone(),
+            manager_info.clone(),
             lending_program_info.clone(),
         ],
     )?;